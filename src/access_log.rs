@@ -0,0 +1,320 @@
+use std::fmt::Debug;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name of the span each request is served in. Its fields and those recorded on the spans under
+/// it make up the request's access log record.
+pub const REQUEST_SPAN: &str = "request";
+
+/// Target of the spans opened by the handlers of the S3 operations.
+const HANDLER_TARGET: &str = "s3_reproxy::server";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum AccessLogFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// One line of `key=value` pairs per request.
+    Logfmt,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct AccessRecord {
+    /// Milliseconds since the Unix epoch at which the request arrived.
+    timestamp_ms: u128,
+    request_id: Option<String>,
+    operation: Option<String>,
+    method: Option<String>,
+    bucket: Option<String>,
+    key: Option<String>,
+    status: Option<u64>,
+    bytes_in: Option<u64>,
+    bytes_out: Option<u64>,
+    /// Remote a read was served by.
+    remote: Option<String>,
+    remotes_succeeded: Option<u64>,
+    remotes_failed: Option<u64>,
+    /// Remotes that stored a write, comma-separated.
+    succeeded_remotes: Option<String>,
+    /// Remotes that answered a write with an error, comma-separated.
+    failed_remotes: Option<String>,
+    /// Milliseconds until the response headers were sent.
+    latency_ms: u128,
+}
+
+impl AccessRecord {
+    /// Splits a path-style request path into its bucket and key, decoding them. Stands in for
+    /// requests whose handler does not name them, e.g. ones rejected before reaching it.
+    fn set_path(&mut self, path: &str) {
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        self.bucket = parts.next().filter(|b| !b.is_empty()).map(percent_decode);
+        self.key = parts.next().filter(|k| !k.is_empty()).map(percent_decode);
+    }
+
+    fn logfmt(&self) -> String {
+        let text = |value: &Option<String>| value.clone();
+        let number = |value: Option<u64>| value.map(|v| v.to_string());
+        [
+            ("timestamp_ms", Some(self.timestamp_ms.to_string())),
+            ("request_id", text(&self.request_id)),
+            ("operation", text(&self.operation)),
+            ("method", text(&self.method)),
+            ("bucket", text(&self.bucket)),
+            ("key", text(&self.key)),
+            ("status", number(self.status)),
+            ("bytes_in", number(self.bytes_in)),
+            ("bytes_out", number(self.bytes_out)),
+            ("remote", text(&self.remote)),
+            ("remotes_succeeded", number(self.remotes_succeeded)),
+            ("remotes_failed", number(self.remotes_failed)),
+            ("succeeded_remotes", text(&self.succeeded_remotes)),
+            ("failed_remotes", text(&self.failed_remotes)),
+            ("latency_ms", Some(self.latency_ms.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let value = value?;
+            if value.is_empty() || value.contains([' ', '=', '"']) {
+                Some(format!("{name}={value:?}"))
+            } else {
+                Some(format!("{name}={value}"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+impl Visit for AccessRecord {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "id" => self.request_id = Some(value.to_owned()),
+            "method" => self.method = Some(value.to_owned()),
+            "path" => self.set_path(value),
+            "remote.selected" => self.remote = Some(value.to_owned()),
+            "remote.succeeded" => self.succeeded_remotes = Some(value.to_owned()),
+            "remote.failed" => self.failed_remotes = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "status" => self.status = Some(value),
+            "bytes_in" => self.bytes_in = Some(value),
+            "bytes_out" => self.bytes_out = Some(value),
+            "remote.success_count" => self.remotes_succeeded = Some(value),
+            "remote.failure_count" => self.remotes_failed = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if let Ok(value) = u64::try_from(value) {
+            self.record_u64(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// The bucket and key named by the handler of an operation, as s3s parsed them from the request.
+/// They take over from those split off the path, which are not decoded the same way, nor
+/// addressed the same way under virtual-hosted-style requests.
+struct HandlerFields<'a>(&'a mut AccessRecord);
+
+impl Visit for HandlerFields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "bucket" => self.0.bucket = Some(value.to_owned()),
+            "key" => self.0.key = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// When a request started, kept on its span until the span closes.
+struct Started(Instant);
+
+/// Writes one record per request, built from the fields of its [`REQUEST_SPAN`] and of the spans
+/// under it, when the request span closes.
+pub struct AccessLogLayer {
+    format: AccessLogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLogLayer {
+    /// Writes to the file at `destination`, appending to it, or to stdout when it is `-`.
+    pub fn new(destination: &Path, format: AccessLogFormat) -> std::io::Result<Self> {
+        let out: Box<dyn Write + Send> = if destination == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(destination)?,
+            )
+        };
+        Ok(Self {
+            format,
+            out: Mutex::new(out),
+        })
+    }
+
+    fn write(&self, record: &AccessRecord) {
+        let line = match self.format {
+            AccessLogFormat::Json => serde_json::to_string(record).unwrap_or_default(),
+            AccessLogFormat::Logfmt => record.logfmt(),
+        };
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{line}");
+        let _ = out.flush();
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if span.name() == REQUEST_SPAN {
+            let mut record = AccessRecord {
+                timestamp_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                ..Default::default()
+            };
+            attrs.record(&mut record);
+            let mut extensions = span.extensions_mut();
+            extensions.insert(record);
+            extensions.insert(Started(Instant::now()));
+            return;
+        }
+        let Some(request) = span.scope().skip(1).find(|s| s.name() == REQUEST_SPAN) else {
+            return;
+        };
+        let mut extensions = request.extensions_mut();
+        let Some(record) = extensions.get_mut::<AccessRecord>() else {
+            return;
+        };
+        // the outermost span of the proxy under a request is the handler of its operation
+        if record.operation.is_none() && span.metadata().target() == HANDLER_TARGET {
+            record.operation = Some(span.name().trim_start_matches("s3s/").to_owned());
+            attrs.record(&mut HandlerFields(record));
+        }
+        attrs.record(record);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let Some(request) = span.scope().find(|s| s.name() == REQUEST_SPAN) else {
+            return;
+        };
+        if let Some(record) = request.extensions_mut().get_mut::<AccessRecord>() {
+            values.record(record);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if span.name() != REQUEST_SPAN {
+            return;
+        }
+        let mut extensions = span.extensions_mut();
+        let (Some(mut record), Some(Started(started))) = (
+            extensions.remove::<AccessRecord>(),
+            extensions.remove::<Started>(),
+        ) else {
+            return;
+        };
+        record.latency_ms = started.elapsed().as_millis();
+        self.write(&record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn records_are_written_as_logfmt() {
+        let mut record = AccessRecord {
+            timestamp_ms: 1,
+            request_id: Some("abc".to_owned()),
+            operation: Some("put_object".to_owned()),
+            status: Some(200),
+            failed_remotes: Some("b c".to_owned()),
+            ..Default::default()
+        };
+        record.set_path("/bucket/videos/a.mp4");
+
+        assert_eq!(record.bucket.as_deref(), Some("bucket"));
+        assert_eq!(record.key.as_deref(), Some("videos/a.mp4"));
+        assert_eq!(
+            record.logfmt(),
+            "timestamp_ms=1 request_id=abc operation=put_object bucket=bucket \
+             key=videos/a.mp4 status=200 failed_remotes=\"b c\" latency_ms=0"
+        );
+    }
+
+    #[test]
+    fn path_is_decoded() {
+        let mut record = AccessRecord::default();
+        record.set_path("/bucket/photos/caf%C3%A9%20menu.jpg%");
+
+        assert_eq!(record.bucket.as_deref(), Some("bucket"));
+        assert_eq!(record.key.as_deref(), Some("photos/café menu.jpg%"));
+    }
+}
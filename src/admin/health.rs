@@ -0,0 +1,103 @@
+use serde::Serialize;
+
+use crate::server::remote::S3Remote;
+use crate::server::status::{CircuitState, StatusSnapshot};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RemoteHealth {
+    pub name: String,
+    pub read_request: bool,
+    #[serde(flatten)]
+    pub status: StatusSnapshot,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct HealthReport {
+    /// Whether some readable remote is not known to be down.
+    pub ready: bool,
+    pub remotes: Vec<RemoteHealth>,
+}
+
+pub fn report(remotes: &[S3Remote]) -> HealthReport {
+    let remotes = remotes
+        .iter()
+        .map(|r| RemoteHealth {
+            name: r.name.clone(),
+            read_request: r.read_request,
+            status: r.status.snapshot(),
+        })
+        .collect::<Vec<_>>();
+    HealthReport {
+        ready: remotes
+            .iter()
+            .any(|r| r.read_request && r.status.circuit != CircuitState::Open),
+        remotes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use pretty_assertions::assert_eq;
+
+    use crate::admin::stats::StatsCache;
+    use crate::admin::Admin;
+    use crate::server::reload::RemoteSet;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn readyz_reports_every_remote() {
+        let up = S3Remote::stub("up");
+        up.status.record(true);
+        let down = S3Remote::stub("down");
+        down.status.record(false);
+        let admin = Admin {
+            remotes: Arc::new(RemoteSet::new(vec![up, down])),
+            stats: StatsCache::new(Default::default()),
+            maintenance: None,
+        };
+
+        let response = admin
+            .handle(Request::get("/readyz").body(()).unwrap())
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["ready"], true);
+        for (remote, circuit) in body["remotes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(["open", "closed"])
+        {
+            for field in [
+                "name",
+                "circuit",
+                "last_success",
+                "error_rate",
+                "draining",
+                "replication_lag_secs",
+            ] {
+                assert!(remote.get(field).is_some(), "missing {field}");
+            }
+            assert_eq!(remote["circuit"], circuit);
+        }
+    }
+
+    #[test]
+    fn not_ready_when_every_readable_remote_is_down() {
+        let down = S3Remote::stub("down");
+        down.status.record(false);
+        let writer = S3Remote {
+            read_request: false,
+            ..S3Remote::stub("writer")
+        };
+
+        assert!(!report(&[down, writer]).ready);
+    }
+}
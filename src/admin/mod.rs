@@ -0,0 +1,233 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http::{header, Method, Request, Response, StatusCode};
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tracing::{error, info, instrument, warn, Instrument};
+
+use crate::db::MongoDB;
+use crate::server::abandoned;
+use crate::server::reload::RemoteSet;
+
+use self::stats::StatsCache;
+
+pub mod health;
+pub mod stats;
+
+/// State behind the operator-facing HTTP endpoints, served on a port separate from S3.
+pub struct Admin {
+    pub remotes: Arc<RemoteSet>,
+    pub stats: StatsCache,
+    /// Serves the maintenance endpoints when set, which `admin_token` enables outside of
+    /// `read_only` mode.
+    pub maintenance: Option<Maintenance>,
+}
+
+/// What the maintenance endpoints need: the database they act on, and the bearer token every
+/// request to them must carry since the admin port is not otherwise protected.
+pub struct Maintenance {
+    pub db: Arc<MongoDB>,
+    pub token: String,
+    /// How many remotes an abort is sent to at once.
+    pub fanout_concurrency: usize,
+}
+
+impl Admin {
+    pub async fn handle<B>(&self, req: Request<B>) -> Response<Full<Bytes>> {
+        let remotes = self.remotes.load();
+        if let (Some(maintenance), &Method::POST, Some(upload_id)) = (
+            &self.maintenance,
+            req.method(),
+            upload_to_abort(req.uri().path()),
+        ) {
+            if !authorized(&req, &maintenance.token) {
+                warn!("rejected unauthorized maintenance request");
+                let mut response = empty(StatusCode::UNAUTHORIZED);
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Bearer"),
+                );
+                return response;
+            }
+            return abort_upload(&remotes, maintenance, upload_id).await;
+        }
+        match (req.method(), req.uri().path()) {
+            (&Method::GET, "/admin/stats") => json(&self.stats.get(&remotes).await),
+            (&Method::GET, "/readyz") => {
+                let report = health::report(&remotes);
+                let mut response = json(&report);
+                if !report.ready {
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                }
+                response
+            }
+            _ => empty(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`, compared in constant time.
+fn authorized<B>(req: &Request<B>, token: &str) -> bool {
+    let Some(given) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.as_bytes().strip_prefix(b"Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// The upload id in `/admin/uploads/{upload_id}/abort`.
+fn upload_to_abort(path: &str) -> Option<&str> {
+    path.strip_prefix("/admin/uploads/")?
+        .strip_suffix("/abort")
+        .filter(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// Aborts a multipart upload on every remote it is open on, reporting on each of them. Answers
+/// `502 Bad Gateway` if some remote could not be reached.
+#[instrument(skip(remotes, maintenance))]
+async fn abort_upload(
+    remotes: &[crate::server::remote::S3Remote],
+    maintenance: &Maintenance,
+    upload_id: &str,
+) -> Response<Full<Bytes>> {
+    let Ok(id) = ObjectId::parse_str(upload_id) else {
+        warn!("invalid upload_id");
+        return empty(StatusCode::BAD_REQUEST);
+    };
+    let forced =
+        abandoned::force_abort(remotes, &maintenance.db, id, maintenance.fanout_concurrency);
+    match forced.await {
+        Ok(Some(result)) => {
+            let mut response = json(&result);
+            if !result.aborted {
+                *response.status_mut() = StatusCode::BAD_GATEWAY;
+            }
+            response
+        }
+        Ok(None) => empty(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("mongodb error: {:?}", e);
+            empty(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Full<Bytes>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap(),
+        Err(e) => {
+            error!("failed to serialize response: {:?}", e);
+            empty(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn empty(status: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::default())
+        .unwrap()
+}
+
+#[instrument(name = "admin", skip_all)]
+pub async fn serve(listener: TcpListener, admin: Arc<Admin>) {
+    info!("Admin endpoints listening on {:?}", listener.local_addr());
+    let http_server = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let admin = Arc::clone(&admin);
+        let service = service_fn(move |req| {
+            let admin = Arc::clone(&admin);
+            async move { Ok::<_, Infallible>(admin.handle(req).await) }
+        });
+        let serve = http_server
+            .serve_connection(TokioIo::new(stream), service)
+            .into_owned();
+        tokio::spawn(
+            async move {
+                let _ = serve.await;
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn abort_route_takes_the_upload_id() {
+        assert_eq!(
+            upload_to_abort("/admin/uploads/65f0c0ffee0000000000abcd/abort"),
+            Some("65f0c0ffee0000000000abcd")
+        );
+        assert_eq!(upload_to_abort("/admin/uploads//abort"), None);
+        assert_eq!(upload_to_abort("/admin/uploads/a/b/abort"), None);
+        assert_eq!(upload_to_abort("/admin/stats"), None);
+    }
+
+    #[tokio::test]
+    async fn abort_route_is_off_without_maintenance() {
+        let admin = Admin {
+            remotes: Arc::new(RemoteSet::new(vec![])),
+            stats: StatsCache::new(Default::default()),
+            maintenance: None,
+        };
+
+        let response = admin
+            .handle(
+                Request::post("/admin/uploads/65f0c0ffee0000000000abcd/abort")
+                    .body(())
+                    .unwrap(),
+            )
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn maintenance_takes_the_bearer_token_alone() {
+        let request = |authorization: &str| {
+            Request::post("/admin/uploads/65f0c0ffee0000000000abcd/abort")
+                .header(header::AUTHORIZATION, authorization)
+                .body(())
+                .unwrap()
+        };
+
+        assert!(authorized(&request("Bearer s3cret"), "s3cret"));
+        assert!(!authorized(&request("Bearer s3cre"), "s3cret"));
+        assert!(!authorized(&request("Bearer s3cret2"), "s3cret"));
+        assert!(!authorized(&request("Basic s3cret"), "s3cret"));
+        assert!(!authorized(
+            &Request::post("/admin/uploads/65f0c0ffee0000000000abcd/abort")
+                .body(())
+                .unwrap(),
+            "s3cret"
+        ));
+    }
+}
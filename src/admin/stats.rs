@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::server::remote::{list_all_objects, S3Remote};
+
+/// Object count and total size of a remote. Both are `None` if the remote could not be listed.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RemoteStats {
+    pub name: String,
+    pub objects: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StorageStats {
+    pub remotes: Vec<RemoteStats>,
+    /// Whether the remotes that could be listed disagree on their totals.
+    pub divergent: bool,
+}
+
+/// Lists every remote in full and totals its objects.
+pub async fn collect(remotes: &[S3Remote]) -> StorageStats {
+    let remotes = futures::future::join_all(remotes.iter().map(|remote| async move {
+        let mut bytes = 0u64;
+        let objects = list_all_objects(&remote.tx, |object| {
+            bytes += object.size.unwrap_or_default().max(0) as u64;
+        })
+        .await;
+        if objects.is_none() {
+            warn!("remote({:?}) could not be listed. skipping", remote.name);
+        }
+        RemoteStats {
+            name: remote.name.clone(),
+            objects: objects.map(|o| o as u64),
+            bytes: objects.map(|_| bytes),
+        }
+    }))
+    .await;
+
+    let mut totals = remotes
+        .iter()
+        .filter_map(|r| r.objects.zip(r.bytes))
+        .collect::<Vec<_>>();
+    totals.dedup();
+    StorageStats {
+        divergent: totals.len() > 1,
+        remotes,
+    }
+}
+
+/// Keeps the last [`collect`] result for `ttl`, since a full listing of every remote is expensive.
+pub struct StatsCache {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, StorageStats)>>,
+}
+
+impl StatsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn get(&self, remotes: &[S3Remote]) -> StorageStats {
+        let mut cached = self.cached.lock().await;
+        if let Some((at, stats)) = cached.as_ref() {
+            if at.elapsed() < self.ttl {
+                return stats.clone();
+            }
+        }
+        info!("collecting storage stats...");
+        let stats = collect(remotes).await;
+        *cached = Some((Instant::now(), stats.clone()));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+    use aws_sdk_s3::types::Object;
+    use pretty_assertions::assert_eq;
+
+    use crate::server::remote::RemoteMessage;
+
+    /// A remote holding `count` objects of `size` bytes, listed in pages like S3 does.
+    fn seeded_remote(name: &str, count: usize, size: i64) -> S3Remote {
+        let keys = (0..count)
+            .map(|i| format!("key-{i:05}"))
+            .collect::<Vec<_>>();
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::ListObjects {
+                max_keys,
+                start_after,
+                reply,
+                ..
+            } = message
+            {
+                let rest = keys
+                    .iter()
+                    .filter(|k| start_after.as_ref().map_or(true, |s| *k > s))
+                    .collect::<Vec<_>>();
+                let page = rest.len().min(max_keys.unwrap_or(1000) as usize);
+                let output = ListObjectsV2Output::builder()
+                    .set_contents(Some(
+                        rest[..page]
+                            .iter()
+                            .map(|k| Object::builder().key(*k).size(size).build())
+                            .collect(),
+                    ))
+                    .is_truncated(page < rest.len())
+                    .build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn stats_reflect_seeded_objects() {
+        let remotes = [
+            seeded_remote("a", 2500, 10),
+            seeded_remote("b", 2498, 10),
+            S3Remote::stub("down"),
+        ];
+
+        let stats = collect(&remotes).await;
+
+        assert_eq!(
+            stats,
+            StorageStats {
+                remotes: vec![
+                    RemoteStats {
+                        name: "a".to_owned(),
+                        objects: Some(2500),
+                        bytes: Some(25000),
+                    },
+                    RemoteStats {
+                        name: "b".to_owned(),
+                        objects: Some(2498),
+                        bytes: Some(24980),
+                    },
+                    RemoteStats {
+                        name: "down".to_owned(),
+                        objects: None,
+                        bytes: None,
+                    },
+                ],
+                divergent: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn identical_remotes_do_not_diverge() {
+        let remotes = [seeded_remote("a", 10, 1), seeded_remote("b", 10, 1)];
+
+        assert!(!collect(&remotes).await.divergent);
+    }
+}
@@ -1,18 +1,20 @@
 use clap::Parser;
 use derivative::Derivative;
 use duration_string::DurationString;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use thiserror::Error;
 use tokio::fs;
 use tracing::instrument;
 
+use crate::access_log::AccessLogFormat;
 use crate::error::SpanErr;
 
-use self::s3_target::Config;
+use self::s3_target::{Config, WriteQuorum};
 
 pub mod s3_target;
 
-#[derive(Parser, Derivative)]
+#[derive(Parser, Derivative, Clone)]
 #[derivative(Debug)]
 #[clap(
     name = "s3-reproxy",
@@ -27,6 +29,20 @@ pub(crate) struct AppArgs {
     #[clap(long, default_value = "9000", env = "PORT")]
     pub port: u16,
 
+    /// Port for the admin endpoints (`/admin/...`). Disabled when unset.
+    #[clap(long, env = "ADMIN_PORT")]
+    pub admin_port: Option<u16>,
+
+    /// Bearer token the maintenance endpoints on the admin port, e.g.
+    /// `POST /admin/uploads/{upload_id}/abort`, require. They are not served when unset.
+    #[clap(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    #[derivative(Debug = "ignore")]
+    pub admin_token: Option<String>,
+
+    /// Port serving Prometheus metrics on `/metrics`. Disabled when unset.
+    #[clap(long, env = "METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
     #[clap(long, env = "MONGO_URI", hide_env_values = true)]
     pub mongo_uri: String,
 
@@ -35,9 +51,30 @@ pub(crate) struct AppArgs {
 
     #[clap(long, default_value = "5s")]
     pub stream_stall_grace_period: DurationString,
+
+    /// How long a shutdown waits for in-flight requests to finish before closing the remotes
+    /// under them.
+    #[clap(long, default_value = "30s")]
+    pub shutdown_timeout: DurationString,
+
+    /// Write one access log record per request to this file, or to stdout when `-`.
+    /// Disabled when unset.
+    #[clap(long, env = "ACCESS_LOG")]
+    pub access_log: Option<PathBuf>,
+
+    #[clap(long, value_enum, default_value = "json", env = "ACCESS_LOG_FORMAT")]
+    pub access_log_format: AccessLogFormat,
+
+    /// Dump the multipart upload and listing token state to this file and exit.
+    #[clap(long, conflicts_with = "import_state")]
+    pub export_state: Option<PathBuf>,
+
+    /// Load a state dump written by `--export-state` and exit. Safe to repeat.
+    #[clap(long)]
+    pub import_state: Option<PathBuf>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct S3ReproxySetup {
     pub config: Config,
     pub args: AppArgs,
@@ -51,8 +88,55 @@ pub(crate) enum Error {
     #[error("Failed to parse config file {0}:\n {1}")]
     Serde(PathBuf, #[source] serde_yaml::Error),
 
-    #[error("At least one readable target must be specified")]
+    #[error("At least one remote must be specified under `remotes`")]
+    NoRemotes,
+
+    #[error("Remote name {0:?} is used by more than one remote")]
+    DuplicateRemote(String),
+
+    #[error("At least one remote must have `read_request: true`, or no read can be served")]
     MissingReadableTarget,
+
+    #[error("Remote {0:?} referenced by {1} is not defined")]
+    UnknownRemote(String, &'static str),
+
+    #[error("Secret key of remote {0:?} refers to environment variable {1}, which is not set")]
+    MissingSecretEnv(String, String),
+
+    #[error("Failed to read secret key of remote {0:?} from {1}: {2}")]
+    SecretFile(String, PathBuf, #[source] std::io::Error),
+
+    #[error("write_quorum of {0} cannot be met by {1} remotes")]
+    UnreachableQuorum(usize, usize),
+
+    #[error("fanout_concurrency must be at least 1, or no write is ever sent")]
+    NoFanout,
+
+    #[error("{0} needs multipart uploads kept in MongoDB, which signed_upload_ids are not")]
+    UntrackedUploads(&'static str),
+
+    #[error("The virtual bucket cannot change from {0:?} to {1:?} without a restart")]
+    BucketChanged(String, String),
+}
+
+/// Resolves a secret given as `${ENV_VAR}` or `file:/path/to/secret` to the value it refers to.
+/// Any other value is the secret itself. Trailing newlines of secret files are dropped.
+async fn resolve_secret(remote: &str, secret: &str) -> Result<String, Error> {
+    if let Some(var) = secret
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        return std::env::var(var)
+            .map_err(|_| Error::MissingSecretEnv(remote.to_owned(), var.to_owned()));
+    }
+    if let Some(path) = secret.strip_prefix("file:") {
+        let path = PathBuf::from(path);
+        let contents = fs::read_to_string(&path)
+            .await
+            .map_err(|e| Error::SecretFile(remote.to_owned(), path, e))?;
+        return Ok(contents.trim_end_matches(['\n', '\r']).to_owned());
+    }
+    Ok(secret.to_owned())
 }
 
 impl S3ReproxySetup {
@@ -62,30 +146,183 @@ impl S3ReproxySetup {
             .await
             .map_err(|e| Error::Io(args.config_file.clone(), e))?;
 
-        let config: Config = serde_yaml::from_slice(&config_slice)
+        let mut config: Config = serde_yaml::from_slice(&config_slice)
             .map_err(|e| Error::Serde(args.config_file.clone(), e))?;
 
-        let setup = Self { config, args };
+        for target in &mut config.remotes {
+            target.s3.secret_key = resolve_secret(&target.name, &target.s3.secret_key).await?;
+        }
+
+        config.validate()?;
 
-        Self::validate_config(&setup)?;
+        Ok(Self { config, args })
+    }
 
+    /// Reads the config file again. Fails when the virtual bucket changed, since the continuation
+    /// tokens and upload ids handed to clients belong to it.
+    #[instrument(name = "setup/reload", skip_all)]
+    pub async fn reload(&self) -> Result<Self, SpanErr<Error>> {
+        let setup = Self::new(self.args.clone()).await?;
+        if setup.config.bucket != self.config.bucket {
+            Err(Error::BucketChanged(
+                self.config.bucket.clone(),
+                setup.config.bucket.clone(),
+            ))?;
+        }
         Ok(setup)
     }
+}
+
+impl Config {
+    /// Rejects configs the proxy would start with but could not serve requests from.
+    #[instrument(name = "setup/validation", skip_all)]
+    pub(crate) fn validate(&self) -> Result<(), SpanErr<Error>> {
+        if self.remotes.is_empty() {
+            Err(Error::NoRemotes)?;
+        }
 
-    /// TODO: 名前の重複に対してエラーを出
-    #[instrument(name = "setup/validation")]
-    fn validate_config(setup: &Self) -> Result<(), SpanErr<Error>> {
-        if setup
-            .config
-            .remotes
-            .iter()
-            .filter(|t| t.read_request)
-            .count()
-            < 1
-        {
+        let mut names = HashSet::new();
+        if let Some(target) = self.remotes.iter().find(|t| !names.insert(&t.name)) {
+            Err(Error::DuplicateRemote(target.name.clone()))?;
+        }
+
+        if !self.remotes.iter().any(|t| t.read_request) {
             Err(Error::MissingReadableTarget)?;
         }
 
+        let remotes = self.remotes.len();
+        if let Some(WriteQuorum::Count(count)) = self.write_quorum {
+            if count == 0 || count > remotes {
+                Err(Error::UnreachableQuorum(count, remotes))?;
+            }
+        }
+
+        if self.fanout_concurrency == 0 {
+            Err(Error::NoFanout)?;
+        }
+
+        if let Some(name) = &self.list_buckets_from {
+            if !self.remotes.iter().any(|t| &t.name == name) {
+                Err(Error::UnknownRemote(name.clone(), "list_buckets_from"))?;
+            }
+        }
+
+        if self.signed_upload_ids.is_some() {
+            if self.max_active_multipart_uploads.is_some() {
+                Err(Error::UntrackedUploads("max_active_multipart_uploads"))?;
+            }
+            if self.abandoned_uploads.is_some() {
+                Err(Error::UntrackedUploads("abandoned_uploads"))?;
+            }
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn secrets_are_resolved_from_env_and_files() {
+        std::env::set_var("S3_REPROXY_TEST_SECRET", "from-env");
+        let path = std::env::temp_dir().join(format!("s3-reproxy-secret-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        assert_eq!(
+            resolve_secret("a", "${S3_REPROXY_TEST_SECRET}")
+                .await
+                .unwrap(),
+            "from-env"
+        );
+        assert_eq!(
+            resolve_secret("a", &format!("file:{}", path.display()))
+                .await
+                .unwrap(),
+            "from-file"
+        );
+        assert_eq!(resolve_secret("a", "plain").await.unwrap(), "plain");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_secrets_fail() {
+        assert!(matches!(
+            resolve_secret("a", "${S3_REPROXY_TEST_UNSET}").await,
+            Err(Error::MissingSecretEnv(remote, var))
+                if remote == "a" && var == "S3_REPROXY_TEST_UNSET"
+        ));
+        assert!(matches!(
+            resolve_secret("a", "file:/nonexistent/s3-reproxy-secret").await,
+            Err(Error::SecretFile(..))
+        ));
+    }
+
+    #[test]
+    fn unusable_configs_are_rejected() {
+        let validate = |remotes: &[(&str, bool)]| {
+            let remotes = remotes
+                .iter()
+                .map(|(name, read_request)| {
+                    format!(
+                        "{{ name: {name}, read_request: {read_request}, s3: {{ endpoint: \
+                         http://localhost:9000, access_key: a, secret_key: b, bucket: test }} }}"
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let yaml =
+                format!("{{ access_key: a, secret_key: b, bucket: test, remotes: [{remotes}] }}");
+            serde_yaml::from_str::<Config>(&yaml)
+                .unwrap()
+                .validate()
+                .map_err(|e| e.error)
+        };
+
+        assert!(matches!(validate(&[]), Err(Error::NoRemotes)));
+        assert!(matches!(
+            validate(&[("a", true), ("a", false)]),
+            Err(Error::DuplicateRemote(name)) if name == "a"
+        ));
+        assert!(matches!(
+            validate(&[("a", false), ("b", false)]),
+            Err(Error::MissingReadableTarget)
+        ));
+        assert!(validate(&[("a", true), ("b", false)]).is_ok());
+    }
+
+    #[test]
+    fn conflicting_settings_are_rejected() {
+        let validate = |settings: &str| {
+            let yaml = format!(
+                "{{ access_key: a, secret_key: b, bucket: test, {settings}, remotes: [{{ name: a, \
+                 s3: {{ endpoint: http://localhost:9000, access_key: a, secret_key: b, \
+                 bucket: test }} }}] }}"
+            );
+            serde_yaml::from_str::<Config>(&yaml)
+                .unwrap()
+                .validate()
+                .map_err(|e| e.error)
+        };
+        let signed = "signed_upload_ids: { secret: s }";
+
+        assert!(matches!(
+            validate("fanout_concurrency: 0"),
+            Err(Error::NoFanout)
+        ));
+        assert!(validate(signed).is_ok());
+        assert!(matches!(
+            validate(&format!("{signed}, max_active_multipart_uploads: 10")),
+            Err(Error::UntrackedUploads("max_active_multipart_uploads"))
+        ));
+        assert!(matches!(
+            validate(&format!(
+                "{signed}, abandoned_uploads: {{ idle_timeout: 1h, sweep_interval: 1m }}"
+            )),
+            Err(Error::UntrackedUploads("abandoned_uploads"))
+        ));
+    }
+}
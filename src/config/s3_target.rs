@@ -1,4 +1,10 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use derivative::Derivative;
+use duration_string::DurationString;
+use mongodb::options::{Acknowledgment, WriteConcern};
 use serde::{Deserialize, Serialize};
 
 #[derive(Derivative, Clone, Serialize, Deserialize, PartialEq)]
@@ -9,6 +15,466 @@ pub struct Config {
     #[derivative(Debug = "ignore")]
     pub secret_key: String,
     pub bucket: String,
+
+    /// Name of the remote whose `ListBuckets` response backs the proxy's bucket listing.
+    /// When unset, `ListBuckets` is intercepted and answers with the virtual bucket only.
+    #[serde(default)]
+    pub list_buckets_from: Option<String>,
+
+    /// Number of read remotes consulted by `HeadObject`, including the one that serves the response.
+    /// Remotes after the first are only used to verify `content_length` and `content_type`.
+    #[serde(default = "default_head_verify_count")]
+    pub head_verify_count: usize,
+
+    /// HEAD the remotes verifying a `HeadObject` response all at once instead of one after
+    /// another. Costs the same requests but only the latency of the slowest one.
+    #[serde(default)]
+    pub head_verify_parallel: bool,
+
+    /// What to do when the verified remotes disagree on the object's metadata.
+    #[serde(default)]
+    pub head_divergence: DivergencePolicy,
+
+    /// HEAD at least one more remote on every `HeadObject`, and log and count in
+    /// `reproxy_head_metadata_divergence_total` every difference in ETag, size, content type or
+    /// user metadata from the remote answering. A diagnostic for checking that replication keeps
+    /// metadata in sync and not just bytes; responses are left as they are, and the extra HEAD is
+    /// not subject to `head_divergence`. Also applies to fresh reads.
+    #[serde(default)]
+    pub strict_reads: bool,
+
+    /// What to do when a `CopyObject` with the `COPY` metadata directive leaves the remotes with
+    /// different metadata, e.g. because their source objects already disagreed.
+    #[serde(default)]
+    pub copy_divergence: DivergencePolicy,
+
+    /// What to do when the remotes return different ETags for the same `UploadPart`, which
+    /// `CompleteMultipartUpload` would later trip over. Under `strict` the part is rejected
+    /// without being recorded, for the client to send again. Not compared when unset.
+    #[serde(default)]
+    pub part_etag_divergence: Option<DivergencePolicy>,
+
+    /// Read-ahead for clients that fetch an object through sequential ranged GETs.
+    /// Disabled when unset.
+    #[serde(default)]
+    pub range_prefetch: Option<RangePrefetchConfig>,
+
+    /// When minting a `ListObjectsV2` continuation token fails, return the page that was
+    /// already listed without a `next_continuation_token` instead of failing the request.
+    #[serde(default)]
+    pub list_token_fallback: bool,
+
+    /// How long an unconsumed `ListObjectsV2` continuation token is kept. MongoDB removes older
+    /// ones, and clients presenting them get `InvalidToken`.
+    #[serde(default = "default_list_token_ttl")]
+    pub list_token_ttl: DurationString,
+
+    /// Name of the remote that serves every `ListObjectsV2`, handing its own continuation tokens
+    /// to clients instead of tokens kept in MongoDB. No other remote understands those tokens, so
+    /// listings do not fail over; only use it when that remote is stable.
+    #[serde(default)]
+    pub native_list_tokens_from: Option<String>,
+
+    /// Per-remote approximate key membership used to try remotes that cannot hold the requested
+    /// key last on `GetObject`. Advisory only: keys written other than through this replica are
+    /// missed, so no remote is left out of a read. Disabled when unset.
+    #[serde(default)]
+    pub key_filter: Option<KeyFilterConfig>,
+
+    /// Region reported in the `x-amz-bucket-region` header of `HeadBucket` on the managed bucket,
+    /// which SDKs use to resolve the signing region. Omitted when unset.
+    #[serde(default)]
+    pub reported_region: Option<String>,
+
+    /// Bytes of a `GetObject` body read ahead before the response is committed to a remote.
+    /// If the body fails within them, the next remote is tried instead of sending a truncated
+    /// object. Failures past this point still abort the transfer. Disabled when unset.
+    #[serde(default)]
+    pub get_retry_buffer_bytes: Option<usize>,
+
+    /// Ask remotes for their stored checksum on `GetObject` and check the body against it while
+    /// streaming. A mismatch noticed within `get_retry_buffer_bytes` fails over to the next remote;
+    /// later ones abort the transfer.
+    #[serde(default)]
+    pub verify_get_checksums: bool,
+
+    /// Remember the part count of objects written by multipart uploads, and report it on
+    /// `HeadObject`/`GetObject` with `partNumber` when the remote does not. Costs a MongoDB write
+    /// on every write and delete.
+    #[serde(default)]
+    pub report_parts_count: bool,
+
+    /// Write `PutObject` bodies of at least `spool_threshold_bytes` to a temporary file that each
+    /// remote reads at its own pace, instead of holding them in memory until the slowest remote
+    /// has read them. Bodies of unknown length are always spooled.
+    #[serde(default)]
+    pub spool_to_disk: bool,
+
+    /// Directory of spooled bodies. The system's temporary directory when unset.
+    #[serde(default)]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Smallest body spooled to disk when `spool_to_disk` is enabled.
+    #[serde(default = "default_spool_threshold_bytes")]
+    pub spool_threshold_bytes: u64,
+
+    /// How many times a read asks the same remote again, after a short backoff, when it fails
+    /// to respond before moving on to the next remote.
+    #[serde(default)]
+    pub read_quick_retries: usize,
+
+    /// How `GetObject` picks the remote it reads from.
+    #[serde(default)]
+    pub read_strategy: ReadStrategy,
+
+    /// Time in milliseconds a `GetObject` waits on a remote before also asking the next one, and
+    /// answering with whichever succeeds first, instead of waiting for it to fail. Reads fall back
+    /// strictly one remote after another when unset. Does not apply to the `hedged` strategy.
+    #[serde(default)]
+    pub read_hedge_delay_ms: Option<u64>,
+
+    /// Honor the `x-reproxy-remote` request header on `GetObject`, `HeadObject` and
+    /// `ListObjectsV2`, which sends the read to the named remote alone, e.g. to find the remote
+    /// holding stale data. Any client can pick the remote its reads go to, so leave it off in
+    /// production.
+    #[serde(default)]
+    pub debug_headers: bool,
+
+    /// How many times a part is resent to a remote that failed to respond before the remote is
+    /// dropped from the rest of the multipart upload. Parts are buffered in memory when enabled.
+    #[serde(default)]
+    pub upload_part_retries: usize,
+
+    /// Largest part, by its `Content-Length`, buffered to be resent under `upload_part_retries`.
+    /// Larger parts, and parts of unknown size, are streamed and never resent. 64 MiB by default.
+    #[serde(default = "default_upload_part_retry_max_bytes")]
+    pub upload_part_retry_max_bytes: u64,
+
+    /// How many remotes a write, or a background delete or abort, is sent to at once. Raise it for
+    /// deployments with more remotes than that, so the last ones are not held back until the first
+    /// ones answer. At least 1.
+    #[serde(default = "default_fanout_concurrency")]
+    pub fanout_concurrency: usize,
+
+    /// How many remotes must store a `PutObject`, `UploadPart` or `CompleteMultipartUpload` for
+    /// it to succeed: `one`, `majority`, `all` or a number. Below it the client gets an error to
+    /// retry on instead of taking the write as durable. When unset, `PutObject` and `UploadPart`
+    /// need one remote and `CompleteMultipartUpload` needs every remote of the upload.
+    #[serde(default)]
+    pub write_quorum: Option<WriteQuorum>,
+
+    /// Error answering a request that no remote could serve, e.g. because all of them are down.
+    /// `InternalError` when unset.
+    #[serde(default)]
+    pub no_remote_error: FailureResponse,
+
+    /// Error answering a write that some remotes stored but fewer than `write_quorum` did.
+    /// `ServiceUnavailable` when unset.
+    #[serde(default = "default_below_quorum_error")]
+    pub below_quorum_error: FailureResponse,
+
+    /// Time in milliseconds a target has to answer a request before it is treated as down for
+    /// that request, unless the target sets its own `timeout_ms`. The time covers sending the
+    /// body of a write, so leave room for the largest objects. No timeout when unset.
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Connection settings of the HTTP client of targets that do not set their own `http`.
+    /// The SDK's defaults apply when unset.
+    #[serde(default)]
+    pub http: Option<HttpClientConfig>,
+
+    /// Encode the per-remote upload ids of a multipart upload into a signed `upload_id` instead
+    /// of storing them in MongoDB. Such uploads leave no trace in MongoDB: a remote cancelled
+    /// mid-upload is still sent the later parts and fails `CompleteMultipartUpload` on its own,
+    /// `ListMultipartUploads` and the admin abort endpoint do not know them, and
+    /// `x-amz-object-size` is not checked on their completion. Cannot be combined with
+    /// `max_active_multipart_uploads` or `abandoned_uploads`, which only see uploads kept in
+    /// MongoDB. Disabled when unset.
+    #[serde(default)]
+    pub signed_upload_ids: Option<SignedUploadIdConfig>,
+
+    /// Number of multipart uploads that may be open at once. `CreateMultipartUpload` answers
+    /// `SlowDown` beyond it. Unlimited when unset.
+    #[serde(default)]
+    pub max_active_multipart_uploads: Option<u64>,
+
+    /// Write concern of the collections holding multipart upload ids and list continuation
+    /// tokens. `w: majority` with `journal: true` keeps them across a primary failover at the cost
+    /// of write latency. A weaker concern can lose uploads or tokens that were just handed out, which
+    /// clients then see as `NoSuchUpload` or `InvalidToken`. The server default applies when unset.
+    #[serde(default)]
+    pub mongo_write_concern: Option<MongoWriteConcern>,
+
+    /// Expire objects written through the proxy after a TTL, given per write with the
+    /// `x-reproxy-ttl` header or by `default_ttl`. Disabled when unset.
+    #[serde(default)]
+    pub object_ttl: Option<ObjectTtlConfig>,
+
+    /// Abort multipart uploads that received no part for a while, on the assumption that the
+    /// client went away. Disabled when unset.
+    #[serde(default)]
+    pub abandoned_uploads: Option<AbandonedUploadConfig>,
+
+    /// Probe every remote in the background, and keep reads away from remotes found down until
+    /// they pass a probe again. Reads only learn of a remote being down by trying it when unset.
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+
+    /// Copy objects to the remotes a `PutObject` failed on from a remote it succeeded on, in the
+    /// background. Inconsistent writes stay inconsistent when unset. A copy is skipped once the
+    /// source holds another version of the object or the target a newer one, and objects under a
+    /// customer encryption key are never copied.
+    #[serde(default)]
+    pub write_repair: Option<WriteRepairConfig>,
+
+    /// S3 operations served by this deployment, e.g. `GetObject` or `DeleteObjects`. Any other
+    /// is answered `MethodNotAllowed` before it reaches a remote. All are served when unset.
+    #[serde(default)]
+    pub enabled_operations: Option<HashSet<String>>,
+
+    /// Answer every operation that would write to the remotes with `AccessDenied` before it
+    /// reaches any of them, e.g. to verify a new set of remotes with a read-only workload. The
+    /// expiry and abandoned upload sweeps, write repair and the admin abort are off as well.
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// POST an S3 event notification to a webhook after each successful `PutObject`,
+    /// `CompleteMultipartUpload` and `DeleteObject`. Disabled when unset.
+    #[serde(default)]
+    pub event_webhook: Option<WebhookConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HealthCheckConfig {
+    /// How often every remote is probed.
+    pub interval: DurationString,
+
+    /// How long a probe waits on a remote before counting it as failed. `interval` when unset.
+    #[serde(default)]
+    pub timeout: Option<DurationString>,
+
+    /// Failed requests in a row, probes included, that open a remote's circuit.
+    #[serde(default = "default_failures_to_open")]
+    pub failures_to_open: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HttpClientConfig {
+    /// Most idle connections kept open to the target for reuse. Unlimited when unset.
+    #[serde(default)]
+    pub max_idle_connections: Option<usize>,
+
+    /// How long an idle connection is kept open for reuse. 90 seconds when unset.
+    #[serde(default)]
+    pub idle_timeout: Option<DurationString>,
+
+    /// Time to establish a connection, including the TLS handshake. 3.1 seconds when unset.
+    #[serde(default)]
+    pub connect_timeout: Option<DurationString>,
+
+    /// Time to wait for the first byte of a response. No timeout when unset.
+    #[serde(default)]
+    pub read_timeout: Option<DurationString>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WriteRepairConfig {
+    /// How often pending repairs are attempted. A repair that keeps failing waits twice as long
+    /// after each attempt, up to an hour.
+    pub interval: DurationString,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AbandonedUploadConfig {
+    /// How long an upload may go without a new part before it is aborted. Keep it above the
+    /// longest pause a client may take between parts.
+    pub idle_timeout: DurationString,
+
+    /// How often idle uploads are looked for.
+    pub sweep_interval: DurationString,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    /// Endpoint the events are POSTed to. Only `http://` is supported.
+    pub url: String,
+
+    /// Only notify of keys starting with this.
+    #[serde(default)]
+    pub prefix: Option<String>,
+
+    /// Only notify of keys ending with this, e.g. `.jpg`.
+    #[serde(default)]
+    pub suffix: Option<String>,
+
+    /// How many times an event the endpoint did not accept is sent again, with a growing backoff,
+    /// before it is dropped.
+    #[serde(default = "default_webhook_retries")]
+    pub retries: usize,
+
+    /// How long a delivery waits for the endpoint to connect and answer before it counts as
+    /// failed. 10 seconds by default.
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: DurationString,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MongoWriteConcern {
+    /// Number of nodes, `majority`, or a custom tag set that must acknowledge a write.
+    #[serde(default)]
+    pub w: Option<Acknowledgment>,
+
+    /// Whether a write must reach the on-disk journal before it is acknowledged.
+    #[serde(default)]
+    pub journal: Option<bool>,
+}
+
+impl MongoWriteConcern {
+    pub fn write_concern(&self) -> WriteConcern {
+        WriteConcern::builder()
+            .w(self.w.clone())
+            .journal(self.journal)
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ObjectTtlConfig {
+    /// TTL of objects written without the header. Such objects never expire when unset.
+    #[serde(default)]
+    pub default_ttl: Option<DurationString>,
+
+    /// How often expired objects are looked for and deleted from every remote.
+    pub sweep_interval: DurationString,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Derivative)]
+#[derivative(Debug)]
+pub struct SignedUploadIdConfig {
+    /// Key used to sign upload ids. Every replica must share it.
+    #[derivative(Debug = "ignore")]
+    pub secret: String,
+
+    /// Longest `upload_id` handed out; uploads whose mapping does not fit are stored in MongoDB.
+    #[serde(default = "default_max_token_bytes")]
+    pub max_token_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyFilterConfig {
+    /// Number of keys each remote is expected to hold; the filter is sized for it.
+    pub expected_keys: usize,
+
+    /// Acceptable probability of a remote being consulted for a key it does not hold.
+    pub false_positive_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RangePrefetchConfig {
+    /// How many bytes past the requested range are fetched when a sequential read is detected.
+    pub window_bytes: u64,
+
+    /// Upper bound on the bytes buffered across all keys. A single request is never prefetched
+    /// if its range plus the window would exceed it.
+    pub max_cached_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergencePolicy {
+    /// Log the divergence and respond with the first remote's metadata.
+    #[default]
+    Warn,
+    /// Reject the request so the client never sees metadata that other remotes disagree with.
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteQuorum {
+    /// Any single remote.
+    #[default]
+    One,
+    /// More than half of the remotes.
+    Majority,
+    /// Every remote.
+    All,
+    #[serde(untagged)]
+    Count(usize),
+}
+
+impl WriteQuorum {
+    /// How many of `total` remotes have to store a write.
+    pub fn required(self, total: usize) -> usize {
+        match self {
+            WriteQuorum::One => 1,
+            WriteQuorum::Majority => total / 2 + 1,
+            WriteQuorum::All => total,
+            WriteQuorum::Count(count) => count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct FailureResponse {
+    /// S3 error code sent to the client.
+    #[serde(default)]
+    pub code: FailureCode,
+
+    /// Seconds sent in a `Retry-After` header, telling the client when to try again. Not sent
+    /// with `internal_error`, nor when unset.
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCode {
+    /// `InternalError` (500), which most clients retry a few times before giving up.
+    #[default]
+    InternalError,
+    /// `ServiceUnavailable` (503).
+    ServiceUnavailable,
+    /// `SlowDown` (503), which SDKs retry with a longer backoff.
+    SlowDown,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadStrategy {
+    /// Try the remotes one after another, in priority order.
+    #[default]
+    Sequential,
+    /// Send the read to the two highest-priority remotes at once and answer with whichever
+    /// succeeds first, then fall back to the others one after another. Costs an extra request
+    /// per read to cut the latency of a slow remote.
+    Hedged,
+    /// Pick the first remote at random among the readable remotes sharing the highest priority,
+    /// in proportion to their `weight`, then fall back to the others in priority order.
+    WeightedRandom,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizeOwnership {
+    /// A canned ACL such as `bucket-owner-full-control`.
+    CannedAcl(String),
+    /// A grantee given full control, e.g. `id=<canonical user id>`.
+    GrantFullControl(String),
+}
+
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FaultInjection {
+    /// Delay, in milliseconds, added to every request sent to the target.
+    #[serde(default)]
+    pub inject_latency_ms: Option<u64>,
+
+    /// Fraction of requests, from 0 to 1, answered as if the target were down. Failures are
+    /// spread evenly rather than drawn at random, so a run can be repeated exactly.
+    #[serde(default)]
+    pub inject_error_rate: Option<f64>,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Derivative)]
@@ -16,15 +482,90 @@ pub struct Config {
 pub struct S3Credential {
     pub endpoint: String,
     pub access_key: String,
+    /// The secret itself, or `${ENV_VAR}` or `file:/path/to/secret` to read it from there at
+    /// startup.
     #[derivative(Debug = "ignore")]
     pub secret_key: String,
     pub bucket: String,
+
+    /// Region requests to this target are signed for, which AWS requires to match the bucket's.
+    /// MinIO and R2 endpoints can leave it unset. `us-east-1` when unset.
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// Address the bucket in the path (`endpoint/bucket/key`) rather than in the host name
+    /// (`bucket.endpoint/key`). MinIO needs path-style; AWS prefers virtual-hosted-style.
+    #[serde(default = "default_force_path_style")]
+    pub force_path_style: bool,
+}
+
+fn default_list_token_ttl() -> DurationString {
+    Duration::from_secs(60 * 60).into()
+}
+
+const fn default_force_path_style() -> bool {
+    true
+}
+
+const fn default_max_token_bytes() -> usize {
+    1024
+}
+
+const fn default_fanout_concurrency() -> usize {
+    8
+}
+
+const fn default_retries() -> usize {
+    2
+}
+
+const fn default_retry_base_delay_ms() -> u64 {
+    100
+}
+
+const fn default_webhook_retries() -> usize {
+    3
+}
+
+fn default_webhook_timeout() -> DurationString {
+    Duration::from_secs(10).into()
+}
+
+const fn default_upload_part_retry_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+const fn default_spool_threshold_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+const fn default_below_quorum_error() -> FailureResponse {
+    FailureResponse {
+        code: FailureCode::ServiceUnavailable,
+        retry_after_secs: None,
+    }
+}
+
+const fn default_failures_to_open() -> usize {
+    3
+}
+
+const fn default_head_verify_count() -> usize {
+    1
 }
 
 const fn default_priority() -> u32 {
     1
 }
 
+const fn default_weight() -> u32 {
+    1
+}
+
+const fn default_supports_ranges() -> bool {
+    true
+}
+
 const fn default_read_request() -> bool {
     true
 }
@@ -35,19 +576,97 @@ pub struct S3Target {
     pub name: String,
 
     /// Read priority of this target.
-    /// Read Requests to s3-reproxy are issued in order of priority.
+    /// Read Requests to s3-reproxy are issued in order of priority. Remotes are ordered by
+    /// `read_request` (readable ones first), then by priority (highest first), then by name, and
+    /// writes are sent out in the same order.
     #[serde(default = "default_priority")]
     pub priority: u32,
 
+    /// Priority used once the first choice for a read has failed.
+    /// Falls back to `priority` when unset.
+    #[serde(default)]
+    pub failover_priority: Option<u32>,
+
+    /// Share of the reads this target takes among the targets of the same priority, under the
+    /// `weighted_random` read strategy. A target of weight 0 is only read from once those fail.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+
     /// Whether this target is allowed to read?
     /// For requests to search for or retrieve a file, if all targets with read_request true respond "does not exist", s3-reproxy will not search for the file any further and will respond "does not exist".
     /// However, if all targets with read_request true are down, the one with read_request false and highest priority will be used for reading.
     #[serde(default = "default_read_request")]
     pub read_request: bool,
 
+    /// Checksum algorithms this target accepts, e.g. `CRC32` or `SHA256`.
+    /// Writes requesting any other algorithm are rejected before reaching any target.
+    /// All algorithms are assumed to be supported when unset.
+    #[serde(default)]
+    pub supported_checksums: Option<Vec<String>>,
+
+    /// Largest total size, in bytes, of user metadata keys and values this target accepts.
+    /// Writes exceeding the smallest limit among all targets are rejected before reaching any.
+    #[serde(default)]
+    pub max_metadata_bytes: Option<usize>,
+
+    /// Ownership applied to every object written to this target, replacing any ACL the client
+    /// requested. Leave unset for backends that do not support ACLs.
+    #[serde(default)]
+    pub normalize_ownership: Option<NormalizeOwnership>,
+
+    /// Prefix under which this target stores the objects, e.g. `v1/` for a legacy bucket. It is
+    /// added to the keys of every request to the target and removed from the keys it returns.
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+
+    /// Whether this target rejects uploads without a `Content-Length`.
+    /// Uploads of unknown length are spooled to a temporary file before being sent to it.
+    #[serde(default)]
+    pub requires_content_length: bool,
+
+    /// Whether this target honors `Range` requests. `Accept-Ranges` is stripped from responses
+    /// unless every target does.
+    #[serde(default = "default_supports_ranges")]
+    pub supports_ranges: bool,
+
+    /// Time in milliseconds this target has to answer a request before it is treated as down
+    /// for that request. Overrides `request_timeout_ms`.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+
+    /// Connection settings of this target's HTTP client, e.g. a larger pool for a nearby MinIO
+    /// than for a distant AWS region. Replaces the top-level `http` as a whole.
+    #[serde(default)]
+    pub http: Option<HttpClientConfig>,
+
+    /// How many times a request this target answered with a throttling or server error, or that
+    /// failed to reach it or timed out, is sent again. Writes with a streamed body are sent only
+    /// once.
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+
+    /// Delay in milliseconds before the first retry, doubled for each retry after it.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+
+    /// Faults injected into requests to this target, for testing failover.
+    /// Only builds with the `chaos` feature read these settings.
+    #[cfg(feature = "chaos")]
+    #[serde(flatten)]
+    pub faults: FaultInjection,
+
     pub s3: S3Credential,
 }
 
+impl S3Target {
+    /// Time this target has to answer a request: its own `timeout_ms`, or `request_timeout_ms`.
+    pub fn request_timeout(&self, config: &Config) -> Option<Duration> {
+        self.timeout_ms
+            .or(config.request_timeout_ms)
+            .map(Duration::from_millis)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,11 +690,27 @@ mod tests {
                 name: "cloudflare-r2".to_string(),
                 priority: 1,
                 read_request: true,
+                supported_checksums: None,
+                max_metadata_bytes: None,
+                normalize_ownership: None,
+                failover_priority: None,
+                weight: 1,
+                key_prefix: None,
+                requires_content_length: false,
+                supports_ranges: true,
+                timeout_ms: None,
+                http: None,
+                retries: 2,
+                retry_base_delay_ms: 100,
+                #[cfg(feature = "chaos")]
+                faults: FaultInjection::default(),
                 s3: S3Credential {
                     endpoint: "http://localhost:8080".to_string(),
                     access_key: "abcabc".to_string(),
                     secret_key: "defdef".to_string(),
                     bucket: "test".to_string(),
+                    region: None,
+                    force_path_style: true,
                 },
             }
         );
@@ -112,25 +747,117 @@ mod tests {
                     name: "cloudflare-r2".to_string(),
                     priority: 3,
                     read_request: false,
+                    supported_checksums: None,
+                    max_metadata_bytes: None,
+                    normalize_ownership: None,
+                    failover_priority: None,
+                    weight: 1,
+                    key_prefix: None,
+                    requires_content_length: false,
+                    supports_ranges: true,
+                    timeout_ms: None,
+                    http: None,
+                    retries: 2,
+                    retry_base_delay_ms: 100,
+                    #[cfg(feature = "chaos")]
+                    faults: FaultInjection::default(),
                     s3: S3Credential {
                         endpoint: "http://localhost:8080".to_string(),
                         access_key: "abcabc".to_string(),
                         secret_key: "defdef".to_string(),
                         bucket: "test1".to_string(),
+                        region: None,
+                        force_path_style: true,
                     },
                 },
                 S3Target {
                     name: "local-minio".to_string(),
                     priority: 5,
                     read_request: true,
+                    supported_checksums: None,
+                    max_metadata_bytes: None,
+                    normalize_ownership: None,
+                    failover_priority: None,
+                    weight: 1,
+                    key_prefix: None,
+                    requires_content_length: false,
+                    supports_ranges: true,
+                    timeout_ms: None,
+                    http: None,
+                    retries: 2,
+                    retry_base_delay_ms: 100,
+                    #[cfg(feature = "chaos")]
+                    faults: FaultInjection::default(),
                     s3: S3Credential {
                         endpoint: "http://localhost:8080".to_string(),
                         access_key: "abcabc".to_string(),
                         secret_key: "defdef".to_string(),
                         bucket: "test2".to_string(),
+                        region: None,
+                        force_path_style: true,
                     },
                 },
             ]
         );
     }
+
+    #[test]
+    fn parse_target_http_settings() {
+        let yaml = r#"
+            name: local-minio
+            http:
+              max_idle_connections: 256
+              connect_timeout: 500ms
+            s3:
+              endpoint: http://localhost:9000
+              access_key: abcabc
+              secret_key: defdef
+              bucket: test
+        "#;
+
+        let target: S3Target = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            target.http,
+            Some(HttpClientConfig {
+                max_idle_connections: Some(256),
+                connect_timeout: Some(Duration::from_millis(500).into()),
+                ..HttpClientConfig::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_write_quorum() {
+        let quorum = |yaml: &str| serde_yaml::from_str::<WriteQuorum>(yaml).unwrap();
+
+        assert_eq!(quorum("majority"), WriteQuorum::Majority);
+        assert_eq!(quorum("2"), WriteQuorum::Count(2));
+        assert_eq!(WriteQuorum::default().required(3), 1);
+        assert_eq!(WriteQuorum::Majority.required(3), 2);
+        assert_eq!(WriteQuorum::Majority.required(4), 3);
+        assert_eq!(WriteQuorum::All.required(3), 3);
+    }
+
+    #[test]
+    fn parse_failure_responses() {
+        let yaml = r#"
+            access_key: abcabc
+            secret_key: defdef
+            bucket: test
+            remotes: []
+            no_remote_error:
+              code: service_unavailable
+              retry_after_secs: 30
+        "#;
+
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.no_remote_error,
+            FailureResponse {
+                code: FailureCode::ServiceUnavailable,
+                retry_after_secs: Some(30),
+            }
+        );
+        assert_eq!(config.below_quorum_error, default_below_quorum_error());
+    }
 }
@@ -1,16 +1,35 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use mongodb::bson::doc;
-use mongodb::options::{ClientOptions, IndexOptions};
+use mongodb::error::ErrorKind;
+use mongodb::options::{ClientOptions, CollectionOptions, IndexOptions, WriteConcern};
 use mongodb::IndexModel;
 use serde::{Deserialize, Serialize};
 use tracing::{info, instrument};
 
 use crate::error::SpanErr;
 
+pub mod state;
+
+/// Server error code for an index that exists with the same keys but other options.
+const INDEX_OPTIONS_CONFLICT: i32 = 85;
+
+/// Lets MongoDB remove continuation tokens `ttl` after they were minted.
+fn token_expiry_index(ttl: Duration) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! { "created_at": 1 })
+        .options(IndexOptions::builder().expire_after(ttl).build())
+        .build()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListObjectTokens {
     pub start_after: String,
+    /// The remote that listed the page, which the next page is listed from while it is up.
+    /// Missing on tokens minted before it was recorded.
+    #[serde(default)]
+    pub remote: Option<String>,
     pub created_at: mongodb::bson::DateTime,
     pub consumed_at: Option<mongodb::bson::DateTime>,
 }
@@ -18,7 +37,16 @@ pub struct ListObjectTokens {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MultipartUploadIds {
     pub upload_ids: Vec<RemoteMultipartUploadId>,
+    /// Missing on uploads created before it was recorded.
+    #[serde(default)]
+    pub key: Option<String>,
     pub created_at: mongodb::bson::DateTime,
+    /// When the upload was created or last received a part.
+    #[serde(default)]
+    pub last_activity: Option<mongodb::bson::DateTime>,
+    /// Size of each uploaded part of known length, by part number.
+    #[serde(default)]
+    pub part_sizes: HashMap<String, i64>,
     pub completed_at: Option<mongodb::bson::DateTime>,
     pub aborted_at: Option<mongodb::bson::DateTime>,
 }
@@ -40,6 +68,38 @@ impl RemoteMultipartUploadId {
     }
 }
 
+/// When an object written with a TTL is due to be deleted from every remote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectExpiration {
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub expires_at: mongodb::bson::DateTime,
+}
+
+/// An object a write left missing on some remotes, to be copied there from `source`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectRepair {
+    pub key: String,
+    pub source: String,
+    /// The ETag the write returned on `source`. A copy is only made while the source still holds
+    /// that object.
+    pub e_tag: Option<String>,
+    pub targets: Vec<String>,
+    pub created_at: mongodb::bson::DateTime,
+    pub attempts: i32,
+    /// Until when the repair is claimed by a worker or waits before its next attempt.
+    pub retry_after: Option<mongodb::bson::DateTime>,
+}
+
+/// The number of parts of an object written by a completed multipart upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartObject {
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub parts_count: i32,
+    pub completed_at: mongodb::bson::DateTime,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum PartUploadStatus {
@@ -53,6 +113,9 @@ pub struct MongoDB {
 
     pub list_object_tokens: mongodb::Collection<ListObjectTokens>,
     pub multipart_upload_ids: mongodb::Collection<MultipartUploadIds>,
+    pub object_expirations: mongodb::Collection<ObjectExpiration>,
+    pub multipart_objects: mongodb::Collection<MultipartObject>,
+    pub object_repairs: mongodb::Collection<ObjectRepair>,
 }
 
 impl MongoDB {
@@ -60,36 +123,22 @@ impl MongoDB {
     pub async fn connect(
         uri: String,
         db_name: String,
+        write_concern: Option<WriteConcern>,
+        list_token_ttl: Duration,
     ) -> Result<MongoDB, SpanErr<mongodb::error::Error>> {
         let client_options = ClientOptions::parse(uri).await?;
         let client = mongodb::Client::with_options(client_options)?;
-        let db = client.database(db_name.as_str());
+        let mongo = Self::open(client, &db_name, write_concern);
         info!("Connected to MongoDB ({}).", db_name);
 
-        let mongo = Self {
-            client,
-            list_object_tokens: db.collection("list_object_tokens"),
-            multipart_upload_ids: db.collection("multipart_upload_ids"),
-            db,
-        };
-
         info!("Creating indexes...");
 
-        mongo
-            .list_object_tokens
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "created_at": 1 })
-                    .options(
-                        IndexOptions::builder()
-                            .expire_after(Duration::from_days(1))
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
+        mongo.create_token_expiry_index(list_token_ttl).await?;
 
-        info!("list_object_tokens created_at index created.");
+        info!(
+            "list_object_tokens created_at index created (ttl: {:?}).",
+            list_token_ttl
+        );
 
         mongo
             .list_object_tokens
@@ -107,8 +156,149 @@ impl MongoDB {
 
         info!("list_object_tokens consumed_at index created.");
 
+        mongo
+            .object_expirations
+            .create_index(IndexModel::builder().keys(doc! { "expires_at": 1 }).build())
+            .await?;
+
+        info!("object_expirations expires_at index created.");
+
         info!("Indexes created.");
 
         Ok(mongo)
     }
+
+    /// An existing index with another expiry, such as one created before the TTL was configurable,
+    /// is changed in place, since MongoDB refuses to create it again with different options.
+    async fn create_token_expiry_index(&self, ttl: Duration) -> mongodb::error::Result<()> {
+        let created = self
+            .list_object_tokens
+            .create_index(token_expiry_index(ttl))
+            .await;
+        match created {
+            Err(e) if matches!(*e.kind, ErrorKind::Command(ref c) if c.code == INDEX_OPTIONS_CONFLICT) =>
+            {
+                self.db
+                    .run_command(doc! {
+                        "collMod": self.list_object_tokens.name(),
+                        "index": {
+                            "keyPattern": { "created_at": 1 },
+                            "expireAfterSeconds": ttl.as_secs() as i64,
+                        },
+                    })
+                    .await?;
+                Ok(())
+            }
+            created => created.map(|_| ()),
+        }
+    }
+
+    /// `write_concern` applies to the collections whose loss breaks in-flight client requests.
+    fn open(client: mongodb::Client, db_name: &str, write_concern: Option<WriteConcern>) -> Self {
+        let db = client.database(db_name);
+        let options = CollectionOptions::builder()
+            .write_concern(write_concern)
+            .build();
+        Self {
+            list_object_tokens: db.collection_with_options("list_object_tokens", options.clone()),
+            multipart_upload_ids: db.collection_with_options("multipart_upload_ids", options),
+            object_expirations: db.collection("object_expirations"),
+            multipart_objects: db.collection("multipart_objects"),
+            object_repairs: db.collection("object_repairs"),
+            client,
+            db,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::options::Acknowledgment;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn write_concern_applies_to_state_collections() {
+        let client = mongodb::Client::with_options(
+            ClientOptions::parse("mongodb://localhost:27017")
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let write_concern = WriteConcern::builder()
+            .w(Acknowledgment::Majority)
+            .journal(true)
+            .build();
+
+        let mongo = MongoDB::open(client, "s3-reproxy", Some(write_concern.clone()));
+
+        assert_eq!(
+            mongo.multipart_upload_ids.write_concern(),
+            Some(&write_concern)
+        );
+        assert_eq!(
+            mongo.list_object_tokens.write_concern(),
+            Some(&write_concern)
+        );
+    }
+
+    #[test]
+    fn tokens_expire_after_the_configured_ttl() {
+        let index = token_expiry_index(Duration::from_secs(60 * 60));
+
+        assert_eq!(index.keys, doc! { "created_at": 1 });
+        assert_eq!(
+            index.options.and_then(|o| o.expire_after),
+            Some(Duration::from_secs(60 * 60))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs docker"]
+    async fn token_expiry_index_follows_a_changed_ttl() {
+        use futures::TryStreamExt;
+        use testcontainers_modules::mongo::Mongo;
+        use testcontainers_modules::testcontainers::runners::AsyncRunner;
+
+        let container = Mongo::default().start().await.unwrap();
+        let uri = format!(
+            "mongodb://{}:{}",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(27017).await.unwrap()
+        );
+        let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+        let mongo = MongoDB::open(client, "s3-reproxy", None);
+        mongo
+            .create_token_expiry_index(Duration::from_secs(60 * 60))
+            .await
+            .unwrap();
+
+        let conflict = mongo
+            .list_object_tokens
+            .create_index(token_expiry_index(Duration::from_secs(60)))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(*conflict.kind, ErrorKind::Command(ref c) if c.code == INDEX_OPTIONS_CONFLICT)
+        );
+
+        mongo
+            .create_token_expiry_index(Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let indexes: Vec<IndexModel> = mongo
+            .list_object_tokens
+            .list_indexes()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let expire_after = indexes
+            .into_iter()
+            .find(|index| index.keys == doc! { "created_at": 1 })
+            .and_then(|index| index.options?.expire_after);
+        assert_eq!(expire_after, Some(Duration::from_secs(60)));
+    }
 }
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs;
+use tracing::{info, instrument};
+
+use crate::error::SpanErr;
+
+use super::MongoDB;
+
+/// Snapshot of the collections s3-reproxy keeps its own state in, used to move that state
+/// between MongoDB clusters. Documents are kept verbatim and ordered by `_id`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateDump {
+    pub list_object_tokens: Vec<Document>,
+    pub multipart_upload_ids: Vec<Document>,
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Failed to access state file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to encode state: {0}")]
+    Encode(#[from] mongodb::bson::ser::Error),
+
+    #[error("Failed to decode state: {0}")]
+    Decode(#[from] mongodb::bson::de::Error),
+
+    #[error("MongoDB error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+}
+
+impl StateDump {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StateError> {
+        Ok(mongodb::bson::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        Ok(mongodb::bson::from_slice(bytes)?)
+    }
+}
+
+impl MongoDB {
+    async fn dump_collection(&self, name: &str) -> Result<Vec<Document>, mongodb::error::Error> {
+        self.db
+            .collection::<Document>(name)
+            .find(doc! {})
+            .sort(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// Replaces each document by `_id`, so importing the same dump twice is harmless.
+    async fn restore_collection(
+        &self,
+        name: &str,
+        documents: Vec<Document>,
+    ) -> Result<(), mongodb::error::Error> {
+        let collection = self.db.collection::<Document>(name);
+        for document in documents {
+            let id = document.get("_id").cloned().unwrap_or_default();
+            collection
+                .replace_one(doc! { "_id": id }, document)
+                .upsert(true)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn export_state(&self) -> Result<StateDump, mongodb::error::Error> {
+        Ok(StateDump {
+            list_object_tokens: self.dump_collection("list_object_tokens").await?,
+            multipart_upload_ids: self.dump_collection("multipart_upload_ids").await?,
+        })
+    }
+
+    pub async fn import_state(&self, dump: StateDump) -> Result<(), mongodb::error::Error> {
+        self.restore_collection("list_object_tokens", dump.list_object_tokens)
+            .await?;
+        self.restore_collection("multipart_upload_ids", dump.multipart_upload_ids)
+            .await
+    }
+
+    #[instrument(name = "state/export", skip(self))]
+    pub async fn export_state_to(&self, path: &Path) -> Result<(), SpanErr<StateError>> {
+        let dump = self.export_state().await.map_err(StateError::from)?;
+        fs::write(path, dump.to_bytes()?)
+            .await
+            .map_err(StateError::from)?;
+        info!(
+            "Exported {} list_object_tokens and {} multipart_upload_ids.",
+            dump.list_object_tokens.len(),
+            dump.multipart_upload_ids.len()
+        );
+        Ok(())
+    }
+
+    #[instrument(name = "state/import", skip(self))]
+    pub async fn import_state_from(&self, path: &Path) -> Result<(), SpanErr<StateError>> {
+        let dump = StateDump::from_bytes(&fs::read(path).await.map_err(StateError::from)?)?;
+        info!(
+            "Importing {} list_object_tokens and {} multipart_upload_ids...",
+            dump.list_object_tokens.len(),
+            dump.multipart_upload_ids.len()
+        );
+        self.import_state(dump).await.map_err(StateError::from)?;
+        info!("Import finished.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn dump_round_trips_documents_exactly() {
+        let dump = StateDump {
+            list_object_tokens: vec![doc! {
+                "_id": ObjectId::new(),
+                "start_after": "photos/2024/01.jpg",
+                "created_at": mongodb::bson::DateTime::now(),
+                "consumed_at": null,
+            }],
+            multipart_upload_ids: vec![doc! {
+                "_id": ObjectId::new(),
+                "upload_ids": [
+                    { "status": "open", "remote_name": "r2", "upload_id": "abc" },
+                    { "status": "cancelled", "remote_name": "minio", "upload_id": "def" },
+                ],
+                "created_at": mongodb::bson::DateTime::now(),
+                "completed_at": null,
+                "aborted_at": null,
+            }],
+        };
+
+        let restored = StateDump::from_bytes(&dump.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(restored, dump);
+    }
+}
@@ -2,8 +2,12 @@
 #![feature(duration_constructors)]
 use std::net::Ipv4Addr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::server::remote::spawn_remote;
+use crate::access_log::{AccessLogLayer, REQUEST_SPAN};
+use crate::server::outage::FailureResponses;
+use crate::server::reload::{start_remote, Reloader, RemoteSet};
+use crate::server::request_id::{request_id, REQUEST_ID_HEADER};
 use crate::server::S3Reproxy;
 use clap::Parser;
 use hyper_util::rt::{TokioExecutor, TokioIo};
@@ -13,13 +17,18 @@ use tokio::net::TcpListener;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
-use tower::ServiceBuilder;
 use tracing_subscriber::filter::filter_fn;
+pub mod access_log;
+pub mod admin;
 pub mod config;
 pub mod db;
 pub mod error;
+pub mod metrics;
 pub mod server;
+#[cfg(feature = "otel")]
+mod telemetry;
 
+use self::config::s3_target::WriteQuorum;
 use self::config::S3ReproxySetup;
 use self::error::SpanErr;
 use self::server::remote::RemoteMessage;
@@ -35,7 +44,12 @@ use tracing_subscriber::Layer;
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let _ = dotenv();
-    tracing_subscriber::Registry::default()
+    let args = config::AppArgs::parse();
+    let access_log = args.access_log.as_deref().map(|destination| {
+        AccessLogLayer::new(destination, args.access_log_format)
+            .expect("failed to open the access log")
+    });
+    let registry = tracing_subscriber::Registry::default()
         .with(
             tracing_subscriber::fmt::layer()
                 .with_target(true)
@@ -47,20 +61,30 @@ async fn main() {
                 })),
         )
         .with(ErrorLayer::default())
+        .with(access_log);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(telemetry::layer().expect("failed to set up the OTLP exporter"));
+    registry
         .try_init()
         .expect("failed to initialize tracing subscriber");
 
     tracing::info!("s3-reproxy v{}", env!("CARGO_PKG_VERSION"));
 
-    if let Err(e) = s3_reproxy().await {
+    if let Err(e) = s3_reproxy(args).await {
         tracing::error!(
             "s3-reproxy stopped due to following error:\n\n\x1b[31m\x1b[1m{}\x1b[m\n\n{}",
             e.error,
             color_spantrace::colorize(&e.span)
         );
     }
+
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
 }
 
+/// How long `/admin/stats` reuses a listing before scanning the remotes again.
+const ADMIN_STATS_TTL: Duration = Duration::from_secs(30);
+
 #[derive(Error, Debug)]
 enum S3ProxyError {
     #[error("Failed to setup s3-reproxy: \n{0}")]
@@ -77,39 +101,188 @@ enum S3ProxyError {
 
     #[error("Failed to connect to MongoDB: \n{0}")]
     DB(#[from] mongodb::error::Error),
-}
 
-#[instrument]
-async fn s3_reproxy() -> Result<(), SpanErr<S3ProxyError>> {
-    let args = config::AppArgs::parse();
+    #[error("Failed to transfer state: \n{0}")]
+    State(#[from] db::state::StateError),
+}
 
+#[instrument(skip_all)]
+async fn s3_reproxy(args: config::AppArgs) -> Result<(), SpanErr<S3ProxyError>> {
     let setup = S3ReproxySetup::new(args)
         .await
         .map_err(|e| e.map(S3ProxyError::Setup))?;
 
+    if setup.args.export_state.is_some() || setup.args.import_state.is_some() {
+        let db = db::MongoDB::connect(
+            setup.args.mongo_uri,
+            setup.args.mongo_db,
+            setup
+                .config
+                .mongo_write_concern
+                .as_ref()
+                .map(|c| c.write_concern()),
+            *setup.config.list_token_ttl,
+        )
+        .await
+        .map_err(|e| e.map(S3ProxyError::DB))?;
+        if let Some(path) = &setup.args.export_state {
+            db.export_state_to(path)
+                .await
+                .map_err(|e| e.map(S3ProxyError::State))?;
+        }
+        if let Some(path) = &setup.args.import_state {
+            db.import_state_from(path)
+                .await
+                .map_err(|e| e.map(S3ProxyError::State))?;
+        }
+        return Ok(());
+    }
+
     let mut remote_tasks = JoinSet::new();
-    let remotes = Arc::new(
+    let remotes = Arc::new(RemoteSet::new(
         setup
             .config
             .remotes
             .iter()
-            .map(|t| spawn_remote(t.clone(), &setup, &mut remote_tasks))
+            .map(|t| start_remote(t, &setup, &mut remote_tasks))
             .collect(),
-    );
+    ));
 
     let db = Arc::new(
-        db::MongoDB::connect(setup.args.mongo_uri, setup.args.mongo_db)
-            .await
-            .map_err(|e| e.map(S3ProxyError::DB))?,
+        db::MongoDB::connect(
+            setup.args.mongo_uri,
+            setup.args.mongo_db,
+            setup
+                .config
+                .mongo_write_concern
+                .as_ref()
+                .map(|c| c.write_concern()),
+            *setup.config.list_token_ttl,
+        )
+        .await
+        .map_err(|e| e.map(S3ProxyError::DB))?,
     );
 
+    // background jobs stop between items on shutdown, releasing what they claimed
+    let (stop_jobs, jobs_stopping) = tokio::sync::watch::channel(false);
+    let mut jobs = JoinSet::new();
+    // a read-only deployment leaves the remotes as they are, including what its jobs would change
+    let writes = !setup.config.read_only;
+
+    if let (true, Some(ttl)) = (writes, &setup.config.object_ttl) {
+        jobs.spawn(server::expiry::sweep(
+            Arc::clone(&remotes),
+            Arc::clone(&db),
+            *ttl.sweep_interval,
+            setup.config.fanout_concurrency,
+            jobs_stopping.clone(),
+        ));
+    }
+
+    if let (true, Some(abandoned)) = (writes, &setup.config.abandoned_uploads) {
+        jobs.spawn(server::abandoned::sweep(
+            Arc::clone(&remotes),
+            Arc::clone(&db),
+            *abandoned.idle_timeout,
+            *abandoned.sweep_interval,
+            setup.config.fanout_concurrency,
+            jobs_stopping.clone(),
+        ));
+    }
+
+    if let Some(health_check) = &setup.config.health_check {
+        jobs.spawn(server::health::probe(
+            Arc::clone(&remotes),
+            *health_check.interval,
+            health_check
+                .timeout
+                .map_or(*health_check.interval, |timeout| *timeout),
+            jobs_stopping.clone(),
+        ));
+    }
+
+    if let (true, Some(repair)) = (writes, &setup.config.write_repair) {
+        jobs.spawn(server::repair::drain(
+            Arc::clone(&remotes),
+            Arc::clone(&db),
+            *repair.interval,
+            jobs_stopping.clone(),
+        ));
+    }
+
+    for operation in setup.config.enabled_operations.iter().flatten() {
+        if !server::operations::OPERATIONS.contains(&operation.as_str()) {
+            tracing::warn!("unknown operation in enabled_operations: {:?}", operation);
+        }
+    }
+
+    let mut reloader = Reloader::new(setup.clone(), Arc::clone(&remotes));
+
     let server = S3Reproxy {
         bucket: setup.config.bucket,
         remotes: Arc::clone(&remotes),
-        db,
+        db: Arc::clone(&db),
+        list_buckets_from: setup.config.list_buckets_from,
+        head_verify_count: setup.config.head_verify_count,
+        head_verify_parallel: setup.config.head_verify_parallel,
+        head_divergence: setup.config.head_divergence,
+        strict_reads: setup.config.strict_reads,
+        copy_divergence: setup.config.copy_divergence,
+        part_etag_divergence: setup.config.part_etag_divergence,
+        prefetcher: setup
+            .config
+            .range_prefetch
+            .as_ref()
+            .map(server::prefetch::RangePrefetcher::new),
+        list_token_fallback: setup.config.list_token_fallback,
+        native_list_tokens_from: setup.config.native_list_tokens_from,
+        reported_region: setup.config.reported_region,
+        get_retry_buffer_bytes: setup.config.get_retry_buffer_bytes,
+        verify_get_checksums: setup.config.verify_get_checksums,
+        report_parts_count: setup.config.report_parts_count,
+        disk_spool: setup
+            .config
+            .spool_to_disk
+            .then(|| server::stream::DiskSpool {
+                dir: setup
+                    .config
+                    .spool_dir
+                    .clone()
+                    .unwrap_or_else(std::env::temp_dir),
+                threshold_bytes: setup.config.spool_threshold_bytes,
+            }),
+        read_quick_retries: setup.config.read_quick_retries,
+        read_strategy: setup.config.read_strategy,
+        read_hedge_delay: setup.config.read_hedge_delay_ms.map(Duration::from_millis),
+        debug_headers: setup.config.debug_headers,
+        health_checks: setup.config.health_check.is_some(),
+        repair_writes: setup.config.write_repair.is_some(),
+        upload_part_retries: setup.config.upload_part_retries,
+        upload_part_retry_max_bytes: setup.config.upload_part_retry_max_bytes,
+        fanout_concurrency: setup.config.fanout_concurrency,
+        write_quorum: setup.config.write_quorum.unwrap_or_default(),
+        complete_quorum: setup.config.write_quorum.unwrap_or(WriteQuorum::All),
+        failure_responses: FailureResponses {
+            no_remote: setup.config.no_remote_error,
+            below_quorum: setup.config.below_quorum_error,
+        },
+        upload_tokens: setup
+            .config
+            .signed_upload_ids
+            .as_ref()
+            .map(server::upload_token::UploadTokenCodec::new),
+        max_active_multipart_uploads: setup.config.max_active_multipart_uploads,
+        object_ttl: setup.config.object_ttl,
+        enabled_operations: setup.config.enabled_operations,
+        read_only: setup.config.read_only,
+        notifier: setup
+            .config
+            .event_webhook
+            .as_ref()
+            .map(server::notify::EventNotifier::spawn),
     };
 
-    for r in remotes.iter() {
+    for r in remotes.load().iter() {
         r.tx.send(server::remote::RemoteMessage::HealthCheck {
             reply: tokio::sync::oneshot::channel().0,
         })
@@ -117,6 +290,36 @@ async fn s3_reproxy() -> Result<(), SpanErr<S3ProxyError>> {
         .map_err(S3ProxyError::Remote)?;
     }
 
+    if let Some(port) = setup.args.admin_port {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .map_err(S3ProxyError::Bind)?;
+        tokio::spawn(admin::serve(
+            listener,
+            Arc::new(admin::Admin {
+                remotes: Arc::clone(&remotes),
+                stats: admin::stats::StatsCache::new(ADMIN_STATS_TTL),
+                maintenance: setup
+                    .args
+                    .admin_token
+                    .clone()
+                    .filter(|_| writes)
+                    .map(|token| admin::Maintenance {
+                        db,
+                        token,
+                        fanout_concurrency: setup.config.fanout_concurrency,
+                    }),
+            }),
+        ));
+    }
+
+    if let Some(port) = setup.args.metrics_port {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .map_err(S3ProxyError::Bind)?;
+        tokio::spawn(metrics::serve(listener, Arc::clone(&remotes)));
+    }
+
     let s3_service = {
         let mut builder = S3ServiceBuilder::new(server);
         builder.set_auth(SimpleAuth::from_single(
@@ -130,13 +333,54 @@ async fn s3_reproxy() -> Result<(), SpanErr<S3ProxyError>> {
         .await
         .map_err(S3ProxyError::Bind)?;
 
-    let hyper_s3_service = ServiceBuilder::new().service(s3_service.into_shared());
+    let in_flight = server::in_flight::InFlight::default();
+    let hyper_s3_service = {
+        let s3_service = s3_service.into_shared();
+        let in_flight = in_flight.clone();
+        hyper::service::service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+            let guard = in_flight.enter();
+            let id = request_id(req.headers());
+            let bytes_in = req
+                .headers()
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+            let span = tracing::info_span!(
+                REQUEST_SPAN,
+                id = id,
+                method = %req.method(),
+                path = req.uri().path(),
+                bytes_in,
+                status = tracing::field::Empty,
+                bytes_out = tracing::field::Empty,
+            );
+            let served = span.in_scope(|| hyper::service::Service::call(&s3_service, req));
+            async move {
+                let mut res = served.await;
+                drop(guard);
+                if let Ok(response) = &res {
+                    let span = tracing::Span::current();
+                    span.record("status", response.status().as_u16());
+                    let bytes_out = response
+                        .headers()
+                        .get(http::header::CONTENT_LENGTH)
+                        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+                    span.record("bytes_out", bytes_out);
+                }
+                if let (Ok(response), Ok(id)) = (&mut res, http::HeaderValue::from_str(&id)) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, id);
+                }
+                res
+            }
+            .instrument(span)
+        })
+    };
 
     let http_server = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
     let graceful = hyper_util::server::graceful::GracefulShutdown::new();
 
     let mut sigint = signal(SignalKind::interrupt()).map_err(S3ProxyError::Signal)?;
     let mut sigterm = signal(SignalKind::terminate()).map_err(S3ProxyError::Signal)?;
+    let mut sighup = signal(SignalKind::hangup()).map_err(S3ProxyError::Signal)?;
 
     loop {
         tokio::select! {
@@ -148,6 +392,12 @@ async fn s3_reproxy() -> Result<(), SpanErr<S3ProxyError>> {
                 tracing::info!("Received SIGTERM, shutting down...");
                 break;
             }
+            _ = sighup.recv() => {
+                tracing::info!("Received SIGHUP, reloading config...");
+                if let Err(e) = reloader.reload(&mut remote_tasks).await {
+                    tracing::error!("Failed to reload config, keeping the running one: {}", e.error);
+                }
+            }
             res = listener.accept() => {
 
                 match res {
@@ -172,13 +422,35 @@ async fn s3_reproxy() -> Result<(), SpanErr<S3ProxyError>> {
         }
     }
 
-    for r in remotes.iter() {
+    // let the handlers finish their remote and MongoDB writes before the remotes go away, so a
+    // multipart upload is not left completed on some remotes but not recorded as such
+    drop(listener);
+    let draining = in_flight.count();
+    info!("Waiting for {} in-flight requests...", draining);
+    let timeout = *setup.args.shutdown_timeout;
+    if tokio::time::timeout(timeout, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "{} requests still in flight after {:?}, closing the remotes under them",
+            in_flight.count(),
+            timeout
+        );
+    }
+    info!(
+        "Drained {} in-flight requests",
+        draining.saturating_sub(in_flight.count())
+    );
+
+    let _ = stop_jobs.send(true);
+    while (jobs.join_next().await).is_some() {}
+
+    for r in remotes.load().iter() {
         r.tx.send(server::remote::RemoteMessage::Shutdown)
             .await
             .map_err(S3ProxyError::Remote)?;
     }
-
-    graceful.shutdown().await;
     while (remote_tasks.join_next().await).is_some() {}
 
     info!("Server shutdown complete");
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{header, Method, Request, Response, StatusCode};
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::TcpListener;
+use tracing::{error, info, instrument, Instrument};
+
+use crate::server::reload::RemoteSet;
+use crate::server::remote::S3Remote;
+use crate::server::status::CircuitState;
+
+/// A monotonically increasing counter partitioned by a fixed set of labels.
+pub struct CounterVec {
+    name: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub const fn new(name: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.inc_by(labels, 1);
+    }
+
+    pub fn inc_by(&self, labels: &[&str], value: u64) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "{}", self.name);
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += value;
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, labels: &[&str]) -> u64 {
+        let key: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+        self.values
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (values, count) in self.values.lock().unwrap().iter() {
+            let labels = label_set(self.labels, values, None);
+            let _ = writeln!(out, "{}{} {}", self.name, labels, count);
+        }
+    }
+}
+
+/// Upper bounds in seconds of the buckets of every [`HistogramVec`].
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations at or below each of [`BUCKETS`].
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// A histogram of durations partitioned by a fixed set of labels.
+pub struct HistogramVec {
+    name: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, Histogram>>,
+}
+
+impl HistogramVec {
+    pub const fn new(name: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], duration: Duration) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "{}", self.name);
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        let seconds = duration.as_secs_f64();
+        let mut values = self.values.lock().unwrap();
+        let histogram = values.entry(key).or_default();
+        for (bucket, le) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (values, histogram) in self.values.lock().unwrap().iter() {
+            for (count, le) in histogram.buckets.iter().zip(BUCKETS) {
+                let labels = label_set(self.labels, values, Some(&le.to_string()));
+                let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, count);
+            }
+            let labels = label_set(self.labels, values, Some("+Inf"));
+            let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, histogram.count);
+            let labels = label_set(self.labels, values, None);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, histogram.count);
+        }
+    }
+}
+
+/// `{name="value",...}` in the Prometheus text format, with `le` last when given.
+fn label_set(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let pairs = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Request body bytes streamed to each remote.
+pub static REMOTE_BYTES_SENT: CounterVec =
+    CounterVec::new("reproxy_remote_bytes_sent_total", &["remote"]);
+
+/// Object body bytes streamed from each remote.
+pub static REMOTE_BYTES_RECEIVED: CounterVec =
+    CounterVec::new("reproxy_remote_bytes_received_total", &["remote"]);
+
+/// Requests sent to each remote, whether or not it answered.
+pub static REQUESTS: CounterVec =
+    CounterVec::new("reproxy_requests_total", &["operation", "remote"]);
+
+/// Requests a remote did not answer, because it was down or timed out. Error responses of the
+/// remote are answers and not counted.
+pub static REMOTE_FAILURES: CounterVec =
+    CounterVec::new("reproxy_remote_failures_total", &["operation", "remote"]);
+
+/// Remotes that failed a write the other remotes accepted, leaving them inconsistent.
+pub static INCONSISTENT_WRITES: CounterVec =
+    CounterVec::new("reproxy_inconsistent_writes_total", &["remote"]);
+
+/// Remotes that returned another ETag for an uploaded part than the remote answering the client.
+pub static PART_ETAG_DIVERGENCE: CounterVec =
+    CounterVec::new("reproxy_part_etag_divergence_total", &["remote"]);
+
+/// Fields of a `HeadObject` reply on which a remote disagreed with the remote answering the
+/// client, found by `strict_reads`.
+pub static HEAD_METADATA_DIVERGENCE: CounterVec = CounterVec::new(
+    "reproxy_head_metadata_divergence_total",
+    &["remote", "field"],
+);
+
+/// Writes of object bookkeeping to MongoDB, e.g. an expiry or a part count, that failed after the
+/// object itself was written. The object is served as usual but the record is missing or stale.
+pub static RECORD_FAILURES: CounterVec =
+    CounterVec::new("reproxy_record_failures_total", &["record"]);
+
+/// Remotes whose reply was handed to the client of a read.
+pub static READ_REMOTE_SELECTED: CounterVec = CounterVec::new(
+    "reproxy_read_remote_selected_total",
+    &["operation", "remote"],
+);
+
+/// Repairs of inconsistent writes waiting in MongoDB, as of the last pass of the repair worker.
+pub static PENDING_REPAIRS: AtomicU64 = AtomicU64::new(0);
+
+/// Time each remote took to answer a request, including retries.
+pub static REMOTE_REQUEST_DURATION: HistogramVec = HistogramVec::new(
+    "reproxy_remote_request_duration_seconds",
+    &["operation", "remote"],
+);
+
+/// Every metric in the Prometheus text format, along with whether each remote is up as its
+/// status currently has it.
+pub fn render(remotes: &[S3Remote]) -> String {
+    let mut out = String::new();
+    for counter in [
+        &REQUESTS,
+        &REMOTE_FAILURES,
+        &INCONSISTENT_WRITES,
+        &PART_ETAG_DIVERGENCE,
+        &HEAD_METADATA_DIVERGENCE,
+        &RECORD_FAILURES,
+        &READ_REMOTE_SELECTED,
+        &REMOTE_BYTES_SENT,
+        &REMOTE_BYTES_RECEIVED,
+    ] {
+        counter.render(&mut out);
+    }
+    REMOTE_REQUEST_DURATION.render(&mut out);
+
+    let _ = writeln!(out, "# TYPE reproxy_pending_repairs gauge");
+    let _ = writeln!(
+        out,
+        "reproxy_pending_repairs {}",
+        PENDING_REPAIRS.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE reproxy_remote_up gauge");
+    for remote in remotes {
+        let up = match remote.status.snapshot().circuit {
+            CircuitState::Unknown => continue,
+            CircuitState::Closed => 1,
+            CircuitState::Open => 0,
+        };
+        let labels = label_set(&["remote"], &[remote.name.clone()], None);
+        let _ = writeln!(out, "reproxy_remote_up{} {}", labels, up);
+    }
+    out
+}
+
+fn handle<B>(req: Request<B>, remotes: &[S3Remote]) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(render(remotes))))
+            .unwrap(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::default())
+            .unwrap(),
+    }
+}
+
+#[instrument(name = "metrics", skip_all)]
+pub async fn serve(listener: TcpListener, remotes: Arc<RemoteSet>) {
+    info!("Metrics listening on {:?}", listener.local_addr());
+    let http_server = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let remotes = Arc::clone(&remotes);
+        let service = service_fn(move |req| {
+            std::future::ready(Ok::<_, Infallible>(handle(req, &remotes.load())))
+        });
+        let serve = http_server
+            .serve_connection(TokioIo::new(stream), service)
+            .into_owned();
+        tokio::spawn(
+            async move {
+                let _ = serve.await;
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn counters_render_in_text_format() {
+        let counter = CounterVec::new("test_total", &["operation", "remote"]);
+        counter.inc(&["GetObject", "a"]);
+        counter.inc_by(&["GetObject", "say \"b\""], 2);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+
+        assert_eq!(
+            out,
+            "# TYPE test_total counter\n\
+             test_total{operation=\"GetObject\",remote=\"a\"} 1\n\
+             test_total{operation=\"GetObject\",remote=\"say \\\"b\\\"\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = HistogramVec::new("test_seconds", &["remote"]);
+        histogram.observe(&["a"], Duration::from_millis(250));
+        histogram.observe(&["a"], Duration::from_secs(30));
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[1], "test_seconds_bucket{remote=\"a\",le=\"0.005\"} 0");
+        assert_eq!(lines[5], "test_seconds_bucket{remote=\"a\",le=\"0.1\"} 0");
+        assert_eq!(lines[6], "test_seconds_bucket{remote=\"a\",le=\"0.25\"} 1");
+        assert_eq!(lines[11], "test_seconds_bucket{remote=\"a\",le=\"10\"} 1");
+        assert_eq!(lines[12], "test_seconds_bucket{remote=\"a\",le=\"+Inf\"} 2");
+        assert_eq!(lines[13], "test_seconds_sum{remote=\"a\"} 30.25");
+        assert_eq!(lines[14], "test_seconds_count{remote=\"a\"} 2");
+    }
+}
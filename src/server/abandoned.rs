@@ -0,0 +1,551 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::operation::abort_multipart_upload::{
+    AbortMultipartUploadError, AbortMultipartUploadInput,
+};
+use futures::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{error, info, instrument, warn};
+
+use crate::db::{MongoDB, PartUploadStatus, RemoteMultipartUploadId};
+
+use super::reload::RemoteSet;
+use super::remote::{RemoteMessage, S3Remote};
+
+/// Most uploads aborted per sweep; the rest wait for the next one.
+const SWEEP_BATCH: i64 = 100;
+
+/// How long an upload claimed by a sweep is left alone by the sweeps of other replicas. A claim
+/// outlives a replica that died mid-sweep by at most this long. An upload that could not be
+/// aborted keeps its claim until then, which spaces out the retries.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+/// What became of an upload on one of the remotes it was open on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbortOutcome {
+    Aborted,
+    /// The remote no longer knows the upload.
+    NoSuchUpload,
+    /// The remote is no longer configured.
+    Unconfigured,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteAbort {
+    pub remote: String,
+    pub outcome: AbortOutcome,
+}
+
+/// The result of aborting an upload on an operator's request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForcedAbort {
+    pub upload_id: String,
+    pub key: Option<String>,
+    /// Whether every remote the upload was open on is done with it.
+    pub aborted: bool,
+    pub remotes: Vec<RemoteAbort>,
+}
+
+/// How a claimed upload was left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Swept {
+    Aborted,
+    /// A part arrived since the upload was claimed, so it is not idle anymore.
+    Active,
+    Retry,
+    Cancelled,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdleUpload {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    upload_ids: Vec<RemoteMultipartUploadId>,
+    key: Option<String>,
+    last_activity: mongodb::bson::DateTime,
+}
+
+/// Open uploads whose last part arrived at or before `now - idle_timeout`. Uploads created before
+/// their key was recorded are left out, as the remotes cannot abort an upload without its key.
+fn idle_filter(now: mongodb::bson::DateTime, idle_timeout: Duration) -> Document {
+    let cutoff = mongodb::bson::DateTime::from_millis(
+        now.timestamp_millis() - idle_timeout.as_millis() as i64,
+    );
+    doc! {
+        "completed_at": null,
+        "aborted_at": null,
+        "key": { "$ne": null },
+        "last_activity": { "$lte": cutoff },
+    }
+}
+
+/// Idle uploads that no other sweep holds a live claim on.
+fn claim_filter(now: mongodb::bson::DateTime, idle_timeout: Duration) -> Document {
+    let mut filter = idle_filter(now, idle_timeout);
+    filter.insert(
+        "$or",
+        vec![
+            doc! { "claimed_until": null },
+            doc! { "claimed_until": { "$lte": now } },
+        ],
+    );
+    filter
+}
+
+/// The claimed upload as long as no part arrived since the claim, which would make it active
+/// again.
+fn unchanged_filter(upload: &IdleUpload) -> Document {
+    doc! {
+        "_id": upload.id,
+        "completed_at": null,
+        "aborted_at": null,
+        "last_activity": upload.last_activity,
+    }
+}
+
+#[instrument(name = "abandoned_uploads", skip_all)]
+pub async fn sweep(
+    remotes: Arc<RemoteSet>,
+    db: Arc<MongoDB>,
+    idle_timeout: Duration,
+    interval: Duration,
+    concurrency: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    if let Err(e) = backfill_last_activity(&db).await {
+        error!("mongodb error: {:?}", e);
+    }
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+        let swept = sweep_once(
+            &remotes.load(),
+            &db,
+            idle_timeout,
+            concurrency,
+            &mut shutdown,
+        );
+        if let Err(e) = swept.await {
+            error!("mongodb error: {:?}", e);
+        }
+    }
+    info!("stopped");
+}
+
+/// Uploads created before `last_activity` was recorded are idle since they were created. Without
+/// it they would never match [`idle_filter`] and stay open forever.
+async fn backfill_last_activity(db: &MongoDB) -> Result<(), mongodb::error::Error> {
+    let result = db
+        .multipart_upload_ids
+        .update_many(
+            doc! {
+                "completed_at": null,
+                "aborted_at": null,
+                "key": { "$ne": null },
+                "last_activity": null,
+            },
+            vec![doc! { "$set": { "last_activity": "$created_at" } }],
+        )
+        .await?;
+    if result.modified_count > 0 {
+        info!(
+            "recorded last activity of {} older uploads",
+            result.modified_count
+        );
+    }
+    Ok(())
+}
+
+async fn sweep_once(
+    remotes: &[S3Remote],
+    db: &MongoDB,
+    idle_timeout: Duration,
+    concurrency: usize,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<(), mongodb::error::Error> {
+    for _ in 0..SWEEP_BATCH {
+        let now = mongodb::bson::DateTime::now();
+        let claimed_until = mongodb::bson::DateTime::from_millis(
+            now.timestamp_millis() + CLAIM_LEASE.as_millis() as i64,
+        );
+        let Some(upload) = db
+            .multipart_upload_ids
+            .clone_with_type::<IdleUpload>()
+            .find_one_and_update(
+                claim_filter(now, idle_timeout),
+                doc! { "$set": { "claimed_until": claimed_until } },
+            )
+            .await?
+        else {
+            break;
+        };
+
+        // a part may have been stored while the claim was taken, after the upload was found idle
+        let unchanged = db
+            .multipart_upload_ids
+            .count_documents(unchanged_filter(&upload))
+            .await?;
+        let swept = if unchanged == 0 {
+            info!(
+                "upload({}) is active again since it was claimed. skipping",
+                upload.id
+            );
+            Swept::Active
+        } else {
+            sweep_claimed(remotes, &upload, concurrency, shutdown).await
+        };
+        match swept {
+            Swept::Aborted => {
+                // the remotes dropped the upload, so it is aborted even if a part arrived since
+                let before = db
+                    .multipart_upload_ids
+                    .clone_with_type::<IdleUpload>()
+                    .find_one_and_update(
+                        doc! { "_id": upload.id },
+                        doc! { "$set": { "aborted_at": mongodb::bson::DateTime::now() } },
+                    )
+                    .await?;
+                if before.is_some_and(|u| u.last_activity != upload.last_activity) {
+                    warn!(
+                        "upload({}) received a part while it was being aborted",
+                        upload.id
+                    );
+                }
+                info!("aborted idle upload({}) of {:?}", upload.id, upload.key);
+            }
+            Swept::Retry => {
+                warn!(
+                    "upload({}) could not be aborted everywhere. retrying later",
+                    upload.id
+                );
+                continue;
+            }
+            Swept::Active | Swept::Cancelled => {}
+        }
+        db.multipart_upload_ids
+            .update_one(
+                doc! { "_id": upload.id },
+                doc! { "$unset": { "claimed_until": "" } },
+            )
+            .await?;
+        if swept == Swept::Cancelled {
+            info!("released upload({}) on shutdown", upload.id);
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Aborts a claimed upload everywhere unless shutdown comes first.
+async fn sweep_claimed(
+    remotes: &[S3Remote],
+    upload: &IdleUpload,
+    concurrency: usize,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Swept {
+    let Some(key) = &upload.key else {
+        warn!("upload({}) has no recorded key. skipping", upload.id);
+        return Swept::Retry;
+    };
+    tokio::select! {
+        aborted = abort_everywhere(remotes, key, &upload.upload_ids, concurrency) => {
+            if aborted {
+                Swept::Aborted
+            } else {
+                Swept::Retry
+            }
+        }
+        _ = shutdown.wait_for(|stop| *stop) => Swept::Cancelled,
+    }
+}
+
+/// Aborts the upload on every remote it is still open on. An upload the remote no longer knows
+/// counts as aborted. Returns whether every such remote confirmed.
+async fn abort_everywhere(
+    remotes: &[S3Remote],
+    key: &str,
+    upload_ids: &[RemoteMultipartUploadId],
+    concurrency: usize,
+) -> bool {
+    abort_on_remotes(remotes, key, upload_ids, concurrency)
+        .await
+        .iter()
+        .all(|r| r.outcome != AbortOutcome::Failed)
+}
+
+/// Aborts the upload on every remote it is still open on, `concurrency` at a time, reporting on
+/// each of them.
+async fn abort_on_remotes(
+    remotes: &[S3Remote],
+    key: &str,
+    upload_ids: &[RemoteMultipartUploadId],
+    concurrency: usize,
+) -> Vec<RemoteAbort> {
+    futures::stream::iter(
+        upload_ids
+            .iter()
+            .filter(|u| u.status == PartUploadStatus::Open),
+    )
+    .map(|upload| async move {
+        let outcome = 'outcome: {
+            let Some(remote) = remotes.iter().find(|r| r.name == upload.remote_name) else {
+                warn!(
+                    "remote({:?}) is no longer configured. skipping",
+                    upload.remote_name
+                );
+                break 'outcome AbortOutcome::Unconfigured;
+            };
+            let Ok(input) = AbortMultipartUploadInput::builder()
+                .key(key)
+                .upload_id(&upload.upload_id)
+                .build()
+            else {
+                break 'outcome AbortOutcome::Failed;
+            };
+            let result = remote
+                .request(|reply| RemoteMessage::AbortMultipartUpload { input, reply })
+                .await;
+            match result {
+                Some(Ok(_)) => AbortOutcome::Aborted,
+                Some(Err(e)) if matches!(e.err(), AbortMultipartUploadError::NoSuchUpload(_)) => {
+                    AbortOutcome::NoSuchUpload
+                }
+                Some(Err(_)) | None => AbortOutcome::Failed,
+            }
+        };
+        RemoteAbort {
+            remote: upload.remote_name.clone(),
+            outcome,
+        }
+    })
+    .boxed()
+    .buffer_unordered(concurrency)
+    .collect()
+    .await
+}
+
+/// Aborts the upload `id` of `key` on every remote it is still open on, and records it aborted
+/// once each of them is done with it. An upload some remote could not abort stays open, for the
+/// client or the sweep to try again. Uploads with a signed upload id have no `id` to record.
+pub(crate) async fn abort_upload(
+    remotes: &[S3Remote],
+    db: &MongoDB,
+    id: Option<ObjectId>,
+    key: &str,
+    upload_ids: &[RemoteMultipartUploadId],
+    concurrency: usize,
+) -> Result<Vec<RemoteAbort>, mongodb::error::Error> {
+    let results = abort_on_remotes(remotes, key, upload_ids, concurrency).await;
+    let aborted = results.iter().all(|r| r.outcome != AbortOutcome::Failed);
+    if let (true, Some(id)) = (aborted, id) {
+        db.multipart_upload_ids
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "aborted_at": mongodb::bson::DateTime::now() } },
+            )
+            .await?;
+    }
+    Ok(results)
+}
+
+/// Aborts the open upload `id` everywhere right away, idle or not, for an operator whose client
+/// went away before the sweep would get to it. Returns `None` if no open upload has that id.
+#[instrument(skip(remotes, db))]
+pub async fn force_abort(
+    remotes: &[S3Remote],
+    db: &MongoDB,
+    id: ObjectId,
+    concurrency: usize,
+) -> Result<Option<ForcedAbort>, mongodb::error::Error> {
+    let Some(upload) = db
+        .multipart_upload_ids
+        .find_one(doc! { "_id": id, "completed_at": null, "aborted_at": null })
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(key) = upload.key else {
+        warn!("upload({}) has no recorded key. not aborting", id);
+        return Ok(Some(ForcedAbort {
+            upload_id: id.to_hex(),
+            key: None,
+            aborted: false,
+            remotes: vec![],
+        }));
+    };
+
+    let results =
+        abort_upload(remotes, db, Some(id), &key, &upload.upload_ids, concurrency).await?;
+    let aborted = results.iter().all(|r| r.outcome != AbortOutcome::Failed);
+    if aborted {
+        info!("aborted upload({}) of {:?}", id, key);
+    } else {
+        warn!("upload({}) could not be aborted everywhere", id);
+    }
+    Ok(Some(ForcedAbort {
+        upload_id: id.to_hex(),
+        key: Some(key),
+        aborted,
+        remotes: results,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::abort_multipart_upload::AbortMultipartUploadOutput;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+
+    /// A remote recording the upload ids it was asked to abort.
+    fn remote(name: &str, aborted: Arc<Mutex<Vec<String>>>) -> S3Remote {
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::AbortMultipartUpload { input, reply } = message {
+                aborted.lock().unwrap().extend(input.upload_id);
+                let _ = reply.send(Some(Ok(AbortMultipartUploadOutput::builder().build())));
+            }
+        })
+    }
+
+    fn upload_id(remote: &str, status: PartUploadStatus) -> RemoteMultipartUploadId {
+        RemoteMultipartUploadId {
+            status,
+            remote_name: remote.to_owned(),
+            upload_id: format!("{remote}-upload"),
+        }
+    }
+
+    #[test]
+    fn upload_is_idle_after_the_threshold() {
+        let now = mongodb::bson::DateTime::from_millis(1_700_000_000_000);
+
+        let filter = idle_filter(now, Duration::from_secs(600));
+
+        assert_eq!(
+            filter.get_document("last_activity").unwrap(),
+            &doc! { "$lte": mongodb::bson::DateTime::from_millis(1_699_999_400_000) }
+        );
+        assert!(filter.get("completed_at").is_some());
+        assert!(filter.get("aborted_at").is_some());
+        assert_eq!(filter.get_document("key").unwrap(), &doc! { "$ne": null });
+    }
+
+    #[tokio::test]
+    async fn abandoned_upload_is_aborted_on_open_remotes() {
+        let aborted = Arc::new(Mutex::new(vec![]));
+        let remotes = [
+            remote("a", Arc::clone(&aborted)),
+            remote("b", Arc::clone(&aborted)),
+        ];
+        let upload_ids = [
+            upload_id("a", PartUploadStatus::Open),
+            upload_id("b", PartUploadStatus::Cancelled),
+        ];
+
+        assert!(abort_everywhere(&remotes, "video.mp4", &upload_ids, 8).await);
+        assert_eq!(*aborted.lock().unwrap(), vec!["a-upload"]);
+    }
+
+    #[test]
+    fn upload_is_aborted_only_while_unchanged_since_the_claim() {
+        let last_activity = mongodb::bson::DateTime::from_millis(1_700_000_000_000);
+        let upload = IdleUpload {
+            id: ObjectId::new(),
+            upload_ids: vec![],
+            key: Some("video.mp4".to_owned()),
+            last_activity,
+        };
+
+        let filter = unchanged_filter(&upload);
+
+        assert_eq!(filter.get_object_id("_id").unwrap(), upload.id);
+        assert_eq!(
+            filter.get_datetime("last_activity").unwrap(),
+            &last_activity
+        );
+        assert!(filter.get("completed_at").is_some());
+        assert!(filter.get("aborted_at").is_some());
+    }
+
+    #[test]
+    fn uploads_claimed_by_another_sweep_are_left_alone() {
+        let now = mongodb::bson::DateTime::from_millis(1_700_000_000_000);
+
+        let filter = claim_filter(now, Duration::from_secs(600));
+
+        assert_eq!(
+            filter.get_array("$or").unwrap(),
+            &vec![
+                doc! { "claimed_until": null }.into(),
+                doc! { "claimed_until": { "$lte": now } }.into(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_sweep_releases_its_claim() {
+        // answers nothing, so the abort is still in flight when shutdown comes
+        let mut held = vec![];
+        let remotes = [S3Remote::answering("hanging", move |message| {
+            held.push(message)
+        })];
+        let upload = IdleUpload {
+            id: ObjectId::new(),
+            upload_ids: vec![upload_id("hanging", PartUploadStatus::Open)],
+            key: Some("video.mp4".to_owned()),
+            last_activity: mongodb::bson::DateTime::now(),
+        };
+        let (stop, mut shutdown) = watch::channel(false);
+
+        let swept =
+            tokio::spawn(async move { sweep_claimed(&remotes, &upload, 8, &mut shutdown).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        stop.send(true).unwrap();
+
+        assert_eq!(swept.await.unwrap(), Swept::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn abort_reports_on_each_remote() {
+        let aborted = Arc::new(Mutex::new(vec![]));
+        let remotes = [remote("up", Arc::clone(&aborted)), S3Remote::stub("down")];
+        let upload_ids = [
+            upload_id("up", PartUploadStatus::Open),
+            upload_id("down", PartUploadStatus::Open),
+            upload_id("gone", PartUploadStatus::Open),
+        ];
+
+        let mut results = abort_on_remotes(&remotes, "video.mp4", &upload_ids, 8).await;
+        results.sort_by(|a, b| a.remote.cmp(&b.remote));
+
+        let outcomes = results
+            .iter()
+            .map(|r| (r.remote.as_str(), r.outcome))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                ("down", AbortOutcome::Failed),
+                ("gone", AbortOutcome::Unconfigured),
+                ("up", AbortOutcome::Aborted),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn abort_is_retried_while_a_remote_is_down() {
+        let remotes = [S3Remote::stub("down")];
+        let upload_ids = [upload_id("down", PartUploadStatus::Open)];
+
+        assert!(!abort_everywhere(&remotes, "video.mp4", &upload_ids, 8).await);
+    }
+}
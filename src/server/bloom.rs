@@ -0,0 +1,214 @@
+use std::f64::consts::LN_2;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc;
+use tracing::{info, instrument, warn};
+
+use crate::config::s3_target::KeyFilterConfig;
+
+use super::remote::{list_all_objects, RemoteMessage, S3Remote};
+
+/// Approximate set of the keys a remote holds.
+///
+/// Keys are added whenever a write, copy or repair through this proxy lands on the remote, and the
+/// whole filter is rebuilt from a listing at startup. Until that rebuild finishes the filter
+/// excludes nothing. Writes that bypass this replica, e.g. through another replica or straight to
+/// the remote, are missed, so the filter is only advisory: it reorders the remotes a read goes to
+/// but never leaves one out.
+pub struct KeyFilter {
+    bloom: RwLock<BloomFilter>,
+    ready: AtomicBool,
+}
+
+impl KeyFilter {
+    pub fn new(config: &KeyFilterConfig) -> Self {
+        Self {
+            bloom: RwLock::new(BloomFilter::new(
+                config.expected_keys,
+                config.false_positive_rate,
+            )),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    pub fn insert(&self, key: &str) {
+        self.bloom.write().unwrap().insert(key);
+    }
+
+    /// Whether the remote definitely does not hold `key`.
+    pub fn excludes(&self, key: &str) -> bool {
+        self.ready.load(Ordering::Acquire) && !self.bloom.read().unwrap().contains(key)
+    }
+}
+
+impl fmt::Debug for KeyFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyFilter")
+            .field("ready", &self.ready.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let expected_keys = expected_keys.max(1) as f64;
+        let bits = (-expected_keys * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as usize;
+        let bits = bits.max(64);
+        let hashes = ((bits as f64 / expected_keys) * LN_2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+        }
+    }
+
+    fn indexes(&self, key: &str) -> impl Iterator<Item = usize> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1));
+        let len = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    fn insert(&mut self, key: &str) {
+        for i in self.indexes(key).collect::<Vec<_>>() {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.indexes(key)
+            .all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+}
+
+/// Moves remotes whose filter excludes `key` behind the others of their `read_request` tier,
+/// keeping the order otherwise. A remote only used for reads once the readable ones fail is never
+/// moved ahead of a readable one.
+pub(crate) fn order_by_key_filter<'a>(
+    remotes: impl IntoIterator<Item = &'a S3Remote>,
+    key: Option<&str>,
+) -> Vec<&'a S3Remote> {
+    let mut remotes: Vec<_> = remotes.into_iter().collect();
+    if let Some(key) = key {
+        remotes.sort_by_key(|r| {
+            (
+                !r.read_request,
+                r.key_filter.as_ref().is_some_and(|f| f.excludes(key)),
+            )
+        });
+    }
+    remotes
+}
+
+/// Fills `filter` with every key the remote currently lists, then marks it ready.
+#[instrument(name = "key_filter/rebuild", skip(tx, filter))]
+pub(crate) async fn rebuild(
+    remote: String,
+    tx: mpsc::Sender<RemoteMessage>,
+    filter: Arc<KeyFilter>,
+) {
+    let listed = list_all_objects(&tx, |object| {
+        if let Some(key) = &object.key {
+            filter.insert(key);
+        }
+    })
+    .await;
+    let Some(count) = listed else {
+        warn!("listing failed. key filter stays disabled");
+        return;
+    };
+
+    filter.ready.store(true, Ordering::Release);
+    info!("key filter ready ({} keys)", count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn remote(name: &str, filter: Option<Arc<KeyFilter>>) -> S3Remote {
+        S3Remote {
+            key_filter: filter,
+            ..S3Remote::stub(name)
+        }
+    }
+
+    fn ready_filter(keys: &[&str]) -> Arc<KeyFilter> {
+        let filter = KeyFilter::new(&KeyFilterConfig {
+            expected_keys: 100,
+            false_positive_rate: 0.01,
+        });
+        for key in keys {
+            filter.insert(key);
+        }
+        filter.ready.store(true, Ordering::Release);
+        Arc::new(filter)
+    }
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        let mut bloom = BloomFilter::new(1000, 0.01);
+        let keys = (0..1000).map(|i| format!("key-{i}")).collect::<Vec<_>>();
+        for key in keys.iter() {
+            bloom.insert(key);
+        }
+
+        assert!(keys.iter().all(|k| bloom.contains(k)));
+    }
+
+    #[test]
+    fn reads_skip_remote_whose_filter_excludes_key() {
+        let remotes = [
+            remote("primary", Some(ready_filter(&["other"]))),
+            remote("secondary", Some(ready_filter(&["wanted"]))),
+        ];
+
+        let ordered = order_by_key_filter(remotes.iter(), Some("wanted"));
+
+        assert_eq!(
+            ordered.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["secondary", "primary"]
+        );
+    }
+
+    #[test]
+    fn reads_keep_readable_remotes_ahead_of_the_others() {
+        let remotes = [
+            remote("primary", Some(ready_filter(&["other"]))),
+            S3Remote {
+                read_request: false,
+                ..remote("fallback", Some(ready_filter(&["wanted"])))
+            },
+        ];
+
+        let ordered = order_by_key_filter(remotes.iter(), Some("wanted"));
+
+        assert_eq!(
+            ordered.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["primary", "fallback"]
+        );
+    }
+
+    #[test]
+    fn filter_excludes_nothing_until_ready() {
+        let filter = KeyFilter::new(&KeyFilterConfig {
+            expected_keys: 100,
+            false_positive_rate: 0.01,
+        });
+
+        assert!(!filter.excludes("anything"));
+    }
+}
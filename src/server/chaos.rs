@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::s3_target::FaultInjection;
+
+use super::remote::RemoteMessage;
+
+/// Decides which requests fail so that, over any run, the share of failures matches the rate.
+#[derive(Debug)]
+struct FailureSchedule {
+    rate: f64,
+    owed: f64,
+}
+
+impl FailureSchedule {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            owed: 0.0,
+        }
+    }
+
+    fn next_fails(&mut self) -> bool {
+        self.owed += self.rate;
+        if self.owed >= 1.0 {
+            self.owed -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Puts a relay in front of a remote's channel that delays each request by the injected latency,
+/// and drops the injected share of them, so that callers see the remote as down. Returns `tx`
+/// as is when no fault is configured.
+pub fn inject(
+    name: &str,
+    tx: mpsc::Sender<RemoteMessage>,
+    faults: FaultInjection,
+) -> mpsc::Sender<RemoteMessage> {
+    if faults == FaultInjection::default() {
+        return tx;
+    }
+    warn!("remote({:?}) has injected faults: {:?}", name, faults);
+
+    let latency = faults.inject_latency_ms.map(Duration::from_millis);
+    let mut schedule = FailureSchedule::new(faults.inject_error_rate.unwrap_or(0.0));
+    let name = name.to_owned();
+    let (relay, mut rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let shutdown = matches!(message, RemoteMessage::Shutdown);
+            if !shutdown && schedule.next_fails() {
+                info!("(injected) remote({:?}) dropped a request", name);
+                continue;
+            }
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if let (Some(latency), false) = (latency, shutdown) {
+                    tokio::time::sleep(latency).await;
+                }
+                let _ = tx.send(message).await;
+            });
+        }
+    });
+    relay
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::operation::head_object::{HeadObjectInput, HeadObjectOutput};
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::server::read_order;
+    use crate::server::remote::S3Remote;
+    use crate::server::retry::read_with_quick_retry;
+
+    /// A remote answering every HEAD with its own name as the ETag, behind the injected faults.
+    fn remote(name: &str, priority: u32, faults: FaultInjection) -> S3Remote {
+        let e_tag = name.to_owned();
+        let remote = S3Remote::answering(name, move |message| {
+            if let RemoteMessage::HeadObject { reply, .. } = message {
+                let output = HeadObjectOutput::builder().e_tag(&e_tag).build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+        });
+        S3Remote {
+            priority,
+            tx: inject(name, remote.tx.clone(), faults),
+            ..remote
+        }
+    }
+
+    fn failing(rate: f64) -> FaultInjection {
+        FaultInjection {
+            inject_error_rate: Some(rate),
+            ..Default::default()
+        }
+    }
+
+    /// Reads from the remotes in read order, like `GetObject` does, and returns who answered.
+    async fn read(remotes: &[S3Remote], retries: usize) -> Option<String> {
+        let input = HeadObjectInput::builder().key("key").build().unwrap();
+        for remote in read_order(remotes) {
+            let output =
+                read_with_quick_retry(remote, retries, |reply| RemoteMessage::HeadObject {
+                    input: input.clone(),
+                    reply,
+                })
+                .await;
+            if let Some(Ok(output)) = output {
+                return output.e_tag;
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn failures_are_spread_evenly() {
+        let mut schedule = FailureSchedule::new(0.25);
+
+        let failed = (0..8).map(|_| schedule.next_fails()).collect::<Vec<_>>();
+
+        assert_eq!(
+            failed,
+            [false, false, false, true, false, false, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_remote_fails_over() {
+        let remotes = [
+            remote("preferred", 10, failing(1.0)),
+            remote("backup", 1, FaultInjection::default()),
+        ];
+
+        assert_eq!(read(&remotes, 2).await.as_deref(), Some("backup"));
+    }
+
+    #[tokio::test]
+    async fn quick_retry_rides_out_intermittent_failures() {
+        let remotes = [
+            remote("preferred", 10, failing(0.5)),
+            remote("backup", 1, FaultInjection::default()),
+        ];
+
+        for _ in 0..4 {
+            assert_eq!(read(&remotes, 1).await.as_deref(), Some("preferred"));
+        }
+    }
+
+    #[tokio::test]
+    async fn latency_is_added_to_every_request() {
+        let remotes = [remote(
+            "slow",
+            1,
+            FaultInjection {
+                inject_latency_ms: Some(50),
+                ..Default::default()
+            },
+        )];
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(read(&remotes, 0).await.as_deref(), Some("slow"));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,172 @@
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_sdk_s3::operation::put_object::PutObjectInput;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use s3s::{s3_error, S3Result};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use super::remote::S3Remote;
+
+/// Rejects a write whose checksum algorithm is not supported by every remote it fans out to,
+/// so that it fails up front instead of landing only on some of them.
+pub(super) fn check_checksum_algorithm(
+    remotes: &[S3Remote],
+    algorithm: Option<&str>,
+) -> S3Result<()> {
+    let Some(algorithm) = algorithm else {
+        return Ok(());
+    };
+
+    for remote in remotes {
+        let supported = remote.supported_checksums.as_ref().map_or(true, |s| {
+            s.iter().any(|a| a.eq_ignore_ascii_case(algorithm))
+        });
+        if !supported {
+            info!(
+                "(intercepted) checksum algorithm {:?} unsupported by remote({:?})",
+                algorithm, remote.name
+            );
+            return Err(s3_error!(
+                InvalidRequest,
+                "checksum algorithm {} is not supported",
+                algorithm
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// A checksum computed over a body as it streams through.
+pub(crate) enum RunningChecksum {
+    Md5(Md5),
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl RunningChecksum {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Crc32(hasher) => hasher.update(data),
+            Self::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// The checksum in the base64 form of the `x-amz-checksum-*` and `Content-MD5` headers.
+    pub fn finalize(self) -> String {
+        match self {
+            Self::Md5(hasher) => STANDARD.encode(hasher.finalize()),
+            Self::Crc32(hasher) => STANDARD.encode(hasher.finalize().to_be_bytes()),
+            Self::Crc32c(crc) => STANDARD.encode(crc.to_be_bytes()),
+            Self::Sha1(hasher) => STANDARD.encode(hasher.finalize()),
+            Self::Sha256(hasher) => STANDARD.encode(hasher.finalize()),
+        }
+    }
+}
+
+/// The checksum of the whole object a remote sent along with a GET, and the hasher to check the
+/// body against it. Ranged responses and composite checksums of multipart objects cannot be
+/// checked from the body alone and are passed over.
+pub(crate) fn advertised_checksum(output: &GetObjectOutput) -> Option<(RunningChecksum, String)> {
+    if output.content_range.is_some() {
+        return None;
+    }
+    let (checksum, expected) = if let Some(expected) = &output.checksum_crc32 {
+        (RunningChecksum::Crc32(crc32fast::Hasher::new()), expected)
+    } else if let Some(expected) = &output.checksum_crc32_c {
+        (RunningChecksum::Crc32c(0), expected)
+    } else if let Some(expected) = &output.checksum_sha1 {
+        (RunningChecksum::Sha1(Sha1::new()), expected)
+    } else if let Some(expected) = &output.checksum_sha256 {
+        (RunningChecksum::Sha256(Sha256::new()), expected)
+    } else {
+        return None;
+    };
+    (!expected.contains('-')).then(|| (checksum, expected.clone()))
+}
+
+/// The checksum a client sent along with a `PutObject`, and the hasher to check the body against
+/// it. An `x-amz-checksum-*` header is preferred over `Content-MD5`.
+pub(crate) fn requested_checksum(input: &PutObjectInput) -> Option<(RunningChecksum, String)> {
+    let (checksum, expected) = if let Some(expected) = &input.checksum_crc32 {
+        (RunningChecksum::Crc32(crc32fast::Hasher::new()), expected)
+    } else if let Some(expected) = &input.checksum_crc32_c {
+        (RunningChecksum::Crc32c(0), expected)
+    } else if let Some(expected) = &input.checksum_sha1 {
+        (RunningChecksum::Sha1(Sha1::new()), expected)
+    } else if let Some(expected) = &input.checksum_sha256 {
+        (RunningChecksum::Sha256(Sha256::new()), expected)
+    } else if let Some(expected) = &input.content_md5 {
+        (RunningChecksum::Md5(Md5::new()), expected)
+    } else {
+        return None;
+    };
+    Some((checksum, expected.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    fn remote(name: &str, supported: Option<&[&str]>) -> S3Remote {
+        S3Remote {
+            supported_checksums: supported.map(|s| s.iter().map(|a| a.to_string()).collect()),
+            ..S3Remote::stub(name)
+        }
+    }
+
+    #[test]
+    fn checksums_match_the_s3_header_encoding() {
+        let checksum = |output: GetObjectOutput| {
+            let (mut checksum, expected) = advertised_checksum(&output).unwrap();
+            checksum.update(b"hello");
+            (checksum.finalize(), expected)
+        };
+
+        let (actual, _) = checksum(GetObjectOutput::builder().checksum_crc32("x").build());
+        assert_eq!(actual, "NhCmhg==");
+        let (actual, _) = checksum(GetObjectOutput::builder().checksum_sha256("x").build());
+        assert_eq!(actual, "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=");
+
+        let (mut md5, expected) = requested_checksum(
+            &PutObjectInput::builder()
+                .content_md5("XUFAKrxLKna5cZ2REBfFkg==")
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        md5.update(b"hello");
+        assert_eq!(md5.finalize(), expected);
+        assert!(advertised_checksum(
+            &GetObjectOutput::builder()
+                .checksum_crc32("NhCmhg==-2")
+                .build()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn unsupported_algorithm_is_rejected_up_front() {
+        let remotes = [
+            remote("aws", None),
+            remote("r2", Some(&["CRC32", "SHA256"])),
+        ];
+
+        assert!(check_checksum_algorithm(&remotes, None).is_ok());
+        assert!(check_checksum_algorithm(&remotes, Some("SHA256")).is_ok());
+        assert!(check_checksum_algorithm(&remotes, Some("crc32")).is_ok());
+
+        let err = check_checksum_algorithm(&remotes, Some("CRC32C")).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::InvalidRequest);
+    }
+}
@@ -2,13 +2,14 @@ use std::collections::HashMap;
 
 use aws_sdk_s3::operation::put_object::PutObjectInput;
 use aws_sdk_s3::operation::upload_part::UploadPartInput;
+use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::{
     ChecksumAlgorithm, ObjectCannedAcl, ObjectLockLegalHoldStatus, ObjectLockMode, RequestPayer,
     ServerSideEncryption, StorageClass,
 };
 use aws_smithy_types::DateTime;
 
-use super::stream::{ByteStreamMultiplier, FirstByteSignal};
+use super::stream::{BranchCompletion, ByteStreamMultiplier, FirstByteSignal};
 
 pub struct UploadPartInputMultiplier {
     body: ByteStreamMultiplier,
@@ -64,6 +65,7 @@ pub struct PutObjectInputMultiplier {
     bucket_key_enabled: Option<bool>,
     request_payer: Option<RequestPayer>,
     tagging: Option<String>,
+    if_none_match: Option<String>,
     object_lock_mode: Option<ObjectLockMode>,
     object_lock_retain_until_date: Option<DateTime>,
     object_lock_legal_hold_status: Option<ObjectLockLegalHoldStatus>,
@@ -95,31 +97,37 @@ impl UploadPartInputMultiplier {
         (multiplier, signal)
     }
 
-    pub async fn input(&self) -> Option<UploadPartInput> {
-        let body = self.body.subscribe_stream(self.part_number).await?;
-
-        Some(
-            UploadPartInput::builder()
-                .body(body)
-                .set_bucket(self.bucket.clone())
-                .set_content_length(self.content_length.clone())
-                .set_content_md5(self.content_md5.clone())
-                .set_checksum_algorithm(self.checksum_algorithm.clone())
-                .set_checksum_crc32(self.checksum_crc32.clone())
-                .set_checksum_crc32_c(self.checksum_crc32_c.clone())
-                .set_checksum_sha1(self.checksum_sha1.clone())
-                .set_checksum_sha256(self.checksum_sha256.clone())
-                .set_key(self.key.clone())
-                .set_part_number(self.part_number.clone())
-                .set_upload_id(self.upload_id.clone())
-                .set_sse_customer_algorithm(self.sse_customer_algorithm.clone())
-                .set_sse_customer_key(self.sse_customer_key.clone())
-                .set_sse_customer_key_md5(self.sse_customer_key_md5.clone())
-                .set_request_payer(self.request_payer.clone())
-                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
-                .build()
-                .unwrap(),
-        )
+    /// The input for `remote`, along with whether the remote reads the whole part.
+    pub async fn input(&self, remote: &str) -> Option<(UploadPartInput, BranchCompletion)> {
+        let (body, completion) = self
+            .body
+            .subscribe_tracked_stream(remote, self.part_number)
+            .await?;
+        Some((self.with_body(body), completion))
+    }
+
+    /// Builds the input with a body of the caller's choosing, e.g. to resend a buffered part.
+    pub fn with_body(&self, body: ByteStream) -> UploadPartInput {
+        UploadPartInput::builder()
+            .body(body)
+            .set_bucket(self.bucket.clone())
+            .set_content_length(self.content_length.clone())
+            .set_content_md5(self.content_md5.clone())
+            .set_checksum_algorithm(self.checksum_algorithm.clone())
+            .set_checksum_crc32(self.checksum_crc32.clone())
+            .set_checksum_crc32_c(self.checksum_crc32_c.clone())
+            .set_checksum_sha1(self.checksum_sha1.clone())
+            .set_checksum_sha256(self.checksum_sha256.clone())
+            .set_key(self.key.clone())
+            .set_part_number(self.part_number.clone())
+            .set_upload_id(self.upload_id.clone())
+            .set_sse_customer_algorithm(self.sse_customer_algorithm.clone())
+            .set_sse_customer_key(self.sse_customer_key.clone())
+            .set_sse_customer_key_md5(self.sse_customer_key_md5.clone())
+            .set_request_payer(self.request_payer.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .build()
+            .unwrap()
     }
 
     pub fn close(&mut self) {
@@ -164,6 +172,7 @@ impl PutObjectInputMultiplier {
             bucket_key_enabled: input.bucket_key_enabled,
             request_payer: input.request_payer,
             tagging: input.tagging,
+            if_none_match: input.if_none_match,
             object_lock_mode: input.object_lock_mode,
             object_lock_retain_until_date: input.object_lock_retain_until_date,
             object_lock_legal_hold_status: input.object_lock_legal_hold_status,
@@ -172,54 +181,147 @@ impl PutObjectInputMultiplier {
         (multiplier, signal)
     }
 
-    pub async fn input(&self) -> Option<PutObjectInput> {
-        let body = self.body.subscribe_stream(None).await?;
-
-        Some(
-            PutObjectInput::builder()
-                .set_acl(self.acl.clone())
-                .body(body)
-                .set_bucket(self.bucket.clone())
-                .set_cache_control(self.cache_control.clone())
-                .set_content_disposition(self.content_disposition.clone())
-                .set_content_encoding(self.content_encoding.clone())
-                .set_content_language(self.content_language.clone())
-                .set_content_length(self.content_length)
-                .set_content_md5(self.content_md5.clone())
-                .set_content_type(self.content_type.clone())
-                .set_checksum_algorithm(self.checksum_algorithm.clone())
-                .set_checksum_crc32(self.checksum_crc32.clone())
-                .set_checksum_crc32_c(self.checksum_crc32_c.clone())
-                .set_checksum_sha1(self.checksum_sha1.clone())
-                .set_checksum_sha256(self.checksum_sha256.clone())
-                .set_expires(self.expires)
-                .set_grant_full_control(self.grant_full_control.clone())
-                .set_grant_read(self.grant_read.clone())
-                .set_grant_read_acp(self.grant_read_acp.clone())
-                .set_grant_write_acp(self.grant_write_acp.clone())
-                .set_key(self.key.clone())
-                .set_metadata(self.metadata.clone())
-                .set_server_side_encryption(self.server_side_encryption.clone())
-                .set_storage_class(self.storage_class.clone())
-                .set_website_redirect_location(self.website_redirect_location.clone())
-                .set_sse_customer_algorithm(self.sse_customer_algorithm.clone())
-                .set_sse_customer_key(self.sse_customer_key.clone())
-                .set_sse_customer_key_md5(self.sse_customer_key_md5.clone())
-                .set_ssekms_key_id(self.ssekms_key_id.clone())
-                .set_ssekms_encryption_context(self.ssekms_encryption_context.clone())
-                .set_bucket_key_enabled(self.bucket_key_enabled)
-                .set_request_payer(self.request_payer.clone())
-                .set_tagging(self.tagging.clone())
-                .set_object_lock_mode(self.object_lock_mode.clone())
-                .set_object_lock_retain_until_date(self.object_lock_retain_until_date)
-                .set_object_lock_legal_hold_status(self.object_lock_legal_hold_status.clone())
-                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
-                .build()
-                .unwrap(),
-        )
+    pub async fn input(&self, remote: &str) -> Option<PutObjectInput> {
+        let body = self.body.subscribe_stream(remote, None).await?;
+        Some(self.with_body(body))
+    }
+
+    /// Builds the input with a body of the caller's choosing, e.g. one read from a spooled file.
+    pub fn with_body(&self, body: ByteStream) -> PutObjectInput {
+        PutObjectInput::builder()
+            .set_acl(self.acl.clone())
+            .body(body)
+            .set_bucket(self.bucket.clone())
+            .set_cache_control(self.cache_control.clone())
+            .set_content_disposition(self.content_disposition.clone())
+            .set_content_encoding(self.content_encoding.clone())
+            .set_content_language(self.content_language.clone())
+            .set_content_length(self.content_length)
+            .set_content_md5(self.content_md5.clone())
+            .set_content_type(self.content_type.clone())
+            .set_checksum_algorithm(self.checksum_algorithm.clone())
+            .set_checksum_crc32(self.checksum_crc32.clone())
+            .set_checksum_crc32_c(self.checksum_crc32_c.clone())
+            .set_checksum_sha1(self.checksum_sha1.clone())
+            .set_checksum_sha256(self.checksum_sha256.clone())
+            .set_expires(self.expires)
+            .set_grant_full_control(self.grant_full_control.clone())
+            .set_grant_read(self.grant_read.clone())
+            .set_grant_read_acp(self.grant_read_acp.clone())
+            .set_grant_write_acp(self.grant_write_acp.clone())
+            .set_key(self.key.clone())
+            .set_metadata(self.metadata.clone())
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_website_redirect_location(self.website_redirect_location.clone())
+            .set_sse_customer_algorithm(self.sse_customer_algorithm.clone())
+            .set_sse_customer_key(self.sse_customer_key.clone())
+            .set_sse_customer_key_md5(self.sse_customer_key_md5.clone())
+            .set_ssekms_key_id(self.ssekms_key_id.clone())
+            .set_ssekms_encryption_context(self.ssekms_encryption_context.clone())
+            .set_bucket_key_enabled(self.bucket_key_enabled)
+            .set_request_payer(self.request_payer.clone())
+            .set_tagging(self.tagging.clone())
+            .set_if_none_match(self.if_none_match.clone())
+            .set_object_lock_mode(self.object_lock_mode.clone())
+            .set_object_lock_retain_until_date(self.object_lock_retain_until_date)
+            .set_object_lock_legal_hold_status(self.object_lock_legal_hold_status.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .build()
+            .unwrap()
     }
 
     pub fn close(&mut self) {
         self.body.close();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn encryption_headers_reach_every_remote() {
+        let input = PutObjectInput::builder()
+            .key("secret.txt")
+            .body(ByteStream::from_static(b"secret"))
+            .server_side_encryption(ServerSideEncryption::AwsKms)
+            .ssekms_key_id("key-id")
+            .bucket_key_enabled(true)
+            .build()
+            .unwrap();
+        let (mut multiplier, _signal) = PutObjectInputMultiplier::from_input(input);
+
+        let inputs = [
+            multiplier.input("a").await.unwrap(),
+            multiplier.input("b").await.unwrap(),
+        ];
+        multiplier.close();
+
+        for input in inputs {
+            assert_eq!(
+                input.server_side_encryption,
+                Some(ServerSideEncryption::AwsKms)
+            );
+            assert_eq!(input.ssekms_key_id.as_deref(), Some("key-id"));
+            assert_eq!(input.bucket_key_enabled, Some(true));
+        }
+    }
+
+    #[tokio::test]
+    async fn response_headers_reach_every_remote() {
+        let input = PutObjectInput::builder()
+            .key("index.html")
+            .body(ByteStream::from_static(b"<html></html>"))
+            .cache_control("max-age=3600")
+            .content_disposition("inline")
+            .content_encoding("gzip")
+            .content_language("en")
+            .expires(DateTime::from_secs(1_700_000_000))
+            .build()
+            .unwrap();
+        let (mut multiplier, _signal) = PutObjectInputMultiplier::from_input(input);
+
+        let inputs = [
+            multiplier.input("a").await.unwrap(),
+            multiplier.input("b").await.unwrap(),
+        ];
+        multiplier.close();
+
+        for input in inputs {
+            assert_eq!(input.cache_control.as_deref(), Some("max-age=3600"));
+            assert_eq!(input.content_disposition.as_deref(), Some("inline"));
+            assert_eq!(input.content_encoding.as_deref(), Some("gzip"));
+            assert_eq!(input.content_language.as_deref(), Some("en"));
+            assert_eq!(input.expires, Some(DateTime::from_secs(1_700_000_000)));
+        }
+    }
+
+    #[tokio::test]
+    async fn customer_keys_reach_every_part_upload() {
+        let input = UploadPartInput::builder()
+            .key("secret.txt")
+            .upload_id("upload")
+            .part_number(1)
+            .body(ByteStream::from_static(b"secret"))
+            .sse_customer_algorithm("AES256")
+            .sse_customer_key("a2V5")
+            .sse_customer_key_md5("bWQ1")
+            .build()
+            .unwrap();
+        let (mut multiplier, _signal) = UploadPartInputMultiplier::from_input(input);
+
+        let inputs = [
+            multiplier.input("a").await.unwrap().0,
+            multiplier.input("b").await.unwrap().0,
+        ];
+        multiplier.close();
+
+        for input in inputs {
+            assert_eq!(input.sse_customer_algorithm.as_deref(), Some("AES256"));
+            assert_eq!(input.sse_customer_key.as_deref(), Some("a2V5"));
+            assert_eq!(input.sse_customer_key_md5.as_deref(), Some("bWQ1"));
+        }
+    }
+}
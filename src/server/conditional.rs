@@ -0,0 +1,297 @@
+use aws_sdk_s3::operation::delete_object::DeleteObjectInput;
+use aws_sdk_s3::operation::head_object::HeadObjectInput;
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use aws_smithy_runtime_api::client::result::ServiceError;
+use futures::{StreamExt, TryStreamExt};
+use http::HeaderMap;
+use s3s::{s3_error, S3Result};
+use tracing::{error, info, warn};
+
+use super::outage::FailureResponses;
+use super::remote::S3Remote;
+use super::{convert_sdk_err, remote, S3Reproxy};
+
+impl S3Reproxy {
+    /// Heads `key` on every remote and returns the ETag each one currently holds
+    /// (`None` if the key is absent there). Unreachable remotes are left out.
+    pub(super) async fn current_etags(&self, key: &str) -> S3Result<Vec<(String, Option<String>)>> {
+        let remotes = self.remotes.load();
+        let input = HeadObjectInput::builder()
+            .key(key)
+            .build()
+            .map_err(|e| s3_error!(InternalError, "{}", e))?;
+
+        let etags = futures::stream::iter(remotes.iter())
+            .map(|remote| {
+                let input = input.clone();
+                async move {
+                    let result = remote
+                        .request(|reply| remote::RemoteMessage::HeadObject { input, reply })
+                        .await;
+                    match result {
+                        Some(Ok(output)) => Ok(Some((remote.name.clone(), output.e_tag))),
+                        Some(Err(e)) if e.err().is_not_found() => {
+                            Ok(Some((remote.name.clone(), None)))
+                        }
+                        Some(Err(e)) => Err(convert_sdk_err(e)),
+                        None => {
+                            warn!("remote({:?}) request failed. skipping", remote.name);
+                            Ok(None)
+                        }
+                    }
+                }
+            })
+            .boxed()
+            .buffer_unordered(self.fanout_concurrency)
+            .try_filter_map(|e| async { Ok(e) })
+            .try_collect()
+            .await?;
+
+        Ok(etags)
+    }
+
+    /// Deletes `key` from the remotes in `stored`, which a write-if-absent reached while another
+    /// remote refused it, each with the ETag it returned. They did not hold the object before, so
+    /// this restores their state. A remote that was written again since keeps what it holds.
+    pub(super) async fn undo_write_if_absent(
+        &self,
+        key: &str,
+        stored: &[(&S3Remote, Option<String>)],
+    ) {
+        let Ok(input) = DeleteObjectInput::builder().key(key).build() else {
+            return;
+        };
+        for (remote, e_tag) in stored {
+            if !still_holds(remote, key, e_tag.as_deref()).await {
+                warn!(
+                    "remote({:?}) may no longer hold the {:?} written to it. leaving it",
+                    remote.name, key
+                );
+                continue;
+            }
+            let input = input.clone();
+            let result = remote
+                .request(|reply| remote::RemoteMessage::DeleteObject { input, reply })
+                .await;
+            match result {
+                Some(Ok(_)) => info!("removed {:?} from remote({:?})", key, remote.name),
+                _ => error!(
+                    "failed to remove {:?} from remote({:?}), which now holds it alone",
+                    key, remote.name
+                ),
+            }
+        }
+    }
+}
+
+/// Whether `remote` still holds the object with `e_tag` at `key`. An object without an ETag, or
+/// a remote that cannot tell, counts as replaced.
+async fn still_holds(remote: &S3Remote, key: &str, e_tag: Option<&str>) -> bool {
+    let Some(Ok(input)) =
+        e_tag.map(|e_tag| HeadObjectInput::builder().key(key).if_match(e_tag).build())
+    else {
+        return false;
+    };
+    matches!(
+        remote
+            .request(|reply| remote::RemoteMessage::HeadObject { input, reply })
+            .await,
+        Some(Ok(_))
+    )
+}
+
+/// The `If-None-Match` of a `PutObject`, which S3 only accepts as `*`: write the object only if
+/// the key is absent.
+pub(super) fn write_if_absent(headers: &HeaderMap) -> S3Result<Option<String>> {
+    let Some(value) = headers.get(http::header::IF_NONE_MATCH) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok("*") => Ok(Some("*".to_owned())),
+        _ => Err(s3_error!(
+            NotImplemented,
+            "A header you provided implies functionality that is not implemented"
+        )),
+    }
+}
+
+/// Remotes that refused a conditional write because its precondition failed there, e.g. a
+/// write-if-absent to a remote that already holds the object. They are never retried.
+pub(super) fn failed_preconditions<T, E>(
+    results: &[(String, Result<T, ServiceError<E, HttpResponse>>)],
+) -> Vec<&str> {
+    results
+        .iter()
+        .filter(|(_, result)| {
+            result
+                .as_ref()
+                .is_err_and(|e| e.raw().status().as_u16() == 412)
+        })
+        .map(|(remote, _)| remote.as_str())
+        .collect()
+}
+
+/// Evaluates `If-Match` against the ETags the remotes currently hold.
+/// The write may only proceed when every remote holds the object and all of them match;
+/// remotes that disagree with each other fail the precondition even if one of them matches.
+pub(super) fn check_if_match(
+    expected: &str,
+    current: &[(String, Option<String>)],
+    failure_responses: FailureResponses,
+) -> S3Result<()> {
+    if current.is_empty() {
+        return Err(failure_responses.no_remote_error());
+    }
+
+    if current.iter().all(|(_, etag)| etag.is_none()) {
+        info!("(intercepted) If-Match on a missing key");
+        return Err(s3_error!(NoSuchKey));
+    }
+
+    for (remote, etag) in current {
+        let matches = etag
+            .as_deref()
+            .is_some_and(|etag| expected == "*" || etag_eq(etag, expected));
+        if !matches {
+            info!(
+                "(intercepted) If-Match failed on remote({:?}): {:?}",
+                remote, etag
+            );
+            return Err(s3_error!(PreconditionFailed));
+        }
+    }
+
+    Ok(())
+}
+
+fn etag_eq(a: &str, b: &str) -> bool {
+    a.trim_matches('"') == b.trim_matches('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use aws_smithy_types::error::ErrorMetadata;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    fn etags(values: &[Option<&str>]) -> Vec<(String, Option<String>)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("remote-{i}"), v.map(str::to_owned)))
+            .collect()
+    }
+
+    #[test]
+    fn if_match_passes_when_all_remotes_match() {
+        let current = etags(&[Some("\"abc\""), Some("\"abc\"")]);
+
+        assert!(check_if_match("\"abc\"", &current, FailureResponses::default()).is_ok());
+        assert!(check_if_match("abc", &current, FailureResponses::default()).is_ok());
+    }
+
+    #[test]
+    fn if_match_fails_on_mismatch() {
+        let current = etags(&[Some("\"def\""), Some("\"def\"")]);
+
+        let err = check_if_match("\"abc\"", &current, FailureResponses::default()).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::PreconditionFailed);
+    }
+
+    #[test]
+    fn if_match_fails_when_remotes_diverge() {
+        let diverged = etags(&[Some("\"abc\""), Some("\"def\"")]);
+        let partially_missing = etags(&[Some("\"abc\""), None]);
+
+        for current in [diverged, partially_missing] {
+            let err = check_if_match("\"abc\"", &current, FailureResponses::default()).unwrap_err();
+            assert_eq!(err.code(), &S3ErrorCode::PreconditionFailed);
+        }
+    }
+
+    #[test]
+    fn if_match_on_missing_key() {
+        let err = check_if_match(
+            "\"abc\"",
+            &etags(&[None, None]),
+            FailureResponses::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::NoSuchKey);
+    }
+
+    #[test]
+    fn write_if_absent_only_accepts_a_wildcard() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(http::header::IF_NONE_MATCH, value.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(write_if_absent(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(
+            write_if_absent(&headers("*")).unwrap().as_deref(),
+            Some("*")
+        );
+        let err = write_if_absent(&headers("\"abc\"")).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::NotImplemented);
+    }
+
+    #[test]
+    fn existing_objects_fail_the_write_if_absent() {
+        use aws_sdk_s3::operation::put_object::{PutObjectError, PutObjectOutput};
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        let reply = |remote: &str, status: Option<u16>| {
+            let result = match status {
+                None => Ok(PutObjectOutput::builder().build()),
+                Some(status) => Err(ServiceError::builder()
+                    .source(PutObjectError::generic(ErrorMetadata::builder().build()))
+                    .raw(HttpResponse::new(
+                        StatusCode::try_from(status).unwrap(),
+                        SdkBody::empty(),
+                    ))
+                    .build()),
+            };
+            (remote.to_owned(), result)
+        };
+        let results = [
+            reply("absent", None),
+            reply("present", Some(412)),
+            reply("failing", Some(500)),
+        ];
+
+        assert_eq!(failed_preconditions(&results), ["present"]);
+        assert!(failed_preconditions(&results[..1]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn object_written_again_is_not_undone() {
+        // holds "\"theirs\"", written by another client after this request's write
+        let remote = S3Remote::answering("a", |message| {
+            if let remote::RemoteMessage::HeadObject { input, reply } = message {
+                let result = match input.if_match.as_deref() {
+                    Some("\"theirs\"") => Ok(HeadObjectOutput::builder().build()),
+                    _ => Err(ServiceError::builder()
+                        .source(HeadObjectError::generic(ErrorMetadata::builder().build()))
+                        .raw(HttpResponse::new(
+                            StatusCode::try_from(412).unwrap(),
+                            SdkBody::empty(),
+                        ))
+                        .build()),
+                };
+                let _ = reply.send(Some(result));
+            }
+        });
+
+        assert!(!still_holds(&remote, "video.mp4", Some("\"ours\"")).await);
+        assert!(!still_holds(&remote, "video.mp4", None).await);
+        assert!(still_holds(&remote, "video.mp4", Some("\"theirs\"")).await);
+    }
+}
@@ -0,0 +1,237 @@
+use aws_sdk_s3::operation::copy_object::{CopyObjectError, CopyObjectInput, CopyObjectOutput};
+use aws_sdk_s3::operation::head_object::{HeadObjectInput, HeadObjectOutput};
+use aws_sdk_s3::types::MetadataDirective;
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use aws_smithy_runtime_api::client::result::ServiceError;
+use futures::StreamExt;
+use tracing::warn;
+
+use super::remote::{RemoteMessage, S3Remote};
+
+/// Whether the destination takes its metadata from the source. Each remote copies from its own
+/// replica of the source, so the results are only as consistent as those replicas.
+pub(super) fn copies_metadata(input: &CopyObjectInput) -> bool {
+    input
+        .metadata_directive
+        .as_ref()
+        .map_or(true, |d| *d == MetadataDirective::Copy)
+}
+
+/// Sends the copy to every remote and returns the replies of those that could be reached.
+pub(super) async fn copy_to_remotes(
+    remotes: &[S3Remote],
+    input: &CopyObjectInput,
+    concurrency: usize,
+) -> Vec<(
+    String,
+    Result<CopyObjectOutput, ServiceError<CopyObjectError, HttpResponse>>,
+)> {
+    futures::stream::iter(remotes.iter())
+        .map(|remote| async {
+            let Some(result) = remote
+                .request(|reply| RemoteMessage::CopyObject {
+                    input: input.clone(),
+                    reply,
+                })
+                .await
+            else {
+                warn!("remote({:?}) request failed. skipping", remote.name);
+                return None;
+            };
+            Some((remote.name.clone(), result))
+        })
+        .boxed()
+        .buffer_unordered(concurrency)
+        .filter_map(|e| async { e })
+        .collect()
+        .await
+}
+
+async fn head(remote: &S3Remote, key: &str) -> Option<HeadObjectOutput> {
+    let input = HeadObjectInput::builder().key(key).build().ok()?;
+    match remote
+        .request(|reply| RemoteMessage::HeadObject { input, reply })
+        .await?
+    {
+        Ok(output) => Some(output),
+        Err(e) => {
+            warn!("remote({:?}) could not be verified: {:?}", remote.name, e);
+            None
+        }
+    }
+}
+
+/// HEADs the copied `key` on each of `remotes` and returns the names of the remotes whose metadata
+/// differs from the first one that answered.
+pub(super) async fn diverging_copies(remotes: &[&S3Remote], key: &str) -> Vec<String> {
+    let heads = futures::future::join_all(
+        remotes
+            .iter()
+            .map(|remote| async move { Some((remote.name.clone(), head(remote, key).await?)) }),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    let Some((_, first)) = heads.first() else {
+        return vec![];
+    };
+    heads
+        .iter()
+        .filter(|(_, other)| {
+            other.metadata != first.metadata
+                || other.content_type != first.content_type
+                || other.content_length != first.content_length
+                || other.content_encoding != first.content_encoding
+                || other.content_disposition != first.content_disposition
+                || other.cache_control != first.cache_control
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use aws_smithy_types::error::ErrorMetadata;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    use super::*;
+    use crate::config::s3_target::WriteQuorum;
+    use crate::server::outage::FailureResponses;
+    use crate::server::output_remote_inconsistent;
+
+    type Metadata = HashMap<String, String>;
+
+    fn metadata(pairs: &[(&str, &str)]) -> Metadata {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// A remote holding `src` with `source` as its metadata, which copies objects like S3 does.
+    fn copying_remote(name: &str, source: Metadata) -> S3Remote {
+        remote_holding(name, HashMap::from([("src".to_owned(), source)]))
+    }
+
+    /// A remote holding `objects` by key with their metadata, which copies objects like S3 does.
+    fn remote_holding(name: &str, mut objects: HashMap<String, Metadata>) -> S3Remote {
+        S3Remote::answering(name, move |message| match message {
+            RemoteMessage::CopyObject { input, reply } => {
+                let copy_source = input.copy_source.clone().unwrap();
+                let (_, source_key) = copy_source.split_once('/').unwrap();
+                let Some(source) = objects.get(source_key) else {
+                    let error = ServiceError::builder()
+                        .source(CopyObjectError::generic(
+                            ErrorMetadata::builder().code("NoSuchKey").build(),
+                        ))
+                        .raw(HttpResponse::new(
+                            StatusCode::try_from(404).unwrap(),
+                            SdkBody::empty(),
+                        ))
+                        .build();
+                    let _ = reply.send(Some(Err(error)));
+                    return;
+                };
+                let metadata = if copies_metadata(&input) {
+                    source.clone()
+                } else {
+                    input.metadata.clone().unwrap_or_default()
+                };
+                objects.insert(input.key.unwrap(), metadata);
+                let _ = reply.send(Some(Ok(CopyObjectOutput::builder().build())));
+            }
+            RemoteMessage::HeadObject { input, reply } => {
+                let output = HeadObjectOutput::builder()
+                    .set_metadata(objects.get(input.key.as_deref().unwrap()).cloned())
+                    .build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+            _ => {}
+        })
+    }
+
+    fn copy_input() -> CopyObjectInput {
+        CopyObjectInput::builder()
+            .bucket("virtual")
+            .copy_source("virtual/src")
+            .key("dst")
+            .metadata_directive(MetadataDirective::Copy)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn copy_preserves_metadata_on_every_remote() {
+        let source = metadata(&[("owner", "alice"), ("revision", "3")]);
+        let remotes = ["a", "b", "c"].map(|name| copying_remote(name, source.clone()));
+
+        let results = copy_to_remotes(&remotes, &copy_input(), 4).await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        for remote in &remotes {
+            let copied = head(remote, "dst").await.unwrap();
+            assert_eq!(copied.metadata, Some(source.clone()));
+        }
+        let remotes = remotes.iter().collect::<Vec<_>>();
+        assert_eq!(
+            diverging_copies(&remotes, "dst").await,
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn diverging_source_metadata_is_flagged() {
+        let remotes = [
+            copying_remote("a", metadata(&[("revision", "3")])),
+            copying_remote("b", metadata(&[("revision", "2")])),
+            copying_remote("c", metadata(&[("revision", "3")])),
+        ];
+
+        copy_to_remotes(&remotes, &copy_input(), 4).await;
+
+        let remotes = remotes.iter().collect::<Vec<_>>();
+        assert_eq!(diverging_copies(&remotes, "dst").await, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn source_missing_on_some_remotes_fails_like_a_write() {
+        let source = metadata(&[("revision", "3")]);
+        let remotes = [
+            copying_remote("a", source.clone()),
+            copying_remote("b", source),
+            remote_holding("c", HashMap::new()),
+        ];
+
+        let results = copy_to_remotes(&remotes, &copy_input(), 4).await;
+
+        assert_eq!(results.iter().filter(|(_, r)| r.is_err()).count(), 1);
+        assert!(output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            3,
+            FailureResponses::default()
+        )
+        .is_ok());
+
+        let remotes = ["a", "b"].map(|name| remote_holding(name, HashMap::new()));
+        let results = copy_to_remotes(&remotes, &copy_input(), 4).await;
+        let error = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            2,
+            FailureResponses::default(),
+        )
+        .unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::NoSuchKey);
+    }
+}
@@ -0,0 +1,78 @@
+use http::HeaderMap;
+use s3s::{s3_error, S3Result};
+use tracing::{info, warn};
+
+use super::remote::S3Remote;
+use super::request_id::REMOTE_HEADER;
+use super::S3Reproxy;
+
+/// The remote named by the `x-reproxy-remote` header of a request, if any. Naming a remote that
+/// is not configured is an error rather than a silent fallback to the usual order.
+fn named_remote<'a>(
+    headers: &HeaderMap,
+    remotes: &'a [S3Remote],
+) -> S3Result<Option<&'a S3Remote>> {
+    let Some(name) = headers.get(REMOTE_HEADER) else {
+        return Ok(None);
+    };
+    let remote = name
+        .to_str()
+        .ok()
+        .and_then(|name| remotes.iter().find(|r| r.name == name));
+    match remote {
+        Some(remote) => {
+            info!("(debug) reading from remote({:?}) only", remote.name);
+            Ok(Some(remote))
+        }
+        None => {
+            warn!(
+                "(intercepted) unknown remote {:?} in {}",
+                name, REMOTE_HEADER
+            );
+            Err(s3_error!(
+                InvalidArgument,
+                "{} does not name a configured remote",
+                REMOTE_HEADER
+            ))
+        }
+    }
+}
+
+impl S3Reproxy {
+    /// The only remote a read goes to when the client names one in `x-reproxy-remote`, bypassing
+    /// the read order, health checks and buffers. The header is ignored unless `debug_headers` is
+    /// enabled.
+    pub(super) fn forced_remote<'a>(
+        &self,
+        headers: &HeaderMap,
+        remotes: &'a [S3Remote],
+    ) -> S3Result<Option<&'a S3Remote>> {
+        if !self.debug_headers {
+            return Ok(None);
+        }
+        named_remote(headers, remotes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    #[test]
+    fn reads_can_be_sent_to_a_named_remote() {
+        let remotes = ["a", "b"].map(S3Remote::stub);
+        let mut headers = HeaderMap::new();
+        assert!(named_remote(&headers, &remotes).unwrap().is_none());
+
+        headers.insert(REMOTE_HEADER, "b".parse().unwrap());
+        assert_eq!(named_remote(&headers, &remotes).unwrap().unwrap().name, "b");
+
+        headers.insert(REMOTE_HEADER, "c".parse().unwrap());
+        assert_eq!(
+            named_remote(&headers, &remotes).unwrap_err().code(),
+            &S3ErrorCode::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::operation::delete_object::{
+    DeleteObjectError, DeleteObjectInput, DeleteObjectOutput,
+};
+use aws_sdk_s3::operation::delete_objects::{
+    DeleteObjectsError, DeleteObjectsInput, DeleteObjectsOutput,
+};
+use aws_sdk_s3::types::Error as DeleteError;
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use aws_smithy_runtime_api::client::result::ServiceError;
+use futures::StreamExt;
+use itertools::Itertools;
+use tracing::{info, warn};
+
+use super::remote::{RemoteMessage, S3Remote};
+use super::reply_rank;
+
+type DeleteResult = Result<DeleteObjectOutput, ServiceError<DeleteObjectError, HttpResponse>>;
+
+type DeleteBatchResult =
+    Result<DeleteObjectsOutput, ServiceError<DeleteObjectsError, HttpResponse>>;
+
+/// Sends the delete to every remote and returns the replies of those that could be reached.
+/// A remote answering `NoSuchKey` is counted as having deleted the key, as S3 itself reports
+/// success for deleting a key that does not exist.
+pub(super) async fn delete_on_remotes(
+    remotes: &[S3Remote],
+    input: &DeleteObjectInput,
+    concurrency: usize,
+) -> Vec<(String, DeleteResult)> {
+    futures::stream::iter(remotes.iter())
+        .map(|remote| async {
+            let Some(result) = remote
+                .request(|reply| RemoteMessage::DeleteObject {
+                    input: input.clone(),
+                    reply,
+                })
+                .await
+            else {
+                warn!("remote({:?}) request failed. skipping", remote.name);
+                return None;
+            };
+            Some((remote.name.clone(), absent_as_deleted(&remote.name, result)))
+        })
+        .boxed()
+        .buffer_unordered(concurrency)
+        .filter_map(|e| async { e })
+        .collect()
+        .await
+}
+
+fn absent_as_deleted(remote: &str, result: DeleteResult) -> DeleteResult {
+    match result {
+        Err(e) if e.err().code() == Some("NoSuchKey") => {
+            info!(
+                "remote({:?}) does not have the key. treating as deleted",
+                remote
+            );
+            Ok(DeleteObjectOutput::builder().build())
+        }
+        result => result,
+    }
+}
+
+/// The keys of a `DeleteObjects` batch that some remote which answered did not delete, each with
+/// the error code of the highest-ranked such remote. A remote failing the whole batch failed every
+/// key of it. The client gets a generic message; which remote failed and how is only logged.
+pub(super) fn failed_deletions(
+    remotes: &[S3Remote],
+    input: &DeleteObjectsInput,
+    results: &[(String, DeleteBatchResult)],
+) -> Vec<DeleteError> {
+    let ranked = results
+        .iter()
+        .sorted_by_cached_key(|(remote, _)| reply_rank(remotes, remote))
+        .collect::<Vec<_>>();
+    let objects = input.delete().map(|d| d.objects()).unwrap_or_default();
+    objects
+        .iter()
+        .filter_map(|object| {
+            ranked.iter().find_map(|(remote, result)| {
+                let failure = match result {
+                    Ok(output) => output
+                        .errors()
+                        .iter()
+                        .find(|e| {
+                            e.key() == Some(object.key()) && e.version_id() == object.version_id()
+                        })
+                        .map(|e| (e.code(), e.message()))?,
+                    Err(e) => (e.err().code(), e.err().message()),
+                };
+                warn!(
+                    "remote({:?}) failed to delete {:?}: {:?} {:?}",
+                    remote,
+                    object.key(),
+                    failure.0,
+                    failure.1
+                );
+                Some(
+                    DeleteError::builder()
+                        .key(object.key())
+                        .set_version_id(object.version_id.clone())
+                        .code(failure.0.unwrap_or("InternalError"))
+                        .message("failed to delete the object")
+                        .build(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Reports `failures` as the errors of `output`, and no longer as deleted.
+pub(super) fn with_failed_deletions(
+    mut output: DeleteObjectsOutput,
+    failures: Vec<DeleteError>,
+) -> DeleteObjectsOutput {
+    if let Some(deleted) = &mut output.deleted {
+        deleted.retain(|d| {
+            !failures
+                .iter()
+                .any(|f| f.key() == d.key() && f.version_id() == d.version_id())
+        });
+    }
+    output.errors = (!failures.is_empty()).then_some(failures);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use aws_smithy_types::error::ErrorMetadata;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// A remote holding `keys`, which answers `NoSuchKey` for keys it does not have.
+    fn remote(name: &str, keys: Arc<Mutex<HashSet<String>>>) -> S3Remote {
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::DeleteObject { input, reply } = message {
+                let removed = keys.lock().unwrap().remove(input.key().unwrap());
+                let result = if removed {
+                    Ok(DeleteObjectOutput::builder().build())
+                } else {
+                    Err(ServiceError::builder()
+                        .source(DeleteObjectError::generic(
+                            ErrorMetadata::builder().code("NoSuchKey").build(),
+                        ))
+                        .raw(HttpResponse::new(
+                            StatusCode::try_from(404).unwrap(),
+                            SdkBody::empty(),
+                        ))
+                        .build())
+                };
+                let _ = reply.send(Some(result));
+            }
+        })
+    }
+
+    #[test]
+    fn keys_any_remote_kept_are_reported_as_errors() {
+        use aws_sdk_s3::types::{Delete, DeletedObject, ObjectIdentifier};
+
+        let remotes = [S3Remote::stub("a"), S3Remote::stub("b")];
+        let keys = ["1", "2", "3"];
+        let input = DeleteObjectsInput::builder()
+            .delete(
+                Delete::builder()
+                    .set_objects(Some(
+                        keys.iter()
+                            .map(|k| ObjectIdentifier::builder().key(*k).build().unwrap())
+                            .collect(),
+                    ))
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let deleted = |keys: &[&str]| {
+            keys.iter()
+                .map(|k| DeletedObject::builder().key(*k).build())
+                .collect::<Vec<_>>()
+        };
+        let slow_down = |key: &str| {
+            DeleteError::builder()
+                .key(key)
+                .code("SlowDown")
+                .message("Reduce your request rate")
+                .build()
+        };
+        let results = vec![
+            (
+                "b".to_owned(),
+                Ok(DeleteObjectsOutput::builder()
+                    .set_deleted(Some(deleted(&["1", "2"])))
+                    .errors(slow_down("3"))
+                    .build()),
+            ),
+            (
+                "a".to_owned(),
+                Ok(DeleteObjectsOutput::builder()
+                    .set_deleted(Some(deleted(&["1", "3"])))
+                    .errors(slow_down("2"))
+                    .build()),
+            ),
+        ];
+
+        let failures = failed_deletions(&remotes, &input, &results);
+        let output = with_failed_deletions(results[1].1.clone().unwrap(), failures);
+
+        assert_eq!(output.deleted(), deleted(&["1"]));
+        assert_eq!(
+            output
+                .errors()
+                .iter()
+                .map(|e| (e.key().unwrap(), e.code().unwrap(), e.message().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                ("2", "SlowDown", "failed to delete the object"),
+                ("3", "SlowDown", "failed to delete the object"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_a_partially_present_key_succeeds_everywhere() {
+        let stores = ["a", "b", "c"].map(|_| Arc::new(Mutex::new(HashSet::new())));
+        stores[0].lock().unwrap().insert("doc.txt".to_owned());
+        stores[2].lock().unwrap().insert("doc.txt".to_owned());
+        let remotes = [
+            remote("a", Arc::clone(&stores[0])),
+            remote("b", Arc::clone(&stores[1])),
+            remote("c", Arc::clone(&stores[2])),
+        ];
+        let input = DeleteObjectInput::builder().key("doc.txt").build().unwrap();
+
+        let results = delete_on_remotes(&remotes, &input, 4).await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(stores.iter().all(|s| s.lock().unwrap().is_empty()));
+
+        let again = delete_on_remotes(&remotes, &input, 4).await;
+        assert!(again.iter().all(|(_, r)| r.is_ok()));
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::operation::delete_object::DeleteObjectInput;
+use duration_string::DurationString;
+use futures::{StreamExt, TryStreamExt};
+use http::HeaderMap;
+use mongodb::bson::{doc, Document};
+use s3s::{s3_error, S3Result};
+use tokio::sync::watch;
+use tracing::{error, info, instrument, warn};
+
+use crate::config::s3_target::ObjectTtlConfig;
+use crate::db::{MongoDB, ObjectExpiration};
+use crate::metrics::RECORD_FAILURES;
+
+use super::reload::RemoteSet;
+use super::remote::{RemoteMessage, S3Remote};
+use super::S3Reproxy;
+
+/// Request header carrying the TTL of the object being written, e.g. `x-reproxy-ttl: 1h`.
+pub const TTL_HEADER: &str = "x-reproxy-ttl";
+
+/// Most expirations handled per sweep; the rest wait for the next one.
+const SWEEP_BATCH: i64 = 1000;
+
+/// How far a sweep pushes out the expiry of an object it is deleting. Another sweep leaves the
+/// object alone until then, and takes it over if this one died.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+/// The TTL a write asked for through [`TTL_HEADER`], or the configured default.
+pub(super) fn requested_ttl(
+    headers: &HeaderMap,
+    config: &ObjectTtlConfig,
+) -> S3Result<Option<Duration>> {
+    let Some(value) = headers.get(TTL_HEADER) else {
+        return Ok(config.default_ttl.map(|ttl| *ttl));
+    };
+    let ttl = value
+        .to_str()
+        .ok()
+        .and_then(|v| DurationString::from_string(v.to_owned()).ok())
+        .ok_or_else(|| s3_error!(InvalidArgument, "invalid {} header", TTL_HEADER))?;
+    Ok(Some(*ttl))
+}
+
+impl S3Reproxy {
+    /// Records when `key` expires, or forgets a previous expiry if the new object has no TTL.
+    /// The object is already written by then, so a failure is logged and counted rather than
+    /// failing the request.
+    pub(super) async fn record_expiry(&self, key: &str, ttl: Option<Duration>) {
+        let result = match ttl {
+            Some(ttl) => self
+                .db
+                .object_expirations
+                .replace_one(
+                    doc! { "_id": key },
+                    ObjectExpiration {
+                        key: key.to_owned(),
+                        expires_at: mongodb::bson::DateTime::from_system_time(
+                            std::time::SystemTime::now() + ttl,
+                        ),
+                    },
+                )
+                .upsert(true)
+                .await
+                .map(|_| ()),
+            None => self
+                .db
+                .object_expirations
+                .delete_one(doc! { "_id": key })
+                .await
+                .map(|_| ()),
+        };
+        if let Err(e) = result {
+            error!(
+                "mongodb error: {:?}. the expiry of {:?} is not recorded!",
+                e, key
+            );
+            RECORD_FAILURES.inc(&["expiry"]);
+        }
+    }
+
+    /// Forgets the expiry of each of `keys`, e.g. once a batch delete removed them. A failure
+    /// leaves the sweep to delete objects that are already gone, which it tolerates.
+    pub(super) async fn forget_expiries(&self, keys: Vec<&str>) {
+        if keys.is_empty() {
+            return;
+        }
+        if let Err(e) = self
+            .db
+            .object_expirations
+            .delete_many(doc! { "_id": { "$in": keys } })
+            .await
+        {
+            error!(
+                "mongodb error: {:?}. expiries of deleted objects are kept",
+                e
+            );
+            RECORD_FAILURES.inc(&["expiry"]);
+        }
+    }
+}
+
+/// Periodically deletes objects whose TTL has passed from every remote.
+#[instrument(name = "expiry", skip_all)]
+pub async fn sweep(
+    remotes: Arc<RemoteSet>,
+    db: Arc<MongoDB>,
+    interval: Duration,
+    concurrency: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+        if let Err(e) = sweep_once(&remotes.load(), &db, concurrency, &shutdown).await {
+            error!("mongodb error: {:?}", e);
+        }
+    }
+    info!("stopped");
+}
+
+async fn sweep_once(
+    remotes: &[S3Remote],
+    db: &MongoDB,
+    concurrency: usize,
+    shutdown: &watch::Receiver<bool>,
+) -> Result<(), mongodb::error::Error> {
+    let expired: Vec<ObjectExpiration> = db
+        .object_expirations
+        .find(doc! { "expires_at": { "$lte": mongodb::bson::DateTime::now() } })
+        .limit(SWEEP_BATCH)
+        .await?
+        .try_collect()
+        .await?;
+
+    for expiration in expired {
+        if *shutdown.borrow() {
+            break;
+        }
+        let now = mongodb::bson::DateTime::now();
+        let claimed_until = mongodb::bson::DateTime::from_millis(
+            now.timestamp_millis() + CLAIM_LEASE.as_millis() as i64,
+        );
+        // an object rewritten since it was found carries a new expiry, or none, and must survive
+        let claimed = db
+            .object_expirations
+            .update_one(
+                claim_filter(&expiration, now),
+                doc! { "$set": { "expires_at": claimed_until } },
+            )
+            .await?;
+        if claimed.modified_count == 0 {
+            info!("{:?} was rewritten or claimed. skipping", expiration.key);
+            continue;
+        }
+        if !delete_everywhere(remotes, &expiration.key, concurrency).await {
+            warn!(
+                "{:?} could not be deleted everywhere. retrying later",
+                expiration.key
+            );
+            continue;
+        }
+        db.object_expirations
+            .delete_one(doc! { "_id": &expiration.key, "expires_at": claimed_until })
+            .await?;
+        info!("expired {:?}", expiration.key);
+    }
+    Ok(())
+}
+
+/// Matches the expiry of an object only while it is still the one the sweep found, and has
+/// passed.
+fn claim_filter(expiration: &ObjectExpiration, now: mongodb::bson::DateTime) -> Document {
+    doc! {
+        "_id": &expiration.key,
+        "$and": [
+            { "expires_at": expiration.expires_at },
+            { "expires_at": { "$lte": now } },
+        ],
+    }
+}
+
+/// Deletes `key` from every remote, `concurrency` at a time. Returns whether all of them
+/// confirmed the deletion.
+async fn delete_everywhere(remotes: &[S3Remote], key: &str, concurrency: usize) -> bool {
+    let Ok(input) = DeleteObjectInput::builder().key(key).build() else {
+        return false;
+    };
+    futures::stream::iter(remotes.iter())
+        .map(|remote| {
+            let input = input.clone();
+            async move {
+                let result = remote
+                    .request(|reply| RemoteMessage::DeleteObject { input, reply })
+                    .await;
+                matches!(result, Some(Ok(_)))
+            }
+        })
+        .boxed()
+        .buffer_unordered(concurrency)
+        .all(|deleted| async move { deleted })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::delete_object::DeleteObjectOutput;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+
+    fn config(default_ttl: Option<&str>) -> ObjectTtlConfig {
+        ObjectTtlConfig {
+            default_ttl: default_ttl.map(|t| DurationString::from_string(t.to_owned()).unwrap()),
+            sweep_interval: DurationString::from_string("1m".to_owned()).unwrap(),
+        }
+    }
+
+    /// A remote recording the keys it was asked to delete.
+    fn remote(name: &str, up: bool, deleted: Arc<Mutex<Vec<String>>>) -> S3Remote {
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::DeleteObject { input, reply } = message {
+                deleted.lock().unwrap().extend(input.key);
+                let _ = reply.send(up.then(|| Ok(DeleteObjectOutput::builder().build())));
+            }
+        })
+    }
+
+    #[test]
+    fn ttl_header_overrides_default() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            requested_ttl(&headers, &config(Some("1h"))).unwrap(),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(requested_ttl(&headers, &config(None)).unwrap(), None);
+
+        headers.insert(TTL_HEADER, "10s".parse().unwrap());
+        assert_eq!(
+            requested_ttl(&headers, &config(Some("1h"))).unwrap(),
+            Some(Duration::from_secs(10))
+        );
+
+        headers.insert(TTL_HEADER, "soon".parse().unwrap());
+        assert!(requested_ttl(&headers, &config(None)).is_err());
+    }
+
+    #[test]
+    fn claim_matches_only_the_expiry_that_was_found() {
+        let now = mongodb::bson::DateTime::now();
+        let expiration = ObjectExpiration {
+            key: "cache/item".to_owned(),
+            expires_at: mongodb::bson::DateTime::from_millis(now.timestamp_millis() - 1000),
+        };
+
+        let filter = claim_filter(&expiration, now);
+
+        assert_eq!(filter.get_str("_id").unwrap(), "cache/item");
+        assert_eq!(
+            filter.get_array("$and").unwrap(),
+            &vec![
+                doc! { "expires_at": expiration.expires_at }.into(),
+                doc! { "expires_at": { "$lte": now } }.into(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_object_is_deleted_from_every_remote() {
+        let deleted = Arc::new(Mutex::new(vec![]));
+        let remotes = [
+            remote("a", true, Arc::clone(&deleted)),
+            remote("b", true, Arc::clone(&deleted)),
+        ];
+
+        assert!(delete_everywhere(&remotes, "cache/item", 8).await);
+        assert_eq!(*deleted.lock().unwrap(), vec!["cache/item", "cache/item"]);
+    }
+
+    #[tokio::test]
+    async fn expiry_is_kept_while_a_remote_is_down() {
+        let deleted = Arc::new(Mutex::new(vec![]));
+        let remotes = [
+            remote("a", true, Arc::clone(&deleted)),
+            remote("b", false, Arc::clone(&deleted)),
+        ];
+
+        assert!(!delete_everywhere(&remotes, "cache/item", 8).await);
+    }
+}
@@ -0,0 +1,118 @@
+use aws_sdk_s3::operation::get_object::GetObjectInput;
+use aws_sdk_s3::operation::head_object::{HeadObjectInput, HeadObjectOutput};
+use tracing::warn;
+
+use super::remote::{RemoteMessage, S3Remote};
+
+/// Query parameter selecting how a read picks its remote.
+const READ_PARAM: &str = "reproxy-read";
+
+/// Whether the request asks for `reproxy-read=fresh`, i.e. for the most recently written copy
+/// instead of the one on the preferred remote. Other values are ignored.
+pub(super) fn wants_fresh(uri: &http::Uri) -> bool {
+    uri.query()
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(name, _)| *name == READ_PARAM)
+        .any(|(_, value)| {
+            if value != "fresh" {
+                warn!("(ignored) unknown {}: {:?}", READ_PARAM, value);
+            }
+            value == "fresh"
+        })
+}
+
+/// The HEAD matching a GET, used to compare the copies before choosing one to read.
+pub(super) fn head_input(input: &GetObjectInput) -> Option<HeadObjectInput> {
+    HeadObjectInput::builder()
+        .set_key(input.key.clone())
+        .set_version_id(input.version_id.clone())
+        .set_sse_customer_algorithm(input.sse_customer_algorithm.clone())
+        .set_sse_customer_key(input.sse_customer_key.clone())
+        .set_sse_customer_key_md5(input.sse_customer_key_md5.clone())
+        .set_expected_bucket_owner(input.expected_bucket_owner.clone())
+        .build()
+        .ok()
+}
+
+/// HEADs the object on every readable remote at once and returns the one whose copy was written
+/// last, according to its `Last-Modified`.
+pub(super) async fn newest_remote<'a>(
+    remotes: &[&'a S3Remote],
+    input: &HeadObjectInput,
+) -> Option<(&'a S3Remote, HeadObjectOutput)> {
+    futures::future::join_all(
+        remotes
+            .iter()
+            .filter(|r| r.read_request)
+            .map(|remote| async move {
+                let output = remote
+                    .request(|reply| RemoteMessage::HeadObject {
+                        input: input.clone(),
+                        reply,
+                    })
+                    .await
+                    .and_then(Result::ok);
+                if output.is_none() {
+                    warn!("remote({:?}) has no readable copy. skipping", remote.name);
+                }
+                Some((*remote, output?))
+            }),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .max_by_key(|(_, output)| output.last_modified.map(|t| (t.secs(), t.subsec_nanos())))
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_smithy_types::DateTime;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// A remote whose copy of every object was last modified at `written` (unix seconds).
+    fn remote_written_at(name: &str, priority: u32, written: i64) -> S3Remote {
+        S3Remote {
+            priority,
+            ..S3Remote::answering(name, move |message| {
+                if let RemoteMessage::HeadObject { reply, .. } = message {
+                    let output = HeadObjectOutput::builder()
+                        .last_modified(DateTime::from_secs(written))
+                        .e_tag(format!("\"{written}\""))
+                        .build();
+                    let _ = reply.send(Some(Ok(output)));
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn only_fresh_is_recognized() {
+        let uri = |s: &str| s.parse::<http::Uri>().unwrap();
+
+        assert!(wants_fresh(&uri("/bucket/key?reproxy-read=fresh")));
+        assert!(wants_fresh(&uri(
+            "/bucket/key?x-id=GetObject&reproxy-read=fresh"
+        )));
+        assert!(!wants_fresh(&uri("/bucket/key?reproxy-read=stale")));
+        assert!(!wants_fresh(&uri("/bucket/key")));
+    }
+
+    #[tokio::test]
+    async fn fresh_read_picks_the_newest_copy() {
+        let preferred = remote_written_at("preferred", 10, 1_700_000_000);
+        let newest = remote_written_at("newest", 1, 1_700_000_600);
+        let older = remote_written_at("older", 5, 1_700_000_300);
+        let input = HeadObjectInput::builder().key("key").build().unwrap();
+
+        let (remote, output) = newest_remote(&[&preferred, &newest, &older], &input)
+            .await
+            .unwrap();
+
+        assert_eq!(remote.name, "newest");
+        assert_eq!(output.e_tag.as_deref(), Some("\"1700000600\""));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{oneshot, watch};
+use tracing::{info, instrument, warn};
+
+use super::reload::RemoteSet;
+use super::remote::{RemoteMessage, S3Remote};
+use super::S3Reproxy;
+
+/// Probes every remote with `HeadBucket` each `interval`, giving up on a remote after `timeout`.
+/// A remote found down enough times in a row has its circuit opened, which keeps reads away from
+/// it, and the next probe it passes closes it again.
+#[instrument(name = "health_check", skip_all)]
+pub async fn probe(
+    remotes: Arc<RemoteSet>,
+    interval: Duration,
+    timeout: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+        futures::future::join_all(remotes.load().iter().map(|r| probe_once(r, timeout))).await;
+    }
+    info!("stopped");
+}
+
+/// The remote records the outcome of the probe itself. A remote that does not answer at all, or
+/// not within `timeout`, is recorded as down here.
+async fn probe_once(remote: &S3Remote, timeout: Duration) {
+    let (tx, rx) = oneshot::channel();
+    let answered = tokio::time::timeout(timeout, async {
+        remote
+            .tx
+            .send(RemoteMessage::HealthCheck { reply: tx })
+            .await
+            .ok()?;
+        rx.await.ok()
+    })
+    .await
+    .ok()
+    .flatten();
+    if answered.is_none() {
+        warn!("remote({:?}) did not answer the health check", remote.name);
+        remote.status.record(false);
+    }
+}
+
+/// Leaves out the remotes whose circuit is open, unless that would leave none: a read from a
+/// remote that may be down is still better than no read at all.
+fn admitted(ordered: Vec<&S3Remote>) -> Vec<&S3Remote> {
+    if ordered.iter().all(|r| r.status.is_down()) {
+        return ordered;
+    }
+    ordered
+        .into_iter()
+        .filter(|r| !r.status.is_down())
+        .collect()
+}
+
+impl S3Reproxy {
+    /// Remotes in `ordered` that reads may go to. Remotes found down are only skipped while
+    /// health checks run, since nothing else would bring a remote only used for reads back.
+    pub(super) fn admitted<'a>(&self, ordered: Vec<&'a S3Remote>) -> Vec<&'a S3Remote> {
+        if self.health_checks {
+            admitted(ordered)
+        } else {
+            ordered
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::status::CircuitState;
+    use pretty_assertions::assert_eq;
+
+    fn names(remotes: Vec<&S3Remote>) -> Vec<&str> {
+        remotes.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_skip_remotes_that_are_down() {
+        let remotes = ["a", "b", "c"].map(S3Remote::stub);
+        remotes[0].status.record(false);
+        remotes[1].status.record(true);
+
+        assert_eq!(names(admitted(remotes.iter().collect())), ["b", "c"]);
+
+        remotes[1].status.record(false);
+        remotes[2].status.record(false);
+        assert_eq!(names(admitted(remotes.iter().collect())), ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn unanswered_probe_opens_the_circuit_until_one_passes() {
+        let remote = S3Remote::stub("gone");
+        remote.status.record(true);
+
+        probe_once(&remote, Duration::from_secs(1)).await;
+        assert_eq!(remote.status.snapshot().circuit, CircuitState::Open);
+
+        // what the remote records when it answers the next probe
+        remote.status.record(true);
+        assert_eq!(names(admitted(vec![&remote])), ["gone"]);
+        assert!(!remote.status.is_down());
+    }
+
+    #[tokio::test]
+    async fn hung_probe_is_given_up_on() {
+        // holds on to every message without ever answering it
+        let mut held = vec![];
+        let remote = S3Remote::answering("hung", move |message| held.push(message));
+        remote.status.record(true);
+
+        probe_once(&remote, Duration::from_millis(50)).await;
+        assert_eq!(remote.status.snapshot().circuit, CircuitState::Open);
+    }
+}
@@ -0,0 +1,306 @@
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use itertools::Itertools;
+use tracing::warn;
+
+use crate::config::s3_target::ReadStrategy;
+
+use super::remote::S3Remote;
+
+/// Sends a read to `first` and `second` at once and returns the reply of whichever succeeds
+/// first, dropping the other request. When neither succeeds, the reply of `first` is preferred
+/// over that of `second`, and `None` is returned when neither could be reached.
+pub(super) async fn hedged<'a, T, E, F>(
+    first: &'a S3Remote,
+    second: &'a S3Remote,
+    read: impl Fn(&'a S3Remote) -> F,
+) -> Option<(&'a S3Remote, Result<T, E>)>
+where
+    F: Future<Output = Option<Result<T, E>>>,
+{
+    match select(pin!(read(first)), pin!(read(second))).await {
+        Either::Left((Some(Ok(output)), _)) => Some((first, Ok(output))),
+        Either::Right((Some(Ok(output)), _)) => Some((second, Ok(output))),
+        Either::Left((failed, other)) => match other.await {
+            Some(Ok(output)) => Some((second, Ok(output))),
+            other => failed
+                .map(|r| (first, r))
+                .or_else(|| other.map(|r| (second, r))),
+        },
+        Either::Right((failed, other)) => match other.await {
+            Some(r) => Some((first, r)),
+            None => failed.map(|r| (second, r)),
+        },
+    }
+}
+
+/// Sends a read to `remotes` in order, moving on to the next one when the last one started has
+/// not answered within `delay` or could not be reached, and returns the first success. The reads
+/// already started keep running, so a slow remote can still win. A failed reply stops further
+/// remotes from being started, as in a sequential read. When nothing succeeds, the failure of the
+/// earliest remote is returned, and `None` when none could be reached.
+pub(super) async fn staggered<'a, T, E, F>(
+    remotes: &[&'a S3Remote],
+    delay: Duration,
+    read: impl Fn(&'a S3Remote) -> F,
+) -> Option<(&'a S3Remote, Result<T, E>)>
+where
+    F: Future<Output = Option<Result<T, E>>>,
+{
+    let mut pending = remotes.iter().copied().enumerate();
+    let start =
+        |(rank, remote): (usize, &'a S3Remote)| read(remote).map(move |r| (rank, remote, r));
+    let mut in_flight = FuturesUnordered::new();
+    let mut failed: Option<(usize, &'a S3Remote, E)> = None;
+    loop {
+        if in_flight.is_empty() {
+            match pending.next() {
+                Some(next) if failed.is_none() => in_flight.push(start(next)),
+                _ => break,
+            }
+        }
+        let Ok(reply) = tokio::time::timeout(delay, in_flight.next()).await else {
+            if let Some(next) = pending.next().filter(|_| failed.is_none()) {
+                in_flight.push(start(next));
+            }
+            continue;
+        };
+        match reply {
+            Some((_, remote, Some(Ok(output)))) => return Some((remote, Ok(output))),
+            Some((rank, remote, Some(Err(e)))) => {
+                if failed
+                    .as_ref()
+                    .map_or(true, |(earliest, ..)| rank < *earliest)
+                {
+                    failed = Some((rank, remote, e));
+                }
+            }
+            Some((_, remote, None)) => {
+                warn!("remote({:?}) request failed. skipping", remote.name);
+                if let Some(next) = pending.next().filter(|_| failed.is_none()) {
+                    in_flight.push(start(next));
+                }
+            }
+            None => {}
+        }
+    }
+    failed.map(|(_, remote, e)| (remote, Err(e)))
+}
+
+/// Races a read across the `read_request` remotes of `ordered`, or the others when none of those
+/// are left, as `strategy` and `stagger` call for. Returns the reply the race ended with along
+/// with the remotes to read from next: the one that replied first, so a `NoSuchKey` from a
+/// `read_request` remote is final, and the others after it. Remotes without `read_request` come
+/// last, to be asked only once none of the others could be reached. Reads that are not raced
+/// return no reply.
+pub(super) async fn race_read_tier<'a, T, E, F>(
+    ordered: Vec<&'a S3Remote>,
+    strategy: ReadStrategy,
+    stagger: Option<Duration>,
+    read: impl Fn(&'a S3Remote) -> F,
+) -> (Option<(&'a S3Remote, Result<T, E>)>, Vec<&'a S3Remote>)
+where
+    F: Future<Output = Option<Result<T, E>>>,
+{
+    // a key filter may have moved `read_request` remotes behind the others
+    let (mut tier, mut rest): (Vec<_>, Vec<_>) = ordered.into_iter().partition(|r| r.read_request);
+    if tier.is_empty() {
+        std::mem::swap(&mut tier, &mut rest);
+    }
+    let raced = match (strategy, stagger) {
+        _ if tier.len() < 2 => None,
+        (ReadStrategy::Hedged, _) => {
+            rest.splice(0..0, tier.split_off(2));
+            Some(hedged(tier[0], tier[1], read).await)
+        }
+        (_, Some(delay)) => Some(staggered(&tier, delay, read).await),
+        (_, None) => None,
+    };
+    let Some(reply) = raced else {
+        tier.append(&mut rest);
+        return (None, tier);
+    };
+    // the remote that replied goes first, and the others are asked again if it fails further
+    // along
+    let next = match &reply {
+        Some((first, _)) => tier
+            .iter()
+            .sorted_by_key(|r| r.name != first.name)
+            .copied()
+            .chain(rest)
+            .collect(),
+        None => rest,
+    };
+    (reply, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Replies of two remotes, each answering after its delay in milliseconds.
+    async fn race(
+        first: (u64, Option<Result<&'static str, &'static str>>),
+        second: (u64, Option<Result<&'static str, &'static str>>),
+    ) -> Option<(String, Result<&'static str, &'static str>)> {
+        let remotes = [S3Remote::stub("first"), S3Remote::stub("second")];
+        let winner = hedged(&remotes[0], &remotes[1], |remote| {
+            let (delay, reply) = if remote.name == "first" {
+                first
+            } else {
+                second
+            };
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                reply
+            }
+        })
+        .await;
+        winner.map(|(remote, reply)| (remote.name.clone(), reply))
+    }
+
+    #[tokio::test]
+    async fn fastest_success_wins() {
+        assert_eq!(
+            race((500, Some(Ok("slow"))), (0, Some(Ok("fast")))).await,
+            Some(("second".to_owned(), Ok("fast")))
+        );
+        assert_eq!(
+            race((0, Some(Err("NoSuchKey"))), (50, Some(Ok("late")))).await,
+            Some(("second".to_owned(), Ok("late")))
+        );
+    }
+
+    #[tokio::test]
+    async fn first_failure_is_preferred() {
+        assert_eq!(
+            race((50, Some(Err("NoSuchKey"))), (0, Some(Err("AccessDenied")))).await,
+            Some(("first".to_owned(), Err("NoSuchKey")))
+        );
+        assert_eq!(
+            race((50, None), (0, Some(Err("AccessDenied")))).await,
+            Some(("second".to_owned(), Err("AccessDenied")))
+        );
+        assert_eq!(race((0, None), (0, None)).await, None);
+    }
+
+    /// Replies of remotes given as their delay in milliseconds and reply, started 100ms apart.
+    async fn stagger(
+        replies: &[(u64, Option<Result<&'static str, &'static str>>)],
+    ) -> Option<(String, Result<&'static str, &'static str>)> {
+        let remotes = (0..replies.len())
+            .map(|i| S3Remote::stub(&i.to_string()))
+            .collect::<Vec<_>>();
+        let order = remotes.iter().collect::<Vec<_>>();
+        let winner = staggered(&order, Duration::from_millis(100), |remote| {
+            let (delay, reply) = replies[remote.name.parse::<usize>().unwrap()];
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                reply
+            }
+        })
+        .await;
+        winner.map(|(remote, reply)| (remote.name.clone(), reply))
+    }
+
+    #[tokio::test]
+    async fn staggered_reads_start_the_next_remote_after_the_delay() {
+        assert_eq!(
+            stagger(&[(50, Some(Ok("quick"))), (0, Some(Ok("unused")))]).await,
+            Some(("0".to_owned(), Ok("quick")))
+        );
+        assert_eq!(
+            stagger(&[(1000, Some(Ok("slow"))), (0, Some(Ok("next")))]).await,
+            Some(("1".to_owned(), Ok("next")))
+        );
+        assert_eq!(
+            stagger(&[(150, Some(Ok("slow"))), (500, Some(Ok("next")))]).await,
+            Some(("0".to_owned(), Ok("slow")))
+        );
+    }
+
+    #[tokio::test]
+    async fn staggered_reads_fall_back_like_sequential_ones() {
+        assert_eq!(
+            stagger(&[(0, None), (0, Some(Ok("fallback")))]).await,
+            Some(("1".to_owned(), Ok("fallback")))
+        );
+        assert_eq!(
+            stagger(&[(0, Some(Err("NoSuchKey"))), (0, Some(Ok("unused")))]).await,
+            Some(("0".to_owned(), Err("NoSuchKey")))
+        );
+        assert_eq!(stagger(&[(0, None), (0, None)]).await, None);
+    }
+
+    /// Races a read over `remotes`, each given as its name, whether it has `read_request` and its
+    /// reply, staggered by 100ms. Returns the reply, the remotes to read from next and the remotes
+    /// that were asked.
+    async fn race_tier(
+        remotes: &[(&str, bool, Option<Result<&'static str, &'static str>>)],
+    ) -> (
+        Option<(String, Result<&'static str, &'static str>)>,
+        Vec<String>,
+        Vec<String>,
+    ) {
+        let stubs = remotes
+            .iter()
+            .map(|&(name, read_request, _)| S3Remote {
+                read_request,
+                ..S3Remote::stub(name)
+            })
+            .collect::<Vec<_>>();
+        let asked = std::sync::Mutex::new(vec![]);
+        let (reply, next) = race_read_tier(
+            stubs.iter().collect(),
+            ReadStrategy::Sequential,
+            Some(Duration::from_millis(100)),
+            |remote| {
+                asked.lock().unwrap().push(remote.name.clone());
+                let reply = remotes.iter().find(|r| r.0 == remote.name).unwrap().2;
+                async move { reply }
+            },
+        )
+        .await;
+        (
+            reply.map(|(remote, reply)| (remote.name.clone(), reply)),
+            next.iter().map(|r| r.name.clone()).collect(),
+            asked.into_inner().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn not_found_on_a_read_remote_is_final() {
+        let (reply, _, asked) = race_tier(&[
+            ("fallback", false, Some(Ok("stale"))),
+            ("a", true, Some(Err("NoSuchKey"))),
+            ("b", true, Some(Err("NoSuchKey"))),
+        ])
+        .await;
+
+        assert_eq!(reply, Some(("a".to_owned(), Err("NoSuchKey"))));
+        assert_eq!(asked, ["a"]);
+    }
+
+    #[tokio::test]
+    async fn other_remotes_are_read_when_every_read_remote_is_down() {
+        let (reply, next, asked) = race_tier(&[
+            ("a", true, None),
+            ("fallback", false, Some(Ok("stale"))),
+            ("b", true, None),
+        ])
+        .await;
+
+        assert_eq!(reply, None);
+        assert_eq!(next, ["fallback"]);
+        assert_eq!(asked, ["a", "b"]);
+    }
+}
@@ -0,0 +1,238 @@
+use aws_sdk_s3::operation::delete_objects::DeleteObjectsOutput;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+
+use super::remote::RemoteMessage;
+
+/// Where a remote keeps the objects of the virtual bucket: under `key_prefix` when its target
+/// sets one, e.g. for a legacy bucket that stores them under `v1/`. The rest of the proxy only
+/// ever sees the keys without it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KeyPrefix(Option<String>);
+
+impl KeyPrefix {
+    pub fn new(prefix: Option<String>) -> Self {
+        Self(prefix.filter(|p| !p.is_empty()))
+    }
+
+    /// The remote's key for the client's `key`.
+    pub fn add(&self, key: Option<String>) -> Option<String> {
+        match &self.0 {
+            Some(prefix) => key.map(|key| format!("{prefix}{key}")),
+            None => key,
+        }
+    }
+
+    /// The client's key for the remote's `key`. Keys outside the prefix are left as they are.
+    pub fn strip(&self, key: Option<String>) -> Option<String> {
+        match (&self.0, key) {
+            (Some(prefix), Some(key)) => Some(match key.strip_prefix(prefix.as_str()) {
+                Some(stripped) => stripped.to_owned(),
+                None => key,
+            }),
+            (_, key) => key,
+        }
+    }
+
+    /// The remote's `bucket/key` copy source for the client's.
+    fn add_to_copy_source(&self, copy_source: Option<String>) -> Option<String> {
+        let Some(prefix) = &self.0 else {
+            return copy_source;
+        };
+        copy_source.map(|source| {
+            let source = source.trim_start_matches('/');
+            match source.split_once('/') {
+                Some((bucket, key)) => format!("{bucket}/{prefix}{key}"),
+                None => source.to_owned(),
+            }
+        })
+    }
+
+    /// Rewrites the keys of a request to the remote's. A listing without a prefix covers the
+    /// remote's prefix as a whole, so that common prefixes are still cut at the client's
+    /// delimiter after the remote's prefix.
+    pub fn outgoing(&self, message: RemoteMessage) -> RemoteMessage {
+        let Some(prefix) = &self.0 else {
+            return message;
+        };
+        match message {
+            RemoteMessage::ListObjects {
+                prefix: list_prefix,
+                delimiter,
+                max_keys,
+                start_after,
+                continuation_token,
+                fetch_owner,
+                encoding_type,
+                reply,
+            } => RemoteMessage::ListObjects {
+                prefix: Some(format!("{prefix}{}", list_prefix.unwrap_or_default())),
+                delimiter,
+                max_keys,
+                start_after: self.add(start_after),
+                continuation_token,
+                fetch_owner,
+                encoding_type,
+                reply,
+            },
+            RemoteMessage::CopyObject { mut input, reply } => {
+                input.key = self.add(input.key);
+                input.copy_source = self.add_to_copy_source(input.copy_source);
+                RemoteMessage::CopyObject { input, reply }
+            }
+            RemoteMessage::UploadPartCopy { mut input, reply } => {
+                input.key = self.add(input.key);
+                input.copy_source = self.add_to_copy_source(input.copy_source);
+                RemoteMessage::UploadPartCopy { input, reply }
+            }
+            RemoteMessage::DeleteObjects { mut input, reply } => {
+                for object in input.delete.iter_mut().flat_map(|d| d.objects.iter_mut()) {
+                    object.key = format!("{prefix}{}", object.key);
+                }
+                RemoteMessage::DeleteObjects { input, reply }
+            }
+            RemoteMessage::HeadObject { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::HeadObject { input, reply }
+            }
+            RemoteMessage::GetObject { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::GetObject { input, reply }
+            }
+            RemoteMessage::PutObject { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::PutObject { input, reply }
+            }
+            RemoteMessage::DeleteObject { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::DeleteObject { input, reply }
+            }
+            RemoteMessage::GetObjectTagging { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::GetObjectTagging { input, reply }
+            }
+            RemoteMessage::PutObjectTagging { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::PutObjectTagging { input, reply }
+            }
+            RemoteMessage::DeleteObjectTagging { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::DeleteObjectTagging { input, reply }
+            }
+            RemoteMessage::CreateMultiPartUpload { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::CreateMultiPartUpload { input, reply }
+            }
+            RemoteMessage::UploadPart { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::UploadPart { input, reply }
+            }
+            RemoteMessage::CompleteMultiPartUpload { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::CompleteMultiPartUpload { input, reply }
+            }
+            RemoteMessage::ListParts { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::ListParts { input, reply }
+            }
+            RemoteMessage::AbortMultipartUpload { mut input, reply } => {
+                input.key = self.add(input.key);
+                RemoteMessage::AbortMultipartUpload { input, reply }
+            }
+            message @ (RemoteMessage::HealthCheck { .. }
+            | RemoteMessage::ListBuckets { .. }
+            | RemoteMessage::Shutdown) => message,
+        }
+    }
+
+    /// Rewrites the keys and common prefixes of a listing to the client's.
+    pub fn strip_listing(&self, mut output: ListObjectsV2Output) -> ListObjectsV2Output {
+        if self.0.is_none() {
+            return output;
+        }
+        for object in output.contents.iter_mut().flatten() {
+            object.key = self.strip(object.key.take());
+        }
+        for common in output.common_prefixes.iter_mut().flatten() {
+            common.prefix = self.strip(common.prefix.take());
+        }
+        output.prefix = self.strip(output.prefix).filter(|p| !p.is_empty());
+        output.start_after = self.strip(output.start_after);
+        output
+    }
+
+    /// Rewrites the keys a `DeleteObjects` reports on to the client's.
+    pub fn strip_deletions(&self, mut output: DeleteObjectsOutput) -> DeleteObjectsOutput {
+        for deleted in output.deleted.iter_mut().flatten() {
+            deleted.key = self.strip(deleted.key.take());
+        }
+        for error in output.errors.iter_mut().flatten() {
+            error.key = self.strip(error.key.take());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::get_object::GetObjectInput;
+    use aws_sdk_s3::types::{CommonPrefix, Object};
+    use pretty_assertions::assert_eq;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn requests_reach_the_remote_under_its_prefix() {
+        let prefix = KeyPrefix::new(Some("v1/".to_owned()));
+
+        let input = GetObjectInput::builder().key("a.txt").build().unwrap();
+        let message = RemoteMessage::GetObject {
+            input,
+            reply: oneshot::channel().0,
+        };
+        let RemoteMessage::GetObject { input, .. } = prefix.outgoing(message) else {
+            unreachable!();
+        };
+        assert_eq!(input.key.as_deref(), Some("v1/a.txt"));
+
+        let message = RemoteMessage::ListObjects {
+            prefix: None,
+            delimiter: Some("/".to_owned()),
+            max_keys: None,
+            start_after: Some("photos/".to_owned()),
+            continuation_token: None,
+            fetch_owner: None,
+            encoding_type: None,
+            reply: oneshot::channel().0,
+        };
+        let RemoteMessage::ListObjects {
+            prefix: list_prefix,
+            start_after,
+            ..
+        } = prefix.outgoing(message)
+        else {
+            unreachable!();
+        };
+        assert_eq!(list_prefix.as_deref(), Some("v1/"));
+        assert_eq!(start_after.as_deref(), Some("v1/photos/"));
+    }
+
+    #[test]
+    fn listings_are_stripped_of_the_remote_prefix() {
+        let prefix = KeyPrefix::new(Some("v1/".to_owned()));
+        let output = ListObjectsV2Output::builder()
+            .prefix("v1/")
+            .contents(Object::builder().key("v1/a.txt").build())
+            .common_prefixes(CommonPrefix::builder().prefix("v1/photos/").build())
+            .build();
+
+        let output = prefix.strip_listing(output);
+
+        assert_eq!(output.prefix, None);
+        assert_eq!(output.contents()[0].key(), Some("a.txt"));
+        assert_eq!(output.common_prefixes()[0].prefix(), Some("photos/"));
+        assert_eq!(
+            KeyPrefix::new(None).strip(Some("v1/a.txt".to_owned())),
+            Some("v1/a.txt".to_owned())
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use aws_sdk_s3::operation::list_objects::{ListObjectsInput, ListObjectsOutput};
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+
+/// Answers a `ListObjects` (v1) request with a page listed the v2 way. `NextMarker` is the last
+/// key of the page as listed, which is also what a v2 continuation token resumes from, and is set
+/// whenever the listing is truncated, with or without a delimiter.
+pub(super) fn v1_listing(
+    input: &ListObjectsInput,
+    page: ListObjectsV2Output,
+    page_end: Option<String>,
+) -> ListObjectsOutput {
+    let is_truncated = page.is_truncated.unwrap_or(false);
+    ListObjectsOutput::builder()
+        .set_name(input.bucket.clone())
+        .set_prefix(input.prefix.clone())
+        .set_delimiter(input.delimiter.clone())
+        .set_marker(input.marker.clone())
+        .set_max_keys(input.max_keys.or(page.max_keys))
+        .set_encoding_type(input.encoding_type.clone())
+        .is_truncated(is_truncated)
+        .set_next_marker(page_end.filter(|_| is_truncated))
+        .set_contents(page.contents)
+        .set_common_prefixes(page.common_prefixes)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::types::Object;
+    use pretty_assertions::assert_eq;
+
+    fn page(keys: &[&str], is_truncated: bool) -> ListObjectsV2Output {
+        ListObjectsV2Output::builder()
+            .set_contents(Some(
+                keys.iter()
+                    .map(|k| Object::builder().key(*k).build())
+                    .collect(),
+            ))
+            .is_truncated(is_truncated)
+            .build()
+    }
+
+    #[test]
+    fn next_marker_resumes_after_the_last_key() {
+        let input = ListObjectsInput::builder()
+            .bucket("virtual")
+            .marker("a")
+            .max_keys(2)
+            .build()
+            .unwrap();
+
+        let output = v1_listing(&input, page(&["b", "c"], true), Some("c".to_owned()));
+
+        assert_eq!(output.name(), Some("virtual"));
+        assert_eq!(output.marker(), Some("a"));
+        assert_eq!(output.next_marker(), Some("c"));
+        assert_eq!(output.is_truncated(), Some(true));
+        assert_eq!(
+            output
+                .contents()
+                .iter()
+                .map(|o| o.key())
+                .collect::<Vec<_>>(),
+            [Some("b"), Some("c")]
+        );
+
+        let output = v1_listing(&input, page(&["d"], false), Some("d".to_owned()));
+        assert_eq!(output.next_marker(), None);
+        assert_eq!(output.is_truncated(), Some(false));
+    }
+}
@@ -0,0 +1,70 @@
+use s3s::dto::Metadata;
+use s3s::{s3_error, S3Result};
+use tracing::info;
+
+use super::remote::S3Remote;
+
+/// Rejects a write whose user metadata exceeds the smallest limit configured among the remotes,
+/// so that a lenient remote does not accept an object a strict one refuses.
+pub(super) fn check_metadata_size(
+    remotes: &[S3Remote],
+    metadata: Option<&Metadata>,
+) -> S3Result<()> {
+    let Some(limit) = remotes.iter().filter_map(|r| r.max_metadata_bytes).min() else {
+        return Ok(());
+    };
+    let size = metadata.map_or(0, metadata_size);
+    if size > limit {
+        info!(
+            "(intercepted) metadata of {} bytes exceeds the {} bytes limit",
+            size, limit
+        );
+        return Err(s3_error!(
+            MetadataTooLarge,
+            "user metadata is {} bytes, at most {} bytes are allowed",
+            size,
+            limit
+        ));
+    }
+    Ok(())
+}
+
+/// Size of user metadata as S3 counts it: the UTF-8 length of every key and value.
+fn metadata_size(metadata: &Metadata) -> usize {
+    metadata.iter().map(|(k, v)| k.len() + v.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    fn remote(name: &str, limit: Option<usize>) -> S3Remote {
+        S3Remote {
+            max_metadata_bytes: limit,
+            ..S3Remote::stub(name)
+        }
+    }
+
+    #[test]
+    fn metadata_over_strictest_limit_is_rejected_up_front() {
+        let remotes = [remote("lenient", Some(8192)), remote("strict", Some(16))];
+        let small = Metadata::from([("owner".to_owned(), "alice".to_owned())]);
+        let large = Metadata::from([("description".to_owned(), "x".repeat(32))]);
+
+        assert!(check_metadata_size(&remotes, None).is_ok());
+        assert!(check_metadata_size(&remotes, Some(&small)).is_ok());
+
+        let err = check_metadata_size(&remotes, Some(&large)).unwrap_err();
+        assert_eq!(err.code(), &S3ErrorCode::MetadataTooLarge);
+    }
+
+    #[test]
+    fn metadata_is_unbounded_without_limits() {
+        let remotes = [remote("a", None), remote("b", None)];
+        let large = Metadata::from([("description".to_owned(), "x".repeat(1 << 16))]);
+
+        assert!(check_metadata_size(&remotes, Some(&large)).is_ok());
+    }
+}
@@ -1,13 +1,50 @@
+pub mod abandoned;
+pub mod bloom;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod checksum;
 pub mod clone;
+pub mod conditional;
+pub mod copy;
+pub mod debug;
+pub mod delete;
+pub mod expiry;
+pub mod fresh;
+pub mod health;
+pub mod hedge;
+pub mod in_flight;
+pub mod key_prefix;
+pub mod legacy_list;
+pub mod metadata;
+pub mod notify;
+pub mod operations;
+pub mod outage;
+pub mod ownership;
+pub mod parts;
+pub mod prefetch;
+pub mod reload;
 pub mod remote;
+pub mod repair;
+pub mod request_id;
+pub mod retry;
+pub mod status;
 pub mod stream;
+pub mod tagging;
+pub mod upload_token;
+pub mod uploads;
 use crate::db::{ListObjectTokens, MultipartUploadIds, PartUploadStatus, RemoteMultipartUploadId};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::operation::RequestId;
+use aws_sdk_s3::primitives::ByteStream;
 use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
 use aws_smithy_runtime_api::client::result::ServiceError;
 use futures::StreamExt;
@@ -15,32 +52,112 @@ use itertools::{Either, Itertools};
 use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
 use s3s::dto::{
-    Bucket, CompleteMultipartUploadInput, CompleteMultipartUploadOutput,
-    CreateMultipartUploadInput, CreateMultipartUploadOutput, DeleteObjectInput, DeleteObjectOutput,
-    DeleteObjectsInput, DeleteObjectsOutput, GetBucketLocationInput, GetBucketLocationOutput,
-    GetObjectInput, GetObjectOutput, HeadBucketInput, HeadBucketOutput, HeadObjectInput,
-    HeadObjectOutput, ListBucketsInput, ListBucketsOutput, ListObjectsV2Input, ListObjectsV2Output,
-    PutObjectInput, PutObjectOutput, UploadPartInput, UploadPartOutput,
+    AbortMultipartUploadInput, AbortMultipartUploadOutput, Bucket, CompleteMultipartUploadInput,
+    CompleteMultipartUploadOutput, CopyObjectInput, CopyObjectOutput, CreateBucketInput,
+    CreateBucketOutput, CreateMultipartUploadInput, CreateMultipartUploadOutput, DeleteBucketInput,
+    DeleteBucketOutput, DeleteObjectInput, DeleteObjectOutput, DeleteObjectTaggingInput,
+    DeleteObjectTaggingOutput, DeleteObjectsInput, DeleteObjectsOutput, GetBucketLocationInput,
+    GetBucketLocationOutput, GetObjectInput, GetObjectOutput, GetObjectTaggingInput,
+    GetObjectTaggingOutput, HeadBucketInput, HeadBucketOutput, HeadObjectInput, HeadObjectOutput,
+    ListBucketsInput, ListBucketsOutput, ListMultipartUploadsInput, ListMultipartUploadsOutput,
+    ListObjectsInput, ListObjectsOutput, ListObjectsV2Input, ListObjectsV2Output, ListPartsInput,
+    ListPartsOutput, PutObjectInput, PutObjectOutput, PutObjectTaggingInput,
+    PutObjectTaggingOutput, UploadPartCopyInput, UploadPartCopyOutput, UploadPartInput,
+    UploadPartOutput,
 };
 use s3s::{s3_error, S3Error, S3ErrorCode, S3Request, S3Response, S3Result, S3};
 use s3s_aws::conv::AwsConversion;
-use tokio::sync::oneshot;
+use tracing::field::Empty;
 use tracing::{error, info, instrument, warn};
 
+use crate::config::s3_target::{DivergencePolicy, ObjectTtlConfig, ReadStrategy, WriteQuorum};
 use crate::db::MongoDB;
+use crate::metrics::{
+    HEAD_METADATA_DIVERGENCE, INCONSISTENT_WRITES, PART_ETAG_DIVERGENCE, READ_REMOTE_SELECTED,
+};
 
+use self::abandoned::AbortOutcome;
+use self::bloom::order_by_key_filter;
+use self::checksum::{advertised_checksum, check_checksum_algorithm, requested_checksum};
 use self::clone::{PutObjectInputMultiplier, UploadPartInputMultiplier};
+use self::conditional::{check_if_match, failed_preconditions, write_if_absent};
+use self::copy::{copies_metadata, copy_to_remotes, diverging_copies};
+use self::delete::{delete_on_remotes, failed_deletions, with_failed_deletions};
+use self::expiry::requested_ttl;
+use self::fresh::{head_input, newest_remote, wants_fresh};
+use self::hedge::race_read_tier;
+use self::legacy_list::v1_listing;
+use self::metadata::check_metadata_size;
+use self::notify::EventNotifier;
+use self::outage::FailureResponses;
+use self::parts::{
+    cancel_failed_copies, completed_parts_count, completion_output, copied_part_size,
+    declared_object_size, diverging_part_etags, list_parts_on_remotes, settle_part_upload,
+};
+use self::prefetch::{
+    apply_response_overrides, bypasses_buffer, clear_response_overrides, content_range,
+    parse_content_range_start, parse_content_range_total, parse_range, ranged_output,
+    still_current, PrefetchPlan, RangeMeta, RangePrefetcher,
+};
+use self::reload::{by_preference, RemoteSet};
 use self::remote::S3Remote;
+use self::repair::pending_repair;
+use self::request_id::REMOTE_HEADER;
+use self::retry::{read_with_quick_retry, upload_part_with_retry};
+use self::stream::{
+    buffer_head, sized_body, spool, spool_shared, verify_checksum, verify_upload_checksum,
+    DiskSpool,
+};
+use self::tagging::send_to_all;
+use self::upload_token::UploadTokenCodec;
 
 pub struct S3Reproxy {
     pub bucket: String,
-    pub remotes: Arc<Vec<S3Remote>>,
+    pub remotes: Arc<RemoteSet>,
     pub db: Arc<MongoDB>,
+    pub list_buckets_from: Option<String>,
+    pub head_verify_count: usize,
+    pub head_verify_parallel: bool,
+    pub head_divergence: DivergencePolicy,
+    pub strict_reads: bool,
+    pub copy_divergence: DivergencePolicy,
+    pub part_etag_divergence: Option<DivergencePolicy>,
+    pub prefetcher: Option<RangePrefetcher>,
+    pub list_token_fallback: bool,
+    pub native_list_tokens_from: Option<String>,
+    pub reported_region: Option<String>,
+    pub get_retry_buffer_bytes: Option<usize>,
+    pub verify_get_checksums: bool,
+    pub report_parts_count: bool,
+    pub disk_spool: Option<DiskSpool>,
+    pub read_quick_retries: usize,
+    pub read_strategy: ReadStrategy,
+    pub read_hedge_delay: Option<Duration>,
+    pub debug_headers: bool,
+    pub health_checks: bool,
+    pub repair_writes: bool,
+    pub upload_part_retries: usize,
+    pub upload_part_retry_max_bytes: u64,
+    pub fanout_concurrency: usize,
+    pub write_quorum: WriteQuorum,
+    pub complete_quorum: WriteQuorum,
+    pub failure_responses: FailureResponses,
+    pub upload_tokens: Option<UploadTokenCodec>,
+    pub max_active_multipart_uploads: Option<u64>,
+    pub object_ttl: Option<ObjectTtlConfig>,
+    pub enabled_operations: Option<HashSet<String>>,
+    pub read_only: bool,
+    pub notifier: Option<EventNotifier>,
 }
 
 #[inline(always)]
 fn convert_sdk_err<E: ProvideErrorMetadata>(sdk: ServiceError<E, HttpResponse>) -> S3Error {
-    let mut s3s = S3Error::new(S3ErrorCode::InternalError);
+    // conditional reads are answered without a body, hence without an error code
+    let mut s3s = S3Error::new(match sdk.raw().status().as_u16() {
+        304 => S3ErrorCode::NotModified,
+        412 => S3ErrorCode::PreconditionFailed,
+        _ => S3ErrorCode::InternalError,
+    });
     let meta = sdk.err().meta();
     if let Some(s) = meta
         .code()
@@ -60,19 +177,52 @@ fn convert_sdk_err<E: ProvideErrorMetadata>(sdk: ServiceError<E, HttpResponse>)
 
 #[async_trait]
 impl S3 for S3Reproxy {
-    #[instrument(skip_all)]
+    #[instrument(skip_all, fields(remote.selected = Empty))]
     async fn list_buckets(
         &self,
         _req: S3Request<ListBucketsInput>,
     ) -> S3Result<S3Response<ListBucketsOutput>> {
-        info!("(intercepted) {}", self.bucket);
-        Ok(S3Response::new(ListBucketsOutput {
-            buckets: Some(vec![Bucket {
-                creation_date: None,
-                name: Some(self.bucket.clone()),
-            }]),
-            owner: None,
-        }))
+        self.check_operation("ListBuckets")?;
+        let remotes = self.remotes.load();
+        let Some(remote) = self
+            .list_buckets_from
+            .as_ref()
+            .and_then(|name| remotes.iter().find(|r| &r.name == name))
+        else {
+            info!("(intercepted) {}", self.bucket);
+            return Ok(S3Response::new(ListBucketsOutput {
+                buckets: Some(vec![Bucket {
+                    creation_date: None,
+                    name: Some(self.bucket.clone()),
+                }]),
+                owner: None,
+            }));
+        };
+
+        let Some(result) = remote
+            .request(|reply| remote::RemoteMessage::ListBuckets { reply })
+            .await
+        else {
+            warn!("remote({:?}) request failed.", remote.name);
+            return Err(s3_error!(InternalError));
+        };
+
+        let output = result
+            .map_err(convert_sdk_err)
+            .and_then(ListBucketsOutput::try_from_aws)?;
+
+        info!("ok (remote: {})", remote.name);
+        record_read_remote("ListBuckets", &remote.name);
+
+        let output = ListBucketsOutput {
+            buckets: Some(merge_bucket_listing(
+                &self.bucket,
+                &remote.bucket,
+                output.buckets.unwrap_or_default(),
+            )),
+            owner: output.owner,
+        };
+        Ok(with_remote(S3Response::new(output), &remote.name))
     }
 
     #[instrument(skip_all, fields(bucket = req.input.bucket))]
@@ -80,6 +230,7 @@ impl S3 for S3Reproxy {
         &self,
         req: S3Request<GetBucketLocationInput>,
     ) -> S3Result<S3Response<GetBucketLocationOutput>> {
+        self.check_operation("GetBucketLocation")?;
         if req.input.bucket != self.bucket {
             warn!("(intercepted) not found");
             return Err(s3_error!(NoSuchBucket));
@@ -95,6 +246,7 @@ impl S3 for S3Reproxy {
         &self,
         req: S3Request<HeadBucketInput>,
     ) -> S3Result<S3Response<HeadBucketOutput>> {
+        self.check_operation("HeadBucket")?;
         if req.input.bucket != self.bucket {
             warn!("(intercepted) not found");
             return Err(s3_error!(NoSuchBucket));
@@ -102,25 +254,111 @@ impl S3 for S3Reproxy {
 
         let output = HeadBucketOutput::default();
         info!("(intercepted) ok");
+        with_bucket_region(S3Response::new(output), self.reported_region.as_deref())
+    }
+
+    /// Succeeds for the virtual bucket, which always exists, so that provisioning tools creating
+    /// it before uploading can proceed. Nothing is created on the remotes.
+    #[instrument(skip_all, name = "s3s/create_bucket", fields(bucket = req.input.bucket))]
+    async fn create_bucket(
+        &self,
+        req: S3Request<CreateBucketInput>,
+    ) -> S3Result<S3Response<CreateBucketOutput>> {
+        self.check_operation("CreateBucket")?;
+        if req.input.bucket != self.bucket {
+            warn!("(intercepted) not the virtual bucket");
+            return Err(s3_error!(
+                AccessDenied,
+                "Only the bucket {} can be created on this endpoint",
+                self.bucket
+            ));
+        }
+
+        let output = CreateBucketOutput {
+            location: Some(format!("/{}", self.bucket)),
+        };
+        info!("(intercepted) ok");
         Ok(S3Response::new(output))
     }
 
-    #[instrument(skip_all, name = "s3s/upload_part", fields(part_number = &req.input.part_number))]
+    #[instrument(skip_all, name = "s3s/delete_bucket", fields(bucket = req.input.bucket))]
+    async fn delete_bucket(
+        &self,
+        req: S3Request<DeleteBucketInput>,
+    ) -> S3Result<S3Response<DeleteBucketOutput>> {
+        self.check_operation("DeleteBucket")?;
+        if req.input.bucket != self.bucket {
+            warn!("(intercepted) not found");
+            return Err(s3_error!(NoSuchBucket));
+        }
+
+        warn!("(intercepted) the virtual bucket cannot be deleted");
+        Err(s3_error!(
+            MethodNotAllowed,
+            "The bucket {} is backed by every remote and cannot be deleted",
+            self.bucket
+        ))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/upload_part",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            part_number = &req.input.part_number,
+            mongodb.collection = "multipart_upload_ids",
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
     async fn upload_part(
         &self,
         req: S3Request<UploadPartInput>,
     ) -> S3Result<S3Response<UploadPartOutput>> {
+        self.check_operation("UploadPart")?;
+        let remotes = self.remotes.load();
         info!("multipling...");
-        let (id, remotes) = self.initiate_multipart(req.input.upload_id.clone()).await?;
-
-        let input = UploadPartInput::try_into_aws(req.input)?;
+        let upload_id = req.input.upload_id.clone();
+        let (id, uploads) = self
+            .initiate_multipart(&remotes, upload_id.clone(), &req.input.key)
+            .await?;
+        let total = uploads.len();
+
+        let mut input = UploadPartInput::try_into_aws(req.input)?;
+        let part_number = input.part_number;
+
+        // a part can only be resent to a remote if it is kept around, which large parts are not
+        let retry_body = if self.upload_part_retries > 0
+            && input
+                .content_length
+                .is_some_and(|len| len as u64 <= self.upload_part_retry_max_bytes)
+        {
+            let body = std::mem::take(&mut input.body)
+                .collect()
+                .await
+                .map_err(|e| {
+                    error!("failed to buffer part: {:?}", e);
+                    S3Error::new(S3ErrorCode::InternalError)
+                })?
+                .into_bytes();
+            input.body = ByteStream::from(body.clone());
+            Some(body)
+        } else {
+            None
+        };
 
+        let part_size = input
+            .content_length
+            .or(retry_body.as_ref().map(|b| b.len() as i64));
         let (mut input_multiplier, signal) = UploadPartInputMultiplier::from_input(input);
-        let remotes = futures::stream::iter(remotes.into_iter())
+        let requests = futures::stream::iter(uploads.into_iter())
             .map(|(remote, id)| {
                 let remote = match remote {
                     Some(remote) => {
-                        let input = input_multiplier.input();
+                        let input = input_multiplier.input(&remote.name);
                         (Some((remote, input)), id)
                     }
                     None => (None, id),
@@ -128,16 +366,16 @@ impl S3 for S3Reproxy {
                 async move {
                     match remote {
                         (Some((remote, input)), id) => {
-                            let mut input = input.await.unwrap();
+                            let (mut input, completion) = input.await.unwrap();
                             input.upload_id = Some(id.upload_id.clone());
-                            (Some((remote, input)), id)
+                            (Some((remote, input, completion)), id)
                         }
                         (None, id) => (None, id),
                     }
                 }
             })
             .boxed()
-            .buffer_unordered(8)
+            .buffer_unordered(self.fanout_concurrency)
             .collect::<Vec<_>>()
             .await;
 
@@ -145,22 +383,31 @@ impl S3 for S3Reproxy {
         info!("multiplied (close)");
         signal.await.unwrap();
 
-        let (ids, results) = futures::stream::iter(remotes.into_iter())
+        let input_multiplier = &input_multiplier;
+        let retry_body = &retry_body;
+        let (ids, results) = futures::stream::iter(requests.into_iter())
             .map(|(remote, upload)| async move {
-                if let Some((remote, input)) = remote {
-                    let Some(result) = (try {
-                        let (tx, rx) = oneshot::channel();
-                        remote
-                            .tx
-                            .send(remote::RemoteMessage::UploadPart { input, reply: tx })
-                            .await
-                            .ok()?;
-                        rx.await.ok()??
-                    }) else {
+                if let Some((remote, input, completion)) = remote {
+                    let retries = retry_body.as_ref().map_or(0, |_| self.upload_part_retries);
+                    let resent = AtomicBool::new(false);
+                    let resend = || {
+                        resent.store(true, Ordering::Relaxed);
+                        let mut input = input_multiplier
+                            .with_body(retry_body.clone().unwrap_or_default().into());
+                        input.upload_id = Some(upload.upload_id.clone());
+                        input
+                    };
+                    let Some(result) = upload_part_with_retry(remote, input, retries, resend).await
+                    else {
                         warn!("remote({:?}) request failed. cancelling", remote.name);
                         return (upload.cancelled(), None);
                     };
-                    (upload, Some((remote.name.clone(), result)))
+                    settle_part_upload(
+                        upload,
+                        &completion,
+                        resent.load(Ordering::Relaxed),
+                        (remote.name.clone(), result),
+                    )
                 } else {
                     info!(
                         "remote({:?}) has already been cancelled by another s3-reproxy replica",
@@ -170,87 +417,240 @@ impl S3 for S3Reproxy {
                 }
             })
             .boxed()
-            .buffer_unordered(8)
+            .buffer_unordered(self.fanout_concurrency)
             .collect::<(Vec<_>, Vec<_>)>()
             .await;
 
         let results = results.into_iter().flatten().collect::<Vec<_>>();
 
-        let output = output_remote_inconsistent(results)?;
+        let diverged = match self.part_etag_divergence {
+            Some(_) => diverging_part_etags(&remotes, &results),
+            None => vec![],
+        };
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            self.write_quorum,
+            total,
+            self.failure_responses,
+        )?;
 
-        self.db
-            .multipart_upload_ids
-            .update_one(
-                doc! { "_id": id },
-                doc! {
-                    "$set": {
-                        "upload_ids": mongodb::bson::to_bson(&ids).unwrap(),
-                    },
-                },
-            )
-            .await
-            .map_err(|e| {
-                error!("mongodb error: {:?}", e);
-                S3Error::new(S3ErrorCode::InternalError)
-            })?;
+        // a rejected part is left unrecorded, as the client is told it was not stored
+        self.check_part_etags(part_number, &diverged)?;
+        if let Some(id) = id {
+            self.record_part(id, &ids, part_number, part_size).await?;
+        }
 
-        info!("ok (upload_id: {})", id);
+        info!("ok (upload_id: {})", upload_id);
 
         Ok(S3Response::new(UploadPartOutput::try_from_aws(output)?))
     }
 
-    #[instrument(skip_all, name = "s3s/complete_multipart_upload")]
+    #[instrument(
+        skip_all,
+        name = "s3s/upload_part_copy",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            part_number = &req.input.part_number,
+            mongodb.collection = "multipart_upload_ids",
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
+    async fn upload_part_copy(
+        &self,
+        req: S3Request<UploadPartCopyInput>,
+    ) -> S3Result<S3Response<UploadPartCopyOutput>> {
+        self.check_operation("UploadPartCopy")?;
+        let remotes = self.remotes.load();
+        let upload_id = req.input.upload_id.clone();
+        let (id, uploads) = self
+            .initiate_multipart(&remotes, upload_id.clone(), &req.input.key)
+            .await?;
+        let total = uploads.len();
+
+        let input = UploadPartCopyInput::try_into_aws(req.input)?;
+        let part_number = input.part_number;
+        let part_size = copied_part_size(input.copy_source_range.as_deref());
+
+        let input = &input;
+        let (ids, results) = futures::stream::iter(uploads.into_iter())
+            .map(|(remote, upload)| async move {
+                let Some(remote) = remote else {
+                    info!(
+                        "remote({:?}) has already been cancelled by another s3-reproxy replica",
+                        upload.remote_name
+                    );
+                    return (upload, None);
+                };
+                let mut input = input.clone();
+                input.upload_id = Some(upload.upload_id.clone());
+                let Some(result) = remote
+                    .request(|reply| remote::RemoteMessage::UploadPartCopy { input, reply })
+                    .await
+                else {
+                    warn!("remote({:?}) request failed. cancelling", remote.name);
+                    return (upload.cancelled(), None);
+                };
+                (upload, Some((remote.name.clone(), result)))
+            })
+            .boxed()
+            .buffer_unordered(self.fanout_concurrency)
+            .collect::<(Vec<_>, Vec<_>)>()
+            .await;
+
+        let results = results.into_iter().flatten().collect::<Vec<_>>();
+        let ids = cancel_failed_copies(ids, &results);
+
+        let diverged = match self.part_etag_divergence {
+            Some(_) => diverging_part_etags(&remotes, &results),
+            None => vec![],
+        };
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            self.write_quorum,
+            total,
+            self.failure_responses,
+        )?;
+
+        // a rejected part is left unrecorded, as the client is told it was not stored
+        self.check_part_etags(part_number, &diverged)?;
+        if let Some(id) = id {
+            self.record_part(id, &ids, part_number, part_size).await?;
+        }
+
+        info!("ok (upload_id: {})", upload_id);
+
+        Ok(S3Response::new(UploadPartCopyOutput::try_from_aws(output)?))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/complete_multipart_upload",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            mongodb.collection = "multipart_upload_ids",
+        )
+    )]
     async fn complete_multipart_upload(
         &self,
         req: S3Request<CompleteMultipartUploadInput>,
     ) -> S3Result<S3Response<CompleteMultipartUploadOutput>> {
-        let (id, remotes) = self.initiate_multipart(req.input.upload_id.clone()).await?;
+        self.check_operation("CompleteMultipartUpload")?;
+        let upload_id = req.input.upload_id.clone();
+        let ttl = self
+            .object_ttl
+            .as_ref()
+            .map(|c| requested_ttl(&req.headers, c))
+            .transpose()?;
+        let remotes = self.remotes.load();
+        let (id, uploads) = self
+            .initiate_multipart(&remotes, upload_id.clone(), &req.input.key)
+            .await?;
 
         let input = CompleteMultipartUploadInput::try_into_aws(req.input)?;
+        if let (Some(declared), Some(id)) = (declared_object_size(&req.headers)?, id) {
+            self.check_object_size(id, declared, input.multipart_upload.as_ref())
+                .await?;
+        }
+        self.invalidate_prefetch(input.key.as_deref());
 
-        let results = futures::stream::iter(remotes.into_iter())
+        let outcomes = futures::stream::iter(uploads.into_iter())
             .map(|(remote, upload)| {
                 let value = input.clone();
                 async move {
                     if let Some(remote) = remote {
-                        let Some(_result) = (try {
-                            let (tx, rx) = oneshot::channel();
-                            let mut input = value.clone();
-                            input.upload_id = Some(upload.upload_id.clone());
-                            remote
-                                .tx
-                                .send(remote::RemoteMessage::CompleteMultiPartUpload {
-                                    input,
-                                    reply: tx,
-                                })
-                                .await
-                                .ok()?;
-                            rx.await.ok()??
-                        }) else {
+                        let mut input = value.clone();
+                        input.upload_id = Some(upload.upload_id.clone());
+                        let Some(result) = remote
+                            .request(|reply| remote::RemoteMessage::CompleteMultiPartUpload {
+                                input,
+                                reply,
+                            })
+                            .await
+                        else {
                             warn!("remote({:?}) request failed. cancelling", remote.name);
-                            return upload.cancelled();
+                            return (upload.cancelled(), None);
                         };
-                        upload
+                        if let Err(e) = &result {
+                            warn!("remote({:?}) rejected the upload: {:?}", remote.name, e);
+                        }
+                        (upload, Some((remote.name.clone(), result)))
                     } else {
                         info!(
                             "remote({:?}) has already been cancelled by another s3-reproxy replica",
                             upload.remote_name
                         );
-                        upload
+                        (upload, None)
                     }
                 }
             })
             .boxed()
-            .buffer_unordered(8)
+            .buffer_unordered(self.fanout_concurrency)
             .collect::<Vec<_>>()
             .await;
+        let (results, replies): (Vec<_>, Vec<_>) = outcomes.into_iter().unzip();
+        let replies = replies.into_iter().flatten().collect::<Vec<_>>();
+        // an object under a customer key cannot be read back to copy it
+        let repair = pending_repair(&remotes, &replies, |o| o.e_tag())
+            .filter(|_| input.sse_customer_algorithm.is_none());
+        let (completions, rejections): (Vec<_>, Vec<_>) =
+            replies
+                .into_iter()
+                .partition_map(|(remote, result)| match result {
+                    Ok(output) => Either::Left((remote, output)),
+                    Err(e) => Either::Right((remote, e)),
+                });
+        let completed = completions.len() >= self.complete_quorum.required(results.len()).max(1);
+        if completed {
+            // the remotes that rejected or missed the completion get a copy of the object instead
+            for (remote, _) in rejections.iter() {
+                INCONSISTENT_WRITES.inc(&[remote.as_str()]);
+            }
+            self.queue_repair(input.key.as_deref(), repair).await;
+        }
+        let rejection = most_relevant_error(rejections.into_iter().map(|(_, e)| e).collect());
+        self.record_written_key(
+            input.key.as_deref(),
+            completions.iter().map(|(remote, _)| remote.as_str()),
+        );
+        let output = completed
+            .then(|| {
+                completion_output(
+                    &remotes,
+                    completions,
+                    input.bucket.clone(),
+                    input.key.clone(),
+                )
+            })
+            .transpose()?;
+
+        if let (true, Some(ttl), Some(key)) = (completed, ttl, input.key.as_deref()) {
+            self.record_expiry(key, ttl).await;
+        }
+
+        if let (true, Some(key)) = (completed, input.key.as_deref()) {
+            let parts = completed_parts_count(input.multipart_upload.as_ref());
+            self.record_parts_count(key, Some(parts)).await;
+            self.notify(
+                "ObjectCreated:CompleteMultipartUpload",
+                Some(key),
+                None,
+                output.as_ref().and_then(|o| o.e_tag.as_deref()),
+            );
+        }
 
         let bson = mongodb::bson::to_bson(&results).map_err(|e| {
             error!("mongodb serialization error: {:?}", e);
             S3Error::new(S3ErrorCode::InternalError)
         })?;
 
-        let (set, result) = if results.iter().all(|e| e.status == PartUploadStatus::Open) {
+        let (set, result) = if let Some(output) = output {
             (
                 doc! {
                     "$set": {
@@ -258,11 +658,7 @@ impl S3 for S3Reproxy {
                         "completed_at": mongodb::bson::DateTime::now(),
                     },
                 },
-                Ok(S3Response::new(CompleteMultipartUploadOutput {
-                    bucket: input.bucket,
-                    key: input.key,
-                    ..Default::default()
-                })),
+                Ok(S3Response::new(output)),
             )
         } else {
             warn!("no remotes are remains without rejection in multipart upload.");
@@ -272,76 +668,235 @@ impl S3 for S3Reproxy {
                         "upload_ids": bson,
                     },
                 },
-                Err(S3Error::new(S3ErrorCode::InternalError)),
+                Err(rejection.unwrap_or_else(|| self.failure_responses.no_remote_error())),
             )
         };
 
-        self.db
-            .multipart_upload_ids
-            .update_one(doc! { "_id": id }, set)
+        if let Some(id) = id {
+            self.db
+                .multipart_upload_ids
+                .update_one(doc! { "_id": id }, set)
+                .await
+                .map_err(|e| {
+                    error!("mongodb error: {:?}", e);
+                    S3Error::new(S3ErrorCode::InternalError)
+                })?;
+        }
+
+        info!("ok (upload_id: {})", upload_id);
+
+        result
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/abort_multipart_upload",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            mongodb.collection = "multipart_upload_ids",
+        )
+    )]
+    async fn abort_multipart_upload(
+        &self,
+        req: S3Request<AbortMultipartUploadInput>,
+    ) -> S3Result<S3Response<AbortMultipartUploadOutput>> {
+        self.check_operation("AbortMultipartUpload")?;
+        let remotes = self.remotes.load();
+        let upload_id = req.input.upload_id.clone();
+        // completed and aborted uploads are not found either, which S3 answers with NoSuchUpload
+        let (id, uploads) = self
+            .initiate_multipart(&remotes, upload_id.clone(), &req.input.key)
             .await
             .map_err(|e| {
-                error!("mongodb error: {:?}", e);
-                S3Error::new(S3ErrorCode::InternalError)
+                if *e.code() == S3ErrorCode::InvalidToken {
+                    s3_error!(NoSuchUpload)
+                } else {
+                    e
+                }
             })?;
+        let upload_ids = uploads.into_iter().map(|(_, upload)| upload).collect_vec();
+
+        let results = abandoned::abort_upload(
+            &remotes,
+            &self.db,
+            id,
+            &req.input.key,
+            &upload_ids,
+            self.fanout_concurrency,
+        )
+        .await
+        .map_err(|e| {
+            error!("mongodb error: {:?}", e);
+            S3Error::new(S3ErrorCode::InternalError)
+        })?;
+        let failed = results
+            .iter()
+            .filter(|r| r.outcome == AbortOutcome::Failed)
+            .map(|r| r.remote.as_str())
+            .collect_vec();
+        if !failed.is_empty() {
+            warn!("upload could not be aborted on {:?}", failed);
+            return Err(self
+                .failure_responses
+                .below_quorum_error(results.len() - failed.len(), results.len()));
+        }
 
-        info!("ok (upload_id: {})", id);
+        info!("ok (upload_id: {})", upload_id);
 
-        result
+        Ok(S3Response::new(AbortMultipartUploadOutput::default()))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/list_multipart_uploads",
+        fields(bucket = req.input.bucket)
+    )]
+    async fn list_multipart_uploads(
+        &self,
+        req: S3Request<ListMultipartUploadsInput>,
+    ) -> S3Result<S3Response<ListMultipartUploadsOutput>> {
+        self.check_operation("ListMultipartUploads")?;
+        let input = ListMultipartUploadsInput::try_into_aws(req.input)?;
+        let output = self.in_flight_uploads(&input).await?;
+
+        info!("(intercepted) ok ({} uploads)", output.uploads().len());
+
+        Ok(S3Response::new(ListMultipartUploadsOutput::try_from_aws(
+            output,
+        )?))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/list_parts",
+        fields(bucket = req.input.bucket, key = req.input.key, remote.selected = Empty)
+    )]
+    async fn list_parts(
+        &self,
+        req: S3Request<ListPartsInput>,
+    ) -> S3Result<S3Response<ListPartsOutput>> {
+        self.check_operation("ListParts")?;
+        let remotes = self.remotes.load();
+        let upload_id = req.input.upload_id.clone();
+        // completed and aborted uploads are not found either, which S3 answers with NoSuchUpload
+        let (_, uploads) = self
+            .initiate_multipart(&remotes, upload_id.clone(), &req.input.key)
+            .await
+            .map_err(|e| {
+                if *e.code() == S3ErrorCode::InvalidToken {
+                    s3_error!(NoSuchUpload)
+                } else {
+                    e
+                }
+            })?;
+        let open = uploads
+            .into_iter()
+            .filter_map(|(remote, upload)| Some((remote?.name.clone(), upload.upload_id)))
+            .collect::<HashMap<_, _>>();
+
+        let input = ListPartsInput::try_into_aws(req.input)?;
+        let Some((result, remote)) = list_parts_on_remotes(
+            &read_order(&remotes),
+            &open,
+            &input,
+            self.read_quick_retries,
+        )
+        .await
+        else {
+            return Err(self.failure_responses.no_remote_error());
+        };
+
+        info!("ok (remote: {}, upload_id: {})", remote, upload_id);
+        record_read_remote("ListParts", &remote);
+
+        let mut output = result
+            .map_err(convert_sdk_err)
+            .and_then(ListPartsOutput::try_from_aws)?;
+        output.bucket = Some(self.bucket.clone());
+        output.upload_id = Some(upload_id);
+
+        Ok(with_remote(S3Response::new(output), &remote))
     }
 
-    #[instrument(skip_all, name = "s3s/create_multipart_upload")]
+    #[instrument(
+        skip_all,
+        name = "s3s/create_multipart_upload",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            mongodb.collection = "multipart_upload_ids",
+        )
+    )]
     async fn create_multipart_upload(
         &self,
         req: S3Request<CreateMultipartUploadInput>,
     ) -> S3Result<S3Response<CreateMultipartUploadOutput>> {
+        self.check_operation("CreateMultipartUpload")?;
+        let remotes = self.remotes.load();
+        check_checksum_algorithm(
+            &remotes,
+            req.input.checksum_algorithm.as_ref().map(|a| a.as_str()),
+        )?;
+        check_metadata_size(&remotes, req.input.metadata.as_ref())?;
+        if let Some(limit) = self.max_active_multipart_uploads {
+            let active = self
+                .db
+                .multipart_upload_ids
+                .count_documents(doc! { "completed_at": null, "aborted_at": null })
+                .await
+                .map_err(|e| {
+                    error!("mongodb error: {:?}", e);
+                    S3Error::new(S3ErrorCode::InternalError)
+                })?;
+            check_upload_limit(active, limit)?;
+        }
         let input = CreateMultipartUploadInput::try_into_aws(req.input)?;
-        let results = futures::stream::iter(self.remotes.iter())
+        let results = futures::stream::iter(remotes.iter())
             .map(|remote| async {
-                let Some(result) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::CreateMultiPartUpload {
-                            input: input.clone(),
-                            reply: tx,
-                        })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
+                let Some(result) = remote
+                    .request(|reply| remote::RemoteMessage::CreateMultiPartUpload {
+                        input: input.clone(),
+                        reply,
+                    })
+                    .await
+                else {
                     warn!("remote({:?}) request failed. skipping", remote.name);
                     return None;
                 };
                 Some((remote.name.clone(), result))
             })
             .boxed()
-            .buffer_unordered(8)
+            .buffer_unordered(self.fanout_concurrency)
             .filter_map(|e| async { e })
             .collect::<Vec<_>>()
             .await;
 
-        let ids = results
-            .into_iter()
-            .filter_map(|(remote, result)| match result {
-                Ok(output) => Some(RemoteMultipartUploadId {
-                    remote_name: remote,
-                    upload_id: output.upload_id.expect("upload_id missing"),
-                    status: PartUploadStatus::Open,
-                }),
-                Err(e) => {
-                    warn!("remote({:?}) failed: {:?}", remote, e);
-                    None
-                }
-            });
+        let ids = opened_uploads(results, self.failure_responses)?;
 
+        let now = mongodb::bson::DateTime::now();
         let ids = MultipartUploadIds {
-            upload_ids: ids.collect(),
-            created_at: mongodb::bson::DateTime::now(),
+            upload_ids: ids,
+            key: input.key.clone(),
+            created_at: now,
+            last_activity: Some(now),
+            part_sizes: HashMap::new(),
             completed_at: None,
             aborted_at: None,
         };
 
+        if let Some(token) = self.upload_tokens.as_ref().and_then(|codec| {
+            codec.encode(input.key.as_deref().unwrap_or_default(), &ids.upload_ids)
+        }) {
+            info!("ok (signed upload_id)");
+            return Ok(S3Response::new(CreateMultipartUploadOutput {
+                bucket: input.bucket,
+                key: input.key,
+                upload_id: Some(token),
+                ..Default::default()
+            }));
+        }
+
         let id = self
             .db
             .multipart_upload_ids
@@ -366,197 +921,773 @@ impl S3 for S3Reproxy {
         }))
     }
 
-    #[instrument(skip_all, name = "s3s/put_object")]
+    #[instrument(
+        skip_all,
+        name = "s3s/put_object",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
     async fn put_object(
         &self,
         req: S3Request<PutObjectInput>,
     ) -> S3Result<S3Response<PutObjectOutput>> {
-        let input = PutObjectInput::try_into_aws(req.input)?;
-        let (mut input_multiplier, signal) = PutObjectInputMultiplier::from_input(input);
-        let remotes = futures::stream::iter(self.remotes.iter())
-            .map(|remote| {
-                let input = input_multiplier.input();
-                async move { (remote, input.await.unwrap()) }
+        self.check_operation("PutObject")?;
+        let remotes = self.remotes.load();
+        if let Some(expected) = req.headers.get(http::header::IF_MATCH) {
+            let expected = expected
+                .to_str()
+                .map_err(|_| s3_error!(InvalidArgument, "invalid If-Match header"))?;
+            let current = self.current_etags(&req.input.key).await?;
+            check_if_match(expected, &current, self.failure_responses)?;
+        }
+        let if_none_match = write_if_absent(&req.headers)?;
+
+        check_checksum_algorithm(
+            &remotes,
+            req.input.checksum_algorithm.as_ref().map(|a| a.as_str()),
+        )?;
+        check_metadata_size(&remotes, req.input.metadata.as_ref())?;
+        let ttl = self
+            .object_ttl
+            .as_ref()
+            .map(|c| requested_ttl(&req.headers, c))
+            .transpose()?;
+
+        let mut input = PutObjectInput::try_into_aws(req.input)?;
+        input.if_none_match = if_none_match;
+        self.invalidate_prefetch(input.key.as_deref());
+        let key = input.key.clone();
+        let size = input.content_length;
+        let customer_key = input.sse_customer_algorithm.is_some();
+        let digest = requested_checksum(&input).map(|(checksum, expected)| {
+            let (body, check) =
+                verify_upload_checksum(std::mem::take(&mut input.body), checksum, expected);
+            input.body = body;
+            check
+        });
+        let bad_digest = || {
+            digest.as_ref().is_some_and(|d| d.mismatched()).then(|| {
+                warn!("(intercepted) body does not match the checksum sent with it");
+                s3_error!(
+                    BadDigest,
+                    "The body does not match the checksum you specified"
+                )
             })
-            .boxed()
-            .buffer_unordered(8)
-            .collect::<Vec<_>>()
-            .await;
-        input_multiplier.close();
-        signal.await.unwrap();
-        let results = futures::stream::iter(remotes.into_iter())
-            .map(|(remote, input)| async move {
-                let Some(result) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::PutObject { input, reply: tx })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
+        };
+        let requests = match &self.disk_spool {
+            Some(spool) if spool.applies(input.content_length) => {
+                let body = std::mem::take(&mut input.body);
+                let (template, _) = PutObjectInputMultiplier::from_input(input);
+                let (bodies, length) = spool_shared(body, &spool.dir, remotes.len())
+                    .await
+                    .map_err(|e| {
+                        bad_digest().unwrap_or_else(|| {
+                            error!("failed to spool the body to disk: {:?}", e);
+                            S3Error::new(S3ErrorCode::InternalError)
+                        })
+                    })?;
+                info!("spooled {} bytes to disk", length);
+                remotes
+                    .iter()
+                    .zip(bodies)
+                    .map(|(remote, body)| {
+                        let mut input = template.with_body(body);
+                        input.content_length = Some(length);
+                        (remote, input)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            _ => {
+                let (mut input_multiplier, signal) = PutObjectInputMultiplier::from_input(input);
+                let requests = futures::stream::iter(remotes.iter())
+                    .map(|remote| {
+                        let input = input_multiplier.input(&remote.name);
+                        async move { (remote, input.await.unwrap()) }
+                    })
+                    .boxed()
+                    .buffer_unordered(self.fanout_concurrency)
+                    .collect::<Vec<_>>()
+                    .await;
+                input_multiplier.close();
+                signal.await.unwrap();
+                requests
+            }
+        };
+        let results = futures::stream::iter(requests.into_iter())
+            .map(|(remote, mut input)| async move {
+                if input.content_length.is_none() && remote.requires_content_length {
+                    match spool(std::mem::take(&mut input.body)).await {
+                        Ok((body, length)) => {
+                            input.body = body;
+                            input.content_length = Some(length);
+                        }
+                        Err(e) => {
+                            warn!(
+                                "remote({:?}) upload could not be spooled: {:?}. skipping",
+                                remote.name, e
+                            );
+                            return None;
+                        }
+                    }
+                }
+                let Some(result) = remote
+                    .request(|reply| remote::RemoteMessage::PutObject { input, reply })
+                    .await
+                else {
                     warn!("remote({:?}) request failed. skipping", remote.name);
                     return None;
                 };
                 Some((remote.name.clone(), result))
             })
             .boxed()
-            .buffer_unordered(8)
+            .buffer_unordered(self.fanout_concurrency)
             .filter_map(|e| async { e })
             .collect::<Vec<_>>()
             .await;
+        if let Some(e) = bad_digest() {
+            return Err(e);
+        }
+
+        // a write-if-absent must not land on the remotes missing the object alone
+        let present = failed_preconditions(&results);
+        if !present.is_empty() {
+            info!("(intercepted) object already exists on {:?}", present);
+            let stored = remotes
+                .iter()
+                .filter_map(|r| {
+                    let (_, result) = results.iter().find(|(name, _)| *name == r.name)?;
+                    Some((r, result.as_ref().ok()?.e_tag.clone()))
+                })
+                .collect::<Vec<_>>();
+            if let Some(key) = key.as_deref() {
+                self.undo_write_if_absent(key, &stored).await;
+            }
+            return Err(s3_error!(PreconditionFailed));
+        }
+
+        self.record_written_key(
+            key.as_deref(),
+            results
+                .iter()
+                .filter(|(_, r)| r.is_ok())
+                .map(|(remote, _)| remote.as_str()),
+        );
+
+        // an object under a customer key cannot be read back to copy it
+        let repair = pending_repair(&remotes, &results, |o| o.e_tag()).filter(|_| !customer_key);
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            self.write_quorum,
+            remotes.len(),
+            self.failure_responses,
+        )?;
+        self.queue_repair(key.as_deref(), repair).await;
 
-        let output = output_remote_inconsistent(results)?;
+        if let (Some(ttl), Some(key)) = (ttl, key.as_deref()) {
+            self.record_expiry(key, ttl).await;
+        }
+        if let Some(key) = key.as_deref() {
+            self.record_parts_count(key, None).await;
+        }
+        self.notify(
+            "ObjectCreated:Put",
+            key.as_deref(),
+            size,
+            output.e_tag.as_deref(),
+        );
 
         Ok(S3Response::new(PutObjectOutput::try_from_aws(output)?))
     }
 
-    #[instrument(skip_all, name = "s3s/delete_objects")]
+    #[instrument(
+        skip_all,
+        name = "s3s/copy_object",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
+    async fn copy_object(
+        &self,
+        req: S3Request<CopyObjectInput>,
+    ) -> S3Result<S3Response<CopyObjectOutput>> {
+        self.check_operation("CopyObject")?;
+        let ttl = self
+            .object_ttl
+            .as_ref()
+            .map(|c| requested_ttl(&req.headers, c))
+            .transpose()?;
+        let remotes = self.remotes.load();
+        let input = CopyObjectInput::try_into_aws(req.input)?;
+        self.invalidate_prefetch(input.key.as_deref());
+        let results = copy_to_remotes(&remotes, &input, self.fanout_concurrency).await;
+
+        let copied = remotes
+            .iter()
+            .filter(|remote| {
+                results
+                    .iter()
+                    .any(|(name, result)| *name == remote.name && result.is_ok())
+            })
+            .collect::<Vec<_>>();
+        self.record_written_key(
+            input.key.as_deref(),
+            copied.iter().map(|remote| remote.name.as_str()),
+        );
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            remotes.len(),
+            self.failure_responses,
+        )?;
+
+        // the copy is a new object, which expires like one that was put
+        if let (Some(ttl), Some(key)) = (ttl, input.key.as_deref()) {
+            self.record_expiry(key, ttl).await;
+        }
+        if let Some(key) = input.key.as_deref() {
+            self.record_parts_count(key, None).await;
+        }
+
+        if let (true, Some(key)) = (copies_metadata(&input), input.key.as_deref()) {
+            let diverged = diverging_copies(&copied, key).await;
+            if !diverged.is_empty() {
+                warn!("copied metadata diverges across remotes: {:?}", diverged);
+                if self.copy_divergence == DivergencePolicy::Strict {
+                    return Err(s3_error!(
+                        InternalError,
+                        "copied object metadata diverges across remotes"
+                    ));
+                }
+            }
+        }
+
+        Ok(S3Response::new(CopyObjectOutput::try_from_aws(output)?))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/get_object_tagging",
+        fields(bucket = req.input.bucket, key = req.input.key, remote.selected = Empty)
+    )]
+    async fn get_object_tagging(
+        &self,
+        req: S3Request<GetObjectTaggingInput>,
+    ) -> S3Result<S3Response<GetObjectTaggingOutput>> {
+        self.check_operation("GetObjectTagging")?;
+        let remotes = self.remotes.load();
+        let input = GetObjectTaggingInput::try_into_aws(req.input)?;
+
+        let Some((result, remote)) = ('request: {
+            for remote in read_order(&remotes) {
+                let Some(output) =
+                    read_with_quick_retry(remote, self.read_quick_retries, |reply| {
+                        remote::RemoteMessage::GetObjectTagging {
+                            input: input.clone(),
+                            reply,
+                        }
+                    })
+                    .await
+                else {
+                    warn!("remote({:?}) request failed. skipping", remote.name);
+                    continue;
+                };
+                break 'request Some((output, remote.name.clone()));
+            }
+            None
+        }) else {
+            return Err(self.failure_responses.no_remote_error());
+        };
+
+        info!("ok (remote: {})", remote);
+        record_read_remote("GetObjectTagging", &remote);
+
+        let output = result
+            .map_err(convert_sdk_err)
+            .and_then(GetObjectTaggingOutput::try_from_aws)?;
+        Ok(with_remote(S3Response::new(output), &remote))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/put_object_tagging",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
+    async fn put_object_tagging(
+        &self,
+        req: S3Request<PutObjectTaggingInput>,
+    ) -> S3Result<S3Response<PutObjectTaggingOutput>> {
+        self.check_operation("PutObjectTagging")?;
+        let remotes = self.remotes.load();
+        let input = PutObjectTaggingInput::try_into_aws(req.input)?;
+        let results = send_to_all(&remotes, self.fanout_concurrency, |reply| {
+            remote::RemoteMessage::PutObjectTagging {
+                input: input.clone(),
+                reply,
+            }
+        })
+        .await;
+
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            remotes.len(),
+            self.failure_responses,
+        )?;
+
+        Ok(S3Response::new(PutObjectTaggingOutput::try_from_aws(
+            output,
+        )?))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/delete_object_tagging",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
+    async fn delete_object_tagging(
+        &self,
+        req: S3Request<DeleteObjectTaggingInput>,
+    ) -> S3Result<S3Response<DeleteObjectTaggingOutput>> {
+        self.check_operation("DeleteObjectTagging")?;
+        let remotes = self.remotes.load();
+        let input = DeleteObjectTaggingInput::try_into_aws(req.input)?;
+        let results = send_to_all(&remotes, self.fanout_concurrency, |reply| {
+            remote::RemoteMessage::DeleteObjectTagging {
+                input: input.clone(),
+                reply,
+            }
+        })
+        .await;
+
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            remotes.len(),
+            self.failure_responses,
+        )?;
+
+        Ok(S3Response::new(DeleteObjectTaggingOutput::try_from_aws(
+            output,
+        )?))
+    }
+
+    #[instrument(
+        skip_all,
+        name = "s3s/delete_objects",
+        fields(
+            bucket = req.input.bucket,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
     async fn delete_objects(
         &self,
         req: S3Request<DeleteObjectsInput>,
     ) -> S3Result<S3Response<DeleteObjectsOutput>> {
+        self.check_operation("DeleteObjects")?;
+        let remotes = self.remotes.load();
         let input = DeleteObjectsInput::try_into_aws(req.input)?;
-        let results = futures::stream::iter(self.remotes.iter())
+        let results = futures::stream::iter(remotes.iter())
             .map(|remote| async {
-                let Some(result) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::DeleteObjects {
-                            input: input.clone(),
-                            reply: tx,
-                        })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
+                let Some(result) = remote
+                    .request(|reply| remote::RemoteMessage::DeleteObjects {
+                        input: input.clone(),
+                        reply,
+                    })
+                    .await
+                else {
                     warn!("remote({:?}) request failed. skipping", remote.name);
                     return None;
                 };
                 Some((remote.name.clone(), result))
             })
             .boxed()
-            .buffer_unordered(4)
+            .buffer_unordered(self.fanout_concurrency)
             .filter_map(|e| async { e })
             .collect::<Vec<_>>()
             .await;
 
-        let output = output_remote_inconsistent(results)?;
+        let failures = failed_deletions(&remotes, &input, &results);
+        if !failures.is_empty() {
+            warn!("{} keys were not deleted on every remote", failures.len());
+        }
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            remotes.len(),
+            self.failure_responses,
+        )?;
+
+        let output = with_failed_deletions(output, failures);
+        // keys some remote still holds keep their expiry, so the sweep deletes them eventually
+        if self.object_ttl.is_some() {
+            let deleted = output.deleted().iter().filter_map(|d| d.key()).collect();
+            self.forget_expiries(deleted).await;
+        }
 
         Ok(S3Response::new(DeleteObjectsOutput::try_from_aws(output)?))
     }
 
-    #[instrument(skip_all, name = "s3s/delete_object")]
+    #[instrument(
+        skip_all,
+        name = "s3s/delete_object",
+        fields(
+            bucket = req.input.bucket,
+            key = req.input.key,
+            remote.success_count = Empty,
+            remote.failure_count = Empty,
+            remote.succeeded = Empty,
+            remote.failed = Empty,
+        )
+    )]
     async fn delete_object(
         &self,
         req: S3Request<DeleteObjectInput>,
     ) -> S3Result<S3Response<DeleteObjectOutput>> {
+        self.check_operation("DeleteObject")?;
+        let remotes = self.remotes.load();
         let input = DeleteObjectInput::try_into_aws(req.input)?;
-        let results = futures::stream::iter(self.remotes.iter())
-            .map(|remote| async {
-                let Some(result) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::DeleteObject {
-                            input: input.clone(),
-                            reply: tx,
-                        })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
-                    warn!("remote({:?}) request failed. skipping", remote.name);
-                    return None;
-                };
-                Some((remote.name.clone(), result))
-            })
-            .boxed()
-            .buffer_unordered(4)
-            .filter_map(|e| async { e })
-            .collect::<Vec<_>>()
-            .await;
+        self.invalidate_prefetch(input.key.as_deref());
+        let results = delete_on_remotes(&remotes, &input, self.fanout_concurrency).await;
+
+        let output = output_remote_inconsistent(
+            &remotes,
+            results,
+            WriteQuorum::One,
+            remotes.len(),
+            self.failure_responses,
+        )?;
 
-        let output = output_remote_inconsistent(results)?;
+        if let (Some(_), Some(key)) = (&self.object_ttl, input.key.as_deref()) {
+            self.record_expiry(key, None).await;
+        }
+        if let Some(key) = input.key.as_deref() {
+            self.record_parts_count(key, None).await;
+        }
+        self.notify("ObjectRemoved:Delete", input.key.as_deref(), None, None);
 
         Ok(S3Response::new(DeleteObjectOutput::try_from_aws(output)?))
     }
 
-    #[instrument(skip_all, name = "s3s/get_object")]
+    #[instrument(
+        skip_all,
+        name = "s3s/get_object",
+        fields(bucket = req.input.bucket, key = req.input.key, remote.selected = Empty)
+    )]
     async fn get_object(
         &self,
         req: S3Request<GetObjectInput>,
     ) -> S3Result<S3Response<GetObjectOutput>> {
-        let read_remotes = self.remotes.iter().sorted_by(|a, b| {
-            b.read_request
-                .cmp(&a.read_request)
-                .then_with(|| b.priority.cmp(&a.priority))
-        });
-
-        let input = GetObjectInput::try_into_aws(req.input)?;
+        self.check_operation("GetObject")?;
+        let remotes = self.remotes.load();
+        let mut input = GetObjectInput::try_into_aws(req.input)?;
+        let forced = self.forced_remote(&req.headers, &remotes)?;
+
+        let mut read_remotes = match forced {
+            Some(remote) => vec![remote],
+            None => {
+                let mut ordered = self.admitted(read_order(&remotes));
+                if self.read_strategy == ReadStrategy::WeightedRandom {
+                    ordered = weighted_first(ordered, random());
+                }
+                order_by_key_filter(ordered, input.key.as_deref())
+            }
+        };
 
-        let Some((result, remote)) = ('request: {
-            for remote in read_remotes {
-                let Some(output) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::GetObject {
+        let client_checksum_mode = input.checksum_mode.clone();
+        if self.verify_get_checksums {
+            input.checksum_mode = Some(aws_sdk_s3::types::ChecksumMode::Enabled);
+        }
+
+        let fresh = forced.is_none() && wants_fresh(&req.uri);
+        if fresh {
+            let newest = match head_input(&input) {
+                Some(head) => newest_remote(&read_remotes, &head).await,
+                None => None,
+            };
+            if let Some((newest, _)) = newest {
+                info!("(fresh) newest copy is on remote({:?})", newest.name);
+                read_remotes.retain(|r| r.name != newest.name);
+                read_remotes.insert(0, newest);
+            }
+        }
+
+        // reads sent to a named remote are answered by it, never from the buffer
+        let bypass_buffer = forced.is_some() || bypasses_buffer(&input);
+        let prefetch = match (
+            &self.prefetcher,
+            input.key.clone(),
+            input.range.as_deref().and_then(parse_range),
+        ) {
+            // a remote that ignores ranges would send bytes that do not start where they are
+            // buffered from, so nothing is prefetched unless every remote honors them
+            (Some(prefetcher), Some(key), Some((start, end)))
+                if !fresh && !bypass_buffer && ranges_supported(&remotes) =>
+            {
+                match prefetcher.plan(&key, start, end) {
+                    PrefetchPlan::Cached(data, meta) => {
+                        let current = match read_remotes.first() {
+                            Some(remote) => {
+                                still_current(remote, &input, meta.e_tag.as_deref()).await
+                            }
+                            None => false,
+                        };
+                        if current {
+                            info!("ok (prefetched)");
+                            let mut output = ranged_output(start, data, meta);
+                            apply_response_overrides(&mut output, &input);
+                            let output = GetObjectOutput::try_from_aws(output)?;
+                            return Ok(S3Response::new(output));
+                        }
+                        info!("(prefetch) buffered range of {:?} is outdated", key);
+                        prefetcher.invalidate(&key);
+                        None
+                    }
+                    PrefetchPlan::Prefetch(until) => {
+                        let requested = input.clone();
+                        input.range = Some(format!("bytes={start}-{until}"));
+                        clear_response_overrides(&mut input);
+                        Some((prefetcher, key, start, end, requested))
+                    }
+                    PrefetchPlan::Passthrough => None,
+                }
+            }
+            _ => None,
+        };
+
+        // a fresh read has to come from the newest copy, however long it takes
+        let mut hedge = None;
+        if !fresh {
+            let input = &input;
+            (hedge, read_remotes) = race_read_tier(
+                read_remotes,
+                self.read_strategy,
+                self.read_hedge_delay,
+                |remote| {
+                    read_with_quick_retry(remote, self.read_quick_retries, move |reply| {
+                        remote::RemoteMessage::GetObject {
                             input: input.clone(),
-                            reply: tx,
+                            reply,
+                        }
+                    })
+                },
+            )
+            .await;
+        }
+
+        let Some((mut result, remote)) = ('request: {
+            for remote in read_remotes {
+                let output = match hedge.take() {
+                    Some((hedged, output)) if hedged.name == remote.name => Some(output),
+                    _ => {
+                        read_with_quick_retry(remote, self.read_quick_retries, |reply| {
+                            remote::RemoteMessage::GetObject {
+                                input: input.clone(),
+                                reply,
+                            }
                         })
                         .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
+                    }
+                };
+                let Some(mut output) = output else {
                     warn!("remote({:?}) request failed. skipping", remote.name);
                     continue;
                 };
+                if let Ok(output) = output.as_mut() {
+                    output.body =
+                        sized_body(std::mem::take(&mut output.body), output.content_length);
+                }
+                if let (true, Ok(output)) = (self.verify_get_checksums, output.as_mut()) {
+                    if let Some((checksum, expected)) = advertised_checksum(output) {
+                        output.body =
+                            verify_checksum(std::mem::take(&mut output.body), checksum, expected);
+                    }
+                    if client_checksum_mode.is_none() {
+                        output.checksum_crc32 = None;
+                        output.checksum_crc32_c = None;
+                        output.checksum_sha1 = None;
+                        output.checksum_sha256 = None;
+                    }
+                }
+                if let (Some(limit), Ok(output)) = (self.get_retry_buffer_bytes, output.as_mut()) {
+                    match buffer_head(std::mem::take(&mut output.body), limit).await {
+                        Ok(body) => output.body = body,
+                        Err(e) => {
+                            warn!(
+                                "remote({:?}) body failed within the first {} bytes: {:?}. skipping",
+                                remote.name, limit, e
+                            );
+                            continue;
+                        }
+                    }
+                }
                 break 'request Some((output, remote.name.clone()));
             }
             None
         }) else {
-            warn!("no remotes available!");
-            return Err(s3_error!(InternalError));
+            return Err(self.failure_responses.no_remote_error());
         };
 
         info!("ok (remote: {})", remote);
+        record_read_remote("GetObject", &remote);
+
+        if let (Some((prefetcher, key, start, end, requested)), Ok(output)) =
+            (prefetch, result.as_mut())
+        {
+            // only a reply starting at the requested offset lines up with what the buffer expects
+            let from_start = output
+                .content_range
+                .as_deref()
+                .and_then(parse_content_range_start)
+                == Some(start);
+            if !from_start {
+                warn!(
+                    "remote({:?}) answered the prefetch of {:?} with {:?}. not buffering",
+                    remote, key, output.content_range
+                );
+            } else {
+                let data = std::mem::take(&mut output.body)
+                    .collect()
+                    .await
+                    .map_err(|e| {
+                        error!("failed to buffer prefetched body: {:?}", e);
+                        S3Error::new(S3ErrorCode::InternalError)
+                    })?
+                    .into_bytes();
+                #[allow(deprecated)]
+                let expires = output.expires;
+                let meta = RangeMeta {
+                    total: output
+                        .content_range
+                        .as_deref()
+                        .and_then(parse_content_range_total),
+                    e_tag: output.e_tag.clone(),
+                    content_type: output.content_type.clone(),
+                    cache_control: output.cache_control.clone(),
+                    content_disposition: output.content_disposition.clone(),
+                    content_encoding: output.content_encoding.clone(),
+                    content_language: output.content_language.clone(),
+                    expires,
+                    metadata: output.metadata.clone(),
+                    last_modified: output.last_modified,
+                    server_side_encryption: output.server_side_encryption.clone(),
+                    ssekms_key_id: output.ssekms_key_id.clone(),
+                    bucket_key_enabled: output.bucket_key_enabled,
+                };
+                let total = meta.total;
+                let served = prefetcher.store(&key, start, end, data, meta);
+                output.content_length = Some(served.len() as i64);
+                output.content_range = Some(content_range(start, served.len(), total));
+                output.body = ByteStream::from(served);
+            }
+            apply_response_overrides(output, &requested);
+        }
 
-        let output = result
+        let mut output = result
             .map_err(convert_sdk_err)
             .and_then(GetObjectOutput::try_from_aws)?;
+        if !ranges_supported(&remotes) {
+            output.accept_ranges = None;
+        }
+        output.parts_count = self
+            .recorded_parts_count(input.key.as_deref(), input.part_number, output.parts_count)
+            .await;
 
-        Ok(S3Response::new(output))
+        Ok(with_remote(S3Response::new(output), &remote))
     }
 
-    #[instrument(skip_all, name = "s3s/head_object")]
+    #[instrument(
+        skip_all,
+        name = "s3s/head_object",
+        fields(bucket = req.input.bucket, key = req.input.key, remote.selected = Empty)
+    )]
     async fn head_object(
         &self,
         req: S3Request<HeadObjectInput>,
     ) -> S3Result<S3Response<HeadObjectOutput>> {
-        let read_remotes = self.remotes.iter().sorted_by(|a, b| {
-            b.read_request
-                .cmp(&a.read_request)
-                .then_with(|| b.priority.cmp(&a.priority))
-        });
+        self.check_operation("HeadObject")?;
+        let remotes = self.remotes.load();
+        let forced = self.forced_remote(&req.headers, &remotes)?;
+        let mut read_remotes = match forced {
+            Some(remote) => vec![remote],
+            None => self.admitted(read_order(&remotes)),
+        }
+        .into_iter();
 
         let input = HeadObjectInput::try_into_aws(req.input)?;
 
+        if forced.is_none() && wants_fresh(&req.uri) {
+            if let Some((remote, output)) = newest_remote(read_remotes.as_slice(), &input).await {
+                info!("ok (fresh, remote: {})", remote.name);
+                if self.strict_reads {
+                    let others = read_remotes
+                        .as_slice()
+                        .iter()
+                        .filter(|r| r.name != remote.name)
+                        .take(1)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let compared = verification_heads(&others, &input, false).await;
+                    report_mismatches(&remote.name, &output, &compared);
+                }
+                let mut output = HeadObjectOutput::try_from_aws(output)?;
+                if !ranges_supported(&remotes) {
+                    output.accept_ranges = None;
+                }
+                output.parts_count = self
+                    .recorded_parts_count(
+                        input.key.as_deref(),
+                        input.part_number,
+                        output.parts_count,
+                    )
+                    .await;
+                return Ok(with_remote(S3Response::new(output), &remote.name));
+            }
+        }
+
         let Some((result, remote)) = ('request: {
-            for remote in read_remotes {
-                let Some(output) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::HeadObject {
+            for remote in read_remotes.by_ref() {
+                let Some(output) =
+                    read_with_quick_retry(remote, self.read_quick_retries, |reply| {
+                        remote::RemoteMessage::HeadObject {
                             input: input.clone(),
-                            reply: tx,
-                        })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
+                            reply,
+                        }
+                    })
+                    .await
+                else {
                     warn!("remote({:?}) request failed. skipping", remote.name);
                     continue;
                 };
@@ -564,27 +1695,168 @@ impl S3 for S3Reproxy {
             }
             None
         }) else {
-            warn!("no remotes available!");
-            return Err(s3_error!(InternalError));
+            return Err(self.failure_responses.no_remote_error());
         };
 
         info!("ok (remote: {})", remote);
+        record_read_remote("HeadObject", &remote);
+
+        if let Ok(primary) = &result {
+            let others = read_remotes
+                .by_ref()
+                .take(self.head_verify_count.saturating_sub(1))
+                .collect::<Vec<_>>();
+            let verified = verification_heads(&others, &input, self.head_verify_parallel).await;
+
+            if self.strict_reads {
+                // compares on a HEAD of its own when no remote verifies the response, so that
+                // enabling it never subjects reads to `head_divergence`
+                if verified.is_empty() {
+                    let next = read_remotes.next().into_iter().collect::<Vec<_>>();
+                    let compared = verification_heads(&next, &input, false).await;
+                    report_mismatches(&remote, primary, &compared);
+                } else {
+                    report_mismatches(&remote, primary, &verified);
+                }
+            }
 
-        let output = result
+            let diverged = diverging_remotes(primary, &verified);
+            if !diverged.is_empty() {
+                warn!(
+                    "metadata diverges from remote({:?}): {:?}",
+                    remote, diverged
+                );
+                if self.head_divergence == DivergencePolicy::Strict {
+                    return Err(s3_error!(
+                        InternalError,
+                        "object metadata diverges across remotes"
+                    ));
+                }
+            }
+        }
+
+        let mut output = result
             .map_err(convert_sdk_err)
             .and_then(HeadObjectOutput::try_from_aws)?;
+        if !ranges_supported(&remotes) {
+            output.accept_ranges = None;
+        }
+        output.parts_count = self
+            .recorded_parts_count(input.key.as_deref(), input.part_number, output.parts_count)
+            .await;
 
-        Ok(S3Response::new(output))
+        Ok(with_remote(S3Response::new(output), &remote))
     }
 
-    #[instrument(skip_all, fields(token = &req.input.continuation_token), name = "s3s/list_objects_v2")]
+    #[instrument(
+        skip_all,
+        fields(
+            bucket = req.input.bucket,
+            marker = &req.input.marker,
+            remote.selected = Empty,
+        ),
+        name = "s3s/list_objects"
+    )]
+    async fn list_objects(
+        &self,
+        req: S3Request<ListObjectsInput>,
+    ) -> S3Result<S3Response<ListObjectsOutput>> {
+        self.check_operation("ListObjects")?;
+        let input = ListObjectsInput::try_into_aws(req.input)?;
+
+        let output = match input.max_keys {
+            Some(max_keys) if max_keys < 0 => {
+                return Err(s3_error!(InvalidArgument, "max-keys must not be negative"));
+            }
+            Some(0) => {
+                info!("(intercepted) ok (max-keys=0)");
+                let empty = aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output::builder()
+                    .is_truncated(false)
+                    .build();
+                v1_listing(&input, empty, None)
+            }
+            _ => {
+                // the marker is a key, so it resumes the listing just like a stored `start_after`
+                let (page, page_end, remote) = self
+                    .list_page(
+                        &ListQuery {
+                            prefix: non_empty(input.prefix.clone()),
+                            delimiter: non_empty(input.delimiter.clone()),
+                            max_keys: input.max_keys,
+                            ..Default::default()
+                        },
+                        non_empty(input.marker.clone()),
+                        None,
+                        None,
+                    )
+                    .await?;
+                let output = ListObjectsOutput::try_from_aws(v1_listing(&input, page, page_end))?;
+                return Ok(with_remote(S3Response::new(output), &remote));
+            }
+        };
+
+        Ok(S3Response::new(ListObjectsOutput::try_from_aws(output)?))
+    }
+
+    #[instrument(
+        skip_all,
+        fields(
+            bucket = req.input.bucket,
+            token = &req.input.continuation_token,
+            mongodb.collection = "list_object_tokens",
+            remote.selected = Empty,
+        ),
+        name = "s3s/list_objects_v2"
+    )]
     async fn list_objects_v2(
         &self,
         req: S3Request<ListObjectsV2Input>,
     ) -> S3Result<S3Response<ListObjectsV2Output>> {
+        self.check_operation("ListObjectsV2")?;
+        let remotes = self.remotes.load();
         info!("{:?}", &req);
+        // ignored with native list tokens, which only the remote that handed them out understands
+        let forced = self.forced_remote(&req.headers, &remotes)?;
+
+        match req.input.max_keys {
+            Some(max_keys) if max_keys < 0 => {
+                return Err(s3_error!(InvalidArgument, "max-keys must not be negative"));
+            }
+            Some(0) => {
+                info!("(intercepted) ok (max-keys=0)");
+                let output = ListObjectsV2Output::try_from_aws(empty_listing(&req.input))?;
+                return Ok(S3Response::new(output));
+            }
+            _ => {}
+        }
+
+        if let Some(pinned) = &self.native_list_tokens_from {
+            let Some(remote) = remotes.iter().find(|r| r.name == *pinned) else {
+                error!(
+                    "remote({:?}) for native list tokens is not configured",
+                    pinned
+                );
+                return Err(s3_error!(InternalError));
+            };
+            let output =
+                list_with_native_tokens(remote, self.read_quick_retries, &req.input).await?;
+            info!("ok (native tokens, remote: {})", remote.name);
+            return Ok(with_remote(S3Response::new(output), &remote.name));
+        }
 
-        let start_after = match req.input.continuation_token.clone() {
+        let query = ListQuery {
+            prefix: non_empty(req.input.prefix.clone()),
+            delimiter: non_empty(req.input.delimiter.clone()),
+            max_keys: req.input.max_keys,
+            fetch_owner: req.input.fetch_owner,
+            encoding_type: req
+                .input
+                .encoding_type
+                .as_ref()
+                .map(|e| aws_sdk_s3::types::EncodingType::from(e.as_str())),
+        };
+
+        let (start_after, pinned) = match req.input.continuation_token.clone() {
             Some(continuation_token) => {
                 let list = self
                     .db
@@ -612,111 +1884,571 @@ impl S3 for S3Reproxy {
                         warn!("(intercepted) continuation token not found.");
                         S3Error::new(s3s::S3ErrorCode::InvalidToken)
                     })?;
-                Some(list.start_after)
+                (Some(list.start_after), list.remote)
             }
-            None => None,
+            None => (None, None),
         };
 
-        let read_remotes = self.remotes.iter().sorted_by(|a, b| {
-            b.read_request
-                .cmp(&a.read_request)
-                .then_with(|| b.priority.cmp(&a.priority))
-        });
-
         let start_after = start_after.or(req.input.start_after.clone());
 
-        let Some((result, remote)) = ('request: {
-            for remote in read_remotes {
-                let Some(output) = (try {
-                    let (tx, rx) = oneshot::channel();
-                    remote
-                        .tx
-                        .send(remote::RemoteMessage::ListObjects {
-                            prefix: req.input.prefix.clone(),
-                            delimiter: req.input.delimiter.clone(),
-                            max_keys: req.input.max_keys,
-                            start_after: start_after.clone(),
-                            reply: tx,
-                        })
-                        .await
-                        .ok()?;
-                    rx.await.ok()??
-                }) else {
-                    warn!("remote({:?}) request failed. skipping", remote.name);
-                    continue;
-                };
-                break 'request Some((output, remote.name.clone()));
-            }
-            None
-        }) else {
-            warn!("no remotes available!");
-            return Err(s3_error!(InternalError));
-        };
-
-        info!("ok (remote: {})", remote);
-
-        let mut output = result
-            .map_err(convert_sdk_err)
-            .and_then(ListObjectsV2Output::try_from_aws)?;
+        let (output, page_end, remote) = self
+            .list_page(
+                &query,
+                start_after,
+                pinned.as_deref(),
+                forced.map(|r| r.name.as_str()),
+            )
+            .await?;
+        let mut output = ListObjectsV2Output::try_from_aws(output)?;
 
         output.continuation_token = req.input.continuation_token;
         output.next_continuation_token = match output.next_continuation_token {
             Some(_) => 'm: {
-                let Some(last) = output
-                    .contents
-                    .as_ref()
-                    .and_then(|e| e.last())
-                    .and_then(|e| e.key.clone())
-                else {
+                let Some(last) = page_end else {
                     break 'm None;
                 };
 
-                let list = self
+                let minted = self
                     .db
                     .list_object_tokens
                     .insert_one(ListObjectTokens {
                         start_after: last,
+                        remote: Some(remote.clone()),
                         created_at: mongodb::bson::DateTime::now(),
                         consumed_at: None,
                     })
                     .await
-                    .map_err(|e| {
-                        error!("mongodb error: {:?}", e);
-                        S3Error::new(s3s::S3ErrorCode::InternalError)
-                    })?;
+                    .map(|list| list.inserted_id.as_object_id().unwrap().to_hex());
 
-                Some(list.inserted_id.as_object_id().unwrap().to_hex())
+                continuation_token_or_fallback(minted, self.list_token_fallback)?
             }
             None => None,
         };
 
-        Ok(S3Response::new(output))
+        Ok(with_remote(S3Response::new(output), &remote))
+    }
+}
+
+/// Orders remotes for reads: the first choice [`by_preference`], then the rest by
+/// `failover_priority` (falling back to `priority`). Remotes without `read_request` come last,
+/// and remotes tied otherwise are ordered by `name`.
+fn read_order(remotes: &[S3Remote]) -> Vec<&S3Remote> {
+    let mut ordered = remotes
+        .iter()
+        .sorted_by(|a, b| by_preference(a, b))
+        .collect::<Vec<_>>();
+    if let Some((_, rest)) = ordered.split_first_mut() {
+        rest.sort_by(|a, b| {
+            b.read_request
+                .cmp(&a.read_request)
+                .then_with(|| {
+                    let failover = |r: &S3Remote| r.failover_priority.unwrap_or(r.priority);
+                    failover(b).cmp(&failover(a))
+                })
+                .then_with(|| a.name.cmp(&b.name))
+        });
+    }
+    ordered
+}
+
+/// A random number, good enough to spread reads and retries or to tell requests apart: every
+/// `RandomState` is seeded differently.
+pub(crate) fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Moves a remote drawn from the readable remotes sharing the priority of the first one to the
+/// front of `ordered`, each as likely as its share of their `weight`, keeping the order of the
+/// others. Those of them with no weight come after the rest of them. `draw` is a random number.
+fn weighted_first(ordered: Vec<&S3Remote>, draw: u64) -> Vec<&S3Remote> {
+    let Some(top) = ordered.first().map(|r| r.priority) else {
+        return ordered;
+    };
+    let tier = |r: &&S3Remote| r.read_request && r.priority == top;
+    let total = ordered
+        .iter()
+        .filter(tier)
+        .map(|r| u64::from(r.weight))
+        .sum::<u64>();
+    if total == 0 {
+        return ordered;
+    }
+    let (idle, mut ordered): (Vec<_>, Vec<_>) =
+        ordered.into_iter().partition(|r| tier(r) && r.weight == 0);
+    let after_tier = ordered.iter().rposition(tier).map_or(0, |i| i + 1);
+    ordered.splice(after_tier..after_tier, idle);
+    let mut point = draw % total;
+    let index = ordered.iter().position(|r| {
+        if !tier(r) {
+            return false;
+        }
+        let weight = u64::from(r.weight);
+        if point < weight {
+            return true;
+        }
+        point -= weight;
+        false
+    });
+    if let Some(index) = index {
+        let remote = ordered.remove(index);
+        ordered.insert(0, remote);
+    }
+    ordered
+}
+
+/// Moves the `pinned` remote to the front of `ordered`, keeping the order of the others, so that a
+/// paginated listing stays on one remote while remotes diverge mid-replication.
+fn pinned_first<'a>(mut ordered: Vec<&'a S3Remote>, pinned: Option<&str>) -> Vec<&'a S3Remote> {
+    if let Some(index) = ordered.iter().position(|r| Some(r.name.as_str()) == pinned) {
+        let remote = ordered.remove(index);
+        ordered.insert(0, remote);
+    }
+    ordered
+}
+
+/// Lists a page from `remote` alone, resuming from and handing out its own continuation tokens.
+async fn list_with_native_tokens(
+    remote: &S3Remote,
+    retries: usize,
+    input: &ListObjectsV2Input,
+) -> S3Result<ListObjectsV2Output> {
+    let Some(result) = read_with_quick_retry(remote, retries, |reply| {
+        remote::RemoteMessage::ListObjects {
+            prefix: non_empty(input.prefix.clone()),
+            delimiter: non_empty(input.delimiter.clone()),
+            max_keys: input.max_keys,
+            start_after: input.start_after.clone(),
+            continuation_token: input.continuation_token.clone(),
+            fetch_owner: input.fetch_owner,
+            encoding_type: input
+                .encoding_type
+                .as_ref()
+                .map(|e| aws_sdk_s3::types::EncodingType::from(e.as_str())),
+            reply,
+        }
+    })
+    .await
+    else {
+        warn!("remote({:?}) request failed. not failing over", remote.name);
+        return Err(s3_error!(InternalError));
+    };
+    result
+        .map_err(convert_sdk_err)
+        .and_then(ListObjectsV2Output::try_from_aws)
+}
+
+/// What a page of a listing is asked of a remote with, apart from where it starts.
+#[derive(Debug, Clone, Default)]
+struct ListQuery {
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    max_keys: Option<i32>,
+    fetch_owner: Option<bool>,
+    encoding_type: Option<aws_sdk_s3::types::EncodingType>,
+}
+
+/// Treats an empty `prefix` or `delimiter` like an absent one, as S3 does. Some backends
+/// reject the empty string or match nothing with it.
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+/// The key an entry of a listing stands for. With `encoding-type=url` remotes hand out keys
+/// form-encoded, a space as `+`, while `start-after` always takes the key itself.
+fn listed_key<'a>(
+    key: &'a str,
+    encoding_type: Option<&aws_sdk_s3::types::EncodingType>,
+) -> Cow<'a, str> {
+    if encoding_type != Some(&aws_sdk_s3::types::EncodingType::Url) || !key.contains(['%', '+']) {
+        return Cow::Borrowed(key);
+    }
+    let hex = |b: Option<u8>| b.and_then(|b| char::from(b).to_digit(16));
+    let mut decoded = Vec::with_capacity(key.len());
+    let mut bytes = key.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let mut ahead = bytes.clone();
+                match (hex(ahead.next()), hex(ahead.next())) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        bytes = ahead;
+                    }
+                    _ => decoded.push(byte),
+                }
+            }
+            _ => decoded.push(byte),
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Drops entries at or before `start_after` from a page. A remote that takes over a paginated
+/// listing from another one may not agree on where the previous page ended. Returns the last key
+/// of the page as listed, decoded, which is where the next page resumes even if nothing was left.
+fn realign_page(
+    output: &mut aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
+    start_after: Option<&str>,
+    encoding_type: Option<&aws_sdk_s3::types::EncodingType>,
+) -> Option<String> {
+    let page_end = output
+        .contents
+        .as_ref()
+        .and_then(|c| c.last())
+        .and_then(|o| o.key.as_deref())
+        .map(|k| listed_key(k, encoding_type).into_owned());
+    let Some(start_after) = start_after else {
+        return page_end;
+    };
+
+    let mut dropped = 0;
+    if let Some(contents) = &mut output.contents {
+        let before = contents.len();
+        contents.retain(|o| {
+            o.key
+                .as_deref()
+                .map_or(true, |k| *listed_key(k, encoding_type) > *start_after)
+        });
+        dropped += before - contents.len();
+    }
+    if let Some(prefixes) = &mut output.common_prefixes {
+        let before = prefixes.len();
+        prefixes.retain(|p| {
+            p.prefix.as_deref().map_or(true, |p| {
+                let p = listed_key(p, encoding_type);
+                *p > *start_after || start_after.starts_with(&*p)
+            })
+        });
+        dropped += before - prefixes.len();
+    }
+    if dropped > 0 {
+        warn!(
+            "(realigned) page started before {:?}. dropped {} entries",
+            start_after, dropped
+        );
+        output.key_count = output.key_count.map(|c| c - dropped as i32);
+    }
+    page_end
+}
+
+/// The answer S3 gives to `max-keys=0`: no contents and nothing left to fetch. Some backends
+/// return a full page or an error instead, so it is never forwarded.
+fn empty_listing(
+    input: &ListObjectsV2Input,
+) -> aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output {
+    aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output::builder()
+        .name(input.bucket.clone())
+        .set_prefix(input.prefix.clone())
+        .set_delimiter(input.delimiter.clone())
+        .set_start_after(input.start_after.clone())
+        .set_continuation_token(input.continuation_token.clone())
+        .max_keys(0)
+        .key_count(0)
+        .is_truncated(false)
+        .build()
+}
+
+/// Whether every remote a read may fail over to honors byte ranges. `Accept-Ranges` is only
+/// advertised then, since a client relying on it would break once a read lands elsewhere.
+fn ranges_supported(remotes: &[S3Remote]) -> bool {
+    remotes.iter().all(|r| r.supports_ranges)
+}
+
+/// Filters a remote's bucket listing down to the buckets this proxy is configured with.
+/// The remote's backing bucket is reported under the virtual bucket name, and the virtual
+/// bucket is always listed even when the remote does not return it.
+fn merge_bucket_listing(bucket: &str, backing_bucket: &str, listed: Vec<Bucket>) -> Vec<Bucket> {
+    let mut creation_date = None;
+    for listed in listed {
+        match listed.name.as_deref() {
+            Some(name) if name == backing_bucket => {
+                creation_date = listed.creation_date;
+                break;
+            }
+            Some(name) if name == bucket => {
+                creation_date = creation_date.or(listed.creation_date);
+            }
+            _ => {}
+        }
+    }
+
+    vec![Bucket {
+        creation_date,
+        name: Some(bucket.to_owned()),
+    }]
+}
+
+/// HEADs the object on `others` to verify a response, one after another or all at once.
+/// Remotes that fail or answer with an error are left out.
+async fn verification_heads(
+    others: &[&S3Remote],
+    input: &aws_sdk_s3::operation::head_object::HeadObjectInput,
+    parallel: bool,
+) -> Vec<(String, aws_sdk_s3::operation::head_object::HeadObjectOutput)> {
+    if parallel {
+        futures::future::join_all(others.iter().map(|other| verification_head(other, input)))
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    } else {
+        let mut verified = vec![];
+        for other in others {
+            verified.extend(verification_head(other, input).await);
+        }
+        verified
+    }
+}
+
+async fn verification_head(
+    other: &S3Remote,
+    input: &aws_sdk_s3::operation::head_object::HeadObjectInput,
+) -> Option<(String, aws_sdk_s3::operation::head_object::HeadObjectOutput)> {
+    let output = other
+        .request(|reply| remote::RemoteMessage::HeadObject {
+            input: input.clone(),
+            reply,
+        })
+        .await;
+    match output {
+        Some(Ok(output)) => Some((other.name.clone(), output)),
+        Some(Err(e)) => {
+            warn!("remote({:?}) could not be verified: {:?}", other.name, e);
+            None
+        }
+        None => {
+            warn!("remote({:?}) request failed. skipping", other.name);
+            None
+        }
+    }
+}
+
+/// Returns the remotes whose `content_length` or `content_type` differ from the primary response.
+fn diverging_remotes<'a>(
+    primary: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
+    others: &'a [(String, aws_sdk_s3::operation::head_object::HeadObjectOutput)],
+) -> Vec<&'a str> {
+    others
+        .iter()
+        .filter(|(_, other)| {
+            other.content_length != primary.content_length
+                || other.content_type != primary.content_type
+        })
+        .map(|(remote, _)| remote.as_str())
+        .collect()
+}
+
+/// Logs and counts in `reproxy_head_metadata_divergence_total` every field `strict_reads`
+/// compares on which the `others` differ from the response of `remote`.
+fn report_mismatches(
+    remote: &str,
+    primary: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
+    others: &[(String, aws_sdk_s3::operation::head_object::HeadObjectOutput)],
+) {
+    for (other, output) in others {
+        let fields = mismatched_fields(primary, output);
+        if fields.is_empty() {
+            continue;
+        }
+        warn!(
+            "remote({:?}) disagrees with remote({:?}) on {:?}",
+            other, remote, fields
+        );
+        for field in fields {
+            HEAD_METADATA_DIVERGENCE.inc(&[other.as_str(), field]);
+        }
+    }
+}
+
+/// The fields `strict_reads` compares on which `other` differs from the primary response.
+fn mismatched_fields(
+    primary: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
+    other: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
+) -> Vec<&'static str> {
+    [
+        ("etag", other.e_tag != primary.e_tag),
+        (
+            "content_length",
+            other.content_length != primary.content_length,
+        ),
+        ("content_type", other.content_type != primary.content_type),
+        ("metadata", other.metadata != primary.metadata),
+    ]
+    .into_iter()
+    .filter(|(_, differs)| *differs)
+    .map(|(field, _)| field)
+    .collect()
+}
+
+/// Rejects a new multipart upload with a retryable error once `limit` uploads are open.
+fn check_upload_limit(active: u64, limit: u64) -> S3Result<()> {
+    if active >= limit {
+        warn!("{} multipart uploads are open (limit: {})", active, limit);
+        return Err(s3_error!(
+            SlowDown,
+            "too many multipart uploads are in progress"
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the outcome of minting a continuation token. With `fallback`, a failed insert drops
+/// the token instead of failing a page that was already listed successfully.
+fn continuation_token_or_fallback<E: Debug>(
+    minted: Result<String, E>,
+    fallback: bool,
+) -> S3Result<Option<String>> {
+    match minted {
+        Ok(token) => Ok(Some(token)),
+        Err(e) if fallback => {
+            error!(
+                "mongodb error: {:?}. returning the page without a continuation token!",
+                e
+            );
+            Ok(None)
+        }
+        Err(e) => {
+            error!("mongodb error: {:?}", e);
+            Err(S3Error::new(S3ErrorCode::InternalError))
+        }
+    }
+}
+
+/// Adds `x-reproxy-remote`, so that a client can tell which remote served its read.
+fn with_remote<T>(mut response: S3Response<T>, remote: &str) -> S3Response<T> {
+    if let Ok(value) = http::HeaderValue::from_str(remote) {
+        response.headers.insert(REMOTE_HEADER, value);
     }
+    response
 }
 
+/// Adds `x-amz-bucket-region`, which SDKs read from `HeadBucket` to pick the signing region.
+fn with_bucket_region<T>(
+    mut response: S3Response<T>,
+    region: Option<&str>,
+) -> S3Result<S3Response<T>> {
+    if let Some(region) = region {
+        let value = http::HeaderValue::from_str(region)
+            .map_err(|_| s3_error!(InternalError, "invalid reported_region"))?;
+        response.headers.insert("x-amz-bucket-region", value);
+    }
+    Ok(response)
+}
+
+/// A remote that failed a request, with where its objects live.
+#[derive(Debug, PartialEq)]
+struct RemoteFailure<'a> {
+    remote: &'a str,
+    bucket: Option<&'a str>,
+    endpoint: Option<&'a str>,
+    code: Option<&'a str>,
+}
+
+impl<'a> RemoteFailure<'a> {
+    fn new<E: ProvideErrorMetadata>(
+        remotes: &'a [S3Remote],
+        remote: &'a str,
+        error: &'a ServiceError<E, HttpResponse>,
+    ) -> Self {
+        let configured = remotes.iter().find(|r| r.name == remote);
+        RemoteFailure {
+            remote,
+            bucket: configured.map(|r| r.bucket.as_str()),
+            endpoint: configured.map(|r| r.endpoint.as_str()),
+            code: error.err().code(),
+        }
+    }
+}
+
+/// Where the reply of remote `name` ranks: by `priority`, then by its place in the remote set (see
+/// [`by_preference`]), so that the same remote answers the client whichever remote replied first.
+/// Unknown remotes come last.
+fn reply_rank(remotes: &[S3Remote], name: &str) -> (std::cmp::Reverse<u32>, usize) {
+    remotes
+        .iter()
+        .position(|r| r.name == name)
+        .map_or((std::cmp::Reverse(0), usize::MAX), |i| {
+            (std::cmp::Reverse(remotes[i].priority), i)
+        })
+}
+
+/// Counts a read served by `remote` and notes the remote on the request's span.
+fn record_read_remote(operation: &str, remote: &str) {
+    READ_REMOTE_SELECTED.inc(&[operation, remote]);
+    tracing::Span::current().record("remote.selected", remote);
+}
+
+/// The reply of the highest-ranked remote that stored the write, provided at least as many of the
+/// `total` remotes as `quorum` requires did.
 #[allow(clippy::type_complexity)]
 fn output_remote_inconsistent<T, E: Debug + ProvideErrorMetadata>(
+    remotes: &[S3Remote],
     results: Vec<(String, Result<T, ServiceError<E, HttpResponse>>)>,
+    quorum: WriteQuorum,
+    total: usize,
+    failure_responses: FailureResponses,
 ) -> Result<T, S3Error> {
-    let (successes, failures): (Vec<_>, Vec<_>) =
-        results
-            .into_iter()
-            .partition_map(|(remote, result)| match result {
-                Ok(output) => Either::Left((remote, output)),
-                Err(e) => Either::Right((remote, e)),
-            });
+    let (successes, failures): (Vec<_>, Vec<_>) = results
+        .into_iter()
+        .sorted_by_cached_key(|(remote, _)| reply_rank(remotes, remote))
+        .partition_map(|(remote, result)| match result {
+            Ok(output) => Either::Left((remote, output)),
+            Err(e) => Either::Right((remote, e)),
+        });
+    let span = tracing::Span::current();
+    span.record("remote.success_count", successes.len());
+    span.record(
+        "remote.failure_count",
+        total.saturating_sub(successes.len()),
+    );
+    if !successes.is_empty() {
+        span.record(
+            "remote.succeeded",
+            successes
+                .iter()
+                .map(|(remote, _)| remote.as_str())
+                .join(","),
+        );
+    }
+    if !failures.is_empty() {
+        span.record(
+            "remote.failed",
+            failures.iter().map(|(remote, _)| remote.as_str()).join(","),
+        );
+    }
+
+    let required = quorum.required(total);
+    if !successes.is_empty() && successes.len() < required {
+        error!(
+            "{} of {} remotes ok, below the write quorum of {}.",
+            successes.len(),
+            total,
+            required
+        );
+        for (remote, err) in failures.iter() {
+            INCONSISTENT_WRITES.inc(&[remote.as_str()]);
+            error!(
+                "remote({:?}) failed: {:?} ({:?})",
+                remote,
+                err,
+                RemoteFailure::new(remotes, remote, err)
+            );
+        }
+        return Err(failure_responses.below_quorum_error(successes.len(), required));
+    }
 
     if failures.is_empty() {
-        let (remote, reply) = successes.into_iter().next().map_or_else(
-            || {
-                warn!("no remotes available!");
-                Err(S3Error::new(S3ErrorCode::InternalError))
-            },
-            Result::Ok,
-        )?;
+        let (remote, reply) = successes
+            .into_iter()
+            .next()
+            .ok_or_else(|| failure_responses.no_remote_error())?;
         info!("all remote ok (replied remote: {})", remote);
         Ok(reply)
     } else if successes.is_empty() {
+        for (remote, err) in failures.iter() {
+            info!("{:?}", RemoteFailure::new(remotes, remote, err));
+        }
         let (remote, err) = failures.into_iter().next().unwrap();
         info!("all remote failed (replied remote: {})", remote);
         Err(convert_sdk_err(err))?
@@ -725,8 +2457,14 @@ fn output_remote_inconsistent<T, E: Debug + ProvideErrorMetadata>(
         for (remote, _) in successes.iter() {
             info!("remote({:?}) ok", remote);
         }
-        for (remote, err) in failures {
-            error!("remote({:?}) failed: {:?}", remote, err);
+        for (remote, err) in failures.iter() {
+            INCONSISTENT_WRITES.inc(&[remote.as_str()]);
+            error!(
+                "remote({:?}) failed: {:?} ({:?})",
+                remote,
+                err,
+                RemoteFailure::new(remotes, remote, err)
+            );
         }
         let (remote, reply) = successes.into_iter().next().unwrap();
         info!("some remote ok (replied remote: {})", remote);
@@ -734,11 +2472,191 @@ fn output_remote_inconsistent<T, E: Debug + ProvideErrorMetadata>(
     }
 }
 
+/// The uploads the remotes opened. Fails when none did, since parts sent to an upload without a
+/// single remote behind it would be silently discarded.
+fn opened_uploads<E: Debug + ProvideErrorMetadata>(
+    results: Vec<(
+        String,
+        Result<
+            aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadOutput,
+            ServiceError<E, HttpResponse>,
+        >,
+    )>,
+    failure_responses: FailureResponses,
+) -> S3Result<Vec<RemoteMultipartUploadId>> {
+    let (ids, rejections): (Vec<_>, Vec<_>) =
+        results
+            .into_iter()
+            .partition_map(|(remote, result)| match result {
+                Ok(output) => Either::Left(RemoteMultipartUploadId {
+                    remote_name: remote,
+                    upload_id: output.upload_id.expect("upload_id missing"),
+                    status: PartUploadStatus::Open,
+                }),
+                Err(e) => {
+                    warn!("remote({:?}) failed: {:?}", remote, e);
+                    Either::Right(e)
+                }
+            });
+    if !ids.is_empty() {
+        return Ok(ids);
+    }
+    Err(most_relevant_error(rejections).unwrap_or_else(|| failure_responses.no_remote_error()))
+}
+
+/// Picks the error to answer with when remotes rejected a request. A client error such as
+/// `InvalidPart` is the client's to fix, so it is preferred over a remote's own failure.
+fn most_relevant_error<E: ProvideErrorMetadata>(
+    errors: Vec<ServiceError<E, HttpResponse>>,
+) -> Option<S3Error> {
+    errors
+        .into_iter()
+        .min_by_key(|e| !e.raw().status().is_client_error())
+        .map(convert_sdk_err)
+}
+
 impl S3Reproxy {
-    async fn initiate_multipart(
+    /// Adds `key` to the key filters of the remotes a write landed on.
+    fn record_written_key<'a>(&self, key: Option<&str>, remotes: impl Iterator<Item = &'a str>) {
+        let Some(key) = key else {
+            return;
+        };
+        let current = self.remotes.load();
+        for name in remotes {
+            if let Some(filter) = current
+                .iter()
+                .find(|r| r.name == name)
+                .and_then(|r| r.key_filter.as_ref())
+            {
+                filter.insert(key);
+            }
+        }
+    }
+
+    fn invalidate_prefetch(&self, key: Option<&str>) {
+        if let (Some(prefetcher), Some(key)) = (&self.prefetcher, key) {
+            prefetcher.invalidate(key);
+        }
+    }
+
+    /// Lists a page of keys after `start_after` from the first remote in read order that answers,
+    /// trying the `pinned` remote first. Returns the page along with its last key as listed,
+    /// decoded, which is where the next page resumes, and the name of the remote that listed it.
+    async fn list_page(
+        &self,
+        query: &ListQuery,
+        start_after: Option<String>,
+        pinned: Option<&str>,
+        forced: Option<&str>,
+    ) -> S3Result<(
+        aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
+        Option<String>,
+        String,
+    )> {
+        let remotes = self.remotes.load();
+        let candidates = match forced {
+            Some(forced) => remotes.iter().filter(|r| r.name == forced).collect(),
+            None => pinned_first(self.admitted(read_order(&remotes)), pinned),
+        };
+        let Some((result, remote)) = ('request: {
+            for remote in candidates {
+                let Some(output) =
+                    read_with_quick_retry(remote, self.read_quick_retries, |reply| {
+                        remote::RemoteMessage::ListObjects {
+                            prefix: query.prefix.clone(),
+                            delimiter: query.delimiter.clone(),
+                            max_keys: query.max_keys,
+                            start_after: start_after.clone(),
+                            continuation_token: None,
+                            fetch_owner: query.fetch_owner,
+                            encoding_type: query.encoding_type.clone(),
+                            reply,
+                        }
+                    })
+                    .await
+                else {
+                    warn!("remote({:?}) request failed. skipping", remote.name);
+                    continue;
+                };
+                break 'request Some((output, remote.name.clone()));
+            }
+            None
+        }) else {
+            return Err(self.failure_responses.no_remote_error());
+        };
+
+        info!("ok (remote: {})", remote);
+        record_read_remote("ListObjectsV2", &remote);
+
+        if pinned.is_some_and(|pinned| pinned != remote) {
+            warn!(
+                "remote({:?}) that listed the previous page is down. continuing on remote({:?})",
+                pinned, remote
+            );
+        }
+
+        let mut output = result.map_err(convert_sdk_err)?;
+        let page_end = realign_page(
+            &mut output,
+            start_after.as_deref(),
+            query.encoding_type.as_ref(),
+        );
+        Ok((output, page_end, remote))
+    }
+
+    /// Counts the remotes in `diverged`, which returned another ETag for part `part_number` than
+    /// the one the client gets, and fails the request over them with the strict policy.
+    fn check_part_etags(&self, part_number: Option<i32>, diverged: &[String]) -> S3Result<()> {
+        if diverged.is_empty() {
+            return Ok(());
+        }
+        warn!(
+            "part({:?}) ETags diverge across remotes: {:?}",
+            part_number, diverged
+        );
+        for remote in diverged.iter() {
+            PART_ETAG_DIVERGENCE.inc(&[remote.as_str()]);
+        }
+        if self.part_etag_divergence == Some(DivergencePolicy::Strict) {
+            return Err(s3_error!(
+                InternalError,
+                "part ETags diverge across remotes"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resolves the per-remote upload ids behind `upload_id`. The returned `ObjectId` is `None`
+    /// for signed upload ids, which have no document to update.
+    async fn initiate_multipart<'a>(
         &self,
+        remotes: &'a [S3Remote],
         upload_id: String,
-    ) -> Result<(ObjectId, Vec<(Option<&S3Remote>, RemoteMultipartUploadId)>), S3Error> {
+        key: &str,
+    ) -> Result<
+        (
+            Option<ObjectId>,
+            Vec<(Option<&'a S3Remote>, RemoteMultipartUploadId)>,
+        ),
+        S3Error,
+    > {
+        if let (Some(codec), true) = (&self.upload_tokens, UploadTokenCodec::is_token(&upload_id)) {
+            let ids = codec.decode(key, &upload_id).ok_or_else(|| {
+                warn!("(intercepted) invalid signed upload_id.");
+                S3Error::new(S3ErrorCode::InvalidToken)
+            })?;
+            let remotes = ids
+                .into_iter()
+                .map(|upload| {
+                    (
+                        remotes.iter().find(|r| r.name == upload.remote_name),
+                        upload,
+                    )
+                })
+                .collect_vec();
+            return Ok((None, remotes));
+        }
+
         let id = ObjectId::parse_str(upload_id).map_err(|e| {
             warn!("(intercepted) invalid upload_id: {:?}", e);
             S3Error::new(S3ErrorCode::InvalidToken)
@@ -766,13 +2684,758 @@ impl S3Reproxy {
             .into_iter()
             .map(|upload| match upload.status {
                 PartUploadStatus::Open => (
-                    self.remotes.iter().find(|r| r.name == upload.remote_name),
+                    remotes.iter().find(|r| r.name == upload.remote_name),
                     upload,
                 ),
                 PartUploadStatus::Cancelled => (None, upload),
             })
             .collect_vec();
 
-        Ok((id, remotes))
+        Ok((Some(id), remotes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use s3s::dto::{Timestamp, TimestampFormat};
+
+    fn bucket(name: &str, created: Option<&str>) -> Bucket {
+        Bucket {
+            creation_date: created.map(|c| Timestamp::parse(TimestampFormat::DateTime, c).unwrap()),
+            name: Some(name.to_owned()),
+        }
+    }
+
+    #[test]
+    fn bucket_listing_keeps_configured_buckets() {
+        let buckets = merge_bucket_listing(
+            "virtual",
+            "backing",
+            vec![
+                bucket("unrelated", Some("2020-01-01T00:00:00Z")),
+                bucket("backing", Some("2021-01-01T00:00:00Z")),
+            ],
+        );
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].name.as_deref(), Some("virtual"));
+        assert!(buckets[0].creation_date.is_some());
+    }
+
+    #[tokio::test]
+    async fn verification_heads_run_in_parallel_and_detect_divergence() {
+        use aws_sdk_s3::operation::head_object::{HeadObjectInput, HeadObjectOutput};
+        use std::time::{Duration, Instant};
+        use tokio::sync::mpsc;
+
+        /// A remote answering every HEAD after `delay` with an object of `length` bytes.
+        fn slow_remote(name: &str, length: i64, delay: Duration) -> S3Remote {
+            S3Remote::answering(name, move |message| {
+                if let remote::RemoteMessage::HeadObject { reply, .. } = message {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let output = HeadObjectOutput::builder().content_length(length).build();
+                        let _ = reply.send(Some(Ok(output)));
+                    });
+                }
+            })
+        }
+
+        let delay = Duration::from_millis(100);
+        let remotes = [
+            slow_remote("a", 10, delay),
+            slow_remote("b", 10, delay),
+            slow_remote("c", 12, delay),
+        ];
+        let others = remotes.iter().collect::<Vec<_>>();
+        let input = HeadObjectInput::builder().key("key").build().unwrap();
+        let primary = HeadObjectOutput::builder().content_length(10).build();
+
+        let started = Instant::now();
+        let verified = verification_heads(&others, &input, true).await;
+
+        assert!(started.elapsed() < delay * 2);
+        assert_eq!(verified.len(), 3);
+        assert_eq!(diverging_remotes(&primary, &verified), vec!["c"]);
+    }
+
+    #[test]
+    fn head_divergence_detects_content_length() {
+        use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+
+        let primary = HeadObjectOutput::builder()
+            .content_length(10)
+            .content_type("text/plain")
+            .build();
+        let others = vec![
+            (
+                "same".to_owned(),
+                HeadObjectOutput::builder()
+                    .content_length(10)
+                    .content_type("text/plain")
+                    .build(),
+            ),
+            (
+                "shorter".to_owned(),
+                HeadObjectOutput::builder()
+                    .content_length(7)
+                    .content_type("text/plain")
+                    .build(),
+            ),
+        ];
+
+        assert_eq!(diverging_remotes(&primary, &others), vec!["shorter"]);
+    }
+
+    #[test]
+    fn strict_reads_compare_etag_and_metadata() {
+        use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+
+        let primary = HeadObjectOutput::builder()
+            .e_tag("\"abc\"")
+            .content_length(10)
+            .content_type("text/plain")
+            .metadata("revision", "2")
+            .build();
+        let other = HeadObjectOutput::builder()
+            .e_tag("\"def\"")
+            .content_length(10)
+            .content_type("text/plain")
+            .metadata("revision", "1")
+            .build();
+
+        assert_eq!(mismatched_fields(&primary, &primary), Vec::<&str>::new());
+        assert_eq!(mismatched_fields(&primary, &other), ["etag", "metadata"]);
+        assert!(diverging_remotes(&primary, &[("other".to_owned(), other)]).is_empty());
+    }
+
+    #[test]
+    fn upload_limit_rejects_with_retryable_error() {
+        assert!(check_upload_limit(9, 10).is_ok());
+        assert_eq!(
+            check_upload_limit(10, 10).unwrap_err().code(),
+            &S3ErrorCode::SlowDown
+        );
+    }
+
+    #[test]
+    fn highest_priority_remote_answers_the_client() {
+        use aws_sdk_s3::operation::put_object::{PutObjectError, PutObjectOutput};
+
+        let remote = |name: &str, priority| S3Remote {
+            priority,
+            ..S3Remote::stub(name)
+        };
+        let remotes = [remote("a", 1), remote("b", 10), remote("c", 10)];
+        let stored = |remote: &str| {
+            let output = PutObjectOutput::builder().e_tag(remote).build();
+            (
+                remote.to_owned(),
+                Ok::<_, ServiceError<PutObjectError, HttpResponse>>(output),
+            )
+        };
+
+        for replies in [["a", "b", "c"], ["c", "a", "b"], ["a", "c", "b"]] {
+            let output = output_remote_inconsistent(
+                &remotes,
+                replies.map(stored).to_vec(),
+                WriteQuorum::One,
+                3,
+                FailureResponses::default(),
+            )
+            .unwrap();
+            assert_eq!(output.e_tag.as_deref(), Some("b"));
+        }
+    }
+
+    #[test]
+    fn writes_below_the_quorum_fail() {
+        use aws_sdk_s3::operation::put_object::{PutObjectError, PutObjectOutput};
+
+        let remotes = ["a", "b", "c"].map(S3Remote::stub);
+        let stored = |remote: &str| {
+            (
+                remote.to_owned(),
+                Ok::<_, ServiceError<PutObjectError, HttpResponse>>(
+                    PutObjectOutput::builder().build(),
+                ),
+            )
+        };
+        let replies = || vec![stored("a"), stored("b")];
+
+        assert!(output_remote_inconsistent(
+            &remotes,
+            replies(),
+            WriteQuorum::Majority,
+            3,
+            FailureResponses::default()
+        )
+        .is_ok());
+        let error = output_remote_inconsistent(
+            &remotes,
+            replies(),
+            WriteQuorum::All,
+            3,
+            FailureResponses::default(),
+        )
+        .unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::ServiceUnavailable);
+    }
+
+    #[tokio::test]
+    async fn revalidated_get_is_not_modified() {
+        use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectInput, GetObjectOutput};
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        // answers 304 when the client already holds the current ETag, as S3 does
+        let remote = S3Remote::answering("a", move |message| {
+            if let remote::RemoteMessage::GetObject { input, reply } = message {
+                let result = match input.if_none_match.as_deref() {
+                    Some("\"current\"") => Err(ServiceError::builder()
+                        .source(GetObjectError::generic(ErrorMetadata::builder().build()))
+                        .raw(HttpResponse::new(
+                            StatusCode::try_from(304).unwrap(),
+                            SdkBody::empty(),
+                        ))
+                        .build()),
+                    _ => Ok(GetObjectOutput::builder().e_tag("\"current\"").build()),
+                };
+                let _ = reply.send(Some(result));
+            }
+        });
+        let get = |if_none_match: &str| {
+            let input = GetObjectInput::builder()
+                .key("index.html")
+                .if_none_match(if_none_match)
+                .build()
+                .unwrap();
+            read_with_quick_retry(&remote, 0, move |reply| remote::RemoteMessage::GetObject {
+                input: input.clone(),
+                reply,
+            })
+        };
+
+        let error = convert_sdk_err(get("\"current\"").await.unwrap().unwrap_err());
+        assert_eq!(error.code(), &S3ErrorCode::NotModified);
+        assert_eq!(error.status_code(), Some(hyper::StatusCode::NOT_MODIFIED));
+        assert!(get("\"stale\"").await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn all_writes_denied_surfaces_access_denied() {
+        use aws_sdk_s3::operation::put_object::{PutObjectError, PutObjectOutput};
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        let denied = |remote: &str| {
+            let error = ServiceError::builder()
+                .source(PutObjectError::generic(
+                    ErrorMetadata::builder()
+                        .code("AccessDenied")
+                        .message("Access Denied")
+                        .build(),
+                ))
+                .raw(HttpResponse::new(
+                    StatusCode::try_from(403).unwrap(),
+                    SdkBody::empty(),
+                ))
+                .build();
+            (remote.to_owned(), Err::<PutObjectOutput, _>(error))
+        };
+
+        let error = output_remote_inconsistent(
+            &[],
+            vec![denied("a"), denied("b")],
+            WriteQuorum::One,
+            2,
+            FailureResponses::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.code(), &S3ErrorCode::AccessDenied);
+        assert_eq!(error.message(), Some("Access Denied"));
+        assert_eq!(error.status_code(), Some(hyper::StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn failure_names_the_bucket_and_endpoint_of_the_remote() {
+        use aws_sdk_s3::operation::put_object::PutObjectError;
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        let remotes = [
+            S3Remote {
+                bucket: "media-eu".to_owned(),
+                endpoint: "https://eu.storage.example".to_owned(),
+                ..S3Remote::stub("eu")
+            },
+            S3Remote::stub("us"),
+        ];
+        let error = ServiceError::builder()
+            .source(PutObjectError::generic(
+                ErrorMetadata::builder().code("SlowDown").build(),
+            ))
+            .raw(HttpResponse::new(
+                StatusCode::try_from(503).unwrap(),
+                SdkBody::empty(),
+            ))
+            .build();
+
+        assert_eq!(
+            RemoteFailure::new(&remotes, "eu", &error),
+            RemoteFailure {
+                remote: "eu",
+                bucket: Some("media-eu"),
+                endpoint: Some("https://eu.storage.example"),
+                code: Some("SlowDown"),
+            }
+        );
+        assert_eq!(RemoteFailure::new(&remotes, "gone", &error).endpoint, None);
+    }
+
+    #[test]
+    fn upload_is_not_created_without_a_remote() {
+        use aws_sdk_s3::operation::create_multipart_upload::{
+            CreateMultipartUploadError, CreateMultipartUploadOutput,
+        };
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        let opened = |remote: &str| {
+            let output = CreateMultipartUploadOutput::builder()
+                .upload_id(format!("{remote}-upload"))
+                .build();
+            (remote.to_owned(), Ok(output))
+        };
+        let rejected = |remote: &str| {
+            let error = ServiceError::builder()
+                .source(CreateMultipartUploadError::generic(
+                    ErrorMetadata::builder().code("AccessDenied").build(),
+                ))
+                .raw(HttpResponse::new(
+                    StatusCode::try_from(403).unwrap(),
+                    SdkBody::empty(),
+                ))
+                .build();
+            (remote.to_owned(), Err(error))
+        };
+
+        let ids = opened_uploads(
+            vec![opened("a"), rejected("b")],
+            FailureResponses::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            ids.iter()
+                .map(|id| id.upload_id.as_str())
+                .collect::<Vec<_>>(),
+            ["a-upload"]
+        );
+
+        let error = opened_uploads(
+            vec![rejected("a"), rejected("b")],
+            FailureResponses::default(),
+        )
+        .unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::AccessDenied);
+
+        let error =
+            opened_uploads::<CreateMultipartUploadError>(vec![], FailureResponses::default())
+                .unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::InternalError);
+    }
+
+    #[test]
+    fn rejected_completion_surfaces_invalid_part() {
+        use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadError;
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        let rejected = |code: &str, status: u16| {
+            ServiceError::builder()
+                .source(CompleteMultipartUploadError::generic(
+                    ErrorMetadata::builder().code(code).build(),
+                ))
+                .raw(HttpResponse::new(
+                    StatusCode::try_from(status).unwrap(),
+                    SdkBody::empty(),
+                ))
+                .build()
+        };
+
+        let error = most_relevant_error(vec![
+            rejected("InternalError", 500),
+            rejected("InvalidPart", 400),
+        ])
+        .unwrap();
+
+        assert_eq!(error.code(), &S3ErrorCode::InvalidPart);
+        assert_eq!(error.status_code(), Some(hyper::StatusCode::BAD_REQUEST));
+        assert!(most_relevant_error::<CompleteMultipartUploadError>(vec![]).is_none());
+    }
+
+    #[test]
+    fn continuation_token_failure_falls_back_to_partial_page() {
+        let failed = || Err::<String, _>(std::io::Error::other("connection reset"));
+
+        assert_eq!(
+            continuation_token_or_fallback(failed(), true).unwrap(),
+            None
+        );
+        assert_eq!(
+            continuation_token_or_fallback(failed(), false)
+                .unwrap_err()
+                .code(),
+            &S3ErrorCode::InternalError
+        );
+        assert_eq!(
+            continuation_token_or_fallback(Ok::<_, std::io::Error>("token".to_owned()), false)
+                .unwrap(),
+            Some("token".to_owned())
+        );
+    }
+
+    #[test]
+    fn remote_switch_mid_pagination_has_no_duplicates_or_gaps() {
+        use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+        use aws_sdk_s3::types::Object;
+
+        let keys = (0..10).map(|i| format!("key-{i:02}")).collect::<Vec<_>>();
+        // The second remote treats `start_after` as inclusive, so its first page overlaps.
+        let list = |start_after: Option<&str>, inclusive: bool| {
+            let rest = keys
+                .iter()
+                .filter(|k| {
+                    start_after.map_or(true, |s| k.as_str() > s || (inclusive && k.as_str() == s))
+                })
+                .collect::<Vec<_>>();
+            let page = &rest[..rest.len().min(4)];
+            ListObjectsV2Output::builder()
+                .set_contents(Some(
+                    page.iter()
+                        .map(|k| Object::builder().key(*k).build())
+                        .collect(),
+                ))
+                .key_count(page.len() as i32)
+                .is_truncated(rest.len() > page.len())
+                .build()
+        };
+
+        let mut listed = vec![];
+        let mut start_after = None;
+        for page in 0.. {
+            let mut output = list(start_after.as_deref(), page > 0);
+            let page_end = realign_page(&mut output, start_after.as_deref(), None);
+            listed.extend(output.contents.unwrap().into_iter().map(|o| o.key.unwrap()));
+            if output.is_truncated != Some(true) {
+                break;
+            }
+            start_after = page_end;
+        }
+
+        assert_eq!(listed, keys);
+    }
+
+    #[test]
+    fn url_encoded_pages_resume_after_the_decoded_key() {
+        use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+        use aws_sdk_s3::types::{EncodingType, Object};
+
+        // Encoded, these sort differently: `a%25b` would come first.
+        let keys = ["a b/1", "a b/2", "a%b", "a+b", "a+b c", "ab"];
+        let encode = |k: &str| k.replace('%', "%25").replace('+', "%2B").replace(' ', "+");
+        // Pages after the first overlap by one key, as from a remote taking over the listing.
+        let list = |start_after: Option<&str>, inclusive: bool| {
+            let rest = keys
+                .iter()
+                .filter(|k| start_after.map_or(true, |s| **k > s || (inclusive && **k == s)))
+                .collect::<Vec<_>>();
+            let page = &rest[..rest.len().min(2)];
+            ListObjectsV2Output::builder()
+                .set_contents(Some(
+                    page.iter()
+                        .map(|k| Object::builder().key(encode(k)).build())
+                        .collect(),
+                ))
+                .is_truncated(rest.len() > page.len())
+                .build()
+        };
+
+        let mut listed = vec![];
+        let mut start_after = None;
+        for page in 0.. {
+            let mut output = list(start_after.as_deref(), page > 0);
+            let page_end = realign_page(
+                &mut output,
+                start_after.as_deref(),
+                Some(&EncodingType::Url),
+            );
+            listed.extend(output.contents.unwrap().into_iter().map(|o| o.key.unwrap()));
+            if output.is_truncated != Some(true) {
+                break;
+            }
+            start_after = page_end;
+        }
+
+        assert_eq!(listed, keys.map(encode));
+        let decoded = listed
+            .iter()
+            .map(|k| listed_key(k, Some(&EncodingType::Url)))
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, keys);
+        assert_eq!(listed_key("a+b", None), "a+b");
+    }
+
+    #[tokio::test]
+    async fn native_list_tokens_are_passed_through() {
+        use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+
+        let remote = S3Remote::answering("pinned", move |message| {
+            if let remote::RemoteMessage::ListObjects {
+                continuation_token,
+                reply,
+                ..
+            } = message
+            {
+                let next = match continuation_token.as_deref() {
+                    None => Some("native-1"),
+                    Some("native-1") => None,
+                    Some(other) => panic!("unexpected token {other:?}"),
+                };
+                let output = ListObjectsV2Output::builder()
+                    .set_continuation_token(continuation_token)
+                    .set_next_continuation_token(next.map(str::to_owned))
+                    .is_truncated(next.is_some())
+                    .build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+        });
+        let mut input = list_input(None, None);
+
+        let first = list_with_native_tokens(&remote, 0, &input).await.unwrap();
+        assert_eq!(first.next_continuation_token.as_deref(), Some("native-1"));
+
+        input.continuation_token = first.next_continuation_token;
+        let second = list_with_native_tokens(&remote, 0, &input).await.unwrap();
+        assert_eq!(second.continuation_token.as_deref(), Some("native-1"));
+        assert_eq!(second.next_continuation_token, None);
+    }
+
+    #[test]
+    fn realignment_orders_keys_by_utf8_bytes() {
+        use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+        use aws_sdk_s3::types::{CommonPrefix, Object};
+
+        // S3's order. UTF-16 code units would put the emoji before the halfwidth full stop.
+        let keys = ["a/b", "a0", "z", "é", "日本", "\u{ff61}", "😀"];
+        let mut sorted = keys;
+        sorted.sort();
+        assert_eq!(sorted, keys);
+
+        let mut output = ListObjectsV2Output::builder()
+            .set_contents(Some(
+                keys.iter()
+                    .map(|k| Object::builder().key(*k).build())
+                    .collect(),
+            ))
+            .set_common_prefixes(Some(
+                ["a/", "日/", "😀/"]
+                    .iter()
+                    .map(|p| CommonPrefix::builder().prefix(*p).build())
+                    .collect(),
+            ))
+            .key_count(10)
+            .build();
+
+        realign_page(&mut output, Some("é"), None);
+
+        let listed = output
+            .contents
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(listed, ["日本", "\u{ff61}", "😀"]);
+        let prefixes = output
+            .common_prefixes
+            .unwrap()
+            .into_iter()
+            .map(|p| p.prefix.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(prefixes, ["日/", "😀/"]);
+        assert_eq!(output.key_count, Some(5));
+    }
+
+    #[test]
+    fn bucket_listing_always_contains_virtual_bucket() {
+        let buckets = merge_bucket_listing("virtual", "backing", vec![bucket("other", None)]);
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].name.as_deref(), Some("virtual"));
+        assert!(buckets[0].creation_date.is_none());
+    }
+
+    #[test]
+    fn failover_order_differs_from_read_preference() {
+        let remote = |name: &str, priority, failover_priority| S3Remote {
+            priority,
+            failover_priority,
+            ..S3Remote::stub(name)
+        };
+        let remotes = [
+            remote("fast", 5, Some(20)),
+            remote("cheap", 10, Some(1)),
+            remote("backup", 7, None),
+        ];
+
+        let ordered = read_order(&remotes);
+
+        assert_eq!(
+            ordered.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["cheap", "fast", "backup"]
+        );
+    }
+
+    #[test]
+    fn remotes_tied_on_priority_are_ordered_by_name() {
+        let remote = |name: &str, priority, failover_priority| S3Remote {
+            priority,
+            failover_priority,
+            ..S3Remote::stub(name)
+        };
+        let names = |remotes: &[S3Remote]| {
+            read_order(remotes)
+                .iter()
+                .map(|r| r.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let remotes = [
+            remote("minio-b", 10, None),
+            remote("minio-a", 10, None),
+            remote("cloud-b", 1, Some(5)),
+            remote("cloud-a", 1, Some(5)),
+        ];
+        let mut reversed = remotes.clone();
+        reversed.reverse();
+
+        let expected = ["minio-a", "minio-b", "cloud-a", "cloud-b"];
+        assert_eq!(names(&remotes), expected);
+        assert_eq!(names(&reversed), expected);
+    }
+
+    #[test]
+    fn weighted_reads_are_spread_over_the_top_priority_tier() {
+        let remote = |name: &str, priority, weight| S3Remote {
+            priority,
+            weight,
+            ..S3Remote::stub(name)
+        };
+        let remotes = [
+            remote("minio-a", 10, 1),
+            remote("minio-b", 10, 3),
+            remote("idle", 10, 0),
+            remote("cloud", 1, 100),
+        ];
+        let first = |draw| weighted_first(read_order(&remotes), draw)[0].name.clone();
+
+        assert_eq!(first(0), "minio-a");
+        assert_eq!((1..4).map(first).collect::<Vec<_>>(), ["minio-b"; 3]);
+        assert_eq!(first(4), "minio-a");
+        assert_eq!(
+            weighted_first(read_order(&remotes), 1)
+                .iter()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>(),
+            ["minio-b", "minio-a", "idle", "cloud"]
+        );
+    }
+
+    #[test]
+    fn continued_listing_prefers_the_remote_of_the_previous_page() {
+        let remote = |name: &str, priority| S3Remote {
+            priority,
+            ..S3Remote::stub(name)
+        };
+        let remotes = [remote("a", 10), remote("b", 5), remote("c", 1)];
+        let names =
+            |ordered: Vec<&S3Remote>| ordered.iter().map(|r| r.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(pinned_first(read_order(&remotes), Some("c"))),
+            ["c", "a", "b"]
+        );
+        assert_eq!(
+            names(pinned_first(read_order(&remotes), Some("gone"))),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            names(pinned_first(read_order(&remotes), None)),
+            ["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn ranges_are_advertised_only_if_every_remote_supports_them() {
+        let remote = |name: &str, supports_ranges| S3Remote {
+            supports_ranges,
+            ..S3Remote::stub(name)
+        };
+
+        assert!(ranges_supported(&[remote("a", true), remote("b", true)]));
+        assert!(!ranges_supported(&[remote("a", true), remote("b", false)]));
+    }
+
+    fn list_input(prefix: Option<&str>, max_keys: Option<i32>) -> ListObjectsV2Input {
+        let mut input = ListObjectsV2Input::default();
+        input.bucket = "virtual".to_owned();
+        input.prefix = prefix.map(str::to_owned);
+        input.max_keys = max_keys;
+        input
+    }
+
+    #[test]
+    fn zero_max_keys_lists_nothing() {
+        let output = empty_listing(&list_input(Some("photos/"), Some(0)));
+
+        assert_eq!(output.contents, None);
+        assert_eq!(output.key_count, Some(0));
+        assert_eq!(output.is_truncated, Some(false));
+        assert_eq!(output.prefix.as_deref(), Some("photos/"));
+    }
+
+    #[test]
+    fn empty_prefix_is_treated_as_missing() {
+        assert_eq!(non_empty(Some(String::new())), None);
+        assert_eq!(non_empty(None), None);
+        assert_eq!(
+            non_empty(Some("photos/".to_owned())),
+            Some("photos/".to_owned())
+        );
+    }
+
+    #[test]
+    fn head_bucket_reports_region() {
+        let response = with_bucket_region(
+            S3Response::new(HeadBucketOutput::default()),
+            Some("ap-northeast-1"),
+        )
+        .unwrap();
+        assert_eq!(
+            response.headers.get("x-amz-bucket-region").unwrap(),
+            "ap-northeast-1"
+        );
+
+        let response =
+            with_bucket_region(S3Response::new(HeadBucketOutput::default()), None).unwrap();
+        assert!(response.headers.get("x-amz-bucket-region").is_none());
     }
 }
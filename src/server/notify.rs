@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{header, Request};
+use http_body_util::Full;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, warn};
+
+use crate::config::s3_target::WebhookConfig;
+
+use super::S3Reproxy;
+
+/// Events waiting for delivery. Beyond this, new events are dropped rather than slowing writes.
+const QUEUE_SIZE: usize = 1024;
+
+/// Backoff before the first redelivery, doubled on each one after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// A write or delete that went through, in the terms of S3 event notifications.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectEvent {
+    /// e.g. `ObjectCreated:Put`
+    pub name: &'static str,
+    pub bucket: String,
+    pub key: String,
+    pub size: Option<i64>,
+    pub e_tag: Option<String>,
+}
+
+impl ObjectEvent {
+    /// The event as S3 would POST it, with a single record.
+    fn payload(&self, time: mongodb::bson::DateTime) -> serde_json::Value {
+        let mut object = json!({ "key": self.key });
+        if let Some(size) = self.size {
+            object["size"] = json!(size);
+        }
+        if let Some(e_tag) = &self.e_tag {
+            object["eTag"] = json!(e_tag.trim_matches('"'));
+        }
+        json!({
+            "Records": [{
+                "eventVersion": "2.1",
+                "eventSource": "aws:s3",
+                "eventTime": time.try_to_rfc3339_string().unwrap_or_default(),
+                "eventName": self.name,
+                "s3": {
+                    "s3SchemaVersion": "1.0",
+                    "bucket": { "name": self.bucket },
+                    "object": object,
+                },
+            }],
+        })
+    }
+}
+
+/// Queues object events and POSTs them to a webhook in the background, so that a slow or
+/// unreachable endpoint never holds up the response to the client.
+#[derive(Debug)]
+pub struct EventNotifier {
+    tx: mpsc::Sender<ObjectEvent>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+}
+
+impl EventNotifier {
+    pub fn spawn(config: &WebhookConfig) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(deliver(
+            config.url.clone(),
+            config.retries,
+            *config.timeout,
+            rx,
+        ));
+        EventNotifier {
+            tx,
+            prefix: config.prefix.clone(),
+            suffix: config.suffix.clone(),
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        self.prefix.as_deref().map_or(true, |p| key.starts_with(p))
+            && self.suffix.as_deref().map_or(true, |s| key.ends_with(s))
+    }
+
+    pub fn notify(&self, event: ObjectEvent) {
+        if !self.matches(&event.key) {
+            return;
+        }
+        if let Err(e) = self.tx.try_send(event) {
+            warn!("event notification dropped: {:?}", e);
+        }
+    }
+}
+
+#[instrument(name = "webhook", skip_all, fields(url = %url))]
+async fn deliver(
+    url: String,
+    retries: usize,
+    timeout: Duration,
+    mut rx: mpsc::Receiver<ObjectEvent>,
+) {
+    let mut connector = HttpConnector::new();
+    connector.set_connect_timeout(Some(timeout));
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+    while let Some(event) = rx.recv().await {
+        let body = Bytes::from(event.payload(mongodb::bson::DateTime::now()).to_string());
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                info!("redelivering {:?} ({}/{})", event.key, attempt, retries);
+            }
+            let Ok(request) = Request::post(&url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Full::new(body.clone()))
+            else {
+                error!("invalid webhook url");
+                return;
+            };
+            // an endpoint that stops answering would otherwise hold up every event after this one
+            match tokio::time::timeout(timeout, client.request(request)).await {
+                Ok(Ok(response)) if response.status().is_success() => break,
+                Ok(Ok(response)) => warn!("webhook answered {}", response.status()),
+                Ok(Err(e)) => warn!("webhook request failed: {:?}", e),
+                Err(_) => warn!("webhook did not answer within {:?}", timeout),
+            }
+            if attempt == retries {
+                error!("gave up delivering {} of {:?}", event.name, event.key);
+            }
+        }
+    }
+}
+
+impl S3Reproxy {
+    /// Notifies the webhook, if any, of a write or delete of `key` that went through.
+    pub(super) fn notify(
+        &self,
+        name: &'static str,
+        key: Option<&str>,
+        size: Option<i64>,
+        e_tag: Option<&str>,
+    ) {
+        let (Some(notifier), Some(key)) = (&self.notifier, key) else {
+            return;
+        };
+        notifier.notify(ObjectEvent {
+            name,
+            bucket: self.bucket.clone(),
+            key: key.to_owned(),
+            size,
+            e_tag: e_tag.map(str::to_owned),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http_body_util::BodyExt;
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accepts one connection and sends back the body of every request posted on it.
+    async fn endpoint() -> (String, mpsc::Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                let tx = tx.clone();
+                async move {
+                    let body = req.into_body().collect().await.unwrap().to_bytes();
+                    tx.send(serde_json::from_slice(&body).unwrap())
+                        .await
+                        .unwrap();
+                    Ok::<_, Infallible>(http::Response::new(Full::<Bytes>::default()))
+                }
+            });
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn put_fires_the_webhook() {
+        let (url, mut received) = endpoint().await;
+        let notifier = EventNotifier::spawn(&WebhookConfig {
+            url,
+            prefix: Some("photos/".to_owned()),
+            suffix: None,
+            retries: 0,
+            timeout: Duration::from_secs(10).into(),
+        });
+
+        notifier.notify(ObjectEvent {
+            name: "ObjectCreated:Put",
+            bucket: "virtual".to_owned(),
+            key: "videos/skipped.mp4".to_owned(),
+            size: Some(1),
+            e_tag: None,
+        });
+        notifier.notify(ObjectEvent {
+            name: "ObjectCreated:Put",
+            bucket: "virtual".to_owned(),
+            key: "photos/cat.jpg".to_owned(),
+            size: Some(2048),
+            e_tag: Some("\"9b2cf535f27731c974343645a3985328\"".to_owned()),
+        });
+
+        let payload = received.recv().await.unwrap();
+        let record = &payload["Records"][0];
+        assert_eq!(record["eventName"], "ObjectCreated:Put");
+        assert_eq!(record["s3"]["bucket"]["name"], "virtual");
+        assert_eq!(
+            record["s3"]["object"],
+            json!({
+                "key": "photos/cat.jpg",
+                "size": 2048,
+                "eTag": "9b2cf535f27731c974343645a3985328",
+            })
+        );
+        assert!(record["eventTime"].as_str().unwrap().starts_with("20"));
+    }
+
+    #[tokio::test]
+    async fn unanswered_delivery_times_out_and_is_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (tx, mut received) = mpsc::channel(8);
+        // takes every request in and never answers any of them
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let tx = tx.clone();
+                let service = service_fn(move |_: Request<hyper::body::Incoming>| {
+                    let tx = tx.clone();
+                    async move {
+                        tx.send(()).await.unwrap();
+                        std::future::pending::<Result<http::Response<Full<Bytes>>, Infallible>>()
+                            .await
+                    }
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+        let notifier = EventNotifier::spawn(&WebhookConfig {
+            url,
+            prefix: None,
+            suffix: None,
+            retries: 1,
+            timeout: Duration::from_millis(100).into(),
+        });
+
+        notifier.notify(ObjectEvent {
+            name: "ObjectCreated:Put",
+            bucket: "virtual".to_owned(),
+            key: "photos/cat.jpg".to_owned(),
+            size: None,
+            e_tag: None,
+        });
+
+        let redelivered = async {
+            received.recv().await.unwrap();
+            received.recv().await.unwrap();
+        };
+        tokio::time::timeout(Duration::from_secs(5), redelivered)
+            .await
+            .expect("the unanswered delivery was not retried");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use s3s::{s3_error, S3Result};
+use tracing::info;
+
+use super::S3Reproxy;
+
+/// Operations the proxy serves, by their S3 names. Any other is answered `NotImplemented`.
+pub const OPERATIONS: &[&str] = &[
+    "ListBuckets",
+    "GetBucketLocation",
+    "HeadBucket",
+    "CreateBucket",
+    "DeleteBucket",
+    "CreateMultipartUpload",
+    "UploadPart",
+    "UploadPartCopy",
+    "CompleteMultipartUpload",
+    "AbortMultipartUpload",
+    "ListParts",
+    "ListMultipartUploads",
+    "PutObject",
+    "CopyObject",
+    "DeleteObject",
+    "DeleteObjects",
+    "GetObject",
+    "HeadObject",
+    "GetObjectTagging",
+    "PutObjectTagging",
+    "DeleteObjectTagging",
+    "ListObjects",
+    "ListObjectsV2",
+];
+
+/// Operations that change the bucket or its objects, rejected when the proxy is read-only.
+pub const WRITE_OPERATIONS: &[&str] = &[
+    "CreateBucket",
+    "DeleteBucket",
+    "CreateMultipartUpload",
+    "UploadPart",
+    "UploadPartCopy",
+    "CompleteMultipartUpload",
+    "AbortMultipartUpload",
+    "PutObject",
+    "CopyObject",
+    "DeleteObject",
+    "DeleteObjects",
+    "PutObjectTagging",
+    "DeleteObjectTagging",
+];
+
+/// Rejects `operation` if it writes and the proxy is `read_only`.
+fn check_writable(read_only: bool, operation: &str) -> S3Result<()> {
+    if read_only && WRITE_OPERATIONS.contains(&operation) {
+        info!("(intercepted) {} on a read-only endpoint", operation);
+        return Err(s3_error!(AccessDenied, "This endpoint is read-only"));
+    }
+    Ok(())
+}
+
+/// Rejects `operation` unless it is in `enabled`. Every operation is enabled when unset.
+fn check_enabled(enabled: Option<&HashSet<String>>, operation: &str) -> S3Result<()> {
+    match enabled {
+        Some(enabled) if !enabled.contains(operation) => {
+            info!("(intercepted) {} is disabled", operation);
+            Err(s3_error!(
+                MethodNotAllowed,
+                "{} is disabled on this endpoint",
+                operation
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl S3Reproxy {
+    pub(super) fn check_operation(&self, operation: &str) -> S3Result<()> {
+        check_enabled(self.enabled_operations.as_ref(), operation)?;
+        check_writable(self.read_only, operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use s3s::S3ErrorCode;
+
+    #[test]
+    fn disabled_operation_is_rejected() {
+        let enabled = ["GetObject", "HeadObject", "ListObjectsV2"]
+            .map(str::to_owned)
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        assert!(check_enabled(Some(&enabled), "GetObject").is_ok());
+        let error = check_enabled(Some(&enabled), "DeleteObjects").unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::MethodNotAllowed);
+
+        assert!(check_enabled(None, "DeleteObjects").is_ok());
+    }
+
+    #[test]
+    fn writes_are_rejected_when_read_only() {
+        let error = check_writable(true, "CompleteMultipartUpload").unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::AccessDenied);
+
+        assert!(check_writable(true, "GetObject").is_ok());
+        assert!(check_writable(false, "PutObject").is_ok());
+        assert!(WRITE_OPERATIONS.iter().all(|op| OPERATIONS.contains(op)));
+    }
+}
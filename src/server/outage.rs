@@ -0,0 +1,106 @@
+use hyper::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use s3s::{S3Error, S3ErrorCode};
+use tracing::warn;
+
+use crate::config::s3_target::{FailureCode, FailureResponse};
+
+/// Errors answering requests that failed on the remotes as a whole rather than on one of them,
+/// as configured by `no_remote_error` and `below_quorum_error`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailureResponses {
+    pub no_remote: FailureResponse,
+    pub below_quorum: FailureResponse,
+}
+
+impl Default for FailureResponses {
+    fn default() -> Self {
+        Self {
+            no_remote: FailureResponse::default(),
+            below_quorum: FailureResponse {
+                code: FailureCode::ServiceUnavailable,
+                retry_after_secs: None,
+            },
+        }
+    }
+}
+
+impl FailureResponses {
+    /// No remote could serve the request, because none answered or none is configured.
+    pub fn no_remote_error(&self) -> S3Error {
+        warn!("no remotes available!");
+        failure_error(self.no_remote)
+    }
+
+    /// A write reached `stored` remotes, fewer than the `required` ones.
+    pub fn below_quorum_error(&self, stored: usize, required: usize) -> S3Error {
+        let mut error = failure_error(self.below_quorum);
+        error.set_message(format!(
+            "The write reached {stored} of the {required} required remotes"
+        ));
+        error
+    }
+}
+
+fn failure_error(response: FailureResponse) -> S3Error {
+    let mut error = S3Error::new(match response.code {
+        FailureCode::InternalError => S3ErrorCode::InternalError,
+        FailureCode::ServiceUnavailable => S3ErrorCode::ServiceUnavailable,
+        FailureCode::SlowDown => S3ErrorCode::SlowDown,
+    });
+    // clients are expected to back off on their own from a 500
+    if let (Some(secs), FailureCode::ServiceUnavailable | FailureCode::SlowDown) =
+        (response.retry_after_secs, response.code)
+    {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from(secs));
+        error.set_headers(headers);
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn failures_answer_with_the_configured_code() {
+        let responses = FailureResponses {
+            no_remote: FailureResponse {
+                code: FailureCode::SlowDown,
+                retry_after_secs: Some(5),
+            },
+            ..FailureResponses::default()
+        };
+
+        let error = responses.no_remote_error();
+        assert_eq!(error.code(), &S3ErrorCode::SlowDown);
+        assert_eq!(
+            error.headers().and_then(|h| h.get(RETRY_AFTER)),
+            Some(&HeaderValue::from(5))
+        );
+
+        let error = responses.below_quorum_error(1, 2);
+        assert_eq!(error.code(), &S3ErrorCode::ServiceUnavailable);
+        assert_eq!(
+            error.message(),
+            Some("The write reached 1 of the 2 required remotes")
+        );
+        assert!(error.headers().is_none());
+    }
+
+    #[test]
+    fn internal_errors_carry_no_retry_after() {
+        let responses = FailureResponses {
+            no_remote: FailureResponse {
+                code: FailureCode::InternalError,
+                retry_after_secs: Some(5),
+            },
+            ..FailureResponses::default()
+        };
+
+        let error = responses.no_remote_error();
+        assert_eq!(error.code(), &S3ErrorCode::InternalError);
+        assert!(error.headers().is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use aws_sdk_s3::types::ObjectCannedAcl;
+
+use crate::config::s3_target::NormalizeOwnership;
+
+/// The ACL-related headers of a write request.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct AclHeaders {
+    pub acl: Option<ObjectCannedAcl>,
+    pub grant_full_control: Option<String>,
+    pub grant_read: Option<String>,
+    pub grant_read_acp: Option<String>,
+    pub grant_write_acp: Option<String>,
+}
+
+impl AclHeaders {
+    /// Replaces whatever the client asked for with the remote's configured ownership, so each
+    /// object ends up owned the same way no matter which defaults the backend applies.
+    pub fn normalize(self, config: Option<&NormalizeOwnership>) -> Self {
+        match config {
+            None => self,
+            Some(NormalizeOwnership::CannedAcl(acl)) => AclHeaders {
+                acl: Some(ObjectCannedAcl::from(acl.as_str())),
+                ..Default::default()
+            },
+            Some(NormalizeOwnership::GrantFullControl(grantee)) => AclHeaders {
+                grant_full_control: Some(grantee.clone()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn requested() -> AclHeaders {
+        AclHeaders {
+            acl: None,
+            grant_full_control: Some("id=client".to_owned()),
+            grant_read: Some("uri=http://acs.amazonaws.com/groups/global/AllUsers".to_owned()),
+            grant_read_acp: None,
+            grant_write_acp: None,
+        }
+    }
+
+    #[test]
+    fn writes_carry_the_normalized_ownership() {
+        let canned = NormalizeOwnership::CannedAcl("bucket-owner-full-control".to_owned());
+        let grant = NormalizeOwnership::GrantFullControl("id=owner".to_owned());
+
+        assert_eq!(
+            requested().normalize(Some(&canned)),
+            AclHeaders {
+                acl: Some(ObjectCannedAcl::BucketOwnerFullControl),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            requested().normalize(Some(&grant)),
+            AclHeaders {
+                grant_full_control: Some("id=owner".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(requested().normalize(None), requested());
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+
+use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput;
+use aws_sdk_s3::operation::list_parts::{ListPartsError, ListPartsInput, ListPartsOutput};
+use aws_sdk_s3::operation::upload_part::UploadPartOutput;
+use aws_sdk_s3::operation::upload_part_copy::UploadPartCopyOutput;
+use aws_sdk_s3::types::CompletedMultipartUpload;
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use aws_smithy_runtime_api::client::result::ServiceError;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use s3s::{s3_error, S3Error, S3ErrorCode, S3Result};
+use s3s_aws::conv::AwsConversion;
+use tracing::{error, warn};
+
+use crate::db::{MultipartObject, RemoteMultipartUploadId};
+use crate::metrics::RECORD_FAILURES;
+
+use super::remote::{RemoteMessage, S3Remote};
+use super::retry::read_with_quick_retry;
+use super::stream::BranchCompletion;
+use super::{reply_rank, S3Reproxy};
+
+/// Number of distinct parts a `CompleteMultipartUpload` assembles the object from.
+pub(super) fn completed_parts_count(upload: Option<&CompletedMultipartUpload>) -> i32 {
+    let mut numbers = upload
+        .map(|u| u.parts())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|p| p.part_number)
+        .collect::<Vec<_>>();
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers.len() as i32
+}
+
+/// The upload of a remote that answered for a part, along with its answer. A remote that did not
+/// read the part's `body` to the end may have stored the part truncated, so its upload is
+/// cancelled and its answer dropped, unless the part was `resent` to it in full.
+pub(super) fn settle_part_upload<T>(
+    upload: RemoteMultipartUploadId,
+    body: &BranchCompletion,
+    resent: bool,
+    answer: T,
+) -> (RemoteMultipartUploadId, Option<T>) {
+    if resent || body.complete() {
+        return (upload, Some(answer));
+    }
+    warn!(
+        "remote({:?}) did not read the whole part. cancelling",
+        upload.remote_name
+    );
+    (upload.cancelled(), None)
+}
+
+/// The reply of a remote that stored a part, uploaded or copied.
+pub(super) trait StoredPart {
+    fn part_etag(&self) -> Option<&str>;
+}
+
+impl StoredPart for UploadPartOutput {
+    fn part_etag(&self) -> Option<&str> {
+        self.e_tag.as_deref()
+    }
+}
+
+impl StoredPart for UploadPartCopyOutput {
+    fn part_etag(&self) -> Option<&str> {
+        self.copy_part_result.as_ref()?.e_tag.as_deref()
+    }
+}
+
+/// Remotes that returned another ETag for an uploaded part than the highest-ranked remote that
+/// stored it, i.e. the one whose ETag the client gets. Remotes returning none are not compared.
+pub(super) fn diverging_part_etags<O: StoredPart, E>(
+    remotes: &[S3Remote],
+    results: &[(String, Result<O, E>)],
+) -> Vec<String> {
+    let etags = results
+        .iter()
+        .filter_map(|(remote, result)| Some((remote, result.as_ref().ok()?.part_etag()?)))
+        .collect::<Vec<_>>();
+    let Some((_, first)) = etags
+        .iter()
+        .min_by_key(|(remote, _)| reply_rank(remotes, remote))
+    else {
+        return vec![];
+    };
+    etags
+        .iter()
+        .filter(|(_, etag)| etag != first)
+        .map(|(remote, _)| remote.to_string())
+        .collect()
+}
+
+/// Cancels the uploads of the remotes that could not copy a part another remote copied, since they
+/// can no longer complete the upload. A copy that failed everywhere, e.g. from a missing source,
+/// leaves the upload as it was for the client to retry.
+pub(super) fn cancel_failed_copies<O, E>(
+    uploads: Vec<RemoteMultipartUploadId>,
+    results: &[(String, Result<O, E>)],
+) -> Vec<RemoteMultipartUploadId> {
+    if !results.iter().any(|(_, result)| result.is_ok()) {
+        return uploads;
+    }
+    uploads
+        .into_iter()
+        .map(|upload| {
+            let failed = results
+                .iter()
+                .any(|(remote, result)| *remote == upload.remote_name && result.is_err());
+            if !failed {
+                return upload;
+            }
+            warn!(
+                "remote({:?}) failed to copy the part. cancelling",
+                upload.remote_name
+            );
+            upload.cancelled()
+        })
+        .collect()
+}
+
+/// Size of the part an `x-amz-copy-source-range` of `bytes=first-last` copies. Unknown when the
+/// whole source is copied.
+pub(super) fn copied_part_size(range: Option<&str>) -> Option<i64> {
+    let (first, last) = range?.strip_prefix("bytes=")?.split_once('-')?;
+    let (first, last) = (first.parse::<i64>().ok()?, last.parse::<i64>().ok()?);
+    (first >= 0 && last >= first).then(|| last - first + 1)
+}
+
+/// The reply of the highest-ranked remote that completed the upload, addressed to the bucket and
+/// key the client completed. The remote's `Location` names its own endpoint and bucket, so it is
+/// left out.
+pub(super) fn completion_output(
+    remotes: &[S3Remote],
+    completions: Vec<(String, CompleteMultipartUploadOutput)>,
+    bucket: Option<String>,
+    key: Option<String>,
+) -> S3Result<s3s::dto::CompleteMultipartUploadOutput> {
+    let output = completions
+        .into_iter()
+        .min_by_key(|(remote, _)| reply_rank(remotes, remote))
+        .map_or_else(
+            || CompleteMultipartUploadOutput::builder().build(),
+            |(_, output)| output,
+        );
+    let mut output = s3s::dto::CompleteMultipartUploadOutput::try_from_aws(output)?;
+    output.bucket = bucket;
+    output.key = key;
+    output.location = None;
+    Ok(output)
+}
+
+/// Header in which a client declares the total size of the object it completes.
+const OBJECT_SIZE_HEADER: &str = "x-amz-mp-object-size";
+
+/// The total size the client declared on `CompleteMultipartUpload`, if any.
+pub(super) fn declared_object_size(headers: &http::HeaderMap) -> S3Result<Option<i64>> {
+    let Some(value) = headers.get(OBJECT_SIZE_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|size| *size >= 0)
+        .map(Some)
+        .ok_or_else(|| s3_error!(InvalidArgument, "invalid {} header", OBJECT_SIZE_HEADER))
+}
+
+/// Checks the declared size against the sum of the recorded sizes of the parts being completed.
+/// Returns without checking when the size of some part is unknown, e.g. because it was sent
+/// without a `Content-Length`.
+fn check_declared_size(
+    declared: i64,
+    upload: Option<&CompletedMultipartUpload>,
+    part_sizes: &HashMap<String, i64>,
+) -> S3Result<()> {
+    let sizes: Option<Vec<i64>> = upload
+        .map(|u| u.parts())
+        .unwrap_or_default()
+        .iter()
+        .map(|p| part_sizes.get(&p.part_number?.to_string()).copied())
+        .collect();
+    let Some(sizes) = sizes else {
+        warn!("(skipped) size of some part is unknown. not checking the declared size");
+        return Ok(());
+    };
+    let actual = sizes.iter().sum::<i64>();
+    if actual != declared {
+        warn!(
+            "(intercepted) declared object size {} does not match the parts ({})",
+            declared, actual
+        );
+        return Err(s3_error!(
+            InvalidRequest,
+            "{} does not match the size of the uploaded parts",
+            OBJECT_SIZE_HEADER
+        ));
+    }
+    Ok(())
+}
+
+/// The part count to report: the remote's own if it sent one, otherwise the one recorded when
+/// the upload completed. S3 reports it only for reads by `partNumber`.
+pub(super) fn parts_count(
+    part_number: Option<i32>,
+    reported: Option<i32>,
+    recorded: Option<i32>,
+) -> Option<i32> {
+    part_number?;
+    reported.or(recorded)
+}
+
+impl S3Reproxy {
+    /// Records where upload `id` stands on each remote after a part was stored, along with the
+    /// part's size. A part stored again with an unknown size forgets the size of the previous one.
+    pub(super) async fn record_part(
+        &self,
+        id: ObjectId,
+        uploads: &[RemoteMultipartUploadId],
+        part_number: Option<i32>,
+        size: Option<i64>,
+    ) -> S3Result<()> {
+        let mut set = doc! {
+            "upload_ids": mongodb::bson::to_bson(uploads).unwrap(),
+            "last_activity": mongodb::bson::DateTime::now(),
+        };
+        let mut unset = doc! {};
+        if let Some(part_number) = part_number {
+            let field = format!("part_sizes.{part_number}");
+            match size {
+                Some(size) => set.insert(field, size),
+                None => unset.insert(field, ""),
+            };
+        }
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        self.db
+            .multipart_upload_ids
+            .update_one(doc! { "_id": id }, update)
+            .await
+            .map_err(|e| {
+                error!("mongodb error: {:?}", e);
+                S3Error::new(S3ErrorCode::InternalError)
+            })?;
+        Ok(())
+    }
+
+    /// Rejects completing upload `id` when the client declared a total size that the parts it
+    /// lists do not add up to.
+    pub(super) async fn check_object_size(
+        &self,
+        id: ObjectId,
+        declared: i64,
+        upload: Option<&CompletedMultipartUpload>,
+    ) -> S3Result<()> {
+        let ids = self
+            .db
+            .multipart_upload_ids
+            .find_one(doc! { "_id": id })
+            .await
+            .map_err(|e| {
+                error!("mongodb error: {:?}", e);
+                S3Error::new(S3ErrorCode::InternalError)
+            })?
+            .ok_or_else(|| S3Error::new(S3ErrorCode::NoSuchUpload))?;
+        check_declared_size(declared, upload, &ids.part_sizes)
+    }
+
+    /// Records that `key` was written by a multipart upload of `parts_count` parts, or forgets a
+    /// previous one if it was overwritten or deleted some other way. The object is already written
+    /// by then, so a failure is logged and counted rather than failing the request.
+    pub(super) async fn record_parts_count(&self, key: &str, parts_count: Option<i32>) {
+        if !self.report_parts_count {
+            return;
+        }
+        let result = match parts_count {
+            Some(parts_count) => self
+                .db
+                .multipart_objects
+                .replace_one(
+                    doc! { "_id": key },
+                    MultipartObject {
+                        key: key.to_owned(),
+                        parts_count,
+                        completed_at: mongodb::bson::DateTime::now(),
+                    },
+                )
+                .upsert(true)
+                .await
+                .map(|_| ()),
+            None => self
+                .db
+                .multipart_objects
+                .delete_one(doc! { "_id": key })
+                .await
+                .map(|_| ()),
+        };
+        if let Err(e) = result {
+            error!(
+                "mongodb error: {:?}. the parts count of {:?} is stale!",
+                e, key
+            );
+            RECORD_FAILURES.inc(&["parts_count"]);
+        }
+    }
+
+    /// The part count recorded for `key` when a read by `partNumber` needs one.
+    pub(super) async fn recorded_parts_count(
+        &self,
+        key: Option<&str>,
+        part_number: Option<i32>,
+        reported: Option<i32>,
+    ) -> Option<i32> {
+        let (true, Some(key), Some(_), None) =
+            (self.report_parts_count, key, part_number, reported)
+        else {
+            return reported;
+        };
+        let recorded = match self
+            .db
+            .multipart_objects
+            .find_one(doc! { "_id": key })
+            .await
+        {
+            Ok(recorded) => recorded.map(|o| o.parts_count),
+            Err(e) => {
+                error!("mongodb error: {:?}. not reporting parts count", e);
+                None
+            }
+        };
+        parts_count(part_number, reported, recorded)
+    }
+}
+
+/// Lists the parts of an upload on the first of `remotes` that still has it open, by the remote's
+/// own upload id in `open`, and returns its answer as is along with its name.
+pub(super) async fn list_parts_on_remotes(
+    remotes: &[&S3Remote],
+    open: &HashMap<String, String>,
+    input: &ListPartsInput,
+    retries: usize,
+) -> Option<(
+    Result<ListPartsOutput, ServiceError<ListPartsError, HttpResponse>>,
+    String,
+)> {
+    for remote in remotes {
+        let Some(upload_id) = open.get(&remote.name) else {
+            continue;
+        };
+        let mut input = input.clone();
+        input.upload_id = Some(upload_id.clone());
+        let Some(output) =
+            read_with_quick_retry(remote, retries, |reply| RemoteMessage::ListParts {
+                input: input.clone(),
+                reply,
+            })
+            .await
+        else {
+            warn!("remote({:?}) request failed. skipping", remote.name);
+            continue;
+        };
+        return Some((output, remote.name.clone()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::upload_part::UploadPartInput;
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_sdk_s3::types::{CompletedPart, Part};
+    use bytes::Bytes;
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+    use pretty_assertions::assert_eq;
+
+    use crate::db::PartUploadStatus;
+    use crate::server::clone::UploadPartInputMultiplier;
+
+    fn completed(parts: impl IntoIterator<Item = i32>) -> CompletedMultipartUpload {
+        CompletedMultipartUpload::builder()
+            .set_parts(Some(
+                parts
+                    .into_iter()
+                    .map(|n| CompletedPart::builder().part_number(n).build())
+                    .collect(),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn part_etags_are_compared_with_the_answering_remote() {
+        let remote = |name: &str, priority| S3Remote {
+            priority,
+            ..S3Remote::stub(name)
+        };
+        let remotes = [remote("a", 1), remote("b", 10), remote("c", 1)];
+        let uploaded = |remote: &str, etag: &str| {
+            (
+                remote.to_owned(),
+                Ok::<_, ()>(UploadPartOutput::builder().e_tag(etag).build()),
+            )
+        };
+        let results = [
+            uploaded("a", "\"md5\""),
+            uploaded("b", "\"kms\""),
+            uploaded("c", "\"kms\""),
+        ];
+
+        assert_eq!(diverging_part_etags(&remotes, &results), vec!["a"]);
+        assert!(diverging_part_etags(&remotes, &results[1..]).is_empty());
+    }
+
+    #[test]
+    fn copied_part_etags_are_compared_too() {
+        use aws_sdk_s3::types::CopyPartResult;
+
+        let remotes = [S3Remote::stub("a"), S3Remote::stub("b")];
+        let copied = |remote: &str, etag: &str| {
+            (
+                remote.to_owned(),
+                Ok::<_, ()>(
+                    UploadPartCopyOutput::builder()
+                        .copy_part_result(CopyPartResult::builder().e_tag(etag).build())
+                        .build(),
+                ),
+            )
+        };
+
+        assert_eq!(
+            diverging_part_etags(&remotes, &[copied("a", "\"1\""), copied("b", "\"2\"")]),
+            vec!["b"]
+        );
+    }
+
+    #[test]
+    fn failed_copies_cancel_their_remote_unless_every_copy_failed() {
+        let upload = |remote: &str| RemoteMultipartUploadId {
+            status: PartUploadStatus::Open,
+            remote_name: remote.to_owned(),
+            upload_id: "upload".to_owned(),
+        };
+        let uploads = vec![upload("a"), upload("b")];
+        let result =
+            |remote: &str, ok: bool| (remote.to_owned(), if ok { Ok(()) } else { Err(()) });
+
+        assert_eq!(
+            cancel_failed_copies(uploads.clone(), &[result("a", true), result("b", false)]),
+            vec![upload("a"), upload("b").cancelled()]
+        );
+        assert_eq!(
+            cancel_failed_copies(uploads.clone(), &[result("a", false), result("b", false)]),
+            uploads
+        );
+    }
+
+    #[test]
+    fn copied_part_size_comes_from_the_range() {
+        assert_eq!(copied_part_size(Some("bytes=0-5242879")), Some(5242880));
+        assert_eq!(copied_part_size(Some("bytes=10-10")), Some(1));
+        assert_eq!(copied_part_size(Some("bytes=10-9")), None);
+        assert_eq!(copied_part_size(None), None);
+    }
+
+    #[test]
+    fn completed_upload_returns_the_etag_of_the_answering_remote() {
+        let remotes = [
+            S3Remote::stub("a"),
+            S3Remote {
+                priority: 10,
+                ..S3Remote::stub("b")
+            },
+        ];
+        let completed = |remote: &str| {
+            let output = CompleteMultipartUploadOutput::builder()
+                .bucket(remote)
+                .key("video.mp4")
+                .e_tag(format!("\"{remote}-2\""))
+                .location(format!("http://{remote}.invalid/{remote}/video.mp4"))
+                .build();
+            (remote.to_owned(), output)
+        };
+
+        let output = completion_output(
+            &remotes,
+            vec![completed("a"), completed("b")],
+            Some("virtual".to_owned()),
+            Some("video.mp4".to_owned()),
+        )
+        .unwrap();
+
+        assert_eq!(output.e_tag.as_deref(), Some("\"b-2\""));
+        assert_eq!(output.bucket.as_deref(), Some("virtual"));
+        assert_eq!(output.key.as_deref(), Some("video.mp4"));
+        assert_eq!(output.location, None);
+    }
+
+    #[test]
+    fn declared_size_not_matching_the_parts_is_rejected() {
+        let sizes = HashMap::from([
+            ("1".to_owned(), 5 * 1024 * 1024),
+            ("2".to_owned(), 5 * 1024 * 1024),
+            ("3".to_owned(), 1024),
+        ]);
+        let upload = completed(1..=3);
+
+        assert!(check_declared_size(10 * 1024 * 1024 + 1024, Some(&upload), &sizes).is_ok());
+        let error = check_declared_size(10 * 1024 * 1024, Some(&upload), &sizes).unwrap_err();
+        assert_eq!(error.code(), &S3ErrorCode::InvalidRequest);
+
+        let unknown = completed([1, 4]);
+        assert!(check_declared_size(0, Some(&unknown), &sizes).is_ok());
+    }
+
+    #[test]
+    fn declared_size_must_be_a_number() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(declared_object_size(&headers).unwrap(), None);
+
+        headers.insert(OBJECT_SIZE_HEADER, "1024".parse().unwrap());
+        assert_eq!(declared_object_size(&headers).unwrap(), Some(1024));
+
+        headers.insert(OBJECT_SIZE_HEADER, "-1".parse().unwrap());
+        assert!(declared_object_size(&headers).is_err());
+    }
+
+    #[test]
+    fn multipart_object_reports_recorded_parts_count_on_head() {
+        let upload = completed(1..=3);
+        let recorded = completed_parts_count(Some(&upload));
+        assert_eq!(recorded, 3);
+
+        assert_eq!(parts_count(Some(1), None, Some(recorded)), Some(3));
+        assert_eq!(parts_count(Some(1), Some(5), Some(recorded)), Some(5));
+        assert_eq!(parts_count(None, None, Some(recorded)), None);
+    }
+
+    /// A remote listing two parts of whichever upload it is asked about.
+    fn remote(name: &str) -> S3Remote {
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::ListParts { input, reply } = message {
+                let output = ListPartsOutput::builder()
+                    .set_upload_id(input.upload_id)
+                    .parts(Part::builder().part_number(1).e_tag("\"a1\"").build())
+                    .parts(Part::builder().part_number(3).e_tag("\"c3\"").build())
+                    .build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn parts_are_listed_on_the_first_open_remote() {
+        let cancelled = remote("cancelled");
+        let open = remote("open");
+        let fallback = remote("fallback");
+        let uploads = HashMap::from([
+            ("open".to_owned(), "open-upload".to_owned()),
+            ("fallback".to_owned(), "fallback-upload".to_owned()),
+        ]);
+        let input = ListPartsInput::builder()
+            .key("key")
+            .upload_id("proxy-upload")
+            .build()
+            .unwrap();
+
+        let (output, name) =
+            list_parts_on_remotes(&[&cancelled, &open, &fallback], &uploads, &input, 0)
+                .await
+                .unwrap();
+
+        assert_eq!(name, "open");
+        let output = output.unwrap();
+        assert_eq!(output.upload_id(), Some("open-upload"));
+        assert_eq!(
+            output
+                .parts()
+                .iter()
+                .map(|p| (p.part_number(), p.e_tag()))
+                .collect::<Vec<_>>(),
+            [(Some(1), Some("\"a1\"")), (Some(3), Some("\"c3\""))]
+        );
+    }
+
+    #[tokio::test]
+    async fn parts_cut_short_cancel_their_remote_only() {
+        let chunks = [b"0123".as_slice(), b"4567"]
+            .map(|c| Ok::<_, std::io::Error>(Frame::data(Bytes::from_static(c))));
+        let input = UploadPartInput::builder()
+            .key("video.mp4")
+            .upload_id("upload")
+            .part_number(1)
+            .body(ByteStream::from_body_1_x(StreamBody::new(
+                futures::stream::iter(chunks),
+            )))
+            .build()
+            .unwrap();
+        let (mut multiplier, _signal) = UploadPartInputMultiplier::from_input(input);
+        let (whole, whole_read) = multiplier.input("whole").await.unwrap();
+        let (cut, cut_read) = multiplier.input("cut").await.unwrap();
+        multiplier.close();
+
+        whole.body.collect().await.unwrap();
+        // the remote hangs up after the first chunk
+        let mut cut = cut.body;
+        cut.next().await.unwrap().unwrap();
+        drop(cut);
+
+        let upload = |remote: &str| RemoteMultipartUploadId {
+            status: PartUploadStatus::Open,
+            remote_name: remote.to_owned(),
+            upload_id: "upload".to_owned(),
+        };
+        assert_eq!(
+            settle_part_upload(upload("whole"), &whole_read, false, ()),
+            (upload("whole"), Some(()))
+        );
+        assert_eq!(
+            settle_part_upload(upload("cut"), &cut_read, false, ()),
+            (upload("cut").cancelled(), None)
+        );
+        assert_eq!(
+            settle_part_upload(upload("cut"), &cut_read, true, ()),
+            (upload("cut"), Some(()))
+        );
+    }
+}
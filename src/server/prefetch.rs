@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use aws_sdk_s3::operation::get_object::{GetObjectInput, GetObjectOutput};
+use aws_sdk_s3::operation::head_object::HeadObjectInput;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::ServerSideEncryption;
+use aws_smithy_types::DateTime;
+use bytes::Bytes;
+
+use super::fresh::head_input;
+use super::remote::{RemoteMessage, S3Remote};
+use crate::config::s3_target::RangePrefetchConfig;
+
+/// How many keys the end of the last read is remembered for. The least recently read key is
+/// forgotten first, with its buffer.
+const MAX_TRACKED_KEYS: usize = 4096;
+
+/// Read-ahead cache for clients that download an object through sequential ranged GETs.
+///
+/// The first ranged read of a key only records where it ended. When the next read starts exactly
+/// there, the remote is asked for `window_bytes` more than requested and the surplus is kept so the
+/// following reads can be answered without a round-trip. Anything out of order drops the buffer.
+pub struct RangePrefetcher {
+    window: u64,
+    max_bytes: u64,
+    state: Mutex<PrefetchState>,
+}
+
+#[derive(Default)]
+struct PrefetchState {
+    entries: HashMap<String, PrefetchEntry>,
+    used: u64,
+    clock: u64,
+}
+
+impl PrefetchState {
+    fn forget(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.used -= entry.buffer.map_or(0, |(_, data, _)| data.len() as u64);
+        }
+    }
+}
+
+struct PrefetchEntry {
+    next_offset: u64,
+    buffer: Option<(u64, Bytes, RangeMeta)>,
+    touched: u64,
+}
+
+/// Object metadata needed to answer a ranged GET from the buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RangeMeta {
+    pub total: Option<u64>,
+    pub e_tag: Option<String>,
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub expires: Option<DateTime>,
+    pub metadata: Option<HashMap<String, String>>,
+    pub last_modified: Option<DateTime>,
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    pub ssekms_key_id: Option<String>,
+    pub bucket_key_enabled: Option<bool>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PrefetchPlan {
+    /// The requested range is already buffered.
+    Cached(Bytes, RangeMeta),
+    /// The read continues the previous one; fetch up to this (inclusive) offset instead.
+    Prefetch(u64),
+    /// Forward the request unchanged.
+    Passthrough,
+}
+
+impl RangePrefetcher {
+    pub fn new(config: &RangePrefetchConfig) -> Self {
+        Self {
+            window: config.window_bytes,
+            max_bytes: config.max_cached_bytes,
+            state: Mutex::new(PrefetchState::default()),
+        }
+    }
+
+    /// Decides how to serve the inclusive range `start..=end` of `key`.
+    pub fn plan(&self, key: &str, start: u64, end: u64) -> PrefetchPlan {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.clock += 1;
+        let clock = state.clock;
+
+        let Some(entry) = state.entries.get_mut(key) else {
+            if state.entries.len() >= MAX_TRACKED_KEYS {
+                let oldest = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.touched)
+                    .map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    state.forget(&oldest);
+                }
+            }
+            state.entries.insert(
+                key.to_owned(),
+                PrefetchEntry {
+                    next_offset: end + 1,
+                    buffer: None,
+                    touched: clock,
+                },
+            );
+            return PrefetchPlan::Passthrough;
+        };
+        entry.touched = clock;
+
+        if let Some((buffer_start, data, meta)) = &entry.buffer {
+            let buffer_end = buffer_start + data.len() as u64;
+            if start >= *buffer_start && end < buffer_end {
+                let served =
+                    data.slice((start - buffer_start) as usize..=(end - buffer_start) as usize);
+                let meta = meta.clone();
+                entry.next_offset = end + 1;
+                return PrefetchPlan::Cached(served, meta);
+            }
+        }
+
+        let sequential = entry.next_offset == start;
+        entry.next_offset = end + 1;
+        let released = entry
+            .buffer
+            .take()
+            .map_or(0, |(_, data, _)| data.len() as u64);
+        state.used -= released;
+
+        if sequential && (end - start + 1) + self.window <= self.max_bytes {
+            PrefetchPlan::Prefetch(end + self.window)
+        } else {
+            PrefetchPlan::Passthrough
+        }
+    }
+
+    /// Buffers the part of a prefetched body that lies after the requested `end`, and returns
+    /// the part the client asked for. `data` must start at `start`.
+    pub fn store(&self, key: &str, start: u64, end: u64, data: Bytes, meta: RangeMeta) -> Bytes {
+        let requested = ((end - start + 1) as usize).min(data.len());
+        let served = data.slice(..requested);
+        let surplus = data.slice(requested..);
+        if surplus.is_empty() {
+            return served;
+        }
+
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        while state.used + surplus.len() as u64 > self.max_bytes {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .filter(|(_, e)| e.buffer.is_some())
+                .min_by_key(|(_, e)| e.touched)
+                .map(|(k, _)| k.clone())
+            else {
+                return served;
+            };
+            if let Some((_, data, _)) = state.entries.get_mut(&oldest).and_then(|e| e.buffer.take())
+            {
+                state.used -= data.len() as u64;
+            }
+        }
+
+        state.used += surplus.len() as u64;
+        if let Some(entry) = state.entries.get_mut(key) {
+            entry.buffer = Some((end + 1, surplus, meta));
+        } else {
+            state.used -= surplus.len() as u64;
+        }
+        served
+    }
+
+    /// Forgets everything known about `key`, e.g. after it was overwritten.
+    pub fn invalidate(&self, key: &str) {
+        self.state.lock().unwrap().forget(key);
+    }
+}
+
+/// Builds the response for a range served from the buffer, or trimmed from a prefetched body.
+#[allow(deprecated)]
+pub(crate) fn ranged_output(start: u64, data: Bytes, meta: RangeMeta) -> GetObjectOutput {
+    GetObjectOutput::builder()
+        .content_length(data.len() as i64)
+        .content_range(content_range(start, data.len(), meta.total))
+        .accept_ranges("bytes")
+        .set_e_tag(meta.e_tag)
+        .set_content_type(meta.content_type)
+        .set_cache_control(meta.cache_control)
+        .set_content_disposition(meta.content_disposition)
+        .set_content_encoding(meta.content_encoding)
+        .set_content_language(meta.content_language)
+        .set_expires(meta.expires)
+        .set_metadata(meta.metadata)
+        .set_last_modified(meta.last_modified)
+        .set_server_side_encryption(meta.server_side_encryption)
+        .set_ssekms_key_id(meta.ssekms_key_id)
+        .set_bucket_key_enabled(meta.bucket_key_enabled)
+        .body(ByteStream::from(data))
+        .build()
+}
+
+/// Whether the object read by `input` still has the ETag of a range buffered from it, according
+/// to `remote`. A range without an ETag, or a remote that cannot tell, counts as outdated.
+pub(crate) async fn still_current(
+    remote: &S3Remote,
+    input: &GetObjectInput,
+    e_tag: Option<&str>,
+) -> bool {
+    let (Some(e_tag), Some(head)) = (e_tag, head_input(input)) else {
+        return false;
+    };
+    let input = HeadObjectInput {
+        if_match: Some(e_tag.to_owned()),
+        ..head
+    };
+    matches!(
+        remote
+            .request(|reply| RemoteMessage::HeadObject { input, reply })
+            .await,
+        Some(Ok(_))
+    )
+}
+
+/// Whether a read must go to a remote rather than the buffer. Buffered ranges are the plaintext
+/// of the latest version, so reads of a given version or under a customer key, which the remote
+/// checks, always go to a remote. So do conditional reads, which the remote evaluates.
+pub(crate) fn bypasses_buffer(input: &GetObjectInput) -> bool {
+    input.version_id.is_some()
+        || input.sse_customer_key.is_some()
+        || input.if_match.is_some()
+        || input.if_none_match.is_some()
+        || input.if_modified_since.is_some()
+        || input.if_unmodified_since.is_some()
+}
+
+/// Applies the `response-*` overrides of a GET to a response the remote did not build, as it
+/// would have for a response it served itself.
+pub(crate) fn apply_response_overrides(output: &mut GetObjectOutput, input: &GetObjectInput) {
+    let overrides = [
+        (&mut output.cache_control, &input.response_cache_control),
+        (
+            &mut output.content_disposition,
+            &input.response_content_disposition,
+        ),
+        (
+            &mut output.content_encoding,
+            &input.response_content_encoding,
+        ),
+        (
+            &mut output.content_language,
+            &input.response_content_language,
+        ),
+        (&mut output.content_type, &input.response_content_type),
+    ];
+    for (field, value) in overrides {
+        if value.is_some() {
+            field.clone_from(value);
+        }
+    }
+    if input.response_expires.is_some() {
+        #[allow(deprecated)]
+        {
+            output.expires = input.response_expires;
+        }
+    }
+}
+
+/// Drops the `response-*` overrides from a read whose response is buffered for later reads,
+/// which must not inherit them. They are applied to its own response afterwards.
+pub(crate) fn clear_response_overrides(input: &mut GetObjectInput) {
+    input.response_cache_control = None;
+    input.response_content_disposition = None;
+    input.response_content_encoding = None;
+    input.response_content_language = None;
+    input.response_content_type = None;
+    input.response_expires = None;
+}
+
+/// Formats the `Content-Range` header for `len` bytes starting at `start`.
+pub(crate) fn content_range(start: u64, len: usize, total: Option<u64>) -> String {
+    let end = start + (len as u64).saturating_sub(1);
+    let total = total.map_or_else(|| "*".to_owned(), |t| t.to_string());
+    format!("bytes {start}-{end}/{total}")
+}
+
+/// Parses a closed `bytes=<start>-<end>` range. Open-ended and multi-range requests are not
+/// prefetched.
+pub(crate) fn parse_range(range: &str) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end).then_some((start, end))
+}
+
+/// Extracts where the range starts from a `Content-Range: bytes a-b/total` header.
+pub(crate) fn parse_content_range_start(content_range: &str) -> Option<u64> {
+    content_range
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
+
+/// Extracts the total object size from a `Content-Range: bytes a-b/total` header.
+pub(crate) fn parse_content_range_total(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn meta() -> RangeMeta {
+        RangeMeta {
+            total: Some(1000),
+            e_tag: Some("\"etag\"".to_owned()),
+            content_type: None,
+            cache_control: Some("max-age=3600".to_owned()),
+            content_disposition: None,
+            content_encoding: Some("gzip".to_owned()),
+            content_language: None,
+            expires: None,
+            metadata: None,
+            last_modified: None,
+            server_side_encryption: None,
+            ssekms_key_id: None,
+            bucket_key_enabled: None,
+        }
+    }
+
+    fn prefetcher(window_bytes: u64, max_cached_bytes: u64) -> RangePrefetcher {
+        RangePrefetcher::new(&RangePrefetchConfig {
+            window_bytes,
+            max_cached_bytes,
+        })
+    }
+
+    /// Reads `object` in `chunk`-sized ranges and returns how many requests reached the remote.
+    fn read_sequentially(prefetcher: &RangePrefetcher, object: &Bytes, chunk: u64) -> usize {
+        let mut round_trips = 0;
+        let mut start = 0;
+        while start < object.len() as u64 {
+            let end = (start + chunk - 1).min(object.len() as u64 - 1);
+            let served = match prefetcher.plan("key", start, end) {
+                PrefetchPlan::Cached(data, _) => data,
+                PrefetchPlan::Prefetch(until) => {
+                    round_trips += 1;
+                    let until = until.min(object.len() as u64 - 1);
+                    let fetched = object.slice(start as usize..=until as usize);
+                    prefetcher.store("key", start, end, fetched, meta())
+                }
+                PrefetchPlan::Passthrough => {
+                    round_trips += 1;
+                    object.slice(start as usize..=end as usize)
+                }
+            };
+            assert_eq!(served, object.slice(start as usize..=end as usize));
+            start = end + 1;
+        }
+        round_trips
+    }
+
+    #[test]
+    fn sequential_reads_use_fewer_round_trips() {
+        let object = Bytes::from((0..1000).map(|i| i as u8).collect::<Vec<_>>());
+        let prefetcher = prefetcher(300, 1000);
+
+        assert_eq!(read_sequentially(&prefetcher, &object, 100), 4);
+    }
+
+    #[test]
+    fn out_of_order_read_drops_buffer() {
+        let prefetcher = prefetcher(300, 1000);
+        let object = Bytes::from(vec![0u8; 1000]);
+
+        assert_eq!(prefetcher.plan("key", 0, 99), PrefetchPlan::Passthrough);
+        assert_eq!(
+            prefetcher.plan("key", 100, 199),
+            PrefetchPlan::Prefetch(499)
+        );
+        prefetcher.store("key", 100, 199, object.slice(100..500), meta());
+
+        assert_eq!(prefetcher.plan("key", 700, 799), PrefetchPlan::Passthrough);
+        assert_eq!(prefetcher.state.lock().unwrap().used, 0);
+    }
+
+    #[test]
+    fn buffer_is_bounded() {
+        let prefetcher = prefetcher(300, 500);
+        let object = Bytes::from(vec![0u8; 1000]);
+
+        for key in ["a", "b"] {
+            prefetcher.plan(key, 0, 99);
+            assert_eq!(prefetcher.plan(key, 100, 199), PrefetchPlan::Prefetch(499));
+            prefetcher.store(key, 100, 199, object.slice(100..500), meta());
+        }
+
+        assert_eq!(prefetcher.state.lock().unwrap().used, 300);
+        assert_eq!(prefetcher.plan("a", 200, 299), PrefetchPlan::Prefetch(599));
+    }
+
+    #[test]
+    fn tracked_keys_are_bounded() {
+        let prefetcher = prefetcher(100, 1000);
+
+        for key in 0..=MAX_TRACKED_KEYS {
+            prefetcher.plan(&key.to_string(), 0, 99);
+        }
+
+        assert_eq!(
+            prefetcher.state.lock().unwrap().entries.len(),
+            MAX_TRACKED_KEYS
+        );
+        assert_eq!(
+            prefetcher.plan(&MAX_TRACKED_KEYS.to_string(), 100, 199),
+            PrefetchPlan::Prefetch(299)
+        );
+        assert_eq!(prefetcher.plan("0", 100, 199), PrefetchPlan::Passthrough);
+    }
+
+    #[test]
+    fn cached_range_honors_response_overrides() {
+        let input = GetObjectInput::builder()
+            .key("key")
+            .range("bytes=100-199")
+            .response_content_disposition("attachment; filename=\"report.pdf\"")
+            .response_content_type("application/pdf")
+            .build()
+            .unwrap();
+
+        let mut output = ranged_output(100, Bytes::from(vec![0u8; 100]), meta());
+        apply_response_overrides(&mut output, &input);
+
+        assert_eq!(
+            output.content_disposition.as_deref(),
+            Some("attachment; filename=\"report.pdf\"")
+        );
+        assert_eq!(output.content_type.as_deref(), Some("application/pdf"));
+        assert_eq!(output.e_tag.as_deref(), Some("\"etag\""));
+    }
+
+    #[test]
+    fn prefetched_overrides_stay_with_the_read_that_asked() {
+        let requested = GetObjectInput::builder()
+            .key("key")
+            .range("bytes=100-199")
+            .response_content_disposition("attachment; filename=x.pdf")
+            .build()
+            .unwrap();
+        let mut sent = requested.clone();
+        clear_response_overrides(&mut sent);
+        assert_eq!(sent.response_content_disposition, None);
+
+        let mut output = ranged_output(100, Bytes::from(vec![0u8; 100]), meta());
+        apply_response_overrides(&mut output, &requested);
+        assert_eq!(
+            output.content_disposition.as_deref(),
+            Some("attachment; filename=x.pdf")
+        );
+
+        let later = ranged_output(200, Bytes::from(vec![0u8; 100]), meta());
+        assert_eq!(later.content_disposition, None);
+    }
+
+    #[test]
+    fn cached_range_keeps_the_stored_headers() {
+        let output = ranged_output(100, Bytes::from(vec![0u8; 100]), meta());
+
+        assert_eq!(output.cache_control.as_deref(), Some("max-age=3600"));
+        assert_eq!(output.content_encoding.as_deref(), Some("gzip"));
+    }
+
+    #[test]
+    fn versioned_and_conditional_reads_bypass_the_buffer() {
+        let read = || GetObjectInput::builder().key("key").range("bytes=100-199");
+
+        assert!(!bypasses_buffer(&read().build().unwrap()));
+        assert!(bypasses_buffer(&read().version_id("v1").build().unwrap()));
+        assert!(bypasses_buffer(
+            &read().if_none_match("\"etag\"").build().unwrap()
+        ));
+        assert!(bypasses_buffer(
+            &read().sse_customer_key("key").build().unwrap()
+        ));
+    }
+
+    #[test]
+    fn parses_closed_ranges_only() {
+        assert_eq!(parse_range("bytes=0-99"), Some((0, 99)));
+        assert_eq!(parse_range("bytes=100-"), None);
+        assert_eq!(parse_range("bytes=-100"), None);
+        assert_eq!(parse_content_range_total("bytes 0-99/1000"), Some(1000));
+        assert_eq!(parse_content_range_start("bytes 100-199/1000"), Some(100));
+        assert_eq!(parse_content_range_start("bytes */1000"), None);
+    }
+
+    #[tokio::test]
+    async fn buffered_range_is_checked_against_the_current_etag() {
+        use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+        use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+        use aws_smithy_runtime_api::client::result::ServiceError;
+        use aws_smithy_runtime_api::http::StatusCode;
+        use aws_smithy_types::body::SdkBody;
+        use aws_smithy_types::error::ErrorMetadata;
+
+        // answers HEADs like S3, for an object whose ETag is "new"
+        let remote = S3Remote::answering("a", |message| {
+            if let RemoteMessage::HeadObject { input, reply } = message {
+                let result = match input.if_match.as_deref() {
+                    Some("\"new\"") => Ok(HeadObjectOutput::builder().e_tag("\"new\"").build()),
+                    _ => Err(ServiceError::builder()
+                        .source(HeadObjectError::generic(ErrorMetadata::builder().build()))
+                        .raw(HttpResponse::new(
+                            StatusCode::try_from(412).unwrap(),
+                            SdkBody::empty(),
+                        ))
+                        .build()),
+                };
+                let _ = reply.send(Some(result));
+            }
+        });
+        let input = GetObjectInput::builder()
+            .key("video.mp4")
+            .range("bytes=0-99")
+            .build()
+            .unwrap();
+
+        assert!(still_current(&remote, &input, Some("\"new\"")).await);
+        assert!(!still_current(&remote, &input, Some("\"old\"")).await);
+        assert!(!still_current(&remote, &input, None).await);
+        assert!(!still_current(&S3Remote::stub("down"), &input, Some("\"new\"")).await);
+    }
+}
@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, RwLock};
+
+use tokio::task::JoinSet;
+use tracing::{info, instrument, warn};
+
+use crate::config::s3_target::{Config, S3Target};
+use crate::config::{self, S3ReproxySetup};
+use crate::error::SpanErr;
+
+use super::remote::{spawn_remote, RemoteMessage, S3Remote};
+
+/// The remotes currently configured, replaced as a whole when the config is reloaded. Requests
+/// keep using the remotes they started with. They are kept [`by_preference`], which is also the
+/// order writes are sent out in, whatever the order of the config file.
+#[derive(Debug)]
+pub struct RemoteSet(RwLock<Arc<Vec<S3Remote>>>);
+
+/// Orders remotes by `read_request` (readable ones first), then by `priority` (highest first),
+/// then by `name`, so that remotes tied on both are always taken in the same order.
+pub(crate) fn by_preference(a: &S3Remote, b: &S3Remote) -> Ordering {
+    b.read_request
+        .cmp(&a.read_request)
+        .then_with(|| b.priority.cmp(&a.priority))
+        .then_with(|| a.name.cmp(&b.name))
+}
+
+impl RemoteSet {
+    pub fn new(mut remotes: Vec<S3Remote>) -> Self {
+        remotes.sort_by(by_preference);
+        Self(RwLock::new(Arc::new(remotes)))
+    }
+
+    pub fn load(&self) -> Arc<Vec<S3Remote>> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    fn store(&self, mut remotes: Vec<S3Remote>) {
+        remotes.sort_by(by_preference);
+        *self.0.write().unwrap() = Arc::new(remotes);
+    }
+}
+
+/// Spawns the task of the remote `target` describes, and fills its key filter if it has one.
+pub(crate) fn start_remote(
+    target: &S3Target,
+    setup: &S3ReproxySetup,
+    tasks: &mut JoinSet<()>,
+) -> S3Remote {
+    let remote = spawn_remote(target.clone(), setup, tasks);
+    if let Some(filter) = &remote.key_filter {
+        tokio::spawn(super::bloom::rebuild(
+            remote.name.clone(),
+            remote.tx.clone(),
+            Arc::clone(filter),
+        ));
+    }
+    remote
+}
+
+/// Whether `new` only changes settings of `old` that [`S3Remote::reconfigured`] applies, so the
+/// remote keeps its task and connections.
+fn keeps_connection(old: &S3Target, new: &S3Target) -> bool {
+    let reconfigured = S3Target {
+        priority: new.priority,
+        failover_priority: new.failover_priority,
+        weight: new.weight,
+        read_request: new.read_request,
+        supported_checksums: new.supported_checksums.clone(),
+        max_metadata_bytes: new.max_metadata_bytes,
+        requires_content_length: new.requires_content_length,
+        supports_ranges: new.supports_ranges,
+        ..old.clone()
+    };
+    reconfigured == *new
+}
+
+/// Applies the remotes of the config file again when asked to, without restarting.
+pub(crate) struct Reloader {
+    setup: S3ReproxySetup,
+    remotes: Arc<RemoteSet>,
+}
+
+impl Reloader {
+    pub fn new(setup: S3ReproxySetup, remotes: Arc<RemoteSet>) -> Self {
+        Self { setup, remotes }
+    }
+
+    /// Reads the config file and swaps in its remotes. Remotes whose connection settings did not
+    /// change keep their task and connections, new ones are spawned into `tasks`, and removed ones
+    /// shut down once they answered the requests already sent to them. Only `remotes` is
+    /// reloaded; other settings take a restart.
+    #[instrument(name = "reload", skip_all)]
+    pub async fn reload(&mut self, tasks: &mut JoinSet<()>) -> Result<(), SpanErr<config::Error>> {
+        let setup = self.setup.reload().await?;
+        let unchanged = Config {
+            remotes: setup.config.remotes.clone(),
+            ..self.setup.config.clone()
+        };
+        if unchanged != setup.config {
+            warn!("settings other than remotes changed; they apply after a restart");
+        }
+
+        let current = self.remotes.load();
+        let mut kept = vec![];
+        let remotes = setup
+            .config
+            .remotes
+            .iter()
+            .map(|target| {
+                let old = self
+                    .setup
+                    .config
+                    .remotes
+                    .iter()
+                    .find(|old| old.name == target.name);
+                let remote = current.iter().find(|r| r.name == target.name);
+                match (old, remote) {
+                    (Some(old), Some(remote)) if keeps_connection(old, target) => {
+                        kept.push(remote.name.clone());
+                        remote.reconfigured(target)
+                    }
+                    _ => {
+                        info!("remote({:?}) started", target.name);
+                        start_remote(target, &setup, tasks)
+                    }
+                }
+            })
+            .collect();
+        self.remotes.store(remotes);
+
+        for remote in current.iter().filter(|r| !kept.contains(&r.name)) {
+            info!("remote({:?}) stopping", remote.name);
+            let _ = remote.tx.send(RemoteMessage::Shutdown).await;
+        }
+        info!("reloaded {} remotes", setup.config.remotes.len());
+        self.setup = setup;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(yaml: &str) -> S3Target {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn routing_changes_keep_the_connection() {
+        let old = target(
+            r#"
+            name: minio
+            s3:
+              endpoint: http://localhost:9000
+              access_key: abcabc
+              secret_key: defdef
+              bucket: test
+        "#,
+        );
+        let reprioritized = target(
+            r#"
+            name: minio
+            priority: 5
+            read_request: false
+            s3:
+              endpoint: http://localhost:9000
+              access_key: abcabc
+              secret_key: defdef
+              bucket: test
+        "#,
+        );
+        let slower = target(
+            r#"
+            name: minio
+            timeout_ms: 1000
+            s3:
+              endpoint: http://localhost:9000
+              access_key: abcabc
+              secret_key: defdef
+              bucket: test
+        "#,
+        );
+        let moved = target(
+            r#"
+            name: minio
+            s3:
+              endpoint: http://minio.internal:9000
+              access_key: abcabc
+              secret_key: defdef
+              bucket: test
+        "#,
+        );
+
+        assert!(keeps_connection(&old, &old));
+        assert!(keeps_connection(&old, &reprioritized));
+        assert!(!keeps_connection(&old, &slower));
+        assert!(!keeps_connection(&old, &moved));
+    }
+}
@@ -1,50 +1,212 @@
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
 use aws_sdk_s3::config::{Credentials, Region, StalledStreamProtectionConfig};
 use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::abort_multipart_upload::{
+    AbortMultipartUploadError, AbortMultipartUploadInput, AbortMultipartUploadOutput,
+};
 use aws_sdk_s3::operation::complete_multipart_upload::{
     CompleteMultipartUploadError, CompleteMultipartUploadInput, CompleteMultipartUploadOutput,
 };
+use aws_sdk_s3::operation::copy_object::{CopyObjectError, CopyObjectInput, CopyObjectOutput};
 use aws_sdk_s3::operation::create_multipart_upload::{
     CreateMultipartUploadError, CreateMultipartUploadInput, CreateMultipartUploadOutput,
 };
 use aws_sdk_s3::operation::delete_object::{
     DeleteObjectError, DeleteObjectInput, DeleteObjectOutput,
 };
+use aws_sdk_s3::operation::delete_object_tagging::{
+    DeleteObjectTaggingError, DeleteObjectTaggingInput, DeleteObjectTaggingOutput,
+};
 use aws_sdk_s3::operation::delete_objects::{
     DeleteObjectsError, DeleteObjectsInput, DeleteObjectsOutput,
 };
 use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectInput, GetObjectOutput};
+use aws_sdk_s3::operation::get_object_tagging::{
+    GetObjectTaggingError, GetObjectTaggingInput, GetObjectTaggingOutput,
+};
 use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectInput, HeadObjectOutput};
+use aws_sdk_s3::operation::list_buckets::{ListBucketsError, ListBucketsOutput};
 use aws_sdk_s3::operation::list_objects_v2::{ListObjectsV2Error, ListObjectsV2Output};
+use aws_sdk_s3::operation::list_parts::{ListPartsError, ListPartsInput, ListPartsOutput};
 use aws_sdk_s3::operation::put_object::{PutObjectError, PutObjectInput, PutObjectOutput};
+use aws_sdk_s3::operation::put_object_tagging::{
+    PutObjectTaggingError, PutObjectTaggingInput, PutObjectTaggingOutput,
+};
 use aws_sdk_s3::operation::upload_part::{UploadPartError, UploadPartInput, UploadPartOutput};
+use aws_sdk_s3::operation::upload_part_copy::{
+    UploadPartCopyError, UploadPartCopyInput, UploadPartCopyOutput,
+};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{EncodingType, Object};
 use aws_sdk_s3::Client;
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
 use aws_smithy_runtime_api::client::orchestrator;
 use aws_smithy_runtime_api::client::result::ServiceError;
+use aws_smithy_types::body::SdkBody;
 use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinSet;
 use tracing::{info, info_span, instrument, warn, Instrument};
 
-use crate::config::s3_target::S3Target;
+use crate::config::s3_target::{HttpClientConfig, S3Target};
 use crate::config::S3ReproxySetup;
+use crate::metrics::{REMOTE_FAILURES, REMOTE_REQUEST_DURATION, REQUESTS};
+
+use super::bloom::KeyFilter;
+use super::key_prefix::KeyPrefix;
+use super::ownership::AclHeaders;
+use super::random;
+use super::status::RemoteStatus;
+use super::stream::count_received;
 
-#[derive(Debug)]
+/// Signing region of targets that do not set one.
+const DEFAULT_REGION: &str = "us-east-1";
+
+#[derive(Debug, Clone)]
 pub struct S3Remote {
     pub name: String,
+    pub bucket: String,
+    pub endpoint: String,
     pub priority: u32,
+    pub failover_priority: Option<u32>,
+    pub weight: u32,
     pub read_request: bool,
     pub tx: mpsc::Sender<RemoteMessage>,
+    pub key_filter: Option<Arc<KeyFilter>>,
+    pub supported_checksums: Option<Vec<String>>,
+    pub max_metadata_bytes: Option<usize>,
+    pub requires_content_length: bool,
+    pub supports_ranges: bool,
+    pub status: Arc<RemoteStatus>,
+}
+
+impl S3Remote {
+    /// Sends the request `message` builds and waits for the reply. `None` when the remote is
+    /// down, or did not answer within its timeout.
+    pub async fn request<O>(
+        &self,
+        message: impl FnOnce(oneshot::Sender<Option<O>>) -> RemoteMessage,
+    ) -> Option<O> {
+        let (tx, rx) = oneshot::channel();
+        let message = message(tx);
+        let operation = message.operation();
+        let started = Instant::now();
+        let output = async {
+            self.tx.send(message).await.ok()?;
+            rx.await.ok()?
+        }
+        .await;
+
+        let labels = [operation, self.name.as_str()];
+        REQUESTS.inc(&labels);
+        REMOTE_REQUEST_DURATION.observe(&labels, started.elapsed());
+        if output.is_none() {
+            REMOTE_FAILURES.inc(&labels);
+        }
+        output
+    }
+
+    /// This remote with the settings of `target` that only decide how requests are routed to it.
+    /// Its task, connections and status are kept.
+    pub fn reconfigured(&self, target: &S3Target) -> S3Remote {
+        S3Remote {
+            priority: target.priority,
+            failover_priority: target.failover_priority,
+            weight: target.weight,
+            read_request: target.read_request,
+            supported_checksums: target.supported_checksums.clone(),
+            max_metadata_bytes: target.max_metadata_bytes,
+            requires_content_length: target.requires_content_length,
+            supports_ranges: target.supports_ranges,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+impl S3Remote {
+    /// A readable remote with default settings whose actor is never spawned.
+    pub(crate) fn stub(name: &str) -> Self {
+        S3Remote {
+            name: name.to_owned(),
+            bucket: name.to_owned(),
+            endpoint: format!("http://{name}.invalid"),
+            priority: 1,
+            failover_priority: None,
+            weight: 1,
+            read_request: true,
+            tx: mpsc::channel(1).0,
+            key_filter: None,
+            supported_checksums: None,
+            max_metadata_bytes: None,
+            requires_content_length: false,
+            supports_ranges: true,
+            status: Arc::default(),
+        }
+    }
+
+    /// A remote whose requests are answered by `answer`, one after another.
+    pub(crate) fn answering(
+        name: &str,
+        mut answer: impl FnMut(RemoteMessage) + Send + 'static,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                answer(message);
+            }
+        });
+        S3Remote {
+            tx,
+            ..S3Remote::stub(name)
+        }
+    }
+
+    /// A remote whose requests are each handled by `handle` in a task of their own, and
+    /// cancelled after `timeout`, like those of a spawned remote.
+    pub(crate) fn serving<F>(
+        name: &str,
+        timeout: Option<Duration>,
+        handle: impl Fn(RemoteMessage) -> F + Send + 'static,
+    ) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(serve(rx, timeout, handle));
+        S3Remote {
+            tx,
+            ..S3Remote::stub(name)
+        }
+    }
 }
 
 pub enum RemoteMessage {
     HealthCheck {
         reply: oneshot::Sender<bool>,
     },
+    ListBuckets {
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    ListBucketsOutput,
+                    ServiceError<ListBucketsError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
     ListObjects {
         prefix: Option<String>,
         delimiter: Option<String>,
         max_keys: Option<i32>,
         start_after: Option<String>,
+        continuation_token: Option<String>,
+        fetch_owner: Option<bool>,
+        encoding_type: Option<EncodingType>,
         reply: oneshot::Sender<
             Option<
                 Result<
@@ -78,6 +240,14 @@ pub enum RemoteMessage {
             >,
         >,
     },
+    CopyObject {
+        input: CopyObjectInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<CopyObjectOutput, ServiceError<CopyObjectError, orchestrator::HttpResponse>>,
+            >,
+        >,
+    },
     DeleteObject {
         input: DeleteObjectInput,
         reply: oneshot::Sender<
@@ -100,6 +270,39 @@ pub enum RemoteMessage {
             >,
         >,
     },
+    GetObjectTagging {
+        input: GetObjectTaggingInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    GetObjectTaggingOutput,
+                    ServiceError<GetObjectTaggingError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
+    PutObjectTagging {
+        input: PutObjectTaggingInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    PutObjectTaggingOutput,
+                    ServiceError<PutObjectTaggingError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
+    DeleteObjectTagging {
+        input: DeleteObjectTaggingInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    DeleteObjectTaggingOutput,
+                    ServiceError<DeleteObjectTaggingError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
     CreateMultiPartUpload {
         input: CreateMultipartUploadInput,
         reply: oneshot::Sender<
@@ -119,6 +322,17 @@ pub enum RemoteMessage {
             >,
         >,
     },
+    UploadPartCopy {
+        input: UploadPartCopyInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    UploadPartCopyOutput,
+                    ServiceError<UploadPartCopyError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
     CompleteMultiPartUpload {
         input: CompleteMultipartUploadInput,
         reply: oneshot::Sender<
@@ -130,17 +344,87 @@ pub enum RemoteMessage {
             >,
         >,
     },
+    ListParts {
+        input: ListPartsInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<ListPartsOutput, ServiceError<ListPartsError, orchestrator::HttpResponse>>,
+            >,
+        >,
+    },
+    AbortMultipartUpload {
+        input: AbortMultipartUploadInput,
+        reply: oneshot::Sender<
+            Option<
+                Result<
+                    AbortMultipartUploadOutput,
+                    ServiceError<AbortMultipartUploadError, orchestrator::HttpResponse>,
+                >,
+            >,
+        >,
+    },
     Shutdown,
 }
 
+impl RemoteMessage {
+    /// Name of the S3 operation the message asks the remote for.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            RemoteMessage::HealthCheck { .. } => "HeadBucket",
+            RemoteMessage::ListBuckets { .. } => "ListBuckets",
+            RemoteMessage::ListObjects { .. } => "ListObjectsV2",
+            RemoteMessage::HeadObject { .. } => "HeadObject",
+            RemoteMessage::GetObject { .. } => "GetObject",
+            RemoteMessage::PutObject { .. } => "PutObject",
+            RemoteMessage::CopyObject { .. } => "CopyObject",
+            RemoteMessage::DeleteObject { .. } => "DeleteObject",
+            RemoteMessage::DeleteObjects { .. } => "DeleteObjects",
+            RemoteMessage::GetObjectTagging { .. } => "GetObjectTagging",
+            RemoteMessage::PutObjectTagging { .. } => "PutObjectTagging",
+            RemoteMessage::DeleteObjectTagging { .. } => "DeleteObjectTagging",
+            RemoteMessage::CreateMultiPartUpload { .. } => "CreateMultipartUpload",
+            RemoteMessage::UploadPart { .. } => "UploadPart",
+            RemoteMessage::UploadPartCopy { .. } => "UploadPartCopy",
+            RemoteMessage::CompleteMultiPartUpload { .. } => "CompleteMultipartUpload",
+            RemoteMessage::ListParts { .. } => "ListParts",
+            RemoteMessage::AbortMultipartUpload { .. } => "AbortMultipartUpload",
+            RemoteMessage::Shutdown => "Shutdown",
+        }
+    }
+}
+
+/// Applies `http` to the SDK config of a target. Settings left unset keep the SDK's defaults.
+fn with_http_client_config(
+    mut builder: aws_sdk_s3::config::Builder,
+    http: &HttpClientConfig,
+) -> aws_sdk_s3::config::Builder {
+    builder = builder.timeout_config(
+        TimeoutConfig::builder()
+            .set_connect_timeout(http.connect_timeout.as_deref().copied())
+            .set_read_timeout(http.read_timeout.as_deref().copied())
+            .build(),
+    );
+    if http.max_idle_connections.is_some() || http.idle_timeout.is_some() {
+        let mut pool = hyper_014::Client::builder();
+        if let Some(max) = http.max_idle_connections {
+            pool.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = http.idle_timeout.as_deref() {
+            pool.pool_idle_timeout(*timeout);
+        }
+        builder = builder.http_client(HyperClientBuilder::new().hyper_builder(pool).build_https());
+    }
+    builder
+}
+
 // TODO: ここらへんのunwrap削減するぞ！
-#[instrument(name = "remote", skip_all, fields(name = target.name, bucket = target.s3.bucket))]
+#[instrument(name = "remote", skip_all, fields(name = target.name, bucket = target.s3.bucket, endpoint = target.s3.endpoint))]
 pub fn spawn_remote(target: S3Target, setup: &S3ReproxySetup, set: &mut JoinSet<()>) -> S3Remote {
-    let s3_config = aws_sdk_s3::config::Builder::new()
-        .endpoint_url(target.s3.endpoint)
+    let mut s3_config = aws_sdk_s3::config::Builder::new()
+        .endpoint_url(target.s3.endpoint.clone())
         .credentials_provider(Credentials::new(
-            target.s3.access_key,
-            target.s3.secret_key,
+            target.s3.access_key.clone(),
+            target.s3.secret_key.clone(),
             None,
             None,
             "loaded-from-s3reproxy-config",
@@ -150,292 +434,827 @@ pub fn spawn_remote(target: S3Target, setup: &S3ReproxySetup, set: &mut JoinSet<
                 .grace_period(*setup.args.stream_stall_grace_period)
                 .build(),
         )
-        .region(Region::new(""))
-        .force_path_style(true)
-        .behavior_version_latest()
-        .build();
+        .region(Region::new(
+            target
+                .s3
+                .region
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REGION.to_owned()),
+        ))
+        .force_path_style(target.s3.force_path_style)
+        // requests are retried by the remote, which knows which of them are safe to send again
+        .retry_config(RetryConfig::disabled())
+        .behavior_version_latest();
+    if let Some(http) = target.http.as_ref().or(setup.config.http.as_ref()) {
+        s3_config = with_http_client_config(s3_config, http);
+    }
 
-    let client = Client::from_conf(s3_config);
+    let client = Client::from_conf(s3_config.build());
 
     info!("Created new remote client.");
 
-    let (tx, mut rx) = mpsc::channel(32);
+    let (tx, rx) = mpsc::channel(32);
+    let bucket = target.s3.bucket.clone();
+    let endpoint = target.s3.endpoint.clone();
+    let remote_name = target.name.clone();
+    let status = Arc::new(match &setup.config.health_check {
+        Some(health_check) => RemoteStatus::opening_after(health_check.failures_to_open),
+        None => RemoteStatus::default(),
+    });
+    let key_prefix = KeyPrefix::new(target.key_prefix.clone());
+    let retry = RetryPolicy {
+        retries: target.retries,
+        base_delay: Duration::from_millis(target.retry_base_delay_ms),
+    };
+    let timeout = target.request_timeout(&setup.config);
 
+    let handler = Arc::new(Handler {
+        client,
+        target: target.clone(),
+        status: Arc::clone(&status),
+        retry,
+        key_prefix,
+        remote_name,
+    });
     set.spawn(
-        async move {
-            let mut health: Option<bool> = None;
-
-            loop {
-                tokio::select! {
-                    Some(msg) = rx.recv() => match msg {
-                        RemoteMessage::HealthCheck { reply } => {
-                            info!("Checking health...");
-                            let q = client.head_bucket().bucket(target.s3.bucket.clone()).send().await;
-                            let q = map_health(&mut health, q);
-                            let _ = reply.send(match q {
-                                Some(Ok(_)) => true,
-                                e => {
-                                    warn!("Health check failed: {:?}", e);
-                                    false
-                                },
-                            });
-                        }
-                        RemoteMessage::ListObjects { prefix, delimiter, max_keys, start_after, reply } => {
-                            info!("Listing objects...");
-                            let q = client.list_objects_v2()
-                                .bucket(target.s3.bucket.clone())
-                                .set_prefix(prefix)
-                                .set_start_after(start_after)
-                                .set_delimiter(delimiter)
-                                .set_max_keys(max_keys)
-                                .send()
-                                .await;
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::GetObject { input, reply } => {
-                            info!("Get object...");
-
-                            let q = client.get_object()
-                                .bucket(target.s3.bucket.clone())
-                                .set_checksum_mode(input.checksum_mode)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .set_if_match(input.if_match)
-                                .set_if_modified_since(input.if_modified_since)
-                                .set_if_none_match(input.if_none_match)
-                                .set_if_unmodified_since(input.if_unmodified_since)
-                                .set_key(input.key)
-                                .set_part_number(input.part_number)
-                                .set_range(input.range)
-                                .set_request_payer(input.request_payer)
-                                .set_response_cache_control(input.response_cache_control)
-                                .set_response_content_disposition(input.response_content_disposition)
-                                .set_response_content_encoding(input.response_content_encoding)
-                                .set_response_content_language(input.response_content_language)
-                                .set_response_content_type(input.response_content_type)
-                                .set_response_expires(input.response_expires)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .set_version_id(input.version_id)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::PutObject { input, reply } => {
-                            info!("Put object...");
-                            let q = client.put_object()
-                                .bucket(target.s3.bucket.clone())
-                                .set_acl(input.acl)
-                                .body(input.body)
-                                .set_cache_control(input.cache_control)
-                                .set_content_disposition(input.content_disposition)
-                                .set_content_encoding(input.content_encoding)
-                                .set_content_language(input.content_language)
-                                .set_content_length(input.content_length)
-                                .set_content_md5(input.content_md5)
-                                .set_content_type(input.content_type)
-                                .set_checksum_algorithm(input.checksum_algorithm)
-                                .set_checksum_crc32(input.checksum_crc32)
-                                .set_checksum_crc32_c(input.checksum_crc32_c)
-                                .set_checksum_sha1(input.checksum_sha1)
-                                .set_checksum_sha256(input.checksum_sha256)
-                                .set_expires(input.expires)
-                                .set_grant_full_control(input.grant_full_control)
-                                .set_grant_read(input.grant_read)
-                                .set_grant_read_acp(input.grant_read_acp)
-                                .set_grant_write_acp(input.grant_write_acp)
-                                .set_key(input.key)
-                                .set_metadata(input.metadata)
-                                .set_server_side_encryption(input.server_side_encryption)
-                                .set_storage_class(input.storage_class)
-                                .set_website_redirect_location(input.website_redirect_location)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .set_ssekms_key_id(input.ssekms_key_id)
-                                .set_ssekms_encryption_context(input.ssekms_encryption_context)
-                                .set_bucket_key_enabled(input.bucket_key_enabled)
-                                .set_request_payer(input.request_payer)
-                                .set_tagging(input.tagging)
-                                .set_object_lock_mode(input.object_lock_mode)
-                                .set_object_lock_retain_until_date(input.object_lock_retain_until_date)
-                                .set_object_lock_legal_hold_status(input.object_lock_legal_hold_status)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::DeleteObject { input, reply } => {
-                            info!("Delete object...");
-                            let q = client.delete_object()
-                                .bucket(target.s3.bucket.clone())
-                                .set_key(input.key)
-                                .set_mfa(input.mfa)
-                                .set_version_id(input.version_id)
-                                .set_request_payer(input.request_payer)
-                                .set_bypass_governance_retention(input.bypass_governance_retention)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::DeleteObjects { input, reply } => {
-                            info!("Delete objects...");
-                            let q = client.delete_objects()
-                                .bucket(target.s3.bucket.clone())
-                                .set_delete(input.delete)
-                                .set_mfa(input.mfa)
-                                .set_request_payer(input.request_payer)
-                                .set_bypass_governance_retention(input.bypass_governance_retention)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .set_checksum_algorithm(input.checksum_algorithm)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::HeadObject { input, reply } => {
-                            info!("Head object...");
-                            let q = client.head_object()
-                                .bucket(target.s3.bucket.clone())
-                                .set_if_match(input.if_match)
-                                .set_if_modified_since(input.if_modified_since)
-                                .set_if_unmodified_since(input.if_unmodified_since)
-                                .set_key(input.key)
-                                .set_range(input.range)
-                                .set_response_cache_control(input.response_cache_control)
-                                .set_response_content_disposition(input.response_content_disposition)
-                                .set_response_content_encoding(input.response_content_encoding)
-                                .set_response_content_language(input.response_content_language)
-                                .set_response_content_type(input.response_content_type)
-                                .set_response_expires(input.response_expires)
-                                .set_version_id(input.version_id)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .set_request_payer(input.request_payer)
-                                .set_part_number(input.part_number)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .set_checksum_mode(input.checksum_mode)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::CreateMultiPartUpload { input, reply } => {
-                            info!("Create multipart upload...");
-
-                            let q = client.create_multipart_upload()
-                                .bucket(target.s3.bucket.clone())
-                                .set_acl(input.acl)
-                                .set_cache_control(input.cache_control)
-                                .set_content_disposition(input.content_disposition)
-                                .set_content_encoding(input.content_encoding)
-                                .set_content_language(input.content_language)
-                                .set_content_type(input.content_type)
-                                .set_expires(input.expires)
-                                .set_grant_full_control(input.grant_full_control)
-                                .set_grant_read(input.grant_read)
-                                .set_grant_read_acp(input.grant_read_acp)
-                                .set_grant_write_acp(input.grant_write_acp)
-                                .set_key(input.key)
-                                .set_metadata(input.metadata)
-                                .set_server_side_encryption(input.server_side_encryption)
-                                .set_storage_class(input.storage_class)
-                                .set_website_redirect_location(input.website_redirect_location)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .set_ssekms_key_id(input.ssekms_key_id)
-                                .set_ssekms_encryption_context(input.ssekms_encryption_context)
-                                .set_bucket_key_enabled(input.bucket_key_enabled)
-                                .set_request_payer(input.request_payer)
-                                .set_tagging(input.tagging)
-                                .set_object_lock_mode(input.object_lock_mode)
-                                .set_object_lock_retain_until_date(input.object_lock_retain_until_date)
-                                .set_object_lock_legal_hold_status(input.object_lock_legal_hold_status)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .set_checksum_algorithm(input.checksum_algorithm)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::UploadPart { input, reply } => {
-                            let span = info_span!("upload_part_message", part_number = &input.part_number);
-                            let _guard = span.enter();
-                            info!("Upload part...");
-
-                            let q = client.upload_part()
-                                .bucket(target.s3.bucket.clone())
-                                .body(input.body)
-                                .set_content_length(input.content_length)
-                                .set_content_md5(input.content_md5)
-                                .set_checksum_algorithm(input.checksum_algorithm)
-                                .set_checksum_crc32(input.checksum_crc32)
-                                .set_checksum_crc32_c(input.checksum_crc32_c)
-                                .set_checksum_sha1(input.checksum_sha1)
-                                .set_checksum_sha256(input.checksum_sha256)
-                                .set_key(input.key)
-                                .set_part_number(input.part_number)
-                                .set_upload_id(input.upload_id)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .set_request_payer(input.request_payer)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::CompleteMultiPartUpload { input, reply } => {
-                            info!("Complete multipart upload...");
-
-                            let q = client.complete_multipart_upload()
-                                .bucket(target.s3.bucket.clone())
-                                .set_key(input.key)
-                                .set_multipart_upload(input.multipart_upload)
-                                .set_upload_id(input.upload_id)
-                                .set_checksum_crc32(input.checksum_crc32)
-                                .set_checksum_crc32_c(input.checksum_crc32_c)
-                                .set_checksum_sha1(input.checksum_sha1)
-                                .set_checksum_sha256(input.checksum_sha256)
-                                .set_request_payer(input.request_payer)
-                                .set_expected_bucket_owner(input.expected_bucket_owner)
-                                .set_sse_customer_algorithm(input.sse_customer_algorithm)
-                                .set_sse_customer_key(input.sse_customer_key)
-                                .set_sse_customer_key_md5(input.sse_customer_key_md5)
-                                .send()
-                                .await;
-
-                            let _ = reply.send(map_health(&mut health, q));
-                        }
-                        RemoteMessage::Shutdown => {
-                            break;
-                        }
-                    }
-                }
-            }
-
-            info!("Remote shutting down.");
-        }
+        serve(rx, timeout, move |message| {
+            let handler = Arc::clone(&handler);
+            async move { handler.handle(message).await }.in_current_span()
+        })
         .in_current_span(),
     );
+    #[cfg(feature = "chaos")]
+    let tx = super::chaos::inject(&target.name, tx, target.faults);
     S3Remote {
         name: target.name,
+        bucket,
+        endpoint,
         priority: target.priority,
+        failover_priority: target.failover_priority,
+        weight: target.weight,
         read_request: target.read_request,
         tx,
+        key_filter: setup
+            .config
+            .key_filter
+            .as_ref()
+            .map(|c| Arc::new(KeyFilter::new(c))),
+        supported_checksums: target.supported_checksums,
+        max_metadata_bytes: target.max_metadata_bytes,
+        requires_content_length: target.requires_content_length,
+        supports_ranges: target.supports_ranges,
+        status,
+    }
+}
+
+/// Pages through every object of a remote, 1000 keys at a time, passing each one to `f`.
+/// Returns the number of objects listed, or `None` if a page could not be fetched.
+pub(crate) async fn list_all_objects(
+    tx: &mpsc::Sender<RemoteMessage>,
+    mut f: impl FnMut(&Object),
+) -> Option<usize> {
+    let mut start_after = None;
+    let mut count = 0usize;
+    loop {
+        let page: Option<_> = try {
+            let (reply, rx) = oneshot::channel();
+            tx.send(RemoteMessage::ListObjects {
+                prefix: None,
+                delimiter: None,
+                max_keys: Some(1000),
+                start_after: start_after.clone(),
+                continuation_token: None,
+                fetch_owner: None,
+                encoding_type: None,
+                reply,
+            })
+            .await
+            .ok()?;
+            rx.await.ok()??
+        };
+        let output = page?.ok()?;
+
+        let objects = output.contents.unwrap_or_default();
+        objects.iter().for_each(&mut f);
+        count += objects.len();
+
+        let last = objects.last().and_then(|o| o.key.clone());
+        if output.is_truncated != Some(true) || last.is_none() {
+            return Some(count);
+        }
+        start_after = last;
+    }
+}
+
+/// What the requests sent to a remote are handled with.
+struct Handler {
+    client: Client,
+    target: S3Target,
+    status: Arc<RemoteStatus>,
+    retry: RetryPolicy,
+    key_prefix: KeyPrefix,
+    remote_name: String,
+}
+
+impl Handler {
+    async fn handle(&self, message: RemoteMessage) {
+        let Self {
+            client,
+            target,
+            status,
+            retry,
+            key_prefix,
+            remote_name,
+        } = self;
+        match key_prefix.outgoing(message) {
+            RemoteMessage::HealthCheck { reply } => {
+                info!("Checking health...");
+                let q = client
+                    .head_bucket()
+                    .bucket(target.s3.bucket.clone())
+                    .send()
+                    .await;
+                let q = map_health(status, q);
+                let _ = reply.send(match q {
+                    Some(Ok(_)) => true,
+                    e => {
+                        warn!("Health check failed: {:?}", e);
+                        false
+                    }
+                });
+            }
+            RemoteMessage::ListBuckets { reply } => {
+                info!("Listing buckets...");
+                let q = with_retries(retry, || client.list_buckets().send()).await;
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::ListObjects {
+                prefix,
+                delimiter,
+                max_keys,
+                start_after,
+                continuation_token,
+                fetch_owner,
+                encoding_type,
+                reply,
+            } => {
+                info!("Listing objects...");
+                let q = with_retries(retry, || {
+                    client
+                        .list_objects_v2()
+                        .bucket(target.s3.bucket.clone())
+                        .set_prefix(prefix.clone())
+                        .set_start_after(start_after.clone())
+                        .set_continuation_token(continuation_token.clone())
+                        .set_delimiter(delimiter.clone())
+                        .set_max_keys(max_keys)
+                        .set_fetch_owner(fetch_owner)
+                        .set_encoding_type(encoding_type.clone())
+                        .send()
+                })
+                .await
+                .map(|output| key_prefix.strip_listing(output));
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::GetObject { input, reply } => {
+                info!("Get object...");
+
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .get_object()
+                        .bucket(target.s3.bucket.clone())
+                        .set_checksum_mode(input.checksum_mode)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_if_match(input.if_match)
+                        .set_if_modified_since(input.if_modified_since)
+                        .set_if_none_match(input.if_none_match)
+                        .set_if_unmodified_since(input.if_unmodified_since)
+                        .set_key(input.key)
+                        .set_part_number(input.part_number)
+                        .set_range(input.range)
+                        .set_request_payer(input.request_payer)
+                        .set_response_cache_control(input.response_cache_control)
+                        .set_response_content_disposition(input.response_content_disposition)
+                        .set_response_content_encoding(input.response_content_encoding)
+                        .set_response_content_language(input.response_content_language)
+                        .set_response_content_type(input.response_content_type)
+                        .set_response_expires(input.response_expires)
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                        .set_sse_customer_key(input.sse_customer_key)
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                        .set_version_id(input.version_id)
+                        .send()
+                })
+                .await
+                .map(|mut output| {
+                    output.body = count_received(output.body, remote_name);
+                    output
+                });
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::PutObject { input, reply } => {
+                info!("Put object...");
+                let mut body = ReplayableBody::new(input.body);
+                let retry = body.retry_policy(*retry);
+                let q = with_retries(&retry, || {
+                    let acl = AclHeaders {
+                        acl: input.acl.clone(),
+                        grant_full_control: input.grant_full_control.clone(),
+                        grant_read: input.grant_read.clone(),
+                        grant_read_acp: input.grant_read_acp.clone(),
+                        grant_write_acp: input.grant_write_acp.clone(),
+                    }
+                    .normalize(target.normalize_ownership.as_ref());
+                    client
+                        .put_object()
+                        .bucket(target.s3.bucket.clone())
+                        .set_acl(acl.acl)
+                        .body(body.next())
+                        .set_cache_control(input.cache_control.clone())
+                        .set_content_disposition(input.content_disposition.clone())
+                        .set_content_encoding(input.content_encoding.clone())
+                        .set_content_language(input.content_language.clone())
+                        .set_content_length(input.content_length)
+                        .set_content_md5(input.content_md5.clone())
+                        .set_content_type(input.content_type.clone())
+                        .set_checksum_algorithm(input.checksum_algorithm.clone())
+                        .set_checksum_crc32(input.checksum_crc32.clone())
+                        .set_checksum_crc32_c(input.checksum_crc32_c.clone())
+                        .set_checksum_sha1(input.checksum_sha1.clone())
+                        .set_checksum_sha256(input.checksum_sha256.clone())
+                        .set_expires(input.expires)
+                        .set_grant_full_control(acl.grant_full_control)
+                        .set_grant_read(acl.grant_read)
+                        .set_grant_read_acp(acl.grant_read_acp)
+                        .set_grant_write_acp(acl.grant_write_acp)
+                        .set_key(input.key.clone())
+                        .set_metadata(input.metadata.clone())
+                        .set_server_side_encryption(input.server_side_encryption.clone())
+                        .set_storage_class(input.storage_class.clone())
+                        .set_website_redirect_location(input.website_redirect_location.clone())
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm.clone())
+                        .set_sse_customer_key(input.sse_customer_key.clone())
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5.clone())
+                        .set_ssekms_key_id(input.ssekms_key_id.clone())
+                        .set_ssekms_encryption_context(input.ssekms_encryption_context.clone())
+                        .set_bucket_key_enabled(input.bucket_key_enabled)
+                        .set_request_payer(input.request_payer.clone())
+                        .set_tagging(input.tagging.clone())
+                        .set_if_none_match(input.if_none_match.clone())
+                        .set_object_lock_mode(input.object_lock_mode.clone())
+                        .set_object_lock_retain_until_date(input.object_lock_retain_until_date)
+                        .set_object_lock_legal_hold_status(
+                            input.object_lock_legal_hold_status.clone(),
+                        )
+                        .set_expected_bucket_owner(input.expected_bucket_owner.clone())
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::CopyObject { input, reply } => {
+                info!("Copy object...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    let acl = AclHeaders {
+                        acl: input.acl,
+                        grant_full_control: input.grant_full_control,
+                        grant_read: input.grant_read,
+                        grant_read_acp: input.grant_read_acp,
+                        grant_write_acp: input.grant_write_acp,
+                    }
+                    .normalize(target.normalize_ownership.as_ref());
+                    client
+                        .copy_object()
+                        .bucket(target.s3.bucket.clone())
+                        .set_copy_source(
+                            input
+                                .copy_source
+                                .as_deref()
+                                .and_then(|s| copy_source_in(&target.s3.bucket, s)),
+                        )
+                        .set_acl(acl.acl)
+                        .set_cache_control(input.cache_control)
+                        .set_checksum_algorithm(input.checksum_algorithm)
+                        .set_content_disposition(input.content_disposition)
+                        .set_content_encoding(input.content_encoding)
+                        .set_content_language(input.content_language)
+                        .set_content_type(input.content_type)
+                        .set_copy_source_if_match(input.copy_source_if_match)
+                        .set_copy_source_if_modified_since(input.copy_source_if_modified_since)
+                        .set_copy_source_if_none_match(input.copy_source_if_none_match)
+                        .set_copy_source_if_unmodified_since(input.copy_source_if_unmodified_since)
+                        .set_expires(input.expires)
+                        .set_grant_full_control(acl.grant_full_control)
+                        .set_grant_read(acl.grant_read)
+                        .set_grant_read_acp(acl.grant_read_acp)
+                        .set_grant_write_acp(acl.grant_write_acp)
+                        .set_key(input.key)
+                        .set_metadata(input.metadata)
+                        .set_metadata_directive(input.metadata_directive)
+                        .set_tagging_directive(input.tagging_directive)
+                        .set_server_side_encryption(input.server_side_encryption)
+                        .set_storage_class(input.storage_class)
+                        .set_website_redirect_location(input.website_redirect_location)
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                        .set_sse_customer_key(input.sse_customer_key)
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                        .set_ssekms_key_id(input.ssekms_key_id)
+                        .set_ssekms_encryption_context(input.ssekms_encryption_context)
+                        .set_bucket_key_enabled(input.bucket_key_enabled)
+                        .set_copy_source_sse_customer_algorithm(
+                            input.copy_source_sse_customer_algorithm,
+                        )
+                        .set_copy_source_sse_customer_key(input.copy_source_sse_customer_key)
+                        .set_copy_source_sse_customer_key_md5(
+                            input.copy_source_sse_customer_key_md5,
+                        )
+                        .set_request_payer(input.request_payer)
+                        .set_tagging(input.tagging)
+                        .set_object_lock_mode(input.object_lock_mode)
+                        .set_object_lock_retain_until_date(input.object_lock_retain_until_date)
+                        .set_object_lock_legal_hold_status(input.object_lock_legal_hold_status)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::DeleteObject { input, reply } => {
+                info!("Delete object...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .delete_object()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_mfa(input.mfa)
+                        .set_version_id(input.version_id)
+                        .set_request_payer(input.request_payer)
+                        .set_bypass_governance_retention(input.bypass_governance_retention)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::DeleteObjects { input, reply } => {
+                info!("Delete objects...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .delete_objects()
+                        .bucket(target.s3.bucket.clone())
+                        .set_delete(input.delete)
+                        .set_mfa(input.mfa)
+                        .set_request_payer(input.request_payer)
+                        .set_bypass_governance_retention(input.bypass_governance_retention)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_checksum_algorithm(input.checksum_algorithm)
+                        .send()
+                })
+                .await
+                .map(|output| key_prefix.strip_deletions(output));
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::HeadObject { input, reply } => {
+                info!("Head object...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .head_object()
+                        .bucket(target.s3.bucket.clone())
+                        .set_if_match(input.if_match)
+                        .set_if_modified_since(input.if_modified_since)
+                        .set_if_unmodified_since(input.if_unmodified_since)
+                        .set_key(input.key)
+                        .set_range(input.range)
+                        .set_response_cache_control(input.response_cache_control)
+                        .set_response_content_disposition(input.response_content_disposition)
+                        .set_response_content_encoding(input.response_content_encoding)
+                        .set_response_content_language(input.response_content_language)
+                        .set_response_content_type(input.response_content_type)
+                        .set_response_expires(input.response_expires)
+                        .set_version_id(input.version_id)
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                        .set_sse_customer_key(input.sse_customer_key)
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                        .set_request_payer(input.request_payer)
+                        .set_part_number(input.part_number)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_checksum_mode(input.checksum_mode)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::GetObjectTagging { input, reply } => {
+                info!("Get object tagging...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .get_object_tagging()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_version_id(input.version_id)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_request_payer(input.request_payer)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::PutObjectTagging { input, reply } => {
+                info!("Put object tagging...");
+                // Content-MD5 is left to the SDK, since it covers the tag set as the
+                // SDK serializes it rather than as the client did
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .put_object_tagging()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_version_id(input.version_id)
+                        .set_checksum_algorithm(input.checksum_algorithm)
+                        .set_tagging(input.tagging)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_request_payer(input.request_payer)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::DeleteObjectTagging { input, reply } => {
+                info!("Delete object tagging...");
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .delete_object_tagging()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_version_id(input.version_id)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::CreateMultiPartUpload { input, reply } => {
+                info!("Create multipart upload...");
+                let acl = AclHeaders {
+                    acl: input.acl,
+                    grant_full_control: input.grant_full_control,
+                    grant_read: input.grant_read,
+                    grant_read_acp: input.grant_read_acp,
+                    grant_write_acp: input.grant_write_acp,
+                }
+                .normalize(target.normalize_ownership.as_ref());
+
+                let q = client
+                    .create_multipart_upload()
+                    .bucket(target.s3.bucket.clone())
+                    .set_acl(acl.acl)
+                    .set_cache_control(input.cache_control)
+                    .set_content_disposition(input.content_disposition)
+                    .set_content_encoding(input.content_encoding)
+                    .set_content_language(input.content_language)
+                    .set_content_type(input.content_type)
+                    .set_expires(input.expires)
+                    .set_grant_full_control(acl.grant_full_control)
+                    .set_grant_read(acl.grant_read)
+                    .set_grant_read_acp(acl.grant_read_acp)
+                    .set_grant_write_acp(acl.grant_write_acp)
+                    .set_key(input.key)
+                    .set_metadata(input.metadata)
+                    .set_server_side_encryption(input.server_side_encryption)
+                    .set_storage_class(input.storage_class)
+                    .set_website_redirect_location(input.website_redirect_location)
+                    .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                    .set_sse_customer_key(input.sse_customer_key)
+                    .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                    .set_ssekms_key_id(input.ssekms_key_id)
+                    .set_ssekms_encryption_context(input.ssekms_encryption_context)
+                    .set_bucket_key_enabled(input.bucket_key_enabled)
+                    .set_request_payer(input.request_payer)
+                    .set_tagging(input.tagging)
+                    .set_object_lock_mode(input.object_lock_mode)
+                    .set_object_lock_retain_until_date(input.object_lock_retain_until_date)
+                    .set_object_lock_legal_hold_status(input.object_lock_legal_hold_status)
+                    .set_expected_bucket_owner(input.expected_bucket_owner)
+                    .set_checksum_algorithm(input.checksum_algorithm)
+                    .send()
+                    .await
+                    .map(|mut output| {
+                        output.key = key_prefix.strip(output.key);
+                        output
+                    });
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::UploadPart { input, reply } => {
+                let span = info_span!("upload_part_message", part_number = &input.part_number);
+                let _guard = span.enter();
+                info!("Upload part...");
+
+                let mut body = ReplayableBody::new(input.body);
+                let retry = body.retry_policy(*retry);
+                let q = with_retries(&retry, || {
+                    client
+                        .upload_part()
+                        .bucket(target.s3.bucket.clone())
+                        .body(body.next())
+                        .set_content_length(input.content_length)
+                        .set_content_md5(input.content_md5.clone())
+                        .set_checksum_algorithm(input.checksum_algorithm.clone())
+                        .set_checksum_crc32(input.checksum_crc32.clone())
+                        .set_checksum_crc32_c(input.checksum_crc32_c.clone())
+                        .set_checksum_sha1(input.checksum_sha1.clone())
+                        .set_checksum_sha256(input.checksum_sha256.clone())
+                        .set_key(input.key.clone())
+                        .set_part_number(input.part_number)
+                        .set_upload_id(input.upload_id.clone())
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm.clone())
+                        .set_sse_customer_key(input.sse_customer_key.clone())
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5.clone())
+                        .set_request_payer(input.request_payer.clone())
+                        .set_expected_bucket_owner(input.expected_bucket_owner.clone())
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::UploadPartCopy { input, reply } => {
+                let span = info_span!("upload_part_copy_message", part_number = &input.part_number);
+                let _guard = span.enter();
+                info!("Upload part copy...");
+
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .upload_part_copy()
+                        .bucket(target.s3.bucket.clone())
+                        .set_copy_source(
+                            input
+                                .copy_source
+                                .as_deref()
+                                .and_then(|s| copy_source_in(&target.s3.bucket, s)),
+                        )
+                        .set_copy_source_if_match(input.copy_source_if_match)
+                        .set_copy_source_if_modified_since(input.copy_source_if_modified_since)
+                        .set_copy_source_if_none_match(input.copy_source_if_none_match)
+                        .set_copy_source_if_unmodified_since(input.copy_source_if_unmodified_since)
+                        .set_copy_source_range(input.copy_source_range)
+                        .set_key(input.key)
+                        .set_part_number(input.part_number)
+                        .set_upload_id(input.upload_id)
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                        .set_sse_customer_key(input.sse_customer_key)
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                        .set_copy_source_sse_customer_algorithm(
+                            input.copy_source_sse_customer_algorithm,
+                        )
+                        .set_copy_source_sse_customer_key(input.copy_source_sse_customer_key)
+                        .set_copy_source_sse_customer_key_md5(
+                            input.copy_source_sse_customer_key_md5,
+                        )
+                        .set_request_payer(input.request_payer)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::CompleteMultiPartUpload { input, reply } => {
+                info!("Complete multipart upload...");
+
+                let q = client
+                    .complete_multipart_upload()
+                    .bucket(target.s3.bucket.clone())
+                    .set_key(input.key)
+                    .set_multipart_upload(input.multipart_upload)
+                    .set_upload_id(input.upload_id)
+                    .set_checksum_crc32(input.checksum_crc32)
+                    .set_checksum_crc32_c(input.checksum_crc32_c)
+                    .set_checksum_sha1(input.checksum_sha1)
+                    .set_checksum_sha256(input.checksum_sha256)
+                    .set_request_payer(input.request_payer)
+                    .set_expected_bucket_owner(input.expected_bucket_owner)
+                    .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                    .set_sse_customer_key(input.sse_customer_key)
+                    .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                    .send()
+                    .await
+                    .map(|mut output| {
+                        output.key = key_prefix.strip(output.key);
+                        output
+                    });
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::ListParts { input, reply } => {
+                info!("List parts...");
+
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .list_parts()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_upload_id(input.upload_id)
+                        .set_max_parts(input.max_parts)
+                        .set_part_number_marker(input.part_number_marker)
+                        .set_request_payer(input.request_payer)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .set_sse_customer_algorithm(input.sse_customer_algorithm)
+                        .set_sse_customer_key(input.sse_customer_key)
+                        .set_sse_customer_key_md5(input.sse_customer_key_md5)
+                        .send()
+                })
+                .await
+                .map(|mut output| {
+                    output.key = key_prefix.strip(output.key);
+                    output
+                });
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::AbortMultipartUpload { input, reply } => {
+                info!("Abort multipart upload...");
+
+                let q = with_retries(retry, || {
+                    let input = input.clone();
+                    client
+                        .abort_multipart_upload()
+                        .bucket(target.s3.bucket.clone())
+                        .set_key(input.key)
+                        .set_upload_id(input.upload_id)
+                        .set_request_payer(input.request_payer)
+                        .set_expected_bucket_owner(input.expected_bucket_owner)
+                        .send()
+                })
+                .await;
+
+                let _ = reply.send(map_health(status, q));
+            }
+            RemoteMessage::Shutdown => {}
+        }
+    }
+}
+
+/// Handles the messages sent to a remote, each in its own task so that a slow or retried request
+/// holds up no other, until it is asked to shut down. Shutting down waits for the requests
+/// already received.
+///
+/// A request still running after `timeout` is cancelled, which drops its reply: the caller hears
+/// that the remote is down only once the call to it is torn down.
+async fn serve<F>(
+    mut rx: mpsc::Receiver<RemoteMessage>,
+    timeout: Option<Duration>,
+    handle: impl Fn(RemoteMessage) -> F,
+) where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut requests = JoinSet::new();
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                None | Some(RemoteMessage::Shutdown) => break,
+                Some(message) => {
+                    let operation = message.operation();
+                    let request = handle(message);
+                    requests.spawn(async move {
+                        let Some(timeout) = timeout else {
+                            return request.await;
+                        };
+                        if tokio::time::timeout(timeout, request).await.is_err() {
+                            warn!("{operation} timed out after {timeout:?}");
+                        }
+                    });
+                }
+            },
+            Some(_) = requests.join_next() => {}
+        }
+    }
+    while requests.join_next().await.is_some() {}
+    info!("Remote shutting down.");
+}
+
+/// Points a `bucket/key` copy source, as sent by the client, at this remote's own bucket.
+fn copy_source_in(bucket: &str, copy_source: &str) -> Option<String> {
+    let (_, key) = copy_source.trim_start_matches('/').split_once('/')?;
+    Some(format!("{bucket}/{key}"))
+}
+
+/// How a remote retries the requests it answered with a throttling or server error. Creating and
+/// completing multipart uploads are never retried, since sending them again is not harmless.
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    retries: usize,
+    base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the `attempt`th retry: the base delay doubled for each earlier retry, of
+    /// which a random half is waited, so that remotes answering together do not retry together.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let delay = self.base_delay * 2u32.saturating_pow(attempt as u32 - 1);
+        let jitter = random() as f64 / u64::MAX as f64;
+        delay.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// Whether a request is worth sending again: it was throttled, failed on the remote, timed out,
+/// or never got through to the remote, e.g. because the connection was reset.
+fn retryable<E>(error: &SdkError<E, orchestrator::HttpResponse>) -> bool {
+    match error {
+        SdkError::ServiceError(e) => {
+            let status = e.raw().status();
+            status.as_u16() == 429 || status.is_server_error()
+        }
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) => true,
+        _ => false,
+    }
+}
+
+/// Sends the request built by `send` until it gets an answer other than a throttling or server
+/// error and reaches the remote in time, or `policy` runs out of retries.
+async fn with_retries<T, E, F>(
+    policy: &RetryPolicy,
+    mut send: impl FnMut() -> F,
+) -> Result<T, SdkError<E, orchestrator::HttpResponse>>
+where
+    F: Future<Output = Result<T, SdkError<E, orchestrator::HttpResponse>>>,
+{
+    let mut attempt = 0;
+    loop {
+        match send().await {
+            Err(e) if attempt < policy.retries && retryable(&e) => {
+                attempt += 1;
+                let delay = policy.backoff(attempt);
+                match e.raw_response() {
+                    Some(response) => warn!(
+                        "remote answered {}. retrying in {:?} ({}/{})",
+                        response.status().as_u16(),
+                        delay,
+                        attempt,
+                        policy.retries
+                    ),
+                    None => warn!(
+                        "remote could not be reached: {:?}. retrying in {:?} ({}/{})",
+                        e, delay, attempt, policy.retries
+                    ),
+                }
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Hands out the body of a request for each attempt. Only a body held in memory can be sent more
+/// than once, so requests with any other body are not retried.
+struct ReplayableBody(Option<SdkBody>);
+
+impl ReplayableBody {
+    fn new(body: ByteStream) -> Self {
+        Self(Some(body.into_inner()))
+    }
+
+    fn retry_policy(&self, policy: RetryPolicy) -> RetryPolicy {
+        if self.0.as_ref().is_some_and(|b| b.try_clone().is_some()) {
+            policy
+        } else {
+            RetryPolicy {
+                retries: 0,
+                ..policy
+            }
+        }
+    }
+
+    fn next(&mut self) -> ByteStream {
+        let body = self.0.as_ref().and_then(SdkBody::try_clone);
+        ByteStream::new(
+            body.or_else(|| self.0.take())
+                .unwrap_or_else(SdkBody::taken),
+        )
     }
 }
 
 #[instrument(name = "remote/health", skip_all)]
 fn map_health<T, E1: Debug, E2: Debug>(
-    self_health: &mut Option<bool>,
+    status: &RemoteStatus,
     query: Result<T, SdkError<E1, E2>>,
 ) -> Option<Result<T, ServiceError<E1, E2>>> {
     // ServiceErrorはリモートが返してきたエラーなので, DOWNとは判断しない
@@ -447,13 +1266,132 @@ fn map_health<T, E1: Debug, E2: Debug>(
             (None, false)
         }
     };
-    if *self_health != Some(health) {
-        if health {
-            info!("remote is UP")
-        } else {
-            warn!("remote is DOWN")
-        }
-        *self_health = Some(health);
+    match status.record(health) {
+        Some(true) => info!("remote is UP"),
+        Some(false) => warn!("remote is DOWN"),
+        None => {}
     }
     query
 }
+
+#[cfg(test)]
+mod tests {
+    use aws_smithy_runtime_api::client::result::ConnectorError;
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::error::ErrorMetadata;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    type Answer = Result<GetObjectOutput, SdkError<GetObjectError, orchestrator::HttpResponse>>;
+
+    fn error(status: u16) -> Answer {
+        Err(SdkError::service_error(
+            GetObjectError::generic(ErrorMetadata::builder().build()),
+            orchestrator::HttpResponse::new(
+                StatusCode::try_from(status).unwrap(),
+                SdkBody::empty(),
+            ),
+        ))
+    }
+
+    /// Sends a request answered with `statuses` in turn, then with success, and returns how many
+    /// times it was sent.
+    async fn attempts(retries: usize, statuses: &[u16]) -> usize {
+        let policy = RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(1),
+        };
+        let mut sent = 0;
+        let _ = with_retries(&policy, || {
+            sent += 1;
+            let answer = match statuses.get(sent - 1) {
+                Some(status) => error(*status),
+                None => Ok(GetObjectOutput::builder().build()),
+            };
+            async move { answer }
+        })
+        .await;
+        sent
+    }
+
+    #[tokio::test]
+    async fn throttling_and_server_errors_are_retried() {
+        assert_eq!(attempts(2, &[503, 429]).await, 3);
+        assert_eq!(attempts(2, &[500, 500, 500, 500]).await, 3);
+        assert_eq!(attempts(0, &[503]).await, 1);
+    }
+
+    #[test]
+    fn requests_that_did_not_get_through_are_retried() {
+        let timeout = SdkError::<GetObjectError, _>::timeout_error("read timed out");
+        let reset = SdkError::<GetObjectError, _>::dispatch_failure(ConnectorError::io(
+            "connection reset".into(),
+        ));
+
+        assert!(retryable(&timeout));
+        assert!(retryable(&reset));
+        assert!(!retryable(
+            &SdkError::<GetObjectError, _>::construction_failure("no key")
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        assert_eq!(attempts(2, &[404]).await, 1);
+        assert_eq!(attempts(2, &[503, 403]).await, 2);
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let policy = RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_millis(100),
+        };
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = policy.backoff(attempt);
+            assert!(delay >= Duration::from_millis(full / 2), "{delay:?}");
+            assert!(delay <= Duration::from_millis(full), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn only_bodies_in_memory_are_sent_again() {
+        let policy = RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(100),
+        };
+        let mut body = ReplayableBody::new(ByteStream::from_static(b"part"));
+        assert_eq!(body.retry_policy(policy).retries, 2);
+        assert_eq!(body.next().into_inner().bytes(), Some(&b"part"[..]));
+        assert_eq!(body.next().into_inner().bytes(), Some(&b"part"[..]));
+
+        let streamed = ReplayableBody::new(ByteStream::new(SdkBody::taken()));
+        assert_eq!(streamed.retry_policy(policy).retries, 0);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_hold_up_no_other() {
+        let remote = S3Remote::serving("a", None, |message| async move {
+            if let RemoteMessage::HeadObject { input, reply } = message {
+                if input.key() == Some("slow") {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                let _ = reply.send(Some(Ok(HeadObjectOutput::builder().build())));
+            }
+        });
+        let head = |key: &str| {
+            let input = HeadObjectInput::builder().key(key).build().unwrap();
+            remote.request(|reply| RemoteMessage::HeadObject { input, reply })
+        };
+
+        let slow = head("slow");
+        tokio::pin!(slow);
+        assert!(tokio::time::timeout(Duration::from_millis(20), &mut slow)
+            .await
+            .is_err());
+
+        let fast = tokio::time::timeout(Duration::from_secs(1), head("fast")).await;
+        assert!(matches!(fast, Ok(Some(Ok(_)))));
+    }
+}
@@ -0,0 +1,602 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectInput};
+use aws_sdk_s3::operation::get_object_tagging::GetObjectTaggingInput;
+use aws_sdk_s3::operation::head_object::HeadObjectInput;
+use aws_sdk_s3::operation::put_object::PutObjectInput;
+use aws_sdk_s3::types::Tag;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use tokio::sync::watch;
+use tracing::{error, info, instrument, warn};
+
+use crate::db::{MongoDB, ObjectRepair};
+use crate::metrics::PENDING_REPAIRS;
+
+use super::reload::RemoteSet;
+use super::remote::{RemoteMessage, S3Remote};
+use super::{reply_rank, S3Reproxy};
+
+/// Most repairs attempted per pass; the rest wait for the next one.
+const REPAIR_BATCH: usize = 100;
+
+/// How long a repair claimed by a pass is left alone by the passes of other replicas.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+/// Longest wait between two attempts at the same repair.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How a claimed repair was left.
+#[derive(Debug, PartialEq)]
+enum Repaired {
+    Done,
+    /// These targets still miss the object.
+    Retry(Vec<String>),
+    Cancelled,
+}
+
+/// How copying the object to one target went.
+#[derive(Debug, PartialEq)]
+enum Copied {
+    Done,
+    /// The target was written again after the repaired write, and keeps what it has.
+    Newer,
+    /// The source no longer has the object, which was deleted since.
+    Gone,
+    /// The source holds another object than the one written, which was overwritten since.
+    Replaced,
+    /// The source cannot give the object out, e.g. as it is encrypted with a customer key.
+    Unreadable,
+    Failed,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaimedRepair {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    key: String,
+    source: String,
+    e_tag: Option<String>,
+    targets: Vec<String>,
+    created_at: mongodb::bson::DateTime,
+    attempts: i32,
+}
+
+/// A write that succeeded on some remotes only, to be copied from `source` to `targets`.
+#[derive(Debug, PartialEq)]
+pub(super) struct PendingRepair {
+    source: String,
+    /// What the write returned on `source`, which the copy has to match.
+    e_tag: Option<String>,
+    targets: Vec<String>,
+}
+
+/// The remote to copy from and the remotes to copy to, when a write succeeded on some remotes
+/// only. Remotes that did not answer at all count as failed.
+pub(super) fn pending_repair<T, E>(
+    remotes: &[S3Remote],
+    results: &[(String, Result<T, E>)],
+    e_tag: impl Fn(&T) -> Option<&str>,
+) -> Option<PendingRepair> {
+    let succeeded = |name: &str| {
+        results
+            .iter()
+            .any(|(remote, result)| remote == name && result.is_ok())
+    };
+    let (source, output) = results
+        .iter()
+        .filter_map(|(remote, result)| Some((remote, result.as_ref().ok()?)))
+        .min_by_key(|(remote, _)| reply_rank(remotes, remote))?;
+    let targets = remotes
+        .iter()
+        .filter(|r| !succeeded(&r.name))
+        .map(|r| r.name.clone())
+        .collect::<Vec<_>>();
+    (!targets.is_empty()).then(|| PendingRepair {
+        source: source.clone(),
+        e_tag: e_tag(output).map(str::to_owned),
+        targets,
+    })
+}
+
+/// Waits `interval` after the first failed attempt, doubling with each further one.
+fn retry_delay(interval: Duration, attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    interval.saturating_mul(1 << doublings).min(MAX_RETRY_DELAY)
+}
+
+fn after(now: mongodb::bson::DateTime, delay: Duration) -> mongodb::bson::DateTime {
+    mongodb::bson::DateTime::from_millis(now.timestamp_millis() + delay.as_millis() as i64)
+}
+
+impl S3Reproxy {
+    /// Queues copying `key` to the remotes a write missed. The write already succeeded for the
+    /// client, so a repair that cannot be queued is only logged.
+    pub(super) async fn queue_repair(&self, key: Option<&str>, repair: Option<PendingRepair>) {
+        if !self.repair_writes {
+            return;
+        }
+        let (Some(key), Some(repair)) = (key, repair) else {
+            return;
+        };
+        let queued = self
+            .db
+            .object_repairs
+            .insert_one(ObjectRepair {
+                key: key.to_owned(),
+                source: repair.source,
+                e_tag: repair.e_tag,
+                targets: repair.targets,
+                created_at: mongodb::bson::DateTime::now(),
+                attempts: 0,
+                retry_after: None,
+            })
+            .await;
+        match queued {
+            Ok(_) => info!("queued the repair of {:?}", key),
+            Err(e) => error!("failed to queue the repair of {:?}: {:?}", key, e),
+        }
+    }
+}
+
+/// Copies objects queued by [`S3Reproxy::queue_repair`] to the remotes missing them each
+/// `interval`.
+#[instrument(name = "write_repair", skip_all)]
+pub async fn drain(
+    remotes: Arc<RemoteSet>,
+    db: Arc<MongoDB>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+        if let Err(e) = drain_once(&remotes.load(), &db, interval, &mut shutdown).await {
+            error!("mongodb error: {:?}", e);
+        }
+    }
+    info!("stopped");
+}
+
+async fn drain_once(
+    remotes: &[S3Remote],
+    db: &MongoDB,
+    interval: Duration,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<(), mongodb::error::Error> {
+    for _ in 0..REPAIR_BATCH {
+        let now = mongodb::bson::DateTime::now();
+        let Some(repair) = db
+            .object_repairs
+            .clone_with_type::<ClaimedRepair>()
+            .find_one_and_update(
+                doc! {
+                    "$or": [
+                        { "retry_after": null },
+                        { "retry_after": { "$lte": now } },
+                    ],
+                },
+                doc! {
+                    "$set": { "retry_after": after(now, CLAIM_LEASE) },
+                    "$inc": { "attempts": 1 },
+                },
+            )
+            .await?
+        else {
+            break;
+        };
+
+        match repair_claimed(remotes, &repair, shutdown).await {
+            Repaired::Done => {
+                db.object_repairs
+                    .delete_one(doc! { "_id": repair.id })
+                    .await?;
+                info!("repaired {:?} on {:?}", repair.key, repair.targets);
+            }
+            Repaired::Retry(targets) => {
+                let delay = retry_delay(interval, repair.attempts + 1);
+                warn!(
+                    "{:?} could not be repaired on {:?}. retrying in {:?}",
+                    repair.key, targets, delay
+                );
+                db.object_repairs
+                    .update_one(
+                        doc! { "_id": repair.id },
+                        doc! {
+                            "$set": {
+                                "targets": targets,
+                                "retry_after": after(mongodb::bson::DateTime::now(), delay),
+                            },
+                        },
+                    )
+                    .await?;
+            }
+            Repaired::Cancelled => {
+                db.object_repairs
+                    .update_one(
+                        doc! { "_id": repair.id },
+                        doc! { "$unset": { "retry_after": "" }, "$inc": { "attempts": -1 } },
+                    )
+                    .await?;
+                info!("released the repair of {:?} on shutdown", repair.key);
+                break;
+            }
+        }
+    }
+
+    let pending = db.object_repairs.count_documents(doc! {}).await?;
+    PENDING_REPAIRS.store(pending, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Copies the object of a claimed repair to its targets unless shutdown comes first.
+async fn repair_claimed(
+    remotes: &[S3Remote],
+    repair: &ClaimedRepair,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Repaired {
+    let Some(source) = remotes.iter().find(|r| r.name == repair.source) else {
+        warn!(
+            "source remote({:?}) is no longer configured. dropping the repair",
+            repair.source
+        );
+        return Repaired::Done;
+    };
+    tokio::select! {
+        remaining = copy_to_targets(remotes, source, repair) => {
+            if remaining.is_empty() {
+                Repaired::Done
+            } else {
+                Repaired::Retry(remaining)
+            }
+        }
+        _ = shutdown.wait_for(|stop| *stop) => Repaired::Cancelled,
+    }
+}
+
+/// Returns the targets that still miss the object. None do when the source no longer holds the
+/// object that was written, or cannot give it out, as no later attempt would do better.
+async fn copy_to_targets(
+    remotes: &[S3Remote],
+    source: &S3Remote,
+    repair: &ClaimedRepair,
+) -> Vec<String> {
+    let key = &repair.key;
+    let mut remaining = vec![];
+    for name in &repair.targets {
+        let Some(target) = remotes.iter().find(|r| r.name == *name) else {
+            warn!("remote({:?}) is no longer configured. skipping", name);
+            continue;
+        };
+        match copy_object(source, target, repair).await {
+            Copied::Done => {
+                if let Some(filter) = &target.key_filter {
+                    filter.insert(key);
+                }
+            }
+            Copied::Newer => info!("{:?} was written to remote({:?}) since", key, name),
+            Copied::Gone => {
+                info!("{:?} is gone from remote({:?})", key, source.name);
+                return vec![];
+            }
+            Copied::Replaced => {
+                info!("{:?} was overwritten on remote({:?})", key, source.name);
+                return vec![];
+            }
+            Copied::Unreadable => {
+                warn!(
+                    "{:?} cannot be read from remote({:?}). giving up",
+                    key, source.name
+                );
+                return vec![];
+            }
+            Copied::Failed => remaining.push(name.clone()),
+        }
+    }
+    remaining
+}
+
+/// Whether `target` holds an object written after the repaired write, which the copy must not
+/// overwrite. `None` when the target cannot tell.
+async fn written_since(target: &S3Remote, repair: &ClaimedRepair) -> Option<bool> {
+    let input = HeadObjectInput::builder().key(&repair.key).build().ok()?;
+    match target
+        .request(|reply| RemoteMessage::HeadObject { input, reply })
+        .await?
+    {
+        Ok(head) => {
+            let written = repair.created_at.timestamp_millis();
+            let modified = head.last_modified.and_then(|t| t.to_millis().ok());
+            Some(modified.is_some_and(|modified| modified > written))
+        }
+        Err(e) if e.raw().status().as_u16() == 404 => Some(false),
+        Err(_) => None,
+    }
+}
+
+/// The tags of the object on `source` as the `x-amz-tagging` header of a PUT, when it has any.
+async fn tags_of(source: &S3Remote, key: &str, count: Option<i32>) -> Option<Option<String>> {
+    if count.unwrap_or_default() == 0 {
+        return Some(None);
+    }
+    let input = GetObjectTaggingInput::builder().key(key).build().ok()?;
+    let output = source
+        .request(|reply| RemoteMessage::GetObjectTagging { input, reply })
+        .await?
+        .ok()?;
+    Some(Some(tagging_query(&output.tag_set)))
+}
+
+/// Encodes `tags` as URL query parameters, the form S3 takes them in with a PUT.
+fn tagging_query(tags: &[Tag]) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    char::from(b).to_string()
+                }
+                _ => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+    tags.iter()
+        .map(|tag| format!("{}={}", encode(tag.key()), encode(tag.value())))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+async fn copy_object(source: &S3Remote, target: &S3Remote, repair: &ClaimedRepair) -> Copied {
+    let key = repair.key.as_str();
+    match written_since(target, repair).await {
+        Some(true) => return Copied::Newer,
+        Some(false) => {}
+        None => return Copied::Failed,
+    }
+    let Ok(input) = GetObjectInput::builder()
+        .key(key)
+        .set_if_match(repair.e_tag.clone())
+        .build()
+    else {
+        return Copied::Failed;
+    };
+    let object = match source
+        .request(|reply| RemoteMessage::GetObject { input, reply })
+        .await
+    {
+        Some(Ok(object)) => object,
+        Some(Err(e)) if matches!(e.err(), GetObjectError::NoSuchKey(_)) => return Copied::Gone,
+        Some(Err(e)) if e.raw().status().as_u16() == 412 => return Copied::Replaced,
+        // S3 refuses to read an object under a customer key without that key
+        Some(Err(e)) if e.err().code() == Some("InvalidRequest") => return Copied::Unreadable,
+        _ => return Copied::Failed,
+    };
+    let Some(tagging) = tags_of(source, key, object.tag_count).await else {
+        return Copied::Failed;
+    };
+    #[allow(deprecated)]
+    let expires = object.expires;
+    let Ok(input) = PutObjectInput::builder()
+        .key(key)
+        .body(object.body)
+        .set_content_length(object.content_length)
+        .set_content_type(object.content_type)
+        .set_cache_control(object.cache_control)
+        .set_content_disposition(object.content_disposition)
+        .set_content_encoding(object.content_encoding)
+        .set_content_language(object.content_language)
+        .set_expires(expires)
+        .set_metadata(object.metadata)
+        .set_server_side_encryption(object.server_side_encryption)
+        .set_ssekms_key_id(object.ssekms_key_id)
+        .set_bucket_key_enabled(object.bucket_key_enabled)
+        .set_tagging(tagging)
+        .build()
+    else {
+        return Copied::Failed;
+    };
+    match target
+        .request(|reply| RemoteMessage::PutObject { input, reply })
+        .await
+    {
+        Some(Ok(_)) => Copied::Done,
+        _ => Copied::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::get_object::GetObjectOutput;
+    use aws_sdk_s3::operation::get_object_tagging::GetObjectTaggingOutput;
+    use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+    use aws_sdk_s3::operation::put_object::PutObjectOutput;
+    use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+    use aws_smithy_runtime_api::client::result::ServiceError;
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use aws_smithy_types::error::ErrorMetadata;
+    use pretty_assertions::assert_eq;
+    use std::sync::Mutex;
+
+    fn remote(name: &str, priority: u32) -> S3Remote {
+        S3Remote {
+            priority,
+            ..S3Remote::stub(name)
+        }
+    }
+
+    #[test]
+    fn repair_copies_from_the_top_remote_to_the_rest() {
+        let remotes = [remote("a", 1), remote("b", 10), remote("c", 5)];
+        let results: Vec<(String, Result<&str, ()>)> = vec![
+            ("a".to_owned(), Ok("\"on-a\"")),
+            ("b".to_owned(), Err(())),
+            ("c".to_owned(), Ok("\"on-c\"")),
+        ];
+
+        assert_eq!(
+            pending_repair(&remotes, &results, |e_tag| Some(*e_tag)),
+            Some(PendingRepair {
+                source: "c".to_owned(),
+                e_tag: Some("\"on-c\"".to_owned()),
+                targets: vec!["b".to_owned()],
+            })
+        );
+    }
+
+    #[test]
+    fn unanswered_remotes_are_repaired_too() {
+        let remotes = [remote("a", 1), remote("b", 1)];
+        let results: Vec<(String, Result<(), ()>)> = vec![("a".to_owned(), Ok(()))];
+
+        assert_eq!(
+            pending_repair(&remotes, &results, |_| None),
+            Some(PendingRepair {
+                source: "a".to_owned(),
+                e_tag: None,
+                targets: vec!["b".to_owned()],
+            })
+        );
+        assert_eq!(pending_repair(&remotes[..1], &results, |_| None), None);
+    }
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        let interval = Duration::from_secs(60);
+
+        assert_eq!(retry_delay(interval, 1), Duration::from_secs(60));
+        assert_eq!(retry_delay(interval, 3), Duration::from_secs(240));
+        assert_eq!(retry_delay(interval, 30), MAX_RETRY_DELAY);
+    }
+
+    fn claimed(source: &str, targets: &[&str]) -> ClaimedRepair {
+        ClaimedRepair {
+            id: ObjectId::new(),
+            key: "video.mp4".to_owned(),
+            source: source.to_owned(),
+            e_tag: Some("\"written\"".to_owned()),
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            created_at: mongodb::bson::DateTime::from_millis(1_700_000_000_000),
+            attempts: 1,
+        }
+    }
+
+    fn failure<E>(status: u16, error: E) -> ServiceError<E, HttpResponse> {
+        ServiceError::builder()
+            .source(error)
+            .raw(HttpResponse::new(
+                StatusCode::try_from(status).unwrap(),
+                SdkBody::empty(),
+            ))
+            .build()
+    }
+
+    /// A source holding the object under `e_tag` with `tags`, which answers reads of any other
+    /// ETag like S3 does.
+    fn source(e_tag: &'static str, tags: Vec<Tag>) -> S3Remote {
+        S3Remote::answering("source", move |message| match message {
+            RemoteMessage::GetObject { input, reply } => {
+                let result = match input.if_match.as_deref() {
+                    Some(expected) if expected != e_tag => Err(failure(
+                        412,
+                        GetObjectError::generic(ErrorMetadata::builder().build()),
+                    )),
+                    _ => Ok(GetObjectOutput::builder()
+                        .e_tag(e_tag)
+                        .tag_count(tags.len() as i32)
+                        .build()),
+                };
+                let _ = reply.send(Some(result));
+            }
+            RemoteMessage::GetObjectTagging { reply, .. } => {
+                let output = GetObjectTaggingOutput::builder()
+                    .set_tag_set(Some(tags.clone()))
+                    .build()
+                    .unwrap();
+                let _ = reply.send(Some(Ok(output)));
+            }
+            _ => {}
+        })
+    }
+
+    /// A target last written at `modified` (unix seconds), or missing the object, recording the
+    /// tagging of each PUT it gets.
+    fn target(modified: Option<i64>, put: Arc<Mutex<Vec<Option<String>>>>) -> S3Remote {
+        S3Remote::answering("target", move |message| match message {
+            RemoteMessage::HeadObject { reply, .. } => {
+                let result = match modified {
+                    Some(secs) => Ok(HeadObjectOutput::builder()
+                        .last_modified(aws_smithy_types::DateTime::from_secs(secs))
+                        .build()),
+                    None => Err(failure(
+                        404,
+                        HeadObjectError::generic(ErrorMetadata::builder().build()),
+                    )),
+                };
+                let _ = reply.send(Some(result));
+            }
+            RemoteMessage::PutObject { input, reply } => {
+                put.lock().unwrap().push(input.tagging);
+                let _ = reply.send(Some(Ok(PutObjectOutput::builder().build())));
+            }
+            _ => {}
+        })
+    }
+
+    #[tokio::test]
+    async fn repair_is_retried_while_the_source_is_down() {
+        let remotes = [S3Remote::stub("down"), S3Remote::stub("missing")];
+
+        let remaining =
+            copy_to_targets(&remotes, &remotes[0], &claimed("down", &["missing"])).await;
+
+        assert_eq!(remaining, vec!["missing"]);
+    }
+
+    #[tokio::test]
+    async fn object_is_copied_with_its_tags() {
+        let put = Arc::new(Mutex::new(vec![]));
+        let tag = Tag::builder().key("team").value("a&b c").build().unwrap();
+        let source = source("\"written\"", vec![tag]);
+        let target = target(None, Arc::clone(&put));
+
+        let copied = copy_object(&source, &target, &claimed("source", &["target"])).await;
+
+        assert_eq!(copied, Copied::Done);
+        assert_eq!(
+            *put.lock().unwrap(),
+            vec![Some("team=a%26b%20c".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn overwritten_object_is_not_copied() {
+        let put = Arc::new(Mutex::new(vec![]));
+        let source = source("\"rewritten\"", vec![]);
+        let target = target(None, Arc::clone(&put));
+
+        let copied = copy_object(&source, &target, &claimed("source", &["target"])).await;
+
+        assert_eq!(copied, Copied::Replaced);
+        assert!(put.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn newer_object_on_the_target_is_kept() {
+        let put = Arc::new(Mutex::new(vec![]));
+        let source = source("\"written\"", vec![]);
+        let repair = claimed("source", &["target"]);
+        let later = repair.created_at.timestamp_millis() / 1000 + 60;
+        let target = target(Some(later), Arc::clone(&put));
+
+        assert_eq!(copy_object(&source, &target, &repair).await, Copied::Newer);
+        assert!(put.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,40 @@
+use http::HeaderMap;
+
+use super::random;
+
+/// Header carrying the id of a request, in both directions.
+pub const REQUEST_ID_HEADER: &str = "x-amz-request-id";
+
+/// Header naming the remote that served a read. Omitted when no remote was asked.
+pub const REMOTE_HEADER: &str = "x-reproxy-remote";
+
+/// Longest client-given request id that is kept; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id the client sent with the request, or a new one shaped like S3's.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("{:016X}", random()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn client_ids_are_kept_and_missing_ones_generated() {
+        let mut headers = HeaderMap::new();
+        let generated = request_id(&headers);
+        assert_eq!(generated.len(), 16);
+        assert!(generated.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(request_id(&headers), generated);
+
+        headers.insert(REQUEST_ID_HEADER, "trace-42".parse().unwrap());
+        assert_eq!(request_id(&headers), "trace-42");
+    }
+}
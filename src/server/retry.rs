@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use aws_sdk_s3::operation::upload_part::{UploadPartError, UploadPartInput, UploadPartOutput};
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use aws_smithy_runtime_api::client::result::ServiceError;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use super::remote::{RemoteMessage, S3Remote};
+
+const QUICK_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Sends a read request to `remote`, asking again up to `retries` times with a short backoff
+/// when it fails to respond, so that a single blip does not fail the read over to another remote.
+/// A request that timed out has been cancelled by the remote by the time it is asked again, so
+/// the retry never waits behind it. Errors returned by the remote itself are not retried.
+pub(super) async fn read_with_quick_retry<O>(
+    remote: &S3Remote,
+    retries: usize,
+    message: impl Fn(oneshot::Sender<Option<O>>) -> RemoteMessage,
+) -> Option<O> {
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(QUICK_RETRY_BACKOFF * attempt as u32).await;
+            info!(
+                "retrying remote({:?}) ({}/{})",
+                remote.name, attempt, retries
+            );
+        }
+        let output = remote.request(&message).await;
+        if output.is_some() {
+            return output;
+        }
+    }
+    None
+}
+
+/// Sends a part to `remote`. If the remote fails to respond, the part is sent again up to
+/// `retries` times with an input from `resend`, so a transient failure does not drop the remote
+/// from the rest of the upload. A part that timed out has been cancelled by the remote by the
+/// time it is sent again, so two uploads of the same part never race.
+pub(super) async fn upload_part_with_retry(
+    remote: &S3Remote,
+    input: UploadPartInput,
+    retries: usize,
+    resend: impl Fn() -> UploadPartInput,
+) -> Option<Result<UploadPartOutput, ServiceError<UploadPartError, HttpResponse>>> {
+    let mut input = Some(input);
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(QUICK_RETRY_BACKOFF * attempt as u32).await;
+            warn!(
+                "retrying part on remote({:?}) ({}/{})",
+                remote.name, attempt, retries
+            );
+        }
+        let input = input.take().unwrap_or_else(&resend);
+        let output = remote
+            .request(|reply| RemoteMessage::UploadPart { input, reply })
+            .await;
+        if output.is_some() {
+            return output;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::operation::head_object::{HeadObjectInput, HeadObjectOutput};
+    use aws_sdk_s3::primitives::ByteStream;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A remote whose first `failures` requests go unanswered.
+    fn flaky_remote(failures: usize) -> S3Remote {
+        let mut seen = 0;
+        S3Remote::answering("flaky", move |message| {
+            seen += 1;
+            let answered = seen > failures;
+            match message {
+                RemoteMessage::HeadObject { reply, .. } => {
+                    let output = HeadObjectOutput::builder().content_length(7).build();
+                    let _ = reply.send(answered.then(|| Ok(output)));
+                }
+                RemoteMessage::UploadPart { reply, .. } => {
+                    let output = UploadPartOutput::builder().e_tag("part").build();
+                    let _ = reply.send(answered.then(|| Ok(output)));
+                }
+                _ => {}
+            }
+        })
+    }
+
+    async fn head(remote: &S3Remote, retries: usize) -> Option<HeadObjectOutput> {
+        let input = HeadObjectInput::builder().key("key").build().unwrap();
+        read_with_quick_retry(remote, retries, |reply| RemoteMessage::HeadObject {
+            input: input.clone(),
+            reply,
+        })
+        .await
+        .map(|r| r.unwrap())
+    }
+
+    #[tokio::test]
+    async fn blip_is_absorbed_by_quick_retry() {
+        let output = head(&flaky_remote(1), 1).await;
+
+        assert_eq!(output.and_then(|o| o.content_length), Some(7));
+    }
+
+    #[tokio::test]
+    async fn remote_is_skipped_without_retries() {
+        assert!(head(&flaky_remote(1), 0).await.is_none());
+    }
+
+    /// A remote answering every HEAD after `delay`, which gives up on it after `timeout`.
+    fn slow_remote(delay: Duration, timeout: Option<Duration>) -> S3Remote {
+        S3Remote::serving("slow", timeout, move |message| async move {
+            if let RemoteMessage::HeadObject { reply, .. } = message {
+                tokio::time::sleep(delay).await;
+                let output = HeadObjectOutput::builder().content_length(7).build();
+                let _ = reply.send(Some(Ok(output)));
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn hung_remote_times_out() {
+        let delay = Duration::from_millis(500);
+        let timeout = Duration::from_millis(50);
+
+        let started = tokio::time::Instant::now();
+        assert!(head(&slow_remote(delay, Some(timeout)), 0).await.is_none());
+        assert!(started.elapsed() < delay);
+
+        let output = head(&slow_remote(delay, None), 0).await;
+        assert_eq!(output.and_then(|o| o.content_length), Some(7));
+    }
+
+    #[tokio::test]
+    async fn retry_does_not_wait_behind_a_hung_attempt() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let timeout = Some(Duration::from_millis(50));
+        let remote = S3Remote::serving("hung", timeout, move |message| {
+            let hung = seen.fetch_add(1, Ordering::SeqCst) == 0;
+            async move {
+                if let RemoteMessage::HeadObject { reply, .. } = message {
+                    if hung {
+                        std::future::pending::<()>().await;
+                    }
+                    let output = HeadObjectOutput::builder().content_length(7).build();
+                    let _ = reply.send(Some(Ok(output)));
+                }
+            }
+        });
+
+        let output = tokio::time::timeout(Duration::from_secs(1), head(&remote, 1))
+            .await
+            .unwrap();
+        assert_eq!(output.and_then(|o| o.content_length), Some(7));
+    }
+
+    fn part() -> UploadPartInput {
+        UploadPartInput::builder()
+            .key("key")
+            .part_number(1)
+            .upload_id("upload")
+            .body(ByteStream::from_static(b"part"))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn remote_failing_a_part_transiently_stays_in_upload() {
+        let remote = flaky_remote(1);
+
+        let output = upload_part_with_retry(&remote, part(), 2, part).await;
+
+        assert_eq!(
+            output.and_then(|o| o.unwrap().e_tag),
+            Some("part".to_owned())
+        );
+        assert!(upload_part_with_retry(&flaky_remote(1), part(), 0, part)
+            .await
+            .is_none());
+    }
+
+    /// Marks an upload of a part as cancelled when dropped.
+    struct Cancelled(Arc<AtomicBool>);
+
+    impl Drop for Cancelled {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn part_is_sent_again_only_after_the_hung_upload_is_cancelled() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let timeout = Some(Duration::from_millis(50));
+        let remote = S3Remote::serving("hung", timeout, move |message| {
+            let hung = seen.fetch_add(1, Ordering::SeqCst) == 0;
+            let cancelled = Arc::clone(&cancelled);
+            async move {
+                if let RemoteMessage::UploadPart { reply, .. } = message {
+                    if hung {
+                        let _upload = Cancelled(Arc::clone(&cancelled));
+                        std::future::pending::<()>().await;
+                    }
+                    let e_tag = match cancelled.load(Ordering::SeqCst) {
+                        true => "part",
+                        false => "overlapping",
+                    };
+                    let _ = reply.send(Some(Ok(UploadPartOutput::builder().e_tag(e_tag).build())));
+                }
+            }
+        });
+
+        let output = upload_part_with_retry(&remote, part(), 1, part).await;
+
+        assert_eq!(
+            output.and_then(|o| o.unwrap().e_tag),
+            Some("part".to_owned())
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Weight of the newest request in [`RemoteStatus`]'s error rate.
+const ERROR_RATE_WEIGHT: f64 = 0.1;
+
+/// Operational state of a remote, updated by its actor and read by the admin endpoints.
+#[derive(Debug)]
+pub struct RemoteStatus {
+    /// Failures in a row that open the circuit.
+    open_after: usize,
+    inner: Mutex<StatusInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatusInner {
+    up: Option<bool>,
+    failures: usize,
+    last_success: Option<SystemTime>,
+    error_rate: f64,
+    // Reported as-is; nothing drains a remote or measures its lag yet.
+    draining: bool,
+    replication_lag: Option<Duration>,
+}
+
+/// `Open` while the remote is considered down, `Unknown` until it answered for the first time.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Unknown,
+    Closed,
+    Open,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StatusSnapshot {
+    pub circuit: CircuitState,
+    /// Unix time in seconds of the last request the remote answered.
+    pub last_success: Option<u64>,
+    /// Exponentially weighted share of requests that did not reach the remote.
+    pub error_rate: f64,
+    pub draining: bool,
+    pub replication_lag_secs: Option<f64>,
+}
+
+impl Default for RemoteStatus {
+    fn default() -> Self {
+        Self::opening_after(1)
+    }
+}
+
+impl RemoteStatus {
+    /// A remote whose circuit opens once `failures` requests in a row failed to reach it.
+    pub fn opening_after(failures: usize) -> Self {
+        Self {
+            open_after: failures.max(1),
+            inner: Default::default(),
+        }
+    }
+
+    /// Records the outcome of a request. Returns whether the remote is up if the request closed
+    /// or opened its circuit, and `None` if it left the circuit as it was.
+    pub fn record(&self, success: bool) -> Option<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let failure = if success { 0.0 } else { 1.0 };
+        inner.error_rate += (failure - inner.error_rate) * ERROR_RATE_WEIGHT;
+        if success {
+            inner.last_success = Some(SystemTime::now());
+            inner.failures = 0;
+        } else {
+            inner.failures += 1;
+            if inner.failures < self.open_after {
+                return None;
+            }
+        }
+        (inner.up.replace(success) != Some(success)).then_some(success)
+    }
+
+    /// Whether the last requests failed to reach the remote often enough to open its circuit.
+    pub fn is_down(&self) -> bool {
+        self.inner.lock().unwrap().up == Some(false)
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let inner = self.inner.lock().unwrap();
+        StatusSnapshot {
+            circuit: match inner.up {
+                None => CircuitState::Unknown,
+                Some(true) => CircuitState::Closed,
+                Some(false) => CircuitState::Open,
+            },
+            last_success: inner
+                .last_success
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            error_rate: inner.error_rate,
+            draining: inner.draining,
+            replication_lag_secs: inner.replication_lag.map(|d| d.as_secs_f64()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn failures_open_the_circuit_and_raise_the_error_rate() {
+        let status = RemoteStatus::default();
+        assert_eq!(status.snapshot().circuit, CircuitState::Unknown);
+
+        assert_eq!(status.record(true), Some(true));
+        assert_eq!(status.record(true), None);
+        assert_eq!(status.record(false), Some(false));
+
+        let snapshot = status.snapshot();
+        assert_eq!(snapshot.circuit, CircuitState::Open);
+        assert!(snapshot.last_success.is_some());
+        assert!((snapshot.error_rate - ERROR_RATE_WEIGHT).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures_only() {
+        let status = RemoteStatus::opening_after(3);
+        status.record(true);
+
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(true), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.record(false), None);
+        assert_eq!(status.snapshot().circuit, CircuitState::Closed);
+
+        assert_eq!(status.record(false), Some(false));
+        assert_eq!(status.snapshot().circuit, CircuitState::Open);
+    }
+}
@@ -1,11 +1,28 @@
+use std::collections::VecDeque;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
 use aws_sdk_s3::primitives::ByteStream;
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::error::Error as ByteStreamReadError;
 use bytes::Bytes;
-use http_body::{Body, SizeHint};
+use http_body::{Body, Frame, SizeHint};
+use mongodb::bson::oid::ObjectId;
 use pin_project::pin_project;
 use thiserror::Error;
+use tokio::fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{error, info, info_span, instrument, warn, Instrument};
 
+use crate::metrics::{REMOTE_BYTES_RECEIVED, REMOTE_BYTES_SENT};
+
+use super::checksum::RunningChecksum;
+
 // TODO: unwrap 多すぎ……
 
 //https://docs.rs/aws-sdk-s3/latest/aws_sdk_s3/primitives/struct.SdkBody.html#method.from_body_1_x
@@ -51,7 +68,7 @@ impl ByteStreamMultiplier {
                         .unwrap();
                 }
                 info!("stream ended");
-                drop(listen_tx);
+                let _ = listen_tx.send(None).await;
             }
             .instrument(info_span!("stream_listener")),
         );
@@ -83,7 +100,8 @@ impl ByteStreamMultiplier {
                             }
 
                             for tx in txs.iter_mut() {
-                                tx.send(payload.clone()).await.unwrap();
+                                // a remote that already read everything it needed may be gone
+                                let _ = tx.send(payload.clone()).await;
                             }
 
                             if will_be_new_tx {
@@ -110,17 +128,35 @@ impl ByteStreamMultiplier {
         )
     }
 
-    pub async fn subscribe_stream(&self, part_number: Option<i32>) -> Option<ByteStream> {
+    pub async fn subscribe_stream(
+        &self,
+        remote: &str,
+        part_number: Option<i32>,
+    ) -> Option<ByteStream> {
+        let (stream, _) = self.subscribe_tracked_stream(remote, part_number).await?;
+        Some(stream)
+    }
+
+    /// Subscribes like [`Self::subscribe_stream`], along with whether the subscriber ends up
+    /// reading the whole body.
+    pub async fn subscribe_tracked_stream(
+        &self,
+        remote: &str,
+        part_number: Option<i32>,
+    ) -> Option<(ByteStream, BranchCompletion)> {
         let subscribe_tx = self.subscribe_tx.clone()?;
         let (tx, rx) = oneshot::channel();
         subscribe_tx.send(tx).await.unwrap();
+        let completion = BranchCompletion::default();
         let receiver: ByteStreamReceiver = ByteStreamReceiver {
             frame_rx: rx.await.unwrap(),
             size_hint_rx: self.size_hint_rx.clone(),
             is_end_stream_reached: false,
+            completion: completion.clone(),
             part_number,
+            remote: remote.to_owned(),
         };
-        Some(ByteStream::from_body_1_x(receiver))
+        Some((ByteStream::from_body_1_x(receiver), completion))
     }
 
     pub fn close(&mut self) {
@@ -145,12 +181,25 @@ enum ByteStreamError {
     ByteStreamError(String),
 }
 
+/// Whether a subscriber of a [`ByteStreamMultiplier`] read the body to its end. It did not when
+/// the body failed or the subscriber stopped reading early, e.g. on a connection reset.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BranchCompletion(Arc<AtomicBool>);
+
+impl BranchCompletion {
+    pub fn complete(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 #[pin_project]
 struct ByteStreamReceiver {
     frame_rx: mpsc::Receiver<ByteStreamResult>,
     size_hint_rx: watch::Receiver<http_body::SizeHint>,
     is_end_stream_reached: bool,
+    completion: BranchCompletion,
     part_number: Option<i32>,
+    remote: String,
 }
 
 impl Body for ByteStreamReceiver {
@@ -166,10 +215,16 @@ impl Body for ByteStreamReceiver {
         let project = self.project();
 
         project.frame_rx.poll_recv(cx).map(|r| match r {
-            Some(Some(frame)) => Some(frame.map(http_body::Frame::data)),
+            Some(Some(frame)) => {
+                if let Ok(data) = &frame {
+                    REMOTE_BYTES_SENT.inc_by(&[project.remote.as_str()], data.len() as u64);
+                }
+                Some(frame.map(http_body::Frame::data))
+            }
             Some(None) => {
                 info!("end stream reached");
                 *project.is_end_stream_reached = true;
+                project.completion.0.store(true, Ordering::Relaxed);
                 None
             }
             None => {
@@ -187,3 +242,642 @@ impl Body for ByteStreamReceiver {
         self.size_hint_rx.borrow().clone()
     }
 }
+
+/// Wraps a body returned by `remote` so the bytes streamed out of it are counted.
+pub fn count_received(stream: ByteStream, remote: &str) -> ByteStream {
+    ByteStream::from_body_1_x(CountingBody {
+        inner: stream.into_inner(),
+        remote: remote.to_owned(),
+    })
+}
+
+#[pin_project]
+struct CountingBody {
+    #[pin]
+    inner: SdkBody,
+    remote: String,
+}
+
+impl Body for CountingBody {
+    type Data = Bytes;
+    type Error = <SdkBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let project = self.project();
+        let polled = project.inner.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled {
+            if let Some(data) = frame.data_ref() {
+                REMOTE_BYTES_RECEIVED.inc_by(&[project.remote.as_str()], data.len() as u64);
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        Body::is_end_stream(&self.inner)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        Body::size_hint(&self.inner)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("body does not match its checksum (expected {expected}, got {actual})")]
+pub struct ChecksumMismatch {
+    expected: String,
+    actual: String,
+}
+
+/// Checks `stream` against the checksum `expected` as it is read. A mismatch surfaces as an error
+/// in place of the end of the body.
+pub(crate) fn verify_checksum(
+    stream: ByteStream,
+    checksum: RunningChecksum,
+    expected: String,
+) -> ByteStream {
+    ByteStream::from_body_1_x(ChecksumBody {
+        inner: stream.into_inner(),
+        checksum: Some(checksum),
+        expected,
+    })
+}
+
+#[pin_project]
+struct ChecksumBody {
+    #[pin]
+    inner: SdkBody,
+    checksum: Option<RunningChecksum>,
+    expected: String,
+}
+
+impl Body for ChecksumBody {
+    type Data = Bytes;
+    type Error = <SdkBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let project = self.project();
+        match project.inner.poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Some(checksum)) = (frame.data_ref(), project.checksum.as_mut())
+                {
+                    checksum.update(data);
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Poll::Ready(None) => {
+                let Some(checksum) = project.checksum.take() else {
+                    return Poll::Ready(None);
+                };
+                let actual = checksum.finalize();
+                if actual == *project.expected {
+                    return Poll::Ready(None);
+                }
+                warn!("body does not match the remote's checksum");
+                Poll::Ready(Some(Err(ChecksumMismatch {
+                    expected: project.expected.clone(),
+                    actual,
+                }
+                .into())))
+            }
+            polled => polled,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.checksum.is_none() && Body::is_end_stream(&self.inner)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        Body::size_hint(&self.inner)
+    }
+}
+
+/// Whether an upload checked by [`verify_upload_checksum`] turned out not to match.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DigestCheck(Arc<AtomicBool>);
+
+impl DigestCheck {
+    pub fn mismatched(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Checks an upload against the checksum `expected` as it streams to the remotes. The last chunk
+/// is held back until the whole body is checked, so that on a mismatch the remotes get an error
+/// instead of a complete body they could store.
+pub(crate) fn verify_upload_checksum(
+    stream: ByteStream,
+    checksum: RunningChecksum,
+    expected: String,
+) -> (ByteStream, DigestCheck) {
+    let check = DigestCheck::default();
+    let body = HeldBackChecksumBody {
+        inner: stream.into_inner(),
+        checksum: Some(checksum),
+        expected,
+        held: None,
+        check: check.clone(),
+    };
+    (ByteStream::from_body_1_x(body), check)
+}
+
+#[pin_project]
+struct HeldBackChecksumBody {
+    #[pin]
+    inner: SdkBody,
+    checksum: Option<RunningChecksum>,
+    expected: String,
+    held: Option<Bytes>,
+    check: DigestCheck,
+}
+
+impl Body for HeldBackChecksumBody {
+    type Data = Bytes;
+    type Error = <SdkBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut project = self.project();
+        loop {
+            match ready!(project.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        if let Some(checksum) = project.checksum.as_mut() {
+                            checksum.update(&data);
+                        }
+                        if let Some(held) = project.held.replace(data) {
+                            return Poll::Ready(Some(Ok(Frame::data(held))));
+                        }
+                    }
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    if let Some(checksum) = project.checksum.take() {
+                        let actual = checksum.finalize();
+                        if actual != *project.expected {
+                            warn!("upload does not match the client's checksum");
+                            project.check.0.store(true, Ordering::Relaxed);
+                            project.held.take();
+                            return Poll::Ready(Some(Err(ChecksumMismatch {
+                                expected: project.expected.clone(),
+                                actual,
+                            }
+                            .into())));
+                        }
+                    }
+                    return Poll::Ready(project.held.take().map(|data| Ok(Frame::data(data))));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.checksum.is_none() && self.held.is_none() && Body::is_end_stream(&self.inner)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        Body::size_hint(&self.inner)
+    }
+}
+
+/// Writes `stream` to an unlinked temporary file and returns a stream over it together with its
+/// length, for remotes that refuse uploads without a `Content-Length`.
+pub async fn spool(stream: ByteStream) -> std::io::Result<(ByteStream, i64)> {
+    let (mut streams, length) = spool_shared(stream, &std::env::temp_dir(), 1).await?;
+    Ok((streams.remove(0), length))
+}
+
+/// Writes `stream` to a temporary file in `dir` and returns `readers` streams over it, each with
+/// its own position, together with its length. The file is unlinked before returning, so it is
+/// gone once every stream has been dropped.
+pub async fn spool_shared(
+    mut stream: ByteStream,
+    dir: &Path,
+    readers: usize,
+) -> std::io::Result<(Vec<ByteStream>, i64)> {
+    let path = dir.join(format!("s3-reproxy-spool-{}", ObjectId::new().to_hex()));
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await?;
+    let opened: std::io::Result<Vec<_>> = try {
+        let mut files = vec![];
+        for _ in 1..readers {
+            files.push(fs::File::open(&path).await?);
+        }
+        files
+    };
+    fs::remove_file(&path).await?;
+    let mut files = opened?;
+
+    let mut length = 0;
+    while let Some(chunk) = stream.try_next().await.map_err(std::io::Error::other)? {
+        file.write_all(&chunk).await?;
+        length += chunk.len() as i64;
+    }
+    file.flush().await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    files.insert(0, file);
+
+    let mut streams = vec![];
+    for file in files.into_iter().take(readers) {
+        let stream = ByteStream::read_from()
+            .file(file)
+            .build()
+            .await
+            .map_err(std::io::Error::other)?;
+        streams.push(stream);
+    }
+    Ok((streams, length))
+}
+
+/// Where and from which size request bodies are spooled to disk for every remote to read.
+#[derive(Debug, Clone)]
+pub struct DiskSpool {
+    pub dir: PathBuf,
+    pub threshold_bytes: u64,
+}
+
+impl DiskSpool {
+    /// Whether a body of `content_length` is spooled. A body of unknown length may be arbitrarily
+    /// large, so it always is.
+    pub fn applies(&self, content_length: Option<i64>) -> bool {
+        content_length.map_or(true, |l| l as u64 >= self.threshold_bytes)
+    }
+}
+
+/// Settles the body of a `GetObject` reply on the length the remote gave. The body of an empty
+/// object is not read at all, so the reply goes out at once even from a remote that keeps the
+/// body open. A body of unknown length is streamed to its end without claiming any size.
+pub fn sized_body(body: ByteStream, content_length: Option<i64>) -> ByteStream {
+    match content_length {
+        Some(0) => ByteStream::new(SdkBody::empty()),
+        Some(_) => body,
+        None => ByteStream::from_body_1_x(UnsizedBody {
+            inner: body.into_inner(),
+        }),
+    }
+}
+
+/// A body that makes no claim about its size, whatever the body it wraps says.
+#[pin_project]
+struct UnsizedBody {
+    #[pin]
+    inner: SdkBody,
+}
+
+impl Body for UnsizedBody {
+    type Data = Bytes;
+    type Error = <SdkBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.project().inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        Body::is_end_stream(&self.inner)
+    }
+}
+
+/// Reads up to `limit` bytes of `stream` ahead, so that a body failing early is noticed before
+/// anything is sent to the client. The returned stream yields the buffered bytes followed by the
+/// rest of the body; errors past `limit` still surface while streaming.
+pub async fn buffer_head(
+    mut stream: ByteStream,
+    limit: usize,
+) -> Result<ByteStream, ByteStreamReadError> {
+    let mut head = VecDeque::new();
+    let mut buffered = 0;
+    while buffered < limit {
+        let Some(chunk) = stream.try_next().await? else {
+            return Ok(ByteStream::from(
+                head.into_iter().flatten().collect::<Vec<u8>>(),
+            ));
+        };
+        buffered += chunk.len();
+        head.push_back(chunk);
+    }
+    Ok(ByteStream::from_body_1_x(PrefixedBody {
+        head,
+        buffered: buffered as u64,
+        inner: stream.into_inner(),
+    }))
+}
+
+#[pin_project]
+struct PrefixedBody {
+    head: VecDeque<Bytes>,
+    buffered: u64,
+    #[pin]
+    inner: SdkBody,
+}
+
+impl Body for PrefixedBody {
+    type Data = Bytes;
+    type Error = <SdkBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let project = self.project();
+        if let Some(chunk) = project.head.pop_front() {
+            *project.buffered -= chunk.len() as u64;
+            return Poll::Ready(Some(Ok(Frame::data(chunk))));
+        }
+        project.inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.head.is_empty() && Body::is_end_stream(&self.inner)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner = Body::size_hint(&self.inner);
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner.lower() + self.buffered);
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + self.buffered);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn counts_bytes_received_from_remote() {
+        let body = count_received(ByteStream::from_static(b"0123456789"), "received-test");
+
+        let data = body.collect().await.unwrap().into_bytes();
+
+        assert_eq!(data.len(), 10);
+        assert_eq!(REMOTE_BYTES_RECEIVED.get(&["received-test"]), 10);
+    }
+
+    #[tokio::test]
+    async fn counts_bytes_sent_to_each_remote() {
+        let (mut multiplier, _signal) =
+            ByteStreamMultiplier::from_bytestream(ByteStream::from_static(b"0123456789"));
+        let mut first = multiplier.subscribe_stream("sent-a", None).await.unwrap();
+        let mut second = multiplier.subscribe_stream("sent-b", None).await.unwrap();
+        multiplier.close();
+
+        assert_eq!(first.next().await.unwrap().unwrap().len(), 10);
+        assert_eq!(second.next().await.unwrap().unwrap().len(), 10);
+
+        assert_eq!(REMOTE_BYTES_SENT.get(&["sent-a"]), 10);
+        assert_eq!(REMOTE_BYTES_SENT.get(&["sent-b"]), 10);
+    }
+
+    /// Yields `chunks` one frame at a time, then ends or fails.
+    struct ChunkedBody {
+        chunks: VecDeque<Bytes>,
+        fail: bool,
+    }
+
+    impl ChunkedBody {
+        fn stream(chunks: &[&'static [u8]], fail: bool) -> ByteStream {
+            ByteStream::from_body_1_x(ChunkedBody {
+                chunks: chunks.iter().map(|c| Bytes::from_static(c)).collect(),
+                fail,
+            })
+        }
+    }
+
+    impl Body for ChunkedBody {
+        type Data = Bytes;
+        type Error = std::io::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(match self.chunks.pop_front() {
+                Some(data) => Some(Ok(Frame::data(data))),
+                None if self.fail => Some(Err(std::io::Error::other("connection reset"))),
+                None => None,
+            })
+        }
+    }
+
+    /// Never yields a frame, like a remote that keeps the body of its reply open.
+    struct StalledBody;
+
+    impl Body for StalledBody {
+        type Data = Bytes;
+        type Error = std::io::Error;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_object_is_served_without_reading_its_body() {
+        let body = sized_body(ByteStream::from_body_1_x(StalledBody), Some(0));
+
+        assert_eq!(body.size_hint(), (0, Some(0)));
+        let data = tokio::time::timeout(std::time::Duration::from_secs(1), body.collect())
+            .await
+            .expect("empty body was waited on")
+            .unwrap();
+        assert!(data.into_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn body_of_unknown_length_is_streamed_to_its_end() {
+        let body = sized_body(ChunkedBody::stream(&[b"0123", b"4567", b"89"], false), None);
+
+        assert_eq!(body.size_hint(), (0, None));
+        assert_eq!(
+            &body.collect().await.unwrap().into_bytes()[..],
+            b"0123456789"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_length_upload_reaches_every_remote() {
+        let body = ChunkedBody::stream(&[b"0123", b"4567", b"89"], false);
+        assert_eq!(body.size_hint().1, None);
+
+        let (mut multiplier, _signal) = ByteStreamMultiplier::from_bytestream(body);
+        let first = multiplier
+            .subscribe_stream("chunked-a", None)
+            .await
+            .unwrap();
+        let second = multiplier
+            .subscribe_stream("chunked-b", None)
+            .await
+            .unwrap();
+        multiplier.close();
+
+        for stream in [first, second] {
+            let data = stream.collect().await.unwrap().into_bytes();
+            assert_eq!(&data[..], b"0123456789");
+        }
+    }
+
+    #[tokio::test]
+    async fn spooling_computes_the_length() {
+        let body = ChunkedBody::stream(&[b"0123", b"4567", b"89"], false);
+
+        let (stream, length) = spool(body).await.unwrap();
+
+        assert_eq!(length, 10);
+        assert_eq!(
+            &stream.collect().await.unwrap().into_bytes()[..],
+            b"0123456789"
+        );
+    }
+
+    #[tokio::test]
+    async fn large_upload_reaches_slow_remotes_through_disk() {
+        let dir =
+            std::env::temp_dir().join(format!("s3-reproxy-test-{}", ObjectId::new().to_hex()));
+        fs::create_dir(&dir).await.unwrap();
+        let chunk = [7u8; 1024 * 1024];
+        let body = ByteStream::from_body_1_x(ChunkedBody {
+            chunks: (0..8).map(|_| Bytes::copy_from_slice(&chunk)).collect(),
+            fail: false,
+        });
+
+        let (streams, length) = spool_shared(body, &dir, 3).await.unwrap();
+        assert_eq!(length, 8 * 1024 * 1024);
+
+        let received = futures::future::join_all(streams.into_iter().enumerate().map(
+            |(i, mut stream)| async move {
+                let mut received = 0;
+                while let Some(chunk) = stream.try_next().await.unwrap() {
+                    received += chunk.len();
+                    tokio::time::sleep(std::time::Duration::from_millis(i as u64)).await;
+                }
+                received
+            },
+        ))
+        .await;
+
+        assert_eq!(received, vec![8 * 1024 * 1024; 3]);
+        let mut left = fs::read_dir(&dir).await.unwrap();
+        assert!(left.next_entry().await.unwrap().is_none());
+        fs::remove_dir(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn bodies_of_unknown_length_are_always_spooled() {
+        let spool = DiskSpool {
+            dir: std::env::temp_dir(),
+            threshold_bytes: 1024,
+        };
+
+        assert!(spool.applies(None));
+        assert!(spool.applies(Some(1024)));
+        assert!(!spool.applies(Some(1023)));
+    }
+
+    #[tokio::test]
+    async fn body_not_matching_its_checksum_fails() {
+        let sha256 = || RunningChecksum::Sha256(sha2::Sha256::default());
+        let hello = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_owned();
+
+        let matching = verify_checksum(
+            ChunkedBody::stream(&[b"hel", b"lo"], false),
+            sha256(),
+            hello.clone(),
+        );
+        assert_eq!(
+            &matching.collect().await.unwrap().into_bytes()[..],
+            b"hello"
+        );
+
+        let corrupted = verify_checksum(
+            ChunkedBody::stream(&[b"hel", b"p!"], false),
+            sha256(),
+            hello.clone(),
+        );
+        assert!(corrupted.collect().await.is_err());
+
+        let corrupted = verify_checksum(
+            ChunkedBody::stream(&[b"hel", b"p!"], false),
+            sha256(),
+            hello,
+        );
+        assert!(buffer_head(corrupted, 16).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn corrupted_upload_never_reaches_its_end() {
+        let sha256 = || RunningChecksum::Sha256(sha2::Sha256::default());
+        let hello = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_owned();
+
+        let (matching, check) = verify_upload_checksum(
+            ChunkedBody::stream(&[b"hel", b"lo"], false),
+            sha256(),
+            hello.clone(),
+        );
+        assert_eq!(
+            &matching.collect().await.unwrap().into_bytes()[..],
+            b"hello"
+        );
+        assert!(!check.mismatched());
+
+        let (mut corrupted, check) = verify_upload_checksum(
+            ChunkedBody::stream(&[b"hel", b"p!"], false),
+            sha256(),
+            hello,
+        );
+        assert_eq!(&corrupted.try_next().await.unwrap().unwrap()[..], b"hel");
+        assert!(corrupted.try_next().await.is_err());
+        assert!(check.mismatched());
+    }
+
+    #[tokio::test]
+    async fn body_failing_within_buffer_window_is_detected() {
+        let body = ChunkedBody::stream(&[b"0123"], true);
+
+        assert!(buffer_head(body, 16).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn buffered_head_is_replayed_before_the_rest() {
+        let body = ChunkedBody::stream(&[b"0123", b"4567", b"89"], false);
+
+        let body = buffer_head(body, 4).await.unwrap();
+
+        assert_eq!(
+            &body.collect().await.unwrap().into_bytes()[..],
+            b"0123456789"
+        );
+        assert_eq!(
+            &buffer_head(ByteStream::from_static(b"short"), 16)
+                .await
+                .unwrap()
+                .collect()
+                .await
+                .unwrap()
+                .into_bytes()[..],
+            b"short"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use futures::StreamExt;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use super::remote::{RemoteMessage, S3Remote};
+
+/// Sends the request `message` builds to every remote and returns the replies of those that
+/// could be reached.
+pub(super) async fn send_to_all<O: Send>(
+    remotes: &[S3Remote],
+    concurrency: usize,
+    message: impl Fn(oneshot::Sender<Option<O>>) -> RemoteMessage + Sync,
+) -> Vec<(String, O)> {
+    let message = &message;
+    futures::stream::iter(remotes.iter())
+        .map(|remote| async move {
+            let Some(result) = remote.request(message).await else {
+                warn!("remote({:?}) request failed. skipping", remote.name);
+                return None;
+            };
+            Some((remote.name.clone(), result))
+        })
+        .boxed()
+        .buffer_unordered(concurrency)
+        .filter_map(|e| async { e })
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_s3::operation::put_object_tagging::{
+        PutObjectTaggingInput, PutObjectTaggingOutput,
+    };
+    use aws_sdk_s3::types::{Tag, Tagging};
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// A remote keeping the last tag set it was sent in `stored`.
+    fn tagging_remote(name: &str, stored: Arc<Mutex<Option<Tagging>>>) -> S3Remote {
+        S3Remote::answering(name, move |message| {
+            if let RemoteMessage::PutObjectTagging { input, reply } = message {
+                *stored.lock().unwrap() = input.tagging;
+                let _ = reply.send(Some(Ok(PutObjectTaggingOutput::builder().build())));
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn tags_reach_every_remote_unmodified() {
+        let stored = ["a", "b"].map(|_| Arc::new(Mutex::new(None)));
+        let mut remotes = vec![
+            tagging_remote("a", Arc::clone(&stored[0])),
+            tagging_remote("b", Arc::clone(&stored[1])),
+        ];
+        remotes.push(S3Remote::stub("down"));
+        let tagging = Tagging::builder()
+            .tag_set(
+                Tag::builder()
+                    .key("lifecycle")
+                    .value("30d")
+                    .build()
+                    .unwrap(),
+            )
+            .tag_set(Tag::builder().key("Team").value("a b/c").build().unwrap())
+            .build()
+            .unwrap();
+        let input = PutObjectTaggingInput::builder()
+            .key("key")
+            .tagging(tagging.clone())
+            .build()
+            .unwrap();
+
+        let results = send_to_all(&remotes, 4, |reply| RemoteMessage::PutObjectTagging {
+            input: input.clone(),
+            reply,
+        })
+        .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        for stored in stored {
+            assert_eq!(stored.lock().unwrap().as_ref(), Some(&tagging));
+        }
+    }
+}
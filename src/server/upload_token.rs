@@ -0,0 +1,154 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::s3_target::SignedUploadIdConfig;
+use crate::db::{PartUploadStatus, RemoteMultipartUploadId};
+
+const PREFIX: &str = "s1.";
+const MAC_LEN: usize = 32;
+
+/// Encodes the per-remote upload ids of a multipart upload into the `upload_id` handed to the
+/// client, so parts can be routed without looking the upload up in MongoDB.
+///
+/// The token is HMAC-signed and bound to the object key, so a client can neither alter the
+/// mapping nor reuse it for another key. Uploads whose token would exceed the configured size
+/// are stored in MongoDB as usual.
+///
+/// A token cannot be revoked: it still decodes after its upload was completed or aborted. The
+/// remotes no longer know the upload ids it carries by then, and answer `NoSuchUpload` to it.
+pub struct UploadTokenCodec {
+    secret: Vec<u8>,
+    max_len: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Payload {
+    key: String,
+    uploads: Vec<(String, String)>,
+}
+
+impl UploadTokenCodec {
+    pub fn new(config: &SignedUploadIdConfig) -> Self {
+        Self {
+            secret: config.secret.as_bytes().to_vec(),
+            max_len: config.max_token_bytes,
+        }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    /// Returns `None` if the token would be too large.
+    pub fn encode(&self, key: &str, ids: &[RemoteMultipartUploadId]) -> Option<String> {
+        let payload = Payload {
+            key: key.to_owned(),
+            uploads: ids
+                .iter()
+                .map(|id| (id.remote_name.clone(), id.upload_id.clone()))
+                .collect(),
+        };
+        let mut bytes = mongodb::bson::to_vec(&payload).ok()?;
+        let mut mac = self.mac();
+        mac.update(&bytes);
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+
+        let token = format!("{PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+        (token.len() <= self.max_len).then_some(token)
+    }
+
+    /// Whether `upload_id` was minted by [`encode`](Self::encode) rather than stored in MongoDB.
+    pub fn is_token(upload_id: &str) -> bool {
+        upload_id.starts_with(PREFIX)
+    }
+
+    /// Returns `None` if the token is malformed, was tampered with, or belongs to another key.
+    pub fn decode(&self, key: &str, token: &str) -> Option<Vec<RemoteMultipartUploadId>> {
+        if token.len() > self.max_len {
+            return None;
+        }
+        let bytes = URL_SAFE_NO_PAD.decode(token.strip_prefix(PREFIX)?).ok()?;
+        let (payload, signature) = bytes.split_at(bytes.len().checked_sub(MAC_LEN)?);
+        let mut mac = self.mac();
+        mac.update(payload);
+        mac.verify_slice(signature).ok()?;
+
+        let payload: Payload = mongodb::bson::from_slice(payload).ok()?;
+        (payload.key == key).then(|| {
+            payload
+                .uploads
+                .into_iter()
+                .map(|(remote_name, upload_id)| RemoteMultipartUploadId {
+                    status: PartUploadStatus::Open,
+                    remote_name,
+                    upload_id,
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn codec_with(secret: &str, max_token_bytes: usize) -> UploadTokenCodec {
+        UploadTokenCodec::new(&SignedUploadIdConfig {
+            secret: secret.to_owned(),
+            max_token_bytes,
+        })
+    }
+
+    fn ids() -> Vec<RemoteMultipartUploadId> {
+        ["r2", "minio"]
+            .into_iter()
+            .map(|remote| RemoteMultipartUploadId {
+                status: PartUploadStatus::Open,
+                remote_name: remote.to_owned(),
+                upload_id: format!("{remote}-upload-id"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn token_round_trips() {
+        let codec = codec_with("secret", 1024);
+
+        let token = codec.encode("photos/a.jpg", &ids()).unwrap();
+
+        assert!(UploadTokenCodec::is_token(&token));
+        assert_eq!(codec.decode("photos/a.jpg", &token), Some(ids()));
+    }
+
+    #[test]
+    fn tampered_token_is_rejected() {
+        let codec = codec_with("secret", 1024);
+        let token = codec.encode("photos/a.jpg", &ids()).unwrap();
+
+        let mut bytes = URL_SAFE_NO_PAD
+            .decode(token.strip_prefix(PREFIX).unwrap())
+            .unwrap();
+        bytes[10] ^= 1;
+        let tampered = format!("{PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+
+        assert_eq!(codec.decode("photos/a.jpg", &tampered), None);
+        assert_eq!(codec.decode("photos/b.jpg", &token), None);
+        assert_eq!(
+            codec_with("another secret", 1024).decode("photos/a.jpg", &token),
+            None
+        );
+        assert_eq!(codec.decode("photos/a.jpg", "s1.garbage"), None);
+    }
+
+    #[test]
+    fn oversized_mapping_falls_back_to_database() {
+        assert_eq!(
+            codec_with("secret", 64).encode("photos/a.jpg", &ids()),
+            None
+        );
+    }
+}
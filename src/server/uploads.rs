@@ -0,0 +1,210 @@
+use aws_sdk_s3::operation::list_multipart_uploads::{
+    ListMultipartUploadsInput, ListMultipartUploadsOutput,
+};
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::MultipartUpload;
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Document, Regex};
+use s3s::{s3_error, S3Error, S3ErrorCode, S3Result};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use super::S3Reproxy;
+
+/// Most uploads listed in one page, also when `max-uploads` is not given.
+const MAX_UPLOADS: i32 = 1000;
+
+#[derive(Debug, Deserialize)]
+struct InFlightUpload {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    key: Option<String>,
+    created_at: mongodb::bson::DateTime,
+}
+
+/// Escapes `literal` to match itself in a MongoDB regular expression.
+fn escape_regex(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Uploads neither completed nor aborted, with a key under `prefix` and created after `after`.
+fn in_flight_filter(prefix: Option<&str>, after: Option<ObjectId>) -> Document {
+    let mut filter = doc! {
+        "completed_at": null,
+        "aborted_at": null,
+    };
+    if let Some(prefix) = prefix.filter(|p| !p.is_empty()) {
+        filter.insert(
+            "key",
+            Regex {
+                pattern: format!("^{}", escape_regex(prefix)),
+                options: String::new(),
+            },
+        );
+    }
+    if let Some(after) = after {
+        filter.insert("_id", doc! { "$gt": after });
+    }
+    filter
+}
+
+/// A page of the first `max_uploads` of `found`, which holds one more upload when there is a
+/// next page. The markers of the next page are the key and id of the last upload listed.
+fn uploads_page(
+    input: &ListMultipartUploadsInput,
+    mut found: Vec<InFlightUpload>,
+    max_uploads: i32,
+) -> ListMultipartUploadsOutput {
+    let is_truncated = found.len() > max_uploads as usize;
+    found.truncate(max_uploads as usize);
+    let (next_key_marker, next_upload_id_marker) = match found.last() {
+        Some(last) if is_truncated => (
+            Some(last.key.clone().unwrap_or_default()),
+            Some(last.id.to_hex()),
+        ),
+        _ => (None, None),
+    };
+
+    ListMultipartUploadsOutput::builder()
+        .set_bucket(input.bucket.clone())
+        .set_prefix(input.prefix.clone())
+        .set_key_marker(input.key_marker.clone())
+        .set_upload_id_marker(input.upload_id_marker.clone())
+        .set_next_key_marker(next_key_marker)
+        .set_next_upload_id_marker(next_upload_id_marker)
+        .max_uploads(max_uploads)
+        .is_truncated(is_truncated)
+        .set_uploads(Some(
+            found
+                .into_iter()
+                .map(|upload| {
+                    MultipartUpload::builder()
+                        .upload_id(upload.id.to_hex())
+                        .set_key(upload.key)
+                        .initiated(DateTime::from_millis(upload.created_at.timestamp_millis()))
+                        .build()
+                })
+                .collect(),
+        ))
+        .build()
+}
+
+impl S3Reproxy {
+    /// Lists the uploads in flight in creation order, from the records in MongoDB. Pages follow
+    /// on from `upload-id-marker` alone. Uploads with signed upload ids have no record and are
+    /// never listed.
+    pub(super) async fn in_flight_uploads(
+        &self,
+        input: &ListMultipartUploadsInput,
+    ) -> S3Result<ListMultipartUploadsOutput> {
+        let max_uploads = input
+            .max_uploads
+            .unwrap_or(MAX_UPLOADS)
+            .clamp(1, MAX_UPLOADS);
+        let after = input
+            .upload_id_marker
+            .as_deref()
+            .map(|marker| {
+                ObjectId::parse_str(marker).map_err(|e| {
+                    warn!("(intercepted) invalid upload-id-marker: {:?}", e);
+                    s3_error!(InvalidArgument, "invalid upload-id-marker")
+                })
+            })
+            .transpose()?;
+
+        let found = self
+            .db
+            .multipart_upload_ids
+            .clone_with_type::<InFlightUpload>()
+            .find(in_flight_filter(input.prefix.as_deref(), after))
+            .sort(doc! { "_id": 1 })
+            .limit(max_uploads as i64 + 1)
+            .await
+            .map_err(|e| {
+                error!("mongodb error: {:?}", e);
+                S3Error::new(S3ErrorCode::InternalError)
+            })?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| {
+                error!("mongodb error: {:?}", e);
+                S3Error::new(S3ErrorCode::InternalError)
+            })?;
+
+        Ok(uploads_page(input, found, max_uploads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn upload(key: &str, created_at: i64) -> InFlightUpload {
+        InFlightUpload {
+            id: ObjectId::new(),
+            key: Some(key.to_owned()),
+            created_at: mongodb::bson::DateTime::from_millis(created_at),
+        }
+    }
+
+    #[test]
+    fn prefix_is_matched_literally() {
+        let after = ObjectId::new();
+
+        let filter = in_flight_filter(Some("logs/2024.01+"), Some(after));
+
+        assert_eq!(
+            filter,
+            doc! {
+                "completed_at": null,
+                "aborted_at": null,
+                "key": Regex {
+                    pattern: "^logs/2024\\.01\\+".to_owned(),
+                    options: String::new(),
+                },
+                "_id": { "$gt": after },
+            }
+        );
+        assert_eq!(
+            in_flight_filter(Some(""), None),
+            doc! { "completed_at": null, "aborted_at": null }
+        );
+    }
+
+    #[test]
+    fn truncated_page_points_at_its_last_upload() {
+        let input = ListMultipartUploadsInput::builder()
+            .bucket("virtual")
+            .build()
+            .unwrap();
+        let found = vec![upload("a", 1_000), upload("b", 2_000), upload("c", 3_000)];
+        let last = found[1].id.to_hex();
+
+        let page = uploads_page(&input, found, 2);
+
+        assert_eq!(page.is_truncated(), Some(true));
+        assert_eq!(page.next_key_marker(), Some("b"));
+        assert_eq!(page.next_upload_id_marker(), Some(last.as_str()));
+        assert_eq!(
+            page.uploads()
+                .iter()
+                .map(|u| (u.key(), u.initiated().map(|t| t.to_millis().unwrap())))
+                .collect::<Vec<_>>(),
+            [(Some("a"), Some(1_000)), (Some("b"), Some(2_000))]
+        );
+
+        let found = vec![upload("c", 3_000)];
+        let page = uploads_page(&input, found, 2);
+        assert_eq!(page.is_truncated(), Some(false));
+        assert_eq!(page.next_upload_id_marker(), None);
+    }
+}
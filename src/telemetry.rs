@@ -0,0 +1,36 @@
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The exporter reads its endpoint, headers and timeout from the standard `OTEL_EXPORTER_OTLP_*`
+/// variables.
+const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// A layer exporting spans over OTLP, or none when no endpoint is configured.
+pub fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, trace::Tracer>>, TraceError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if std::env::var_os(ENDPOINT_ENV).is_none() {
+        return Ok(None);
+    }
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(trace::Config::default().with_resource(Resource::new([
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(runtime::TokioCurrentThread)?;
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flushes the spans still waiting in the batch exporter.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,348 @@
+//! Runs the proxy binary in front of two MinIO containers and a MongoDB container, and checks
+//! what ends up on each backend. Needs a Docker daemon, so the tests are ignored by default:
+//!
+//! ```sh
+//! cargo test --test replication -- --ignored
+//! ```
+
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use pretty_assertions::assert_eq;
+use testcontainers_modules::minio::MinIO;
+use testcontainers_modules::mongo::Mongo;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+const BUCKET: &str = "replicated";
+const ACCESS_KEY: &str = "reproxy";
+const SECRET_KEY: &str = "reproxy-secret";
+
+/// MinIO's default root credentials.
+const MINIO_USER: &str = "minioadmin";
+const MINIO_PASSWORD: &str = "minioadmin";
+
+/// Smallest part S3 accepts other than the last one.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+fn client(endpoint: &str, access_key: &str, secret_key: &str) -> Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .credentials_provider(Credentials::new(access_key, secret_key, None, None, "test"))
+        .region(Region::new("us-east-1"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    Client::from_conf(config)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+struct Backend {
+    _container: ContainerAsync<MinIO>,
+    endpoint: String,
+    client: Client,
+}
+
+impl Backend {
+    async fn start() -> Self {
+        let container = MinIO::default().start().await.unwrap();
+        let endpoint = format!(
+            "http://{}:{}",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(9000).await.unwrap()
+        );
+        let client = client(&endpoint, MINIO_USER, MINIO_PASSWORD);
+        client.create_bucket().bucket(BUCKET).send().await.unwrap();
+        Self {
+            _container: container,
+            endpoint,
+            client,
+        }
+    }
+
+    async fn read(&self, key: &str) -> Vec<u8> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(BUCKET)
+            .key(key)
+            .send()
+            .await
+            .unwrap();
+        object.body.collect().await.unwrap().to_vec()
+    }
+}
+
+/// The proxy, with `primary` read before `secondary`.
+struct Proxy {
+    primary: Backend,
+    secondary: Backend,
+    _mongo: ContainerAsync<Mongo>,
+    _process: Child,
+    config_file: PathBuf,
+    client: Client,
+}
+
+impl Proxy {
+    async fn start() -> Self {
+        let (primary, secondary, mongo) = tokio::join!(Backend::start(), Backend::start(), async {
+            Mongo::default().start().await.unwrap()
+        });
+        let mongo_uri = format!(
+            "mongodb://{}:{}",
+            mongo.get_host().await.unwrap(),
+            mongo.get_host_port_ipv4(27017).await.unwrap()
+        );
+
+        let port = free_port();
+        let config_file = std::env::temp_dir().join(format!("s3-reproxy-it-{port}.yaml"));
+        std::fs::write(
+            &config_file,
+            format!(
+                "access_key: {ACCESS_KEY}\n\
+                 secret_key: {SECRET_KEY}\n\
+                 bucket: {BUCKET}\n\
+                 remotes:\n\
+                 {}{}",
+                target("primary", 10, &primary.endpoint),
+                target("secondary", 1, &secondary.endpoint),
+            ),
+        )
+        .unwrap();
+
+        let process = Command::new(env!("CARGO_BIN_EXE_s3-reproxy"))
+            .arg("--config-file")
+            .arg(&config_file)
+            .args(["--port", &port.to_string()])
+            .args(["--mongo-uri", &mongo_uri])
+            .args(["--mongo-db", "s3_reproxy_it"])
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        wait_for_port(port).await;
+
+        Self {
+            primary,
+            secondary,
+            _mongo: mongo,
+            _process: process,
+            config_file,
+            client: client(&format!("http://127.0.0.1:{port}"), ACCESS_KEY, SECRET_KEY),
+        }
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.config_file);
+    }
+}
+
+fn target(name: &str, priority: u32, endpoint: &str) -> String {
+    format!(
+        "  - name: {name}\n    \
+             priority: {priority}\n    \
+             s3:\n      \
+               endpoint: {endpoint}\n      \
+               access_key: {MINIO_USER}\n      \
+               secret_key: {MINIO_PASSWORD}\n      \
+               bucket: {BUCKET}\n"
+    )
+}
+
+async fn wait_for_port(port: u16) {
+    for _ in 0..100 {
+        if TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("s3-reproxy did not start listening on port {port}");
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn put_lands_on_every_remote() {
+    let proxy = Proxy::start().await;
+
+    proxy
+        .client
+        .put_object()
+        .bucket(BUCKET)
+        .key("hello.txt")
+        .body(ByteStream::from_static(b"hello"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(proxy.primary.read("hello.txt").await, b"hello");
+    assert_eq!(proxy.secondary.read("hello.txt").await, b"hello");
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn get_reads_from_the_priority_remote() {
+    let proxy = Proxy::start().await;
+    for (backend, body) in [(&proxy.primary, "primary"), (&proxy.secondary, "secondary")] {
+        backend
+            .client
+            .put_object()
+            .bucket(BUCKET)
+            .key("diverged.txt")
+            .body(ByteStream::from_static(body.as_bytes()))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let object = proxy
+        .client
+        .get_object()
+        .bucket(BUCKET)
+        .key("diverged.txt")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(object.body.collect().await.unwrap().to_vec(), b"primary");
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn response_headers_round_trip() {
+    let proxy = Proxy::start().await;
+
+    proxy
+        .client
+        .put_object()
+        .bucket(BUCKET)
+        .key("index.html")
+        .body(ByteStream::from_static(b"<html></html>"))
+        .cache_control("max-age=3600")
+        .content_disposition("inline")
+        .content_encoding("identity")
+        .content_language("en")
+        .send()
+        .await
+        .unwrap();
+
+    let object = proxy
+        .client
+        .get_object()
+        .bucket(BUCKET)
+        .key("index.html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(object.cache_control(), Some("max-age=3600"));
+    assert_eq!(object.content_disposition(), Some("inline"));
+    assert_eq!(object.content_encoding(), Some("identity"));
+    assert_eq!(object.content_language(), Some("en"));
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn get_honors_response_overrides() {
+    let proxy = Proxy::start().await;
+    proxy
+        .client
+        .put_object()
+        .bucket(BUCKET)
+        .key("report")
+        .body(ByteStream::from_static(b"%PDF"))
+        .send()
+        .await
+        .unwrap();
+
+    let object = proxy
+        .client
+        .get_object()
+        .bucket(BUCKET)
+        .key("report")
+        .response_content_disposition("attachment; filename=x.pdf")
+        .response_content_type("application/pdf")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        object.content_disposition(),
+        Some("attachment; filename=x.pdf")
+    );
+    assert_eq!(object.content_type(), Some("application/pdf"));
+}
+
+#[tokio::test]
+#[ignore = "needs docker"]
+async fn multipart_upload_completes_on_every_remote() {
+    let proxy = Proxy::start().await;
+    let parts = [vec![b'a'; MIN_PART_SIZE], b"tail".to_vec()];
+
+    let upload = proxy
+        .client
+        .create_multipart_upload()
+        .bucket(BUCKET)
+        .key("video.mp4")
+        .send()
+        .await
+        .unwrap();
+    let upload_id = upload.upload_id().unwrap();
+    let mut completed = vec![];
+    for (part_number, part) in (1..).zip(&parts) {
+        let uploaded = proxy
+            .client
+            .upload_part()
+            .bucket(BUCKET)
+            .key("video.mp4")
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(part.clone()))
+            .send()
+            .await
+            .unwrap();
+        completed.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(uploaded.e_tag)
+                .build(),
+        );
+    }
+    proxy
+        .client
+        .complete_multipart_upload()
+        .bucket(BUCKET)
+        .key("video.mp4")
+        .upload_id(upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(completed))
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    let expected = parts.concat();
+    assert_eq!(proxy.primary.read("video.mp4").await, expected);
+    assert_eq!(proxy.secondary.read("video.mp4").await, expected);
+}